@@ -0,0 +1,253 @@
+//! Baseline numbers for the voxel hot paths. None of this touches the GPU or the network,
+//! so it can run anywhere `cargo bench` can.
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rand::{Rng, SeedableRng};
+
+use gestalt_core::common::voxelmath::VoxelRange;
+use gestalt_core::vpos;
+use gestalt_core::world::chunk::{Chunk, CHUNK_SIZE};
+use gestalt_core::world::meshing::{self, CubeArtNotes, FaceAo, OcclusionGrid, NO_OCCLUSION};
+use gestalt_core::world::tilespace::TileSpace;
+use gestalt_core::world::voxelstorage::VoxelStorage;
+use gestalt_core::world::worldgen::NoiseWorldGen;
+use gestalt_core::world::{ChunkPos, TileId};
+
+const AIR: TileId = 0;
+const STONE: TileId = 1;
+const DIRT: TileId = 2;
+const GRASS: TileId = 3;
+
+fn chunk_positions() -> VoxelRange<u8> {
+	VoxelRange {
+		lower: vpos!(0, 0, 0),
+		upper: vpos!(CHUNK_SIZE as u8, CHUNK_SIZE as u8, CHUNK_SIZE as u8),
+	}
+}
+
+/// Every tile set, with a small palette - representative of underground terrain.
+fn full_chunk() -> Chunk<TileId> {
+	let mut chunk = Chunk::new(AIR);
+	let mut rng = rand::rngs::StdRng::seed_from_u64(1337);
+	for pos in chunk_positions() {
+		let tile = match rng.gen_range(0..3) {
+			0 => STONE,
+			1 => DIRT,
+			_ => GRASS,
+		};
+		chunk.set(pos, tile).unwrap();
+	}
+	chunk
+}
+
+/// Mostly air with a few scattered tiles - representative of the surface or sky.
+fn sparse_chunk() -> Chunk<TileId> {
+	let mut chunk = Chunk::new(AIR);
+	let mut rng = rand::rngs::StdRng::seed_from_u64(1337);
+	for _ in 0..64 {
+		let pos = vpos!(
+			rng.gen_range(0..CHUNK_SIZE as u8),
+			rng.gen_range(0..CHUNK_SIZE as u8),
+			rng.gen_range(0..CHUNK_SIZE as u8)
+		);
+		chunk.set(pos, STONE).unwrap();
+	}
+	chunk
+}
+
+/// Rolling hills with the surface running through the chunk - what most of the chunks near players look like.
+fn terrain_chunk() -> Chunk<TileId> {
+	NoiseWorldGen::new(1337, 24.0).generate_at(vpos!(0, 0, 0))
+}
+
+/// Every tile but air drawn as a plain cube, which is all the benchmark chunks use.
+fn art_notes(tile: TileId) -> CubeArtNotes {
+	match tile {
+		AIR => CubeArtNotes::default(),
+		_ => CubeArtNotes {
+			visible_this_pass: true,
+			cull_self: true,
+			cull_others: true,
+			ao_enabled: true,
+			is_cross: false,
+		},
+	}
+}
+
+fn occlusion_grid(chunk: &Chunk<TileId>) -> OcclusionGrid {
+	let mut grid = OcclusionGrid::new();
+	for pos in chunk_positions() {
+		let solid = *chunk.get(pos).unwrap() != AIR;
+		grid.set_solid(pos.x as i32, pos.y as i32, pos.z as i32, solid);
+	}
+	grid
+}
+
+/// Does the same work as the client's greedy mesher, minus building vertices: culls hidden faces, works out
+/// ambient occlusion for the rest, and merges them into rectangles. Returns how many rectangles came out.
+fn greedy_mesh(chunk: &Chunk<TileId>, occluders: &OcclusionGrid) -> usize {
+	let size = CHUNK_SIZE as i32;
+	let tile_at = |pos: [i32; 3]| -> Option<TileId> {
+		if pos.iter().all(|coord| (0..size).contains(coord)) {
+			Some(*chunk.get(vpos!(pos[0] as u8, pos[1] as u8, pos[2] as u8)).unwrap())
+		} else {
+			None
+		}
+	};
+	let mut rects = 0;
+	for normal_axis in 0..3 {
+		let axis_a = (normal_axis + 1) % 3;
+		let axis_b = (normal_axis + 2) % 3;
+		for sign in [1, -1] {
+			for depth in 0..size {
+				let face_at = |a: usize, b: usize| -> Option<(TileId, FaceAo)> {
+					let mut pos = [0; 3];
+					pos[normal_axis] = depth;
+					pos[axis_a] = a as i32;
+					pos[axis_b] = b as i32;
+					let tile = tile_at(pos).unwrap();
+					let notes = art_notes(tile);
+					if !notes.visible_this_pass {
+						return None;
+					}
+					let mut outside = pos;
+					outside[normal_axis] += sign;
+					// Faces on the edge of the chunk are never culled, since the neighbor chunk isn't here.
+					let hidden = tile_at(outside)
+						.map(|neighbor| notes.is_face_hidden_by(tile, &art_notes(neighbor), neighbor))
+						.unwrap_or(false);
+					if hidden {
+						return None;
+					}
+					let mut ao = NO_OCCLUSION;
+					for (u, v) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+						let mut toward = [0; 3];
+						toward[axis_a] = if u == 1 { 1 } else { -1 };
+						toward[axis_b] = if v == 1 { 1 } else { -1 };
+						ao[u + 2 * v] = occluders.corner_ao(outside, toward);
+					}
+					Some((tile, ao))
+				};
+				meshing::greedy_rects(
+					CHUNK_SIZE,
+					face_at,
+					|(_, ao)| ao.iter().all(|corner| *corner == ao[0]),
+					|_, _, _, _, _| rects += 1,
+				);
+			}
+		}
+	}
+	rects
+}
+
+fn bench_chunk_get(c: &mut Criterion) {
+	let full = full_chunk();
+	let sparse = sparse_chunk();
+	c.bench_function("chunk_get_all_full", |b| {
+		b.iter(|| {
+			for pos in chunk_positions() {
+				black_box(full.get(pos).unwrap());
+			}
+		})
+	});
+	c.bench_function("chunk_get_all_sparse", |b| {
+		b.iter(|| {
+			for pos in chunk_positions() {
+				black_box(sparse.get(pos).unwrap());
+			}
+		})
+	});
+}
+
+fn bench_chunk_set(c: &mut Criterion) {
+	c.bench_function("chunk_set_all_from_uniform", |b| {
+		b.iter_batched(
+			|| Chunk::new(AIR),
+			|mut chunk| {
+				for pos in chunk_positions() {
+					chunk.set(pos, STONE).unwrap();
+				}
+				chunk
+			},
+			BatchSize::SmallInput,
+		)
+	});
+	c.bench_function("chunk_build_full", |b| b.iter(full_chunk));
+	c.bench_function("chunk_build_sparse", |b| b.iter(sparse_chunk));
+}
+
+fn bench_tilespace_fill_range(c: &mut Criterion) {
+	let chunk_range: VoxelRange<i32> = VoxelRange {
+		lower: vpos!(-1, -1, -1),
+		upper: vpos!(1, 1, 1),
+	};
+	let fill_range: VoxelRange<i32> = VoxelRange {
+		lower: vpos!(-24, -8, -24),
+		upper: vpos!(24, 8, 24),
+	};
+	c.bench_function("tilespace_fill_range", |b| {
+		b.iter_batched(
+			|| {
+				let mut space = TileSpace::new();
+				for chunk_pos in chunk_range {
+					let chunk_pos: ChunkPos = chunk_pos;
+					space.ingest_loaded_chunk(chunk_pos, Chunk::new(AIR)).unwrap();
+				}
+				space
+			},
+			|mut space| {
+				for pos in fill_range {
+					space.set(pos, STONE).unwrap();
+				}
+				space
+			},
+			BatchSize::SmallInput,
+		)
+	});
+}
+
+fn bench_meshing(c: &mut Criterion) {
+	let full = full_chunk();
+	let sparse = sparse_chunk();
+	let terrain = terrain_chunk();
+	c.bench_function("occlusion_grid_build_full", |b| b.iter(|| occlusion_grid(black_box(&full))));
+	for (name, chunk) in [("full", &full), ("sparse", &sparse), ("terrain", &terrain)] {
+		let occluders = occlusion_grid(chunk);
+		c.bench_function(&format!("greedy_mesh_{name}"), |b| {
+			b.iter(|| greedy_mesh(black_box(chunk), &occluders))
+		});
+	}
+}
+
+fn bench_chunk_serialization(c: &mut Criterion) {
+	let full = full_chunk();
+	let sparse = sparse_chunk();
+	let terrain = terrain_chunk();
+	for (name, chunk) in [("full", &full), ("sparse", &sparse), ("terrain", &terrain)] {
+		c.bench_function(&format!("chunk_to_rle_{name}"), |b| b.iter(|| black_box(chunk).to_rle().unwrap()));
+		c.bench_function(&format!("chunk_write_{name}"), |b| {
+			b.iter_batched(
+				Vec::new,
+				|mut buf| {
+					black_box(chunk).write_chunk(&mut buf).unwrap();
+					buf
+				},
+				BatchSize::SmallInput,
+			)
+		});
+		let mut encoded = Vec::new();
+		chunk.write_chunk(&mut encoded).unwrap();
+		c.bench_function(&format!("chunk_read_{name}"), |b| {
+			b.iter(|| Chunk::<TileId>::read_chunk(&mut black_box(encoded.as_slice())).unwrap())
+		});
+	}
+}
+
+criterion_group!(
+	benches,
+	bench_chunk_get,
+	bench_chunk_set,
+	bench_tilespace_fill_range,
+	bench_meshing,
+	bench_chunk_serialization
+);
+criterion_main!(benches);
@@ -1,6 +1,26 @@
+use std::collections::HashMap;
+
 use regex::Regex;
 use walkdir::WalkDir;
 
+include!("../gestalt-proc-macros/src/netmsg_name_hash.rs");
+
+/// NetMsgIds which mean something to the session layer itself, and so can't be given to a NetMsg by hand.
+/// Keep in sync with the *_RESERVED consts in net::netmsg.
+const RESERVED_NETMSG_IDS: &[(u32, &str)] = &[(0, "DISCONNECT_RESERVED"), (1, "FRAGMENT_RESERVED")];
+
+/// Reads an integer literal the way rustc would, e.g. `1_000`, `0x2A` or `40u32`.
+fn parse_id_literal(literal: &str) -> Option<u32> {
+	let literal = literal.replace('_', "");
+	let literal = literal.strip_suffix("u32").unwrap_or(&literal);
+	match literal.get(..2) {
+		Some("0x") => u32::from_str_radix(&literal[2..], 16).ok(),
+		Some("0o") => u32::from_str_radix(&literal[2..], 8).ok(),
+		Some("0b") => u32::from_str_radix(&literal[2..], 2).ok(),
+		_ => literal.parse().ok(),
+	}
+}
+
 fn main() {
 	// Collect all NetMsgs and add them to a lookup table. here be jank.
 	// NOTE: does not work with nested modules inside files (`mod inner { some stuff }`)
@@ -18,9 +38,16 @@ fn main() {
 	)
 	.unwrap();
 	let test_regex = Regex::new(
-		r#"#\[cfg\(test\)\][[:space:]]+(?:pub[[:space:]]+)?mod[[:space:]]+([A-Za-z0-9_]+)"#,
+		r#"#\[cfg\(test\)\][[:space:]]+(?:pub(?:\(crate\))?[[:space:]]+)?mod[[:space:]]+([A-Za-z0-9_]+)"#,
 	)
 	.unwrap();
+	let name_id_regex = Regex::new(r#"^#\[netmsg\([[:space:]]*name[[:space:]]*=[[:space:]]*"([^"]*)""#).unwrap();
+	let literal_id_regex = Regex::new(r#"^#\[netmsg\([[:space:]]*([0-9][0-9A-Za-z_]*)[[:space:]]*,"#).unwrap();
+	// Name-derived ID -> (name, where it was declared), to catch two names hashing to the same ID.
+	let mut named_ids: HashMap<u32, (String, String)> = HashMap::new();
+	// Literal ID -> where it was declared, to catch two messages picking the same ID.
+	// IDs given as an identifier (e.g. a const) would need evaluating, so they aren't checked.
+	let mut literal_ids: HashMap<u32, String> = HashMap::new();
 
 	let mut output = r#"use std::collections::HashMap;
 use toolbelt::once::InitOnce;
@@ -28,7 +55,7 @@ use crate::net::netmsg::{NetMsg, NetMsgId, NetMsgType};
 
 static NETMSG_LOOKUP_TABLE: InitOnce<HashMap<NetMsgId, NetMsgType>> = InitOnce::uninitialized();
 
-pub(crate) fn get_netmsg_table() -> &'static HashMap<NetMsgId, NetMsgType> {
+pub fn get_netmsg_table() -> &'static HashMap<NetMsgId, NetMsgType> {
     NETMSG_LOOKUP_TABLE.get_or_init(|| {
         let mut msgs = HashMap::new();
         "#
@@ -41,6 +68,11 @@ pub(crate) fn get_netmsg_table() -> &'static HashMap<NetMsgId, NetMsgType> {
 	{
 		let contents = std::fs::read_to_string(entry.path()).unwrap();
 		for cap in attr_regex.captures_iter(&contents) {
+			// Skip anything mentioned in a comment, e.g. an example attribute in a doc comment.
+			let line_start = contents[..cap.get(0).unwrap().start()].rfind('\n').map_or(0, |i| i + 1);
+			if contents[line_start..cap.get(0).unwrap().start()].trim_start().starts_with("//") {
+				continue;
+			}
 			let mut segments = entry
 				.path()
 				.iter()
@@ -56,6 +88,7 @@ pub(crate) fn get_netmsg_table() -> &'static HashMap<NetMsgId, NetMsgType> {
 			}
 			let before = &contents[..cap.get(0).unwrap().end()];
 			let after = &contents[cap.get(0).unwrap().end()..];
+			let attr_text = cap.get(0).unwrap().as_str();
 			if let Some(cap) = struct_regex.captures_iter(after).next() {
 				let mut is_test = false;
 				if test_regex.is_match(before) {
@@ -63,6 +96,31 @@ pub(crate) fn get_netmsg_table() -> &'static HashMap<NetMsgId, NetMsgType> {
 					is_test = true;
 				}
 				segments.push(cap.get(1).unwrap().as_str().to_string());
+				if let Some(name_cap) = name_id_regex.captures(attr_text) {
+					let name = name_cap.get(1).unwrap().as_str().to_string();
+					let id = netmsg_id_from_name(&name);
+					let declared_at = segments.join("::");
+					if let Some((other_name, other_declared_at)) = named_ids.get(&id) {
+						panic!(
+							"NetMsg names \"{name}\" ({declared_at}) and \"{other_name}\" ({other_declared_at}) \
+							both hash to ID {id:#x}. Rename one of them."
+						);
+					}
+					named_ids.insert(id, (name, declared_at));
+				}
+				if let Some(literal_cap) = literal_id_regex.captures(attr_text) {
+					let literal = literal_cap.get(1).unwrap().as_str();
+					let declared_at = segments.join("::");
+					let id = parse_id_literal(literal)
+						.unwrap_or_else(|| panic!("Could not read NetMsg ID `{literal}` of {declared_at}."));
+					if let Some((_, reserved_name)) = RESERVED_NETMSG_IDS.iter().find(|(reserved, _)| *reserved == id) {
+						panic!("NetMsg ID {id} ({declared_at}) is reserved by the session layer as {reserved_name}. Pick another ID.");
+					}
+					if let Some(other_declared_at) = literal_ids.get(&id) {
+						panic!("NetMsgs {declared_at} and {other_declared_at} both use ID {id}. Pick another ID for one of them.");
+					}
+					literal_ids.insert(id, declared_at);
+				}
 				output.push_str(&format!(
 					"\n{0}        msgs.insert(crate::{1}::net_msg_id(), crate::{1}::net_msg_type());",
 					if is_test {
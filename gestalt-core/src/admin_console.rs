@@ -0,0 +1,196 @@
+//! Runtime control for a headless server. Commands get typed into the server's terminal, parsed into
+//! AdminCommands here, and handed to the server main loop over a channel - which does the actual work
+//! and sends back a line of text saying how it went.
+
+use std::str::FromStr;
+
+use log::{error, info};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::oneshot;
+
+use crate::common::identity::{DecodeIdentityError, NodeIdentity};
+use crate::message::{MessageSender, MpscChannel, MpscSender, SendError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+	/// Save everything that has changed in the world right now, rather than waiting on autosave.
+	Save,
+	/// Shut the server down cleanly (saving on the way out).
+	Stop,
+	Kick(NodeIdentity),
+	Ban(NodeIdentity),
+	/// List the users who are currently on the server.
+	List,
+	/// How far out (in chunks) the server keeps track of things for each client.
+	SetViewDistance(u32),
+	/// Re-read server_config.ron and apply whatever can be changed without a restart.
+	ReloadConfig,
+}
+
+impl AdminCommand {
+	/// Usage line for every command, printed by "help".
+	pub const USAGE: &'static str = "Commands: save, stop, kick <identity>, ban <identity>, list, \
+		set-view-distance <chunks>, reload-config, help";
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AdminCommandError {
+	#[error("no command given")]
+	Empty,
+	#[error("unknown command \"{0}\"")]
+	UnknownCommand(String),
+	#[error("\"{0}\" needs an argument: {1}")]
+	MissingArgument(&'static str, &'static str),
+	#[error("\"{0}\" was given more arguments than it takes")]
+	TooManyArguments(&'static str),
+	#[error("could not parse identity: {0}")]
+	BadIdentity(#[from] DecodeIdentityError),
+	#[error("could not parse \"{0}\" as a number")]
+	BadNumber(String),
+}
+
+impl FromStr for AdminCommand {
+	type Err = AdminCommandError;
+
+	fn from_str(line: &str) -> Result<Self, Self::Err> {
+		let mut words = line.split_whitespace();
+		let command = words.next().ok_or(AdminCommandError::Empty)?;
+		let (parsed, name) = match command.to_lowercase().as_str() {
+			"save" => (AdminCommand::Save, "save"),
+			"stop" => (AdminCommand::Stop, "stop"),
+			"list" => (AdminCommand::List, "list"),
+			"reload-config" => (AdminCommand::ReloadConfig, "reload-config"),
+			"kick" => {
+				let ident = words.next().ok_or(AdminCommandError::MissingArgument("kick", "identity"))?;
+				(AdminCommand::Kick(NodeIdentity::from_base64(ident)?), "kick")
+			}
+			"ban" => {
+				let ident = words.next().ok_or(AdminCommandError::MissingArgument("ban", "identity"))?;
+				(AdminCommand::Ban(NodeIdentity::from_base64(ident)?), "ban")
+			}
+			"set-view-distance" => {
+				let distance = words
+					.next()
+					.ok_or(AdminCommandError::MissingArgument("set-view-distance", "chunks"))?;
+				let distance = distance
+					.parse()
+					.map_err(|_| AdminCommandError::BadNumber(distance.to_string()))?;
+				(AdminCommand::SetViewDistance(distance), "set-view-distance")
+			}
+			_ => return Err(AdminCommandError::UnknownCommand(command.to_string())),
+		};
+		if words.next().is_some() {
+			return Err(AdminCommandError::TooManyArguments(name));
+		}
+		Ok(parsed)
+	}
+}
+
+/// An AdminCommand on its way to the server, plus somewhere to send the result.
+#[derive(Debug)]
+pub struct AdminRequest {
+	pub command: AdminCommand,
+	pub reply: oneshot::Sender<String>,
+}
+
+static_channel_atom!(AdminCommands, MpscChannel<AdminRequest>, AdminRequest, 64);
+
+pub struct AdminConsole {
+	sender: MpscSender<AdminRequest>,
+}
+
+impl AdminConsole {
+	pub fn new(sender: MpscSender<AdminRequest>) -> Self {
+		Self { sender }
+	}
+
+	/// Parses one line of input, runs it on the server, and returns what to print back.
+	pub async fn submit(&self, line: &str) -> String {
+		if line.trim().eq_ignore_ascii_case("help") {
+			return AdminCommand::USAGE.to_string();
+		}
+		let command = match AdminCommand::from_str(line) {
+			Ok(command) => command,
+			Err(e) => return format!("Error: {e}. {}", AdminCommand::USAGE),
+		};
+		let (reply, reply_receiver) = oneshot::channel();
+		if let Err(e) = self.sender.send(AdminRequest { command, reply }) {
+			return match e {
+				SendError::Full => String::from("Error: the server is too busy to take commands right now."),
+				e => format!("Error: could not reach the server: {e}"),
+			};
+		}
+		reply_receiver
+			.await
+			.unwrap_or_else(|_| String::from("Error: the server stopped before it could reply."))
+	}
+
+	/// Reads commands from stdin, one per line, until stdin closes.
+	pub async fn run_stdin(self) {
+		info!("Admin console is listening on stdin. Type \"help\" for a list of commands.");
+		let mut lines = BufReader::new(tokio::io::stdin()).lines();
+		loop {
+			match lines.next_line().await {
+				Ok(Some(line)) => {
+					if line.trim().is_empty() {
+						continue;
+					}
+					println!("{}", self.submit(&line).await);
+				}
+				Ok(None) => break,
+				Err(e) => {
+					error!("Admin console could not read from stdin: {e}");
+					break;
+				}
+			}
+		}
+		info!("Admin console closed.");
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::common::identity::IdentityKeyPair;
+	use crate::message::{MessageReceiverAsync, SenderSubscribe};
+
+	#[test]
+	fn parse_admin_commands() {
+		let ident = IdentityKeyPair::generate_for_tests().public;
+		assert_eq!(AdminCommand::from_str("save").unwrap(), AdminCommand::Save);
+		assert_eq!(AdminCommand::from_str("  STOP ").unwrap(), AdminCommand::Stop);
+		assert_eq!(
+			AdminCommand::from_str(&format!("kick {}", ident.to_base64())).unwrap(),
+			AdminCommand::Kick(ident)
+		);
+		assert_eq!(
+			AdminCommand::from_str("set-view-distance 8").unwrap(),
+			AdminCommand::SetViewDistance(8)
+		);
+		assert!(matches!(AdminCommand::from_str(""), Err(AdminCommandError::Empty)));
+		assert!(matches!(AdminCommand::from_str("fly"), Err(AdminCommandError::UnknownCommand(_))));
+		assert!(matches!(AdminCommand::from_str("ban"), Err(AdminCommandError::MissingArgument(..))));
+		assert!(matches!(AdminCommand::from_str("ban abc"), Err(AdminCommandError::BadIdentity(_))));
+		assert!(matches!(
+			AdminCommand::from_str("set-view-distance far"),
+			Err(AdminCommandError::BadNumber(_))
+		));
+		assert!(matches!(AdminCommand::from_str("save now"), Err(AdminCommandError::TooManyArguments(_))));
+	}
+
+	#[tokio::test]
+	async fn console_round_trip() {
+		let channel: MpscChannel<AdminRequest> = MpscChannel::new(4);
+		let mut receiver = channel.take_receiver().unwrap();
+		let console = AdminConsole::new(channel.sender_subscribe());
+
+		let server = tokio::spawn(async move {
+			let request = receiver.recv_wait().await.unwrap();
+			assert_eq!(request.command, AdminCommand::List);
+			request.reply.send(String::from("Nobody is here.")).unwrap();
+		});
+		assert_eq!(console.submit("list").await, "Nobody is here.");
+		assert!(console.submit("dance").await.starts_with("Error"));
+		server.await.unwrap();
+	}
+}
@@ -1,10 +1,20 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
 use serde::{Deserialize, Serialize};
 use winit::window::Fullscreen;
 
+use crate::common::Color;
+use crate::world::DEFAULT_MAX_TICKS_PER_FRAME;
+use crate::common::camera::KeyBindings;
+
 pub const WINDOW_TITLE: &str = "Gestalt";
 pub const CLIENT_CONFIG_FILENAME: &str = "client_config.ron";
+/// Bump this whenever a change to ClientConfig needs more than new fields with defaults,
+/// and add the upgrade step to ClientConfig::migrate().
+pub const CLIENT_CONFIG_VERSION: u32 = 1;
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WindowMode {
 	Windowed {
 		/// If windowed, can this be resized with the OS' drag-and-drop controls?
@@ -24,7 +34,8 @@ impl Default for WindowMode {
 	}
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DisplaySize {
 	pub width: u32,
 	pub height: u32,
@@ -59,7 +70,53 @@ impl From<DisplaySize> for winit::dpi::Size {
 	}
 }
 
+/// How terrain textures are filtered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextureQuality {
+	/// Nearest-neighbor filtering - crisp, blocky pixels.
+	PixelArt,
+	/// Linear filtering.
+	Smooth,
+	/// Linear filtering plus anisotropic filtering at the given level (1, 2, 4, 8 or 16),
+	/// which looks better at grazing angles. Falls back to Smooth if the graphics card can't do it.
+	SmoothAniso(u8),
+}
+impl Default for TextureQuality {
+	fn default() -> Self {
+		TextureQuality::PixelArt
+	}
+}
+
+/// Which curve is used to squash the HDR scene down into the range the screen can show.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMapOperator {
+	/// Simple and gentle, but tends to look a little washed out.
+	Reinhard,
+	/// Filmic curve - more contrast, and highlights roll off more naturally.
+	Aces,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapConfig {
+	pub operator: ToneMapOperator,
+	/// Scene brightness is multiplied by this before tonemapping. 1.0 leaves it alone.
+	pub exposure: f32,
+	/// Display gamma. 2.2 is correct for nearly every monitor - raise it to darken midtones, lower it to brighten them.
+	pub gamma: f32,
+}
+impl Default for ToneMapConfig {
+	fn default() -> Self {
+		Self {
+			operator: ToneMapOperator::Aces,
+			exposure: 1.0,
+			gamma: 2.2,
+		}
+	}
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DisplayConfig {
 	pub size: DisplaySize,
 	pub window_mode: WindowMode,
@@ -67,6 +124,8 @@ pub struct DisplayConfig {
 	pub monitor: Option<String>,
 	/// Which graphics card?
 	pub device: Option<String>,
+	pub texture_quality: TextureQuality,
+	pub tonemap: ToneMapConfig,
 }
 
 impl DisplayConfig {
@@ -93,21 +152,276 @@ impl DisplayConfig {
 	}
 }
 
+/// Settings for the highlight drawn around whichever block the player is aiming at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockOutlineConfig {
+	pub show: bool,
+	pub color: Color,
+}
+impl Default for BlockOutlineConfig {
+	fn default() -> Self {
+		Self {
+			show: true,
+			color: Color { r: 16, g: 16, b: 16 },
+		}
+	}
+}
+
+/// Any field missing from the file gets its value from ClientConfig::default(), so config files written
+/// by older versions still load - see ClientConfig::from_ron_str().
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ClientConfig {
+	/// Which CLIENT_CONFIG_VERSION the file was written with. Files from before this existed count as 0.
+	#[serde(default)]
+	pub config_version: u32,
 	pub your_display_name: String,
 	pub display_properties: DisplayConfig,
 	pub mouse_sensitivity_x: f32,
 	pub mouse_sensitivity_y: f32,
+	pub block_outline: BlockOutlineConfig,
+	/// Sky color, i.e. what the screen gets cleared to before drawing.
+	pub clear_color: Color,
+	/// If set, the client keeps an eye on client_config.ron while running and picks up changes to it.
+	pub watch_config_file: bool,
+	/// How far out, in chunks, we'd like to hear about changes to the world. Servers may cap this.
+	pub view_distance: u32,
+	/// Upper limit on frames drawn per second, on top of whatever vsync does. 0 means no limit.
+	pub max_fps: u32,
+	pub key_bindings: KeyBindings,
+	/// Most game ticks to run in a single frame. After a long stall, any time past this gets skipped
+	/// rather than caught up on, so the client doesn't freeze trying.
+	pub max_ticks_per_frame: u32,
+}
+
+fn default_view_distance() -> u32 {
+	8
+}
+
+fn default_clear_color() -> Color {
+	Color { r: 89, g: 102, b: 204 }
 }
 
 impl Default for ClientConfig {
 	fn default() -> Self {
 		Self {
+			config_version: CLIENT_CONFIG_VERSION,
 			your_display_name: String::from("player"),
 			display_properties: Default::default(),
 			mouse_sensitivity_x: 64.0,
 			mouse_sensitivity_y: 64.0,
+			block_outline: Default::default(),
+			clear_color: default_clear_color(),
+			watch_config_file: false,
+			view_distance: default_view_distance(),
+			max_fps: 0,
+			key_bindings: KeyBindings::default(),
+			max_ticks_per_frame: DEFAULT_MAX_TICKS_PER_FRAME,
 		}
 	}
 }
+
+/// Anything worth mentioning about how a config file was read, beyond the config itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigLoadReport {
+	/// The version the file was written with, if it had to be upgraded.
+	pub migrated_from: Option<u32>,
+	/// Settings (as dotted paths, e.g. "display_properties.tonemap") the file didn't have, which got defaults.
+	pub defaulted_fields: Vec<String>,
+}
+
+/// Adds the path of every field in expected which found doesn't have, descending into nested structs.
+fn collect_missing_fields(found: &ron::Value, expected: &ron::Value, prefix: &str, missing: &mut Vec<String>) {
+	let (ron::Value::Map(found), ron::Value::Map(expected)) = (found, expected) else {
+		return;
+	};
+	for (key, expected_value) in expected.iter() {
+		let ron::Value::String(name) = key else {
+			continue;
+		};
+		let path = if prefix.is_empty() { name.clone() } else { format!("{prefix}.{name}") };
+		match found.iter().find(|(found_key, _)| *found_key == key).map(|(_, value)| value) {
+			Some(found_value) => collect_missing_fields(found_value, expected_value, &path, missing),
+			None => missing.push(path),
+		}
+	}
+}
+
+/// A setting which was changed while the client was running, but which can't take effect until it restarts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeferredConfigChange {
+	/// Already sent to the server when we joined.
+	DisplayName,
+	WindowSize,
+	WindowMode,
+	Monitor,
+	Device,
+	/// Baked into the terrain texture's sampler when the renderer starts.
+	TextureQuality,
+}
+
+impl ClientConfig {
+	/// Parses a client config file, upgrading it if it was written by an older version of the game.
+	/// Missing settings get defaults instead of failing the whole file, and are listed in the report.
+	pub fn from_ron_str(text: &str) -> Result<(Self, ConfigLoadReport), ron::error::SpannedError> {
+		let mut config: ClientConfig = ron::from_str(text)?;
+		let mut report = ConfigLoadReport::default();
+
+		// ron::Value loses enum variant names, so it's only good for seeing which fields are there -
+		// the config itself always comes straight from the text.
+		if let Ok(found) = ron::from_str::<ron::Value>(text) {
+			let defaults = ron::to_string(&ClientConfig::default()).expect("default client config must serialize");
+			let expected: ron::Value = ron::from_str(&defaults).expect("default client config must parse");
+			collect_missing_fields(&found, &expected, "", &mut report.defaulted_fields);
+			// Covered by migrated_from instead.
+			report.defaulted_fields.retain(|field| field != "config_version");
+		}
+
+		if config.config_version < CLIENT_CONFIG_VERSION {
+			report.migrated_from = Some(config.config_version);
+			config.migrate();
+		}
+		Ok((config, report))
+	}
+
+	/// Brings a config from an older CLIENT_CONFIG_VERSION up to date.
+	fn migrate(&mut self) {
+		// Version 0 is every file from before config_version existed. Everything added between then and
+		// version 1 was a new field, which serde has already defaulted, so nothing needs rewriting.
+		// Steps for later versions (renamed fields, changed units, etc.) go here, oldest first.
+		self.config_version = CLIENT_CONFIG_VERSION;
+	}
+
+	/// Takes on every setting in new_config. Mouse sensitivity, block outline, clear color, tonemapping,
+	/// view distance, max_fps, key bindings and watch_config_file are read as the client runs, so those take effect right away - anything
+	/// else that changed is returned, since it won't apply until a restart. Deferred settings are
+	/// still kept, so they get written back out on exit and used next launch.
+	pub fn apply_live(&mut self, new_config: ClientConfig) -> Vec<DeferredConfigChange> {
+		let mut deferred = Vec::new();
+		if new_config.your_display_name != self.your_display_name {
+			deferred.push(DeferredConfigChange::DisplayName);
+		}
+		let old_display = &self.display_properties;
+		let new_display = &new_config.display_properties;
+		if new_display.size != old_display.size {
+			deferred.push(DeferredConfigChange::WindowSize);
+		}
+		if new_display.window_mode != old_display.window_mode {
+			deferred.push(DeferredConfigChange::WindowMode);
+		}
+		if new_display.monitor != old_display.monitor {
+			deferred.push(DeferredConfigChange::Monitor);
+		}
+		if new_display.device != old_display.device {
+			deferred.push(DeferredConfigChange::Device);
+		}
+		if new_display.texture_quality != old_display.texture_quality {
+			deferred.push(DeferredConfigChange::TextureQuality);
+		}
+		*self = new_config;
+		deferred
+	}
+}
+
+/// How often ConfigFileWatcher looks at the file's modification time.
+pub const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Notices when a config file has been changed on disk, by polling its modification time.
+pub struct ConfigFileWatcher {
+	path: PathBuf,
+	last_modified: Option<SystemTime>,
+	last_checked: Instant,
+}
+
+impl ConfigFileWatcher {
+	pub fn new(path: PathBuf) -> Self {
+		let last_modified = Self::modified_time(&path);
+		Self {
+			path,
+			last_modified,
+			last_checked: Instant::now(),
+		}
+	}
+
+	fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+		std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+	}
+
+	/// Cheap enough to call every frame - only actually touches the filesystem once per CONFIG_WATCH_INTERVAL.
+	/// Returns true if the file has changed since the last time this returned true (or since new()).
+	pub fn poll_changed(&mut self) -> bool {
+		if self.last_checked.elapsed() < CONFIG_WATCH_INTERVAL {
+			return false;
+		}
+		self.last_checked = Instant::now();
+		let modified = Self::modified_time(&self.path);
+		if modified.is_some() && modified != self.last_modified {
+			self.last_modified = modified;
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Call this after writing to the file ourselves, so that doesn't get picked up as a change.
+	pub fn acknowledge_write(&mut self) {
+		self.last_modified = Self::modified_time(&self.path);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn apply_live_reports_deferred_changes() {
+		let mut config = ClientConfig::default();
+		let mut new_config = ClientConfig::default();
+		new_config.mouse_sensitivity_x = 12.0;
+		new_config.clear_color = Color { r: 0, g: 0, b: 0 };
+		assert!(config.apply_live(new_config).is_empty());
+		assert_eq!(config.mouse_sensitivity_x, 12.0);
+		assert_eq!(config.clear_color, Color { r: 0, g: 0, b: 0 });
+
+		let mut new_config = ClientConfig::default();
+		new_config.display_properties.window_mode = WindowMode::BorderlessFullscreenWindow;
+		new_config.display_properties.texture_quality = TextureQuality::Smooth;
+		let deferred = config.apply_live(new_config);
+		assert_eq!(deferred, vec![DeferredConfigChange::WindowMode, DeferredConfigChange::TextureQuality]);
+		// Still kept, so it gets saved.
+		assert_eq!(config.display_properties.texture_quality, TextureQuality::Smooth);
+	}
+
+	#[test]
+	fn old_config_files_load_with_defaults() {
+		// Written before config_version, block_outline, tonemapping and friends existed.
+		let old_file = r#"(
+			your_display_name: "gyro",
+			display_properties: (
+				size: (width: 1920, height: 1080),
+				window_mode: BorderlessFullscreenWindow,
+				monitor: None,
+				device: None,
+			),
+			mouse_sensitivity_x: 32.0,
+			mouse_sensitivity_y: 48.0,
+		)"#;
+		let (config, report) = ClientConfig::from_ron_str(old_file).unwrap();
+		assert_eq!(config.your_display_name, "gyro");
+		assert_eq!(config.display_properties.window_mode, WindowMode::BorderlessFullscreenWindow);
+		assert_eq!(config.mouse_sensitivity_y, 48.0);
+		assert_eq!(config.block_outline, BlockOutlineConfig::default());
+		assert_eq!(config.config_version, CLIENT_CONFIG_VERSION);
+		assert_eq!(report.migrated_from, Some(0));
+		for field in ["display_properties.texture_quality", "display_properties.tonemap", "clear_color", "view_distance"] {
+			assert!(report.defaulted_fields.iter().any(|f| f == field), "{field} should have been defaulted");
+		}
+		assert!(!report.defaulted_fields.iter().any(|f| f == "your_display_name"));
+
+		// A file we wrote ourselves has nothing to report.
+		let current = ron::to_string(&ClientConfig::default()).unwrap();
+		let (_, report) = ClientConfig::from_ron_str(&current).unwrap();
+		assert_eq!(report, ConfigLoadReport::default());
+	}
+}
@@ -4,6 +4,7 @@ use std::{
 	error::Error,
 	fs::OpenOptions,
 	io::{BufReader, Read, Write},
+	path::PathBuf,
 	time::{Duration, Instant},
 };
 
@@ -22,23 +23,23 @@ use winit::{
 };
 
 use crate::{
-	client::{client_config::ClientConfig, render::{Renderer, drawable::{BillboardDrawable, BillboardStyle}, voxel_art::{VoxelArt, CubeArt, CubeTex}, voxel_mesher::make_mesh_completely}},
+	client::{client_config::{ClientConfig, ConfigFileWatcher, CLIENT_CONFIG_VERSION}, render::{Renderer, drawable::{BillboardDrawable, BillboardStyle}, voxel_art::{VoxelArt, CubeArt, CubeTex}, voxel_mesher::make_mesh_completely}},
 	common::{
 		identity::{IdentityKeyPair, NodeIdentity},
-		voxelmath::{VoxelPos, VoxelRange, VoxelRaycast, VoxelSide, SidesArray}, DegreeAngle, Color,
+		voxelmath::{VoxelRange, VoxelRaycast, VoxelSide, SidesArray}, DegreeAngle,
 	},
 	message::{self, MessageSender},
 	message_types::{
 		voxel::{VoxelChangeAnnounce, VoxelChangeRequest},
-		JoinDefaultEntry,
+		JoinDefaultEntry, PlayerPositionReport,
 	},
 	net::net_channels::{net_recv_channel::NetMsgReceiver, net_send_channel, NetSendChannel},
-	resource::{ResourceKind, image::ID_MISSING_TEXTURE},
+	resource::{ResourceKind, image::{ID_MISSING_TEXTURE, LocalImageLoader}},
 	world::{
 		chunk::ChunkInner, fsworldstorage::WorldDefaults,
 		/*tilespace::{TileSpace, TileSpaceError}, fsworldstorage::{path_local_worlds, WorldDefaults, self, StoredWorldRole},*/
-		voxelstorage::VoxelSpace, ChunkPos, TilePos, WorldId, TickLength, tilespace::{TileSpace, TileSpaceError},
-	}, entity::{EntityPos, EntityVec3, EntityRot, EntityScale, EntityVelocity, tick_movement_system, LastPos},
+		voxelstorage::VoxelSpace, ChunkPos, TilePos, WorldId, TickLength, TickAccumulator, worldgen::{ChunkGenerator, NoiseWorldGen}, tilespace::{TileSpace, TileSpaceError}, edithistory::EditHistory,
+	}, entity::{EntityPos, EntityVec3, EntityRot, EntityScale, EntityVelocity, tick_movement_system, tick_lifetime_system, tick_transform_hierarchy_system, LastPos, SystemSchedule},
 };
 use crate::{
 	//client::render::CubeArt,
@@ -52,10 +53,13 @@ use crate::{
 	},
 };
 
-use super::camera::{self, Camera};
+use crate::common::camera::{self, Camera};
+use crate::common::frame_limiter::FrameLimiter;
 
 pub const WINDOW_TITLE: &str = "Gestalt";
 pub const CLIENT_CONFIG_FILENAME: &str = "client_config.ron";
+/// How many game ticks between each time we tell the server where we are.
+pub const POSITION_REPORT_INTERVAL_TICKS: u64 = 5;
 
 // Core / main part of the game client. Windowing and event dispatching lives here.
 // Input events come in through here.
@@ -72,6 +76,9 @@ pub enum StartClientError {
 	CreateWindowError(#[from] winit::error::OsError),
 }
 
+/// Seed for the terrain around the dev world's test chunk.
+const DEV_WORLD_SEED: u64 = 0x6765_7374_616c_74;
+
 // Dirt simple worldgen for the sake of early testing / development
 pub fn gen_test_chunk(chunk_position: ChunkPos) -> Chunk<TileId> {
 	const AIR_ID: TileId = 0;
@@ -106,16 +113,24 @@ pub fn gen_test_chunk(chunk_position: ChunkPos) -> Chunk<TileId> {
 	}
 }
 
-pub fn click_voxel(world_space: &TileSpace, camera: &Camera, ignore: &[TileId], max_steps: u32) -> Result<(TilePos, TileId, VoxelSide), TileSpaceError> {
-	let mut raycast = VoxelRaycast::new(*camera.get_position(), *camera.get_front());
-	for _i in 0..max_steps {
-		let resl = world_space.get(raycast.pos)?;
-		if !ignore.contains(resl) {
-			return Ok((raycast.pos, *resl, raycast.hit_side()));
-		}
-		raycast.step();
+/// Returns Ok(None) if nothing but ignored tiles lies within max_distance of the camera.
+pub fn click_voxel(world_space: &TileSpace, camera: &Camera, ignore: &[TileId], max_distance: f32) -> Result<Option<(TilePos, TileId, VoxelSide)>, TileSpaceError> {
+	let ray = camera.center_ray();
+	let mut raycast = VoxelRaycast::new(ray.origin, ray.direction);
+	// Stop on access errors too, so they can be reported below rather than stepped past.
+	let hit = raycast.cast_until(max_distance, |pos| match world_space.get(pos) {
+		Ok(tile) => !ignore.contains(tile),
+		Err(_) => true,
+	});
+	match hit {
+		Some((pos, side)) => Ok(Some((pos, *world_space.get(pos)?, side))),
+		None => Ok(None),
 	}
-	todo!()
+}
+
+/// Like click_voxel(), but for per-frame use: any failure (nothing in reach, unloaded chunks) just means no target.
+pub fn targeted_voxel(world_space: &TileSpace, camera: &Camera, ignore: &[TileId], max_distance: f32) -> Option<TilePos> {
+	click_voxel(world_space, camera, ignore, max_distance).ok().flatten().map(|(pos, _, _)| pos)
 }
 
 /*
@@ -180,7 +195,7 @@ pub fn get_lobby_world_id(pubkey: &NodeIdentity) -> WorldId {
 	}
 }
 
-pub fn load_or_generate_dev_world(world: &mut TileSpace, world_id: &WorldId, chunk_range: VoxelRange<i32>, mut terrain_notify: Option<&mut TerrainRenderer>) -> Result<(), Box<dyn Error>> {
+pub fn load_or_generate_dev_world(world: &mut TileSpace, world_id: &WorldId, generator: &impl ChunkGenerator, chunk_range: VoxelRange<i32>, mut terrain_notify: Option<&mut TerrainRenderer>) -> Result<(), Box<dyn Error>> {
 	let worldgen_start = Instant::now();
 	// Build chunks and then immediately let the mesher know they're new.
 	for chunk_position in chunk_range {
@@ -189,7 +204,7 @@ pub fn load_or_generate_dev_world(world: &mut TileSpace, world_id: &WorldId, chu
 			fsworldstorage::load_chunk(&world_id, StoredWorldRole::Local, &chunk_position)?
 		}
 		else {
-			gen_test_chunk(chunk_position)
+			generator.generate_chunk(world_id, chunk_position)
 		};
 		world.ingest_loaded_chunk(chunk_position, chunk)?;
 		if let Some(terrain_renderer) = terrain_notify.as_mut() {
@@ -202,19 +217,12 @@ pub fn load_or_generate_dev_world(world: &mut TileSpace, world_id: &WorldId, chu
 }*/
 
 // Never returns. Unfortunately the event loop's exit functionality does not just destroy the event loop, it closes the program.
-pub fn run_client(
-	identity_keys: IdentityKeyPair,
-	voxel_event_sender: NetSendChannel<VoxelChangeRequest>,
-	mut voxel_event_receiver: NetMsgReceiver<VoxelChangeAnnounce>,
-	server_identity: Option<NodeIdentity>,
-	async_runtime: tokio::runtime::Runtime,
-) {
-	let event_loop = winit::event_loop::EventLoop::new();
-	// Open config
+/// Reads client_config.ron, without falling back to defaults if that doesn't work.
+pub fn load_client_config_file() -> Result<ClientConfig, StartClientError> {
 	let mut open_options = std::fs::OpenOptions::new();
 	open_options.read(true).append(true).create(true);
 
-	let config_maybe: Result<ClientConfig, StartClientError> = open_options
+	open_options
 		.open(CLIENT_CONFIG_FILENAME)
 		.map_err(StartClientError::from)
 		.and_then(|file| {
@@ -225,9 +233,35 @@ pub fn run_client(
 				.map_err(StartClientError::from)?;
 			Ok(contents)
 		})
-		.and_then(|e| ron::from_str(e.as_str()).map_err(StartClientError::from));
+		.and_then(|contents| {
+			// We just created it, nothing to parse.
+			if contents.trim().is_empty() {
+				return Ok(ClientConfig::default());
+			}
+			let (config, report) = ClientConfig::from_ron_str(contents.as_str())?;
+			if let Some(version) = report.migrated_from {
+				info!("Upgraded {CLIENT_CONFIG_FILENAME} from config version {version} to {CLIENT_CONFIG_VERSION}.");
+			}
+			if !report.defaulted_fields.is_empty() {
+				info!("{CLIENT_CONFIG_FILENAME} did not set {}, using defaults for those.",
+					report.defaulted_fields.join(", "));
+			}
+			Ok(config)
+		})
+}
+
+pub fn run_client(
+	identity_keys: IdentityKeyPair,
+	voxel_event_sender: NetSendChannel<VoxelChangeRequest>,
+	mut voxel_event_receiver: NetMsgReceiver<VoxelChangeAnnounce>,
+	server_identity: Option<NodeIdentity>,
+	async_runtime: tokio::runtime::Runtime,
+) {
+	let event_loop = winit::event_loop::EventLoop::new();
+	// Open config
+	let config_maybe = load_client_config_file();
 	//If that didn't load, just use built-in defaults.
-	let config: ClientConfig = match config_maybe {
+	let mut config: ClientConfig = match config_maybe {
 		Ok(c) => c,
 		Err(e) => {
 			warn!("Couldn't open client config, using defaults. Error was: {:?}", e);
@@ -275,49 +309,49 @@ pub fn run_client(
 	let grass_id = 3;
 	let dome_thing_id = 4;
 
-	let mut image_loader = DevImageLoader::new();
+	let mut image_loader = LocalImageLoader::new();
 
 	let test_dome_thing_image_id = image_loader
-		.preload_image_file("test.png", identity_keys)
+		.add_image_file("test.png")
 		.unwrap();
 	let test_grass_image_id = image_loader
-		.preload_image_file("testgrass.png", identity_keys)
+		.add_image_file("testgrass.png")
 		.unwrap();
 	let test_stone_image_id = image_loader
-		.preload_image_file("teststone.png", identity_keys)
+		.add_image_file("teststone.png")
 		.unwrap();
 	let test_dirt_image_id = image_loader
-		.preload_image_file("testdirt.png", identity_keys)
+		.add_image_file("testdirt.png")
 		.unwrap();
 
 	let testlet_image_id = image_loader
-		.preload_image_file("testlet.png", identity_keys)
+		.add_image_file("testlet.png")
 		.unwrap();
 	let testlet_2_image_id = image_loader
-		.preload_image_file("testvesaria.png", identity_keys)
+		.add_image_file("testvesaria.png")
 		.unwrap();
 	let testlet_3_image_id = image_loader
-		.preload_image_file("testpoak.png", identity_keys)
+		.add_image_file("testpoak.png")
 		.unwrap();
 
 	
 	let test_posi_x_image_id = image_loader
-		.preload_image_file("test_posi_x.png", identity_keys)
+		.add_image_file("test_posi_x.png")
 		.unwrap();
 	let test_posi_y_image_id = image_loader
-		.preload_image_file("test_posi_y.png", identity_keys)
+		.add_image_file("test_posi_y.png")
 		.unwrap();
 	let test_posi_z_image_id = image_loader
-		.preload_image_file("test_posi_z.png", identity_keys)
+		.add_image_file("test_posi_z.png")
 		.unwrap();
 	let test_nega_x_image_id = image_loader
-		.preload_image_file("test_nega_x.png", identity_keys)
+		.add_image_file("test_nega_x.png")
 		.unwrap();
 	let test_nega_y_image_id = image_loader
-		.preload_image_file("test_nega_y.png", identity_keys)
+		.add_image_file("test_nega_y.png")
 		.unwrap();
 	let test_nega_z_image_id = image_loader
-		.preload_image_file("test_nega_z.png", identity_keys)
+		.add_image_file("test_nega_z.png")
 		.unwrap();
 	
 	let mut sides = SidesArray::new_uniform(&ID_MISSING_TEXTURE);
@@ -332,6 +366,7 @@ pub fn run_client(
 		textures: CubeTex::AllSides(Box::new(sides)),
 		cull_self: true, 
 		cull_others: true, 
+		ao_enabled: true,
 	});
 	// Set up our test world a bit
 
@@ -365,10 +400,11 @@ pub fn run_client(
 	let mut world_space = TileSpace::new();
 	world_space.ingest_loaded_chunk(vpos!(0,0,0), test_chunk).unwrap();
 	let test_world_range: VoxelRange<i32> = VoxelRange{upper: vpos!(2,2,2), lower: vpos!(-1,-2,-1) };
+	let dev_worldgen = NoiseWorldGen::new(DEV_WORLD_SEED, 12.0);
 	for chunk_pos in test_world_range {
 		renderer.terrain_renderer.notify_chunk_remesh_needed(&chunk_pos);
 		if chunk_pos != vpos!(0,0,0) { 
-			world_space.ingest_loaded_chunk(chunk_pos, gen_test_chunk(chunk_pos)).unwrap();
+			world_space.ingest_loaded_chunk(chunk_pos, dev_worldgen.generate_at(chunk_pos)).unwrap();
 		}
 	}
 	renderer.terrain_renderer.process_remesh(&world_space, &tiles_to_art).unwrap();
@@ -387,12 +423,24 @@ pub fn run_client(
 
 	let mut is_alt_down = false;
 	let mut is_tab_down = false;
+	let mut is_ctrl_down = false;
+
+	// Every voxel edit this client makes, so Ctrl+Z / Ctrl+Y can walk back through them.
+	let mut edit_history = EditHistory::default();
 
 	let mut has_focus = true;
 
 	let mut entity_world = crate::entity::EcsWorld::default();
 	
 	let tick_length = TickLength::from_tps(30.0);
+	let mut systems = SystemSchedule::new();
+	systems.add_system("movement", tick_movement_system).unwrap();
+	systems.add_system("transform_hierarchy", tick_transform_hierarchy_system).unwrap();
+	systems.add_system("lifetime", |world, tick_length| {
+		// Nothing to release for the despawned entities: their BillboardDrawables (texture handles included)
+		// go with them, and the textures themselves are shared by resource ID, so they stay loaded for reuse.
+		tick_lifetime_system(world, tick_length);
+	}).unwrap();
 
 	let test_entity = entity_world.spawn((
 		EntityPos::new(EntityVec3::new(0.0, 1.0, 0.0)), 
@@ -428,26 +476,34 @@ pub fn run_client(
 		)
 	};
 
-	let clear_color = Color { 
-		r: 89,
-		g: 102, 
-		b: 204
-	};
+	let mut config_watcher = ConfigFileWatcher::new(PathBuf::from(CLIENT_CONFIG_FILENAME));
 
-	let mut accumulated_tick_time: f32 = 0.0;
+	let mut frame_limiter = FrameLimiter::new(config.max_fps);
+
+	let mut tick_accumulator = TickAccumulator::new(tick_length, config.max_ticks_per_frame);
 	let mut game_tick: u64 = 0;
 	//let mut last_tick = Instant::now();
 
 	event_loop.run(move |event, _, control| {
 		let elapsed_secs = prev_frame_time.elapsed().as_secs_f64() as f32;
-		accumulated_tick_time = accumulated_tick_time + elapsed_secs;
-		while accumulated_tick_time >= tick_length.get() { 
+		let ticks_due = tick_accumulator.advance(elapsed_secs);
+		for _ in 0..ticks_due { 
 			game_tick +=1; 
-			accumulated_tick_time -= tick_length.get();
 			if (game_tick % 300) == 0 {
 				info!("Ticking game for the {game_tick}th time."); 
 			}
-			tick_movement_system(&mut entity_world, tick_length);
+			systems.run(&mut entity_world, tick_length);
+			if let Some(server) = server_identity.as_ref() {
+				if (game_tick % POSITION_REPORT_INTERVAL_TICKS) == 0 {
+					let report = PlayerPositionReport {
+						position: *camera.get_position(),
+						view_distance: config.view_distance,
+					};
+					if let Err(e) = net_send_channel::send_to(report, server) {
+						warn!("Could not report our position to the server: {e:?}");
+					}
+				}
+			}
 			//last_tick = Instant::now(); 
 		}
 		if let Ok(events) = voxel_event_receiver.recv_poll() {
@@ -504,10 +560,11 @@ pub fn run_client(
 				},
 				..
 			} => {
-				let hit = match click_voxel(&world_space, &camera, &[air_id], 1024) {
-					Ok((result_position, result_id, _)) => {
+				let hit = match click_voxel(&world_space, &camera, &[air_id], 1024.0) {
+					Ok(Some((result_position, result_id, _))) => {
 						Some((result_position, result_id))
 					},
+					Ok(None) => None,
 					Err(TileSpaceError::NotYetLoaded(pos) ) => {
 						info!("Tried to set a block on chunk {:?}, which is not yet loaded. Ignoring.", pos);
 						None
@@ -518,7 +575,7 @@ pub fn run_client(
 					},
 				};
 				if let Some((result_position, _result_id)) = hit {
-					match world_space.set(result_position, air_id) {
+					match world_space.set_tracked(result_position, air_id, &mut edit_history) {
 						Ok(()) => {
 
 							if let Some(_server) = server_identity.as_ref() {
@@ -544,10 +601,11 @@ pub fn run_client(
 				},
 				..
 			} => {
-				let hit = match click_voxel(&world_space, &camera, &[air_id], 1024) {
-					Ok((result_position, result_id, side)) => {
+				let hit = match click_voxel(&world_space, &camera, &[air_id], 1024.0) {
+					Ok(Some((result_position, result_id, side))) => {
 						Some((result_position, result_id, side))
 					},
+					Ok(None) => None,
 					Err(TileSpaceError::NotYetLoaded(pos) ) => {
 						println!("Tried to set a block on chunk {:?}, which is not yet loaded. Ignoring.", pos);
 						None
@@ -564,7 +622,7 @@ pub fn run_client(
 					if let Ok(placement_id) = world_space.get(placement_position) {
 						//Don't waste time setting stone to stone.
 						if *placement_id != stone_id {
-							match world_space.set(placement_position, stone_id) {
+							match world_space.set_tracked(placement_position, stone_id, &mut edit_history) {
 								Ok(()) => {
 
 									if let Some(_server) = server_identity.as_ref() {
@@ -614,8 +672,36 @@ pub fn run_client(
 						is_alt_down = true;
 					} else if input.virtual_keycode == Some(VirtualKeyCode::Tab) {
 						is_tab_down = true;
+					} else if (input.virtual_keycode == Some(VirtualKeyCode::LControl))
+						|| (input.virtual_keycode == Some(VirtualKeyCode::RControl))
+					{
+						is_ctrl_down = true;
+					} else if is_ctrl_down
+						&& ((input.virtual_keycode == Some(VirtualKeyCode::Z))
+							|| (input.virtual_keycode == Some(VirtualKeyCode::Y)))
+					{
+						let result = if input.virtual_keycode == Some(VirtualKeyCode::Z) {
+							edit_history.undo(&mut world_space)
+						} else {
+							edit_history.redo(&mut world_space)
+						};
+						match result {
+							Ok(edits) => {
+								for edit in edits {
+									if server_identity.is_some() {
+										voxel_event_sender.send(VoxelChangeRequest {
+											pos: edit.pos,
+											new_tile: edit.new_tile,
+										}).unwrap();
+									}
+									renderer.terrain_renderer.notify_changed(&edit.pos);
+								}
+							},
+							Err(TileSpaceError::NotYetLoaded(pos)) => info!("Can't undo or redo an edit at {:?}, which is not loaded anymore.", pos),
+							Err(e) => error!("Tile access error: {:?}", e),
+						}
 					}
-					let dir_maybe = input.virtual_keycode.and_then(camera::Directions::from_key);
+					let dir_maybe = input.virtual_keycode.and_then(|key| config.key_bindings.direction_for(key));
 					if let Some(dir) = dir_maybe {
 						current_down.insert(dir);
 					}
@@ -633,13 +719,20 @@ pub fn run_client(
 						is_alt_down = false;
 					} else if input.virtual_keycode == Some(VirtualKeyCode::Tab) {
 						is_tab_down = false;
+					} else if (input.virtual_keycode == Some(VirtualKeyCode::LControl))
+						|| (input.virtual_keycode == Some(VirtualKeyCode::RControl))
+					{
+						is_ctrl_down = false;
+					} else if input.virtual_keycode == Some(VirtualKeyCode::F4) {
+						let mode = renderer.cycle_debug_pipeline_mode();
+						info!("Terrain debug pipeline mode is now {mode:?}");
 					} else if input.virtual_keycode == Some(VirtualKeyCode::Escape) {
 						async_runtime
 							.block_on(message::quit_game(Duration::from_secs(5)))
 							.unwrap();
 						*control = ControlFlow::Exit;
 					}
-					let dir_maybe = input.virtual_keycode.and_then(camera::Directions::from_key);
+					let dir_maybe = input.virtual_keycode.and_then(|key| config.key_bindings.direction_for(key));
 					if let Some(dir) = dir_maybe {
 						current_down.remove(&dir);
 					}
@@ -676,12 +769,28 @@ pub fn run_client(
 				let elapsed_time = prev_frame_time.elapsed();
 				prev_frame_time = Instant::now();
 
-				if has_focus {
-					//Move camera
-					for dir in current_down.iter() {
-						camera.key_interact(*dir, elapsed_time);
+				if config.watch_config_file && config_watcher.poll_changed() {
+					match load_client_config_file() {
+						Ok(new_config) => {
+							let deferred = config.apply_live(new_config);
+							renderer.set_block_outline_config(config.block_outline);
+							renderer.set_tonemap_config(config.display_properties.tonemap);
+							info!("Reloaded {CLIENT_CONFIG_FILENAME}.");
+							if !deferred.is_empty() {
+								warn!("These config changes will not take effect until the game is restarted: {deferred:?}");
+							}
+						}
+						Err(e) => warn!("Could not reload {CLIENT_CONFIG_FILENAME}, keeping the current config. Error was: {e}"),
 					}
 				}
+
+				//Move camera
+				if has_focus {
+					camera.update_movement(current_down.iter().copied(), elapsed_time);
+				} else {
+					camera.update_movement([], elapsed_time);
+				}
+				camera.update_shake(elapsed_time);
 				match entity_world.query_one_mut::<&mut EntityPos>(test_entity_2) {
 					Ok(position) => {
 						let mut inner = position.get();
@@ -692,13 +801,17 @@ pub fn run_client(
 				}
 
 				
+				renderer.retry_pending_images(&mut image_loader);
+
 				// Remesh if it's not too spammy.
 				if last_remesh_time.elapsed().as_millis() > 64 {
 					let meshing_start = Instant::now();
 					let was_remesh_needed = renderer.terrain_renderer.process_remesh(&world_space, &tiles_to_art).unwrap();
 					if was_remesh_needed {
 						let meshing_elapsed_millis = meshing_start.elapsed().as_micros() as f32 / 1000.0;
-						info!("Took {meshing_elapsed_millis} milliseconds to do meshing");
+						let stats = renderer.terrain_renderer.last_remesh_stats();
+						info!("Took {meshing_elapsed_millis} milliseconds to do meshing ({} quads, {} vertices, {} faces culled, {:?} spent in the mesher itself)",
+							stats.quads, stats.vertices, stats.culled_faces, stats.meshing_time);
 
 						let start_upload_gpu = Instant::now();
 						renderer.process_terrain_mesh_uploads(&mut image_loader).unwrap();
@@ -714,10 +827,11 @@ pub fn run_client(
 				//Tell us some about it.
 				let draw_time = draw_start.elapsed();
 
+				renderer.set_block_outline(targeted_voxel(&world_space, &camera, &[air_id], 1024.0));
 				renderer.render_frame(&camera,
 					&entity_world, 
-					&clear_color, 
-					accumulated_tick_time as f32).unwrap();
+					&config.clear_color, 
+					tick_accumulator.get_accumulated()).unwrap();
 
 				let total_time = game_start_time.elapsed();
 				let current_fps = (total_frames as f64) / (total_time.as_secs_f64());
@@ -736,6 +850,11 @@ pub fn run_client(
 				if has_focus {
 					window.set_cursor_position(window_center).unwrap();
 				}
+
+				// Picks up changes from a config reload, too.
+				frame_limiter.set_max_fps(config.max_fps);
+				tick_accumulator.set_max_ticks_per_frame(config.max_ticks_per_frame);
+				frame_limiter.wait_for_next_frame();
 			}
 			winit::event::Event::LoopDestroyed => {
 				// Cleanup on quit.
@@ -755,9 +874,7 @@ pub fn run_client(
 				}
 				// Save world files
 				/*
-				let chunks = world_space.get_loaded_chunks();
-				for chunk_pos in chunks {
-					let chunk = world_space.borrow_chunk(chunk_pos).unwrap();
+				for (chunk_pos, chunk) in world_space.iter_loaded_sorted() {
 					fsworldstorage::save_chunk(&world_id,
 						StoredWorldRole::Local,
 						chunk_pos,
@@ -8,7 +8,7 @@ use log::{error, info, warn};
 
 use crate::client::render::{generate_missing_texture_image, generate_pending_texture_image};
 
-use super::{generate_error_texture_image, LoadedTexture};
+use super::{generate_error_texture_image, LoadedTexture, SamplerSpec};
 
 const INDEX_MISSING_TEXTURE: usize = 0;
 const INDEX_PENDING_TEXTURE: usize = 1;
@@ -187,6 +187,7 @@ pub struct ArrayTexture {
 	/// How many cells to add each time we run out of cells and have to rebuild.
 	max_cells: u32,
 	current_cell_capacity: u32,
+	sampler_spec: SamplerSpec,
 	texture: LoadedTexture,
 	error_image: RgbaImage, 
 	missing_image: RgbaImage,
@@ -220,7 +221,7 @@ impl ArrayTexture {
 		);
         let texture_view = new_texture_buffer.create_view(&wgpu::TextureViewDescriptor::default());
         
-        let sampler = device.create_sampler(&Self::get_sampler_config());
+        let sampler = device.create_sampler(&self.sampler_spec.to_descriptor());
 
         let bind_group = device.create_bind_group(
             &wgpu::BindGroupDescriptor {
@@ -336,6 +337,7 @@ impl ArrayTexture {
 	pub fn new(
 		layout: ArrayTextureLayout,
 		max_cells: Option<u32>,
+		sampler_spec: SamplerSpec,
 		bind_group_layout: &wgpu::BindGroupLayout,
 		device: &mut wgpu::Device,
 	) -> Result<Self, ArrayTextureError> {
@@ -390,7 +392,7 @@ impl ArrayTexture {
 
         let texture_view = texture_buffer.create_view(&wgpu::TextureViewDescriptor::default());
         
-        let sampler = device.create_sampler(&Self::get_sampler_config());
+        let sampler = device.create_sampler(&sampler_spec.to_descriptor());
 
         let bind_group = device.create_bind_group(
             &wgpu::BindGroupDescriptor {
@@ -420,6 +422,7 @@ impl ArrayTexture {
 			layout,
 			max_cells,
 			current_cell_capacity,
+			sampler_spec,
 			texture,
 			missing_image,
 			error_image,
@@ -427,18 +430,61 @@ impl ArrayTexture {
 		})
 	}
 
-	pub(in super) fn get_handle(&self) -> &LoadedTexture { 
-		&self.texture
+	/// Make room for at least this many cells (up to max_cells). If the buffer has to grow, it's replaced
+	/// with a new, blank one - write every layer again afterwards, or do this before writing anything.
+	pub fn reserve_cells(&mut self,
+			cells: u32,
+			bind_group_layout: &wgpu::BindGroupLayout,
+			device: &mut wgpu::Device) {
+		let cells = cells.min(self.max_cells);
+		if cells > self.current_cell_capacity {
+			self.current_cell_capacity = cells;
+			self.resize_buffer(bind_group_layout, device);
+		}
 	}
-	fn get_sampler_config() -> wgpu::SamplerDescriptor<'static> { 
-		wgpu::SamplerDescriptor {
-			address_mode_u: wgpu::AddressMode::Repeat,
-			address_mode_v: wgpu::AddressMode::Repeat,
-			address_mode_w: wgpu::AddressMode::ClampToEdge,
-			mag_filter: wgpu::FilterMode::Nearest,
-			min_filter: wgpu::FilterMode::Nearest,
-			mipmap_filter: wgpu::FilterMode::Nearest,
-			..Default::default()
+
+	/// Upload one already-loaded image into one layer, leaving every other layer alone.
+	pub fn write_layer(&self,
+			queue: &wgpu::Queue,
+			layer: u32,
+			image: &InternalImage) -> Result<(), ArrayTextureError> {
+		let texture_size = self.layout.texture_size;
+		if image.dimensions() != texture_size {
+			return Err(ArrayTextureError::WrongImageSize(format!("(layer {layer})"), image.dimensions(), texture_size));
+		}
+		if layer >= self.current_cell_capacity {
+			return Err(ArrayTextureError::BuildOverMax(layer as usize + 1, self.current_cell_capacity as usize));
 		}
+		let texture_size_layer = wgpu::Extent3d {
+			width: texture_size.0,
+			height: texture_size.1,
+			depth_or_array_layers: 1
+		};
+		queue.write_texture(
+			//Dest
+			wgpu::ImageCopyTexture {
+				texture: &self.texture.buffer_handle,
+				mip_level: 0,
+				origin: wgpu::Origin3d { 
+					x: 0, 
+					y: 0, 
+					z: layer,
+				},
+				aspect: wgpu::TextureAspect::All,
+			},
+			//Source
+			image,
+			wgpu::ImageDataLayout {
+				offset: 0,
+				bytes_per_row: std::num::NonZeroU32::new(4 * texture_size_layer.width),
+				rows_per_image: std::num::NonZeroU32::new(texture_size_layer.height),
+			},
+			texture_size_layer,
+		);
+		Ok(())
+	}
+
+	pub(in super) fn get_handle(&self) -> &LoadedTexture { 
+		&self.texture
 	}
 }
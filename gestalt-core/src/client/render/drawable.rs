@@ -1,9 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entity::ComponentSaveRegistry;
 use crate::resource::ResourceId;
 
 use super::TextureHandle;
 
 #[repr(u8)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BillboardStyle { 
     Spherical,
     Cylindrical,
@@ -13,7 +16,7 @@ pub enum BillboardStyle {
 // and array-texture-index selection. However, I realized I was falling into "Waterfall" again.
 // I didn't understand the problem domain well enough to start generalizing and abstracting in it.
 // So, we'll figure out how to structure things like this as we go along. 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BillboardDrawable {
     pub texture: ResourceId,
     /// Size in-world (in meters) that the sprite should appear as. 
@@ -21,6 +24,8 @@ pub struct BillboardDrawable {
     /// Size in-world (in meters) that the sprite should appear as. 
     pub height: f32,
     pub style: BillboardStyle,
+    /// Only means anything to the renderer which set it, so it's looked up again after loading.
+    #[serde(skip)]
     pub(in crate::client::render) texture_handle: Option<TextureHandle>,
 }
 
@@ -38,4 +43,9 @@ impl BillboardDrawable {
         self.width = width;
         self.height = height;
     }
-}
\ No newline at end of file
+}
+
+/// Lets BillboardDrawables get saved along with the rest of an entity.
+pub fn register_saveable_components(registry: &mut ComponentSaveRegistry) {
+    registry.register::<BillboardDrawable>("billboard");
+}
@@ -0,0 +1,230 @@
+use std::path::PathBuf;
+
+use glam::Vec3;
+use log::warn;
+use wgpu::TextureView;
+
+use super::load_test_shader;
+use crate::common::Color;
+
+/// How many line vertices (two per line segment) can be drawn in a single frame.
+pub const MAX_LINE_VERTICES: usize = 4096;
+
+/// How far (in meters) a block outline is pushed out from the faces of the block it surrounds,
+/// so that it doesn't z-fight with the terrain.
+pub const BLOCK_OUTLINE_OFFSET: f32 = 0.002;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+	position: [f32; 3],
+	color: [f32; 4],
+}
+impl LineVertex {
+	pub fn new(position: Vec3, color: &Color) -> Self {
+		let (r, g, b) = color.to_normalized_float();
+		Self {
+			position: position.to_array(),
+			color: [r, g, b, 1.0],
+		}
+	}
+	fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+		use std::mem;
+		wgpu::VertexBufferLayout {
+			array_stride: mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+			step_mode: wgpu::VertexStepMode::Vertex,
+			attributes: &[
+				wgpu::VertexAttribute {
+					offset: 0,
+					shader_location: 0,
+					format: wgpu::VertexFormat::Float32x3,
+				},
+				wgpu::VertexAttribute {
+					offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+					shader_location: 1,
+					format: wgpu::VertexFormat::Float32x4,
+				},
+			],
+		}
+	}
+}
+
+/// Produces the 12 edges of the axis-aligned box spanning min to max, as a line list.
+pub fn box_outline(min: Vec3, max: Vec3, color: &Color) -> [LineVertex; 24] {
+	let corner = |x: bool, y: bool, z: bool| {
+		LineVertex::new(
+			Vec3::new(
+				if x { max.x } else { min.x },
+				if y { max.y } else { min.y },
+				if z { max.z } else { min.z },
+			),
+			color,
+		)
+	};
+	[
+		// Bottom face
+		corner(false, false, false), corner(true, false, false),
+		corner(true, false, false), corner(true, false, true),
+		corner(true, false, true), corner(false, false, true),
+		corner(false, false, true), corner(false, false, false),
+		// Top face
+		corner(false, true, false), corner(true, true, false),
+		corner(true, true, false), corner(true, true, true),
+		corner(true, true, true), corner(false, true, true),
+		corner(false, true, true), corner(false, true, false),
+		// Verticals
+		corner(false, false, false), corner(false, true, false),
+		corner(true, false, false), corner(true, true, false),
+		corner(true, false, true), corner(true, true, true),
+		corner(false, false, true), corner(false, true, true),
+	]
+}
+
+/// A very simple immediate-mode line drawing path, used for things like
+/// the selection highlight around whichever block the player is aiming at.
+/// Lines are queued up over the course of a frame and then all drawn (and cleared) by draw().
+pub struct LineRenderer {
+	pending: Vec<LineVertex>,
+	vertex_buffer: wgpu::Buffer,
+	render_pipeline: wgpu::RenderPipeline,
+}
+
+impl LineRenderer {
+	pub fn new(camera_layout: &wgpu::BindGroupLayout,
+			device: &wgpu::Device,
+			render_format: &wgpu::TextureFormat,
+			depth_format: &wgpu::TextureFormat)
+				-> Self {
+		let line_shader_source = load_test_shader(PathBuf::from("line_shader.wgsl"));
+		let line_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some("Line Shader"),
+			source: line_shader_source,
+		});
+
+		let render_pipeline_layout =
+			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: Some("Line Pipeline Layout"),
+				bind_group_layouts: &[
+					camera_layout,
+				],
+				push_constant_ranges: &[],
+			});
+
+		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("Line Render Pipeline"),
+			layout: Some(&render_pipeline_layout),
+			vertex: wgpu::VertexState {
+				module: &line_shader,
+				entry_point: "vs_main",
+				buffers: &[
+					LineVertex::desc(),
+				],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: &line_shader,
+				entry_point: "fs_main",
+				targets: &[Some(wgpu::ColorTargetState {
+					format: render_format.clone(),
+					blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+			}),
+			primitive: wgpu::PrimitiveState {
+				topology: wgpu::PrimitiveTopology::LineList,
+				strip_index_format: None,
+				front_face: wgpu::FrontFace::Ccw,
+				cull_mode: None,
+				polygon_mode: wgpu::PolygonMode::Fill,
+				unclipped_depth: false,
+				conservative: false,
+			},
+			depth_stencil: Some(wgpu::DepthStencilState {
+				format: *depth_format,
+				// Lines are overlays, they shouldn't occlude anything drawn after them.
+				depth_write_enabled: false,
+				depth_compare: wgpu::CompareFunction::LessEqual,
+				stencil: wgpu::StencilState::default(),
+				bias: wgpu::DepthBiasState::default(),
+			}),
+			multisample: wgpu::MultisampleState {
+				count: 1,
+				mask: !0,
+				alpha_to_coverage_enabled: false,
+			},
+			multiview: None,
+		});
+
+		let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Line Vertex Buffer"),
+			size: (MAX_LINE_VERTICES * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+			usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		LineRenderer {
+			pending: Vec::with_capacity(MAX_LINE_VERTICES),
+			vertex_buffer,
+			render_pipeline,
+		}
+	}
+
+	/// Queue a single line segment to be drawn this frame.
+	pub fn push_line(&mut self, start: Vec3, end: Vec3, color: &Color) {
+		self.pending.push(LineVertex::new(start, color));
+		self.pending.push(LineVertex::new(end, color));
+	}
+
+	/// Queue the outline of an axis-aligned box to be drawn this frame.
+	pub fn push_box_outline(&mut self, min: Vec3, max: Vec3, color: &Color) {
+		self.pending.extend_from_slice(&box_outline(min, max, color));
+	}
+
+	/// Draws every line queued since the last call to draw(), and then clears the queue.
+	pub fn draw(&mut self,
+			queue: &wgpu::Queue,
+			render_surface_view: &TextureView,
+			depth_texture_view: &TextureView,
+			camera_bind_group: &wgpu::BindGroup,
+			encoder: &mut wgpu::CommandEncoder) {
+		if self.pending.is_empty() {
+			return;
+		}
+		if self.pending.len() > MAX_LINE_VERTICES {
+			warn!("Tried to draw {} line vertices in one frame, but only {} are supported. Truncating.",
+				self.pending.len(),
+				MAX_LINE_VERTICES);
+			self.pending.truncate(MAX_LINE_VERTICES);
+		}
+		queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.pending));
+
+		{
+			let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+				label: Some("Line Render Pass"),
+				color_attachments: &[
+					Some(wgpu::RenderPassColorAttachment {
+						view: render_surface_view,
+						resolve_target: None,
+						ops: wgpu::Operations {
+							load: wgpu::LoadOp::Load,
+							store: true,
+						},
+					}),
+				],
+				depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+					view: depth_texture_view,
+					depth_ops: Some(wgpu::Operations {
+						load: wgpu::LoadOp::Load,
+						store: true,
+					}),
+					stencil_ops: None,
+				}),
+			});
+			render_pass.set_pipeline(&self.render_pipeline);
+			render_pass.set_bind_group(0, camera_bind_group, &[]);
+			render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+			render_pass.draw(0..(self.pending.len() as u32), 0..1);
+		}
+
+		self.pending.clear();
+	}
+}
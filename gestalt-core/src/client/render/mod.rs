@@ -1,12 +1,12 @@
 use std::fs::OpenOptions;
 use std::io::Read;
 use std::iter;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroU8};
 use std::ops::Neg;
 use std::path::{Path, PathBuf};
 use glam::{Quat, Vec3, Mat4, EulerRot};
 use image::{Rgba, RgbaImage};
-use log::info;
+use log::{info, warn};
 use wgpu::util::DeviceExt;
 use std::collections::HashMap;
 use wgpu::{
@@ -15,16 +15,22 @@ use wgpu::{
 };
 use winit::window::Window;
 
-use crate::client::client_config::{ClientConfig, DisplaySize};
+use crate::client::client_config::{BlockOutlineConfig, ClientConfig, DisplaySize, TextureQuality, ToneMapConfig};
 use crate::common::{Color, FastHashMap, new_fast_hash_map};
-use crate::entity::{EcsWorld, EntityPos, EntityScale, EntityVelocity};
-use crate::resource::image::{ID_PENDING_TEXTURE, ID_MISSING_TEXTURE, InternalImage, LoadImageError};
-use crate::resource::{ResourceId, ResourceResult, ResourceProvider};
+use crate::entity::{EcsWorld, EntityAngularVelocity, EntityPos, EntityRot, EntityScale, EntityVelocity};
+use crate::resource::image::{generate_mip_chain, ID_PENDING_TEXTURE, ID_MISSING_TEXTURE, InternalImage, LoadImageError};
+use crate::resource::{ResourceError, ResourceId, ResourceLocation, ResourceProvider};
+use crate::resource::provider::ResourceLoader;
+use crate::world::TilePos;
 
+use self::array_texture::{ArrayTexture, ArrayTextureError, ArrayTextureLayout};
 use self::drawable::BillboardDrawable;
+use self::line_renderer::{LineRenderer, BLOCK_OUTLINE_OFFSET};
 use self::terrain_renderer::{TerrainRendererError, TerrainRenderer};
+use self::tonemap::ToneMapPass;
 
-use super::camera::Camera;
+use crate::common::camera::Camera;
+use crate::common::layer_batcher::LayerBatcher;
 
 pub mod drawable;
 pub mod array_texture;
@@ -32,6 +38,8 @@ pub mod tiletextureatlas;
 pub mod voxel_mesher;
 pub mod voxel_art;
 pub mod terrain_renderer;
+pub mod line_renderer;
+pub mod tonemap;
 
 pub(in self) fn load_test_shader<P: AsRef<Path>>(path: P) -> wgpu::ShaderSource<'static> {
 	let path = path.as_ref();
@@ -59,6 +67,43 @@ pub enum InitRenderError {
 	CannotCreateSurface(#[from] CreateSurfaceError),
 }
 
+/// Debugging aid for figuring out whether a missing or wrong-looking surface is down to winding / backface
+/// culling or to depth testing. Anything other than Normal swaps terrain drawing over to an alternate pipeline.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DebugPipelineMode {
+	#[default]
+	Normal,
+	/// Draw back faces too.
+	NoCulling,
+	/// Everything passes the depth test, so whatever is drawn last ends up on top.
+	DepthAlways,
+	NoCullingDepthAlways,
+}
+
+impl DebugPipelineMode {
+	/// The mode after this one, wrapping back around to Normal - for cycling through them with a key.
+	pub fn next(self) -> Self {
+		match self {
+			DebugPipelineMode::Normal => DebugPipelineMode::NoCulling,
+			DebugPipelineMode::NoCulling => DebugPipelineMode::DepthAlways,
+			DebugPipelineMode::DepthAlways => DebugPipelineMode::NoCullingDepthAlways,
+			DebugPipelineMode::NoCullingDepthAlways => DebugPipelineMode::Normal,
+		}
+	}
+	pub fn cull_mode(self) -> Option<wgpu::Face> {
+		match self {
+			DebugPipelineMode::Normal | DebugPipelineMode::DepthAlways => Some(wgpu::Face::Back),
+			DebugPipelineMode::NoCulling | DebugPipelineMode::NoCullingDepthAlways => None,
+		}
+	}
+	pub fn depth_compare(self) -> wgpu::CompareFunction {
+		match self {
+			DebugPipelineMode::Normal | DebugPipelineMode::NoCulling => wgpu::CompareFunction::Less,
+			DebugPipelineMode::DepthAlways | DebugPipelineMode::NoCullingDepthAlways => wgpu::CompareFunction::Always,
+		}
+	}
+}
+
 /// Renderer-internal handle to a currently-loaded texture.
 pub(in crate::client::render) type TextureHandle = NonZeroU32;
 
@@ -75,7 +120,13 @@ pub enum DrawFrameError {
 	#[error("Unable to draw a frame, could not acquire render surface: {0}")]
 	CannotRequestDevice(#[from] wgpu::SurfaceError),
 	#[error("Unable to draw a frame due to voxel rendering issue: {0}")]
-	VoxelError(#[from] TerrainRendererError)
+	VoxelError(#[from] TerrainRendererError),
+	#[error("This renderer is headless and has no window to draw to - use render_to_image() instead.")]
+	NoSurface,
+	#[error("Cannot read back frames drawn in {0:?}, only 8-bit RGBA / BGRA formats.")]
+	UnsupportedReadbackFormat(wgpu::TextureFormat),
+	#[error("Could not read the rendered image back from the graphics card: {0}")]
+	ReadbackFailed(#[from] wgpu::BufferAsyncError),
 }
 
 #[repr(C)]
@@ -118,14 +169,6 @@ const UNIT_BILLBOARD: &[Vertex] = &[
 	Vertex { position: [0.5, 0.5, 0.0], tex_coords: [1.0, 0.0], },
 ];
 
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: Mat4 = Mat4::from_cols_array(&[
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.0,
-    0.0, 0.0, 0.5, 1.0,
-]);
-
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub(in self) struct CameraUniform {
@@ -155,15 +198,107 @@ impl ModelPush {
 	}
 }
 
+/// Describes how a texture should be sampled. Textures which share a SamplerSpec
+/// share a single sampler object on the GPU.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerSpec {
+	pub address_mode: wgpu::AddressMode,
+	pub mag_filter: wgpu::FilterMode,
+	pub min_filter: wgpu::FilterMode,
+	pub mipmap_filter: wgpu::FilterMode,
+	/// None (or 1) means no anisotropic filtering. Otherwise must be 2, 4, 8 or 16.
+	pub anisotropy_clamp: Option<NonZeroU8>,
+}
+
+impl SamplerSpec {
+	/// Crisp, tiling, pixel-art sampling. This is the default.
+	pub const PIXEL_ART: SamplerSpec = SamplerSpec {
+		address_mode: wgpu::AddressMode::Repeat,
+		mag_filter: wgpu::FilterMode::Nearest,
+		min_filter: wgpu::FilterMode::Nearest,
+		// Blending between mip levels doesn't blur any single level, and keeps distant faces from shimmering.
+		mipmap_filter: wgpu::FilterMode::Linear,
+		anisotropy_clamp: None,
+	};
+	/// Smooth, tiling sampling.
+	pub const SMOOTH: SamplerSpec = SamplerSpec {
+		address_mode: wgpu::AddressMode::Repeat,
+		mag_filter: wgpu::FilterMode::Linear,
+		min_filter: wgpu::FilterMode::Linear,
+		mipmap_filter: wgpu::FilterMode::Linear,
+		anisotropy_clamp: None,
+	};
+	/// Smooth sampling which does not wrap at the edges - useful for UI elements and gradients.
+	pub const SMOOTH_CLAMPED: SamplerSpec = SamplerSpec {
+		address_mode: wgpu::AddressMode::ClampToEdge,
+		mag_filter: wgpu::FilterMode::Linear,
+		min_filter: wgpu::FilterMode::Linear,
+		mipmap_filter: wgpu::FilterMode::Linear,
+		anisotropy_clamp: None,
+	};
+
+	/// Sampler to use for terrain at the given quality setting. If anisotropic filtering
+	/// was requested but the adapter doesn't support it, this falls back to plain smooth filtering.
+	pub fn for_texture_quality(quality: TextureQuality, anisotropy_supported: bool) -> SamplerSpec {
+		match quality {
+			TextureQuality::PixelArt => Self::PIXEL_ART,
+			TextureQuality::Smooth => Self::SMOOTH,
+			TextureQuality::SmoothAniso(level) => {
+				if !anisotropy_supported {
+					warn!("Anisotropic filtering was requested, but this graphics adapter does not support it. Using plain smooth filtering.");
+					return Self::SMOOTH;
+				}
+				// wgpu only accepts powers of two up to 16.
+				let level = level.clamp(1, 16);
+				let level = if level.is_power_of_two() { level } else { level.next_power_of_two() / 2 };
+				SamplerSpec {
+					anisotropy_clamp: NonZeroU8::new(level).filter(|l| l.get() > 1),
+					..Self::SMOOTH
+				}
+			},
+		}
+	}
+
+	pub fn to_descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+		wgpu::SamplerDescriptor {
+			label: Some("texture_sampler"),
+			address_mode_u: self.address_mode,
+			address_mode_v: self.address_mode,
+			// Our textures are 2D, so leave W alone like we always have.
+			address_mode_w: wgpu::AddressMode::ClampToEdge,
+			mag_filter: self.mag_filter,
+			min_filter: self.min_filter,
+			mipmap_filter: self.mipmap_filter,
+			anisotropy_clamp: self.anisotropy_clamp,
+			..Default::default()
+		}
+	}
+}
+
+impl Default for SamplerSpec {
+	fn default() -> Self {
+		Self::PIXEL_ART
+	}
+}
+
 struct TextureManager {
     id_to_texture: FastHashMap<ResourceId, ImageTextureBinding>, 
     loaded_textures: HashMap<u32, LoadedTexture, nohash::BuildNoHashHasher<u32>>,
+    /// One sampler per distinct SamplerSpec, shared between every texture using it.
+    samplers: FastHashMap<SamplerSpec, wgpu::Sampler>,
 	
     next_texture_handle: TextureHandle,
 	
     missing_image: InternalImage,
     pending_image: InternalImage,
     error_image: InternalImage,
+    /// Textures which are showing the pending image while their resource is still loading,
+    /// along with the sampler they were asked for and whether they wanted mips. See retry_pending().
+    pending: FastHashMap<ResourceId, (SamplerSpec, bool)>,
+    /// Decides which layer of which array texture each batched image goes into.
+    batcher: LayerBatcher<ResourceId>,
+    /// Built array textures for batched images, by batch index from the batcher.
+    array_textures: HashMap<u32, ArrayTexture, nohash::BuildNoHashHasher<u32>>,
 }
 
 impl TextureManager {
@@ -183,11 +318,29 @@ impl TextureManager {
 			error_image,
             id_to_texture: new_fast_hash_map(), 
             loaded_textures: HashMap::with_hasher(nohash::BuildNoHashHasher::default()),
+            samplers: new_fast_hash_map(),
+            pending: new_fast_hash_map(),
+            batcher: LayerBatcher::new(BATCHED_TEXTURE_MAX_LAYERS, BATCHED_TEXTURE_BUILTIN_LAYERS),
+            array_textures: HashMap::with_hasher(nohash::BuildNoHashHasher::default()),
 		}
 		
 	}
+	/// Make sure a sampler matching this spec exists, creating it if it doesn't.
+	pub fn init_sampler(&mut self, spec: &SamplerSpec, device: &wgpu::Device) {
+		if !self.samplers.contains_key(spec) {
+			let sampler = device.create_sampler(&spec.to_descriptor());
+			self.samplers.insert(*spec, sampler);
+		}
+	}
+	/// Get the cached sampler for this spec, if init_sampler() has been called for it.
+	pub fn get_sampler(&self, spec: &SamplerSpec) -> Option<&wgpu::Sampler> {
+		self.samplers.get(spec)
+	}
+	/// Upload an image into its own texture. With generate_mips, the texture gets a full mip chain
+	/// (box-filtered on the CPU) rather than just the one level.
 	pub fn load_image(image: &InternalImage,
-		sampler_config: &wgpu::SamplerDescriptor,
+		sampler: &wgpu::Sampler,
+		generate_mips: bool,
 		device: &wgpu::Device,
 		queue: &wgpu::Queue,
 		bind_group_layout: &wgpu::BindGroupLayout
@@ -197,12 +350,13 @@ impl TextureManager {
             height:  image.dimensions().1,
             depth_or_array_layers: 1
         };
+        let mip_chain = if generate_mips { generate_mip_chain(image) } else { Vec::new() };
 
         // Create the buffer on the GPU.
         let texture_buffer = device.create_texture(
             &wgpu::TextureDescriptor {
                 size: texture_size,
-                mip_level_count: 1,
+                mip_level_count: 1 + mip_chain.len() as u32,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 format: wgpu::TextureFormat::Rgba8UnormSrgb,
@@ -229,10 +383,30 @@ impl TextureManager {
             },
             texture_size,
         );
+        for (i, mip) in mip_chain.iter().enumerate() {
+            let mip_size = wgpu::Extent3d {
+                width: mip.width(),
+                height: mip.height(),
+                depth_or_array_layers: 1
+            };
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture_buffer,
+                    mip_level: i as u32 + 1,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                mip,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * mip_size.width),
+                    rows_per_image: std::num::NonZeroU32::new(mip_size.height),
+                },
+                mip_size,
+            );
+        }
         
         let texture_view = texture_buffer.create_view(&wgpu::TextureViewDescriptor::default());
-        
-        let sampler = device.create_sampler(sampler_config);
 
         let bind_group = device.create_bind_group(
             &wgpu::BindGroupDescriptor {
@@ -244,7 +418,7 @@ impl TextureManager {
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&sampler),
+                        resource: wgpu::BindingResource::Sampler(sampler),
                     }
                 ],
                 label: Some("diffuse_bind_group"),
@@ -256,55 +430,160 @@ impl TextureManager {
             bind_group,
         }
 	}
+    /// What to draw for a finished load - the image itself, or one of the engine's stand-ins if it failed.
+    fn image_for_result(&self, result: Result<InternalImage, ResourceError<LoadImageError>>) -> InternalImage {
+        match result {
+            Ok(image) => image,
+            Err(ResourceError::Parse(_, LoadImageError::DoesNotExist(_))) => self.missing_image.clone(),
+            Err(_) => self.error_image.clone(),
+        }
+    }
     // This will likely change when the engine as a whole is more structured.
     // Probably it'll be some kind of message-passing situation. 
+    /// Never blocks on the loader - if the image isn't ready yet, the texture shows the pending image
+    /// until a later retry_pending() finds it ready.
     pub fn ingest_image_resource<P>(&mut self,
 		resource_id: &ResourceId,
-		sampler_config: &wgpu::SamplerDescriptor,
+		sampler_spec: &SamplerSpec,
+		generate_mips: bool,
 		device: &wgpu::Device,
 		queue: &wgpu::Queue,
 		bind_group_layout: &wgpu::BindGroupLayout,
 		loader: &mut P
 	) -> TextureHandle
-            where P: ResourceProvider<InternalImage, Error=LoadImageError> {
+            where P: ResourceLoader<InternalImage, ParseError=LoadImageError> {
+        self.init_sampler(sampler_spec, device);
 
         let image = if resource_id == &ID_PENDING_TEXTURE {
-            &self.pending_image
+            self.pending_image.clone()
         } else if resource_id == &ID_MISSING_TEXTURE {
-            &self.missing_image
+            self.missing_image.clone()
         }
         else {
-            match loader.load_image(resource_id) {
-                ResourceResult::Pending => &self.pending_image,
-                ResourceResult::Err(e) => match e {
-                    crate::resource::image::RetrieveImageError::DoesNotExist(_) => &self.missing_image,
-                    _ => &self.error_image,
-                },
-                ResourceResult::Ready(image) => image,
+            match loader.poll_or_request(&ResourceLocation::Caid(*resource_id)) {
+                Some(result) => self.image_for_result(result),
+                None => {
+                    self.pending.insert(*resource_id, (*sampler_spec, generate_mips));
+                    self.pending_image.clone()
+                }
             }
         };
 		
-		let loaded_texture = Self::load_image(image, sampler_config, device, queue, bind_group_layout);
+		// Guaranteed to be present by the init_sampler() call above.
+		let sampler = self.samplers.get(sampler_spec).unwrap();
+		let loaded_texture = Self::load_image(&image, sampler, generate_mips, device, queue, bind_group_layout);
         let handle = self.next_texture_handle;
         self.next_texture_handle = self.next_texture_handle.checked_add(1)
             .expect("Ran out of texture handle IDs!");
 
         let previous_texture = self.loaded_textures.insert(handle.get(), loaded_texture);
         assert!(previous_texture.is_none());
-        self.id_to_texture.insert(resource_id.clone(), handle);
+        self.id_to_texture.insert(resource_id.clone(), ImageTextureBinding::OneToOne(handle));
         
         handle
+    }
+    /// Like ingest_image_resource(), but packs the image into a layer of an array texture shared with every
+    /// other batched image of the same size, rather than giving it a buffer of its own.
+    /// Images still loading are placed by the size of the pending image, same as the non-batched path.
+    pub fn ingest_image_batched<P>(&mut self,
+		resource_id: &ResourceId,
+		sampler_spec: &SamplerSpec,
+		device: &mut wgpu::Device,
+		queue: &wgpu::Queue,
+		bind_group_layout: &wgpu::BindGroupLayout,
+		loader: &mut P
+	) -> Result<ImageTextureBinding, ArrayTextureError>
+            where P: ResourceLoader<InternalImage, ParseError=LoadImageError> {
+        if let Some(binding) = self.id_to_texture.get(resource_id) {
+            if let ImageTextureBinding::InBatch { .. } = binding {
+                return Ok(*binding);
+            }
+        }
+        let image = match loader.poll_or_request(&ResourceLocation::Caid(*resource_id)) {
+            Some(result) => self.image_for_result(result),
+            None => {
+                // Array textures get a single mip level for now.
+                self.pending.insert(*resource_id, (*sampler_spec, false));
+                self.pending_image.clone()
+            }
+        };
+        let (batch, layer) = self.batcher.place(resource_id, image.dimensions());
+        if !self.array_textures.contains_key(&batch) {
+            // Allocate every layer up front, so adding more images later never has to rebuild the buffer.
+            let layout = ArrayTextureLayout::new(image.dimensions(), Some(self.batcher.get_max_layers()));
+            let mut array_texture = ArrayTexture::new(layout, Some(self.batcher.get_max_layers()), *sampler_spec, bind_group_layout, device)?;
+            array_texture.reserve_cells(self.batcher.get_max_layers(), bind_group_layout, device);
+            let (width, height) = image.dimensions();
+            array_texture.write_layer(queue, INDEX_BATCHED_MISSING_TEXTURE, &generate_missing_texture_image(width, height))?;
+            array_texture.write_layer(queue, INDEX_BATCHED_PENDING_TEXTURE, &generate_pending_texture_image(width, height))?;
+            self.array_textures.insert(batch, array_texture);
+        }
+        // Guaranteed to be present by the block above.
+        let array_texture = self.array_textures.get(&batch).unwrap();
+        array_texture.write_layer(queue, layer, &image)?;
+
+        let binding = ImageTextureBinding::InBatch { array_texture: batch, cell: layer };
+        self.id_to_texture.insert(*resource_id, binding);
+        Ok(binding)
+    }
+    /// Check back on every texture which is still showing the pending image, swapping in the real image
+    /// for any which have finished loading. Handles stay the same, so nothing else needs to know.
+    pub fn retry_pending<P>(&mut self,
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		bind_group_layout: &wgpu::BindGroupLayout,
+		loader: &mut P
+	) where P: ResourceLoader<InternalImage, ParseError=LoadImageError> {
+        let still_pending: Vec<(ResourceId, (SamplerSpec, bool))> = self.pending.iter().map(|(id, spec)| (*id, *spec)).collect();
+        for (resource_id, (sampler_spec, generate_mips)) in still_pending {
+            let result = match loader.poll_or_request(&ResourceLocation::Caid(resource_id)) {
+                Some(result) => result,
+                None => continue,
+            };
+            self.pending.remove(&resource_id);
+            let image = self.image_for_result(result);
+            match self.id_to_texture.get(&resource_id) {
+                Some(ImageTextureBinding::OneToOne(handle)) => {
+                    let sampler = self.samplers.get(&sampler_spec).unwrap();
+                    let loaded_texture = Self::load_image(&image, sampler, generate_mips, device, queue, bind_group_layout);
+                    self.loaded_textures.insert(handle.get(), loaded_texture);
+                },
+                Some(ImageTextureBinding::InBatch { array_texture, cell }) => {
+                    let written = self.array_textures.get(array_texture)
+                        .map(|array_texture| array_texture.write_layer(queue, *cell, &image));
+                    if let Some(Err(e)) = written {
+                        warn!("Could not swap finished image {resource_id} into its array texture: {e}");
+                    }
+                },
+                None => {},
+            }
+        }
+    }
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
     }
 	pub fn get(&self, handle: TextureHandle) -> Option<&LoadedTexture> { 
 		self.loaded_textures.get(&handle.get())
 	}
 	pub fn get_by_resource(&self, resource: &ResourceId) -> Option<&LoadedTexture> { 
-		let id = self.id_to_texture.get(resource)?;
-		self.get(*id)
+		match self.id_to_texture.get(resource)? {
+			ImageTextureBinding::OneToOne(handle) => self.get(*handle),
+			ImageTextureBinding::InBatch { array_texture, .. } => self.get_array_texture(*array_texture)
+				.map(|array_texture| array_texture.get_handle()),
+		}
 	}
 	pub fn get_id_by_resource(&self, resource: &ResourceId) -> Option<&TextureHandle> { 
+		match self.id_to_texture.get(resource)? {
+			ImageTextureBinding::OneToOne(handle) => Some(handle),
+			ImageTextureBinding::InBatch { .. } => None,
+		}
+	}
+	pub fn get_binding_by_resource(&self, resource: &ResourceId) -> Option<&ImageTextureBinding> { 
 		self.id_to_texture.get(resource)
 	}
+	pub fn get_array_texture(&self, batch: u32) -> Option<&ArrayTexture> { 
+		self.array_textures.get(&batch)
+	}
 }
 
 pub(self) struct LoadedTexture {
@@ -314,35 +593,54 @@ pub(self) struct LoadedTexture {
 }
 
 /// Describes where an Image ResourceID lives in the renderer. 
-pub type ImageTextureBinding = TextureHandle;
-
-/*
-#[repr(C)]
-#[derive(Debug, Clone)]
-/// Describes where an Image ResourceID lives in the renderer. 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ImageTextureBinding {
 	/// This image got its own buffer and bindgroup.
 	OneToOne(TextureHandle),
 	/// This image has been batched into one layer of an ArrayTexture.
 	InBatch{
-		array_texture: TextureHandle,
-		cell: u32,
-	},
-	/// This image needed to be in both its own buffer and inside an ArrayTexture at the same time,
-	/// and so it was uploaded to one first and then that texture data was copied to the second 
-	/// buffer via the command queue (this should never be done on the CPU and then uploaded a
-	/// second time!).
-	Both{
-		unary_texture: TextureHandle,
-		array_texture: TextureHandle,
+		/// Which of the TextureManager's array textures.
+		array_texture: u32,
+		/// Which layer of it - what the shader samples by.
 		cell: u32,
 	},
-}*/
+}
+
+/// How many layers each batched array texture gets. Matches what the terrain renderer uses for its tile arrays.
+const BATCHED_TEXTURE_MAX_LAYERS: u32 = 4096;
+// ArrayTextureLayout always starts with these two builtins.
+const INDEX_BATCHED_MISSING_TEXTURE: u32 = 0;
+const INDEX_BATCHED_PENDING_TEXTURE: u32 = 1;
+const BATCHED_TEXTURE_BUILTIN_LAYERS: u32 = 2;
+
+/// An offscreen color + depth buffer pair which the scene can be drawn into
+/// instead of the window, with Renderer::render_to_target().
+pub struct RenderTarget {
+	color: LoadedTexture,
+	depth: (wgpu::Texture, wgpu::TextureView, wgpu::Sampler),
+	width: u32,
+	height: u32,
+}
+impl RenderTarget {
+	pub fn get_size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+	pub fn get_texture_view(&self) -> &wgpu::TextureView {
+		&self.color.texture_view
+	}
+	/// Bind group (texture + sampler) compatible with the billboard pipeline's texture slot.
+	pub fn get_bind_group(&self) -> &wgpu::BindGroup {
+		&self.color.bind_group
+	}
+}
 
 pub struct Renderer {
 	window_size: winit::dpi::PhysicalSize<u32>,
 	instance: wgpu::Instance,
-	surface: wgpu::Surface,
+	/// None for a headless renderer.
+	surface: Option<wgpu::Surface>,
+	/// For a headless renderer this is never actually used to configure a surface, but still holds the
+	/// output format and size.
 	surface_config: wgpu::SurfaceConfiguration,
 	adapter: wgpu::Adapter,
 	queue: wgpu::Queue,
@@ -366,6 +664,13 @@ pub struct Renderer {
     error_texture: LoadedTexture,
 
 	pub terrain_renderer: TerrainRenderer,
+	pub line_renderer: LineRenderer,
+	/// The scene is drawn into this pass's offscreen texture, which it then tonemaps onto the window.
+	tonemap: ToneMapPass,
+
+	block_outline_config: BlockOutlineConfig,
+	/// Which block (if any) should get a selection highlight drawn around it this frame.
+	block_outline_target: Option<TilePos>,
 }
 
 impl Renderer {
@@ -374,6 +679,57 @@ impl Renderer {
 		let instance = wgpu::Instance::new(InstanceDescriptor::default());
 		let surface = unsafe { instance.create_surface(window)? };
 
+		let adapter = Self::select_adapter(&instance, config, Some(&surface)).await?;
+		let (device, queue) = Self::request_device(&adapter).await?;
+
+		//Ensure WGPU knows how to use our surface.
+		let surface_capabilities = surface.get_capabilities(&adapter);
+		if surface_capabilities.formats.is_empty() {
+			return Err(InitRenderError::NoPreferredFormat);
+		}
+		info!("Render surface supports formats: {:?}", &surface_capabilities.formats);
+
+		let render_format = surface_capabilities.formats.first().unwrap();
+
+		let window_size = window.inner_size();
+		let surface_config = wgpu::SurfaceConfiguration {
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT, // When we implement portals I am likely to touch this again.
+			format: render_format.clone(),
+			width: window_size.width,
+			height: window_size.height,
+			present_mode: wgpu::PresentMode::Fifo,
+			alpha_mode: wgpu::CompositeAlphaMode::Auto,
+			view_formats: vec![render_format.clone()],
+		};
+		surface.configure(&device, &surface_config);
+
+		Self::build(instance, Some(surface), adapter, device, queue, surface_config, camera, config)
+	}
+
+	/// A renderer with no window, for drawing images with render_to_image() - e.g. world thumbnails
+	/// on a server with no display. Everything is set up the same as with new(), minus the swapchain.
+	pub async fn new_headless(size: DisplaySize, camera: &Camera, config: &ClientConfig) -> Result<Self, InitRenderError> {
+		let instance = wgpu::Instance::new(InstanceDescriptor::default());
+		let adapter = Self::select_adapter(&instance, config, None).await?;
+		let (device, queue) = Self::request_device(&adapter).await?;
+
+		// Never handed to a surface, it's just where the format and size live.
+		let surface_config = wgpu::SurfaceConfiguration {
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+			format: Self::HEADLESS_FORMAT,
+			width: size.width.max(1),
+			height: size.height.max(1),
+			present_mode: wgpu::PresentMode::Fifo,
+			alpha_mode: wgpu::CompositeAlphaMode::Auto,
+			view_formats: vec![Self::HEADLESS_FORMAT],
+		};
+		Self::build(instance, None, adapter, device, queue, surface_config, camera, config)
+	}
+
+	/// Picks the graphics card named in the config if there is one, otherwise lets wgpu choose.
+	async fn select_adapter(instance: &wgpu::Instance,
+			config: &ClientConfig,
+			compatible_surface: Option<&wgpu::Surface>) -> Result<wgpu::Adapter, InitRenderError> {
 		let mut adapters: HashMap<String, wgpu::Adapter> = instance
 			.enumerate_adapters(wgpu::Backends::all())
 			.map(|a| (a.get_info().name.clone(), a))
@@ -399,27 +755,29 @@ impl Renderer {
 		info!("{}", info_string);
 
 		// Final decision on which device gets used.
-		let adapter = match adapter_select {
+		match adapter_select {
 			// This path is only possible to reach if the adapter was in the set,
 			// it is okay to use unwrap here.
-			Some(adapt_name) => adapters.remove(&adapt_name).unwrap(),
+			Some(adapt_name) => Ok(adapters.remove(&adapt_name).unwrap()),
 			None => instance
 				.request_adapter(&wgpu::RequestAdapterOptions {
 					power_preference: wgpu::PowerPreference::HighPerformance,
-					compatible_surface: Some(&surface),
+					compatible_surface,
 					force_fallback_adapter: false,
 				})
 				.await
-				.ok_or(InitRenderError::CannotRequestAdapter)?,
-		};
+				.ok_or(InitRenderError::CannotRequestAdapter),
+		}
+	}
 
+	async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), InitRenderError> {
 		let features = wgpu::Features::default()
 			.union(wgpu::Features::PUSH_CONSTANTS);
 		// wgpu::Features::TEXTURE_BINDING_ARRAY
 		// wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING
 		let mut limits = wgpu::Limits::default(); 
 		limits.max_push_constant_size = std::mem::size_of::<ModelPush>() as u32;
-		let (mut device, mut queue) = adapter
+		let (device, queue) = adapter
 			.request_device(
 				&DeviceDescriptor {
 					label: None,
@@ -432,26 +790,30 @@ impl Renderer {
 		info!("Max array layers: {} \n Max 3D texture size: {}", 
 			device.limits().max_texture_array_layers,
 			device.limits().max_texture_dimension_3d);
-		//Ensure WGPU knows how to use our surface.
-		let surface_capabilities = surface.get_capabilities(&adapter);
-		if surface_capabilities.formats.is_empty() {
-			return Err(InitRenderError::NoPreferredFormat);
-		}
-		info!("Render surface supports formats: {:?}", &surface_capabilities.formats);
-
-		let render_format = surface_capabilities.formats.first().unwrap();
+		Ok((device, queue))
+	}
 
-		let window_size = window.inner_size();
-		let surface_config = wgpu::SurfaceConfiguration {
-			usage: wgpu::TextureUsages::RENDER_ATTACHMENT, // When we implement portals I am likely to touch this again.
-			format: render_format.clone(),
-			width: window_size.width,
-			height: window_size.height,
-			present_mode: wgpu::PresentMode::Fifo,
-			alpha_mode: wgpu::CompositeAlphaMode::Auto,
-			view_formats: vec![render_format.clone()],
-		};
-		surface.configure(&device, &surface_config);
+	/// Everything past picking a device and (maybe) setting up a surface - shared by new() and new_headless().
+	#[allow(clippy::too_many_arguments)]
+	fn build(instance: wgpu::Instance,
+			surface: Option<wgpu::Surface>,
+			adapter: wgpu::Adapter,
+			mut device: wgpu::Device,
+			mut queue: wgpu::Queue,
+			surface_config: wgpu::SurfaceConfiguration,
+			camera: &Camera,
+			config: &ClientConfig) -> Result<Self, InitRenderError> {
+		let render_format = &surface_config.format;
+		let window_size = winit::dpi::PhysicalSize::new(surface_config.width, surface_config.height);
+
+		// Every scene pipeline draws into this rather than straight into the surface.
+		let scene_format = ToneMapPass::choose_scene_format(&adapter, *render_format);
+		let tonemap = ToneMapPass::new(&device,
+			scene_format,
+			*render_format,
+			surface_config.width,
+			surface_config.height,
+			config.display_properties.tonemap);
 
 		let aspect_ratio = (window_size.width as f32) / (window_size.height as f32);
 
@@ -492,7 +854,7 @@ impl Renderer {
 
 		// Set up the uniform for our camera. 
 		let mut camera_uniform = CameraUniform::new();
-		camera_uniform.update(OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix());
+		camera_uniform.update(camera.build_view_projection_matrix());
 
 		let camera_buffer = device.create_buffer_init(
 			&wgpu::util::BufferInitDescriptor {
@@ -566,7 +928,7 @@ impl Renderer {
                 module: &shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: render_format.clone(),
+                    format: scene_format,
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent::REPLACE,
                         alpha: wgpu::BlendComponent::REPLACE,
@@ -598,44 +960,51 @@ impl Renderer {
 			multiview: None,
 		});
 
-		let depth_texture = Self::create_depth_texture(&device, &surface_config, "depth_texture");
+		let depth_texture = Self::create_depth_texture(&device, surface_config.width, surface_config.height, "depth_texture");
 
-		let texture_manager = TextureManager::new();
+		let mut texture_manager = TextureManager::new();
 
-		let desc = wgpu::SamplerDescriptor {
-			address_mode_u: wgpu::AddressMode::Repeat,
-			address_mode_v: wgpu::AddressMode::Repeat,
-			address_mode_w: wgpu::AddressMode::ClampToEdge,
-			mag_filter: wgpu::FilterMode::Nearest,
-			min_filter: wgpu::FilterMode::Nearest,
-			mipmap_filter: wgpu::FilterMode::Nearest,
-			..Default::default()
-		};
+		let default_spec = SamplerSpec::default();
+		texture_manager.init_sampler(&default_spec, &device);
+		let default_sampler = texture_manager.get_sampler(&default_spec).unwrap();
 
 		// Generate our various types of error textures.
 		let error_image = generate_error_texture_image(64, 64); 
 		let error_texture = TextureManager::load_image(&error_image,
-			&desc,
+			default_sampler,
+			false,
 			&mut device,
 			&mut queue,
 			&texture_bind_group_layout);
 		let missing_image = generate_missing_texture_image(64, 64); 
 		let missing_texture = TextureManager::load_image(&missing_image,
-			&desc,
+			default_sampler,
+			false,
 			&mut device,
 			&mut queue,
 			&texture_bind_group_layout);
 		let pending_image = generate_missing_texture_image(64, 64); 
 		let pending_texture = TextureManager::load_image(&pending_image,
-			&desc,
+			default_sampler,
+			false,
 			&mut device,
 			&mut queue,
 			&texture_bind_group_layout);
 
+		let anisotropy_supported = adapter.get_downlevel_capabilities()
+			.flags
+			.contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING);
+		let terrain_sampler = SamplerSpec::for_texture_quality(config.display_properties.texture_quality,
+			anisotropy_supported);
 		let terrain_renderer = TerrainRenderer::new(64,
+			terrain_sampler,
 			&camera_bind_group_layout, 
 			&device,
-			render_format, 
+			&scene_format, 
+			&Self::DEPTH_FORMAT);
+		let line_renderer = LineRenderer::new(&camera_bind_group_layout,
+			&device,
+			&scene_format,
 			&Self::DEPTH_FORMAT);
 		
 		Ok(Self {
@@ -658,6 +1027,10 @@ impl Renderer {
 			depth_texture,
 			texture_manager,
 			terrain_renderer,
+			line_renderer,
+			tonemap,
+			block_outline_config: config.block_outline,
+			block_outline_target: None,
 			error_texture,
 			missing_texture,
 			pending_texture,
@@ -670,21 +1043,249 @@ impl Renderer {
 			self.window_size = new_size;
 			self.surface_config.width = new_size.width;
 			self.surface_config.height = new_size.height;
-			self.surface.configure(&self.device, &self.surface_config);
+			if let Some(surface) = self.surface.as_ref() {
+				surface.configure(&self.device, &self.surface_config);
+			}
 			self.aspect_ratio = (new_size.width as f32) / (new_size.height as f32);
-			self.depth_texture = Self::create_depth_texture(&self.device, &self.surface_config, "depth_texture");
+			self.depth_texture = Self::create_depth_texture(&self.device, new_size.width, new_size.height, "depth_texture");
+			self.tonemap.resize(&self.device, new_size.width, new_size.height);
 		}
 	}
+	/// Draw the scene from the given camera to the window.
 	pub fn render_frame(&mut self, 
 			camera: &Camera, 
 			ecs_world: &EcsWorld, 
 			clear_color: &Color,
 			secs_since_last_tick: f32) -> Result<(), DrawFrameError> {
-		let view_projection_matrix = camera.build_view_projection_matrix();
-		let output = self.surface.get_current_texture()?;
+		let output = self
+			.surface
+			.as_ref()
+			.ok_or(DrawFrameError::NoSurface)?
+			.get_current_texture()?;
+
+		let surface_texture_view = output
+			.texture
+			.create_view(&wgpu::TextureViewDescriptor::default());
+
+		// The selection highlight only makes sense from the player's point of view, so it's queued here
+		// rather than in draw_scene().
+		if let Some(target) = self.block_outline_target.filter(|_| self.block_outline_config.show) {
+			let min = Vec3::new(target.x as f32, target.y as f32, target.z as f32);
+			self.line_renderer.push_box_outline(min - Vec3::splat(BLOCK_OUTLINE_OFFSET),
+				min + Vec3::splat(1.0 + BLOCK_OUTLINE_OFFSET),
+				&self.block_outline_config.color);
+		}
+
+		let scene_view = self.tonemap.get_scene_view();
+		self.draw_scene(camera, 
+			&scene_view, 
+			None, 
+			ecs_world, 
+			clear_color, 
+			secs_since_last_tick)?;
+
+		let mut encoder = self
+			.device
+			.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+				label: Some("Tonemap Encoder"),
+			});
+		self.tonemap.draw(&surface_texture_view, &mut encoder);
+		self.queue.submit(iter::once(encoder.finish()));
+
+		output.present();
+
+		Ok(())
+	}
+
+	/// Make an offscreen texture which the scene can be drawn into with render_to_target(),
+	/// e.g. for minimaps or portals.
+	pub fn create_render_target(&self, width: u32, height: u32) -> RenderTarget {
+		let texture_size = wgpu::Extent3d {
+			width,
+			height,
+			depth_or_array_layers: 1
+		};
+		let texture_buffer = self.device.create_texture(
+			&wgpu::TextureDescriptor {
+				size: texture_size,
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: wgpu::TextureDimension::D2,
+				// Has to match the scene texture, since that's what our pipelines were built against.
+				// Note this means the result is not tonemapped.
+				format: self.tonemap.get_scene_format(),
+				usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+				label: Some("render_target_texture"),
+				view_formats: &[],
+			}
+		);
+		let texture_view = texture_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+		// Only ever initialized during Renderer::new(), so this will be present.
+		let sampler = self.texture_manager.get_sampler(&SamplerSpec::default()).unwrap();
+		let bind_group = self.device.create_bind_group(
+			&wgpu::BindGroupDescriptor {
+				layout: &self.texture_bind_group_layout,
+				entries: &[
+					wgpu::BindGroupEntry {
+						binding: 0,
+						resource: wgpu::BindingResource::TextureView(&texture_view),
+					},
+					wgpu::BindGroupEntry {
+						binding: 1,
+						resource: wgpu::BindingResource::Sampler(sampler),
+					}
+				],
+				label: Some("render_target_bind_group"),
+			}
+		);
+		RenderTarget {
+			color: LoadedTexture {
+				buffer_handle: Box::new(texture_buffer),
+				texture_view,
+				bind_group,
+			},
+			depth: Self::create_depth_texture(&self.device, width, height, "render_target_depth_texture"),
+			width,
+			height,
+		}
+	}
+
+	/// Draw the scene from the given camera into an offscreen texture rather than the window.
+	/// Returns the view of the resulting texture, which can then be sampled elsewhere.
+	pub fn render_to_target<'t>(&mut self, 
+			camera: &Camera, 
+			target: &'t RenderTarget,
+			ecs_world: &EcsWorld, 
+			clear_color: &Color,
+			secs_since_last_tick: f32) -> Result<&'t wgpu::TextureView, DrawFrameError> {
+		self.draw_scene(camera, 
+			&target.color.texture_view, 
+			Some(&target.depth.1), 
+			ecs_world, 
+			clear_color, 
+			secs_since_last_tick)?;
+		Ok(&target.color.texture_view)
+	}
+
+	/// Draw the scene from the given camera, tonemapped, into an image the size of the window (or the size
+	/// given to new_headless()). Works with or without a window. As with any other frame, terrain has to
+	/// have gone through process_terrain_mesh_uploads() first to show up.
+	pub fn render_to_image(&mut self, 
+			camera: &Camera, 
+			ecs_world: &EcsWorld, 
+			clear_color: &Color,
+			secs_since_last_tick: f32) -> Result<RgbaImage, DrawFrameError> {
+		let format = self.surface_config.format;
+		let swap_red_blue = match format {
+			wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+			wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+			other => return Err(DrawFrameError::UnsupportedReadbackFormat(other)),
+		};
+		let (width, height) = (self.surface_config.width, self.surface_config.height);
+		let texture_size = wgpu::Extent3d {
+			width,
+			height,
+			depth_or_array_layers: 1
+		};
+		let output = self.device.create_texture(
+			&wgpu::TextureDescriptor {
+				size: texture_size,
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: wgpu::TextureDimension::D2,
+				// Has to match the surface, since that's what the tonemap pipeline was built against.
+				format,
+				usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+				label: Some("render_to_image_texture"),
+				view_formats: &[],
+			}
+		);
+		let output_view = output.create_view(&wgpu::TextureViewDescriptor::default());
+
+		let scene_view = self.tonemap.get_scene_view();
+		self.draw_scene(camera, 
+			&scene_view, 
+			None, 
+			ecs_world, 
+			clear_color, 
+			secs_since_last_tick)?;
+
+		// Each row in a texture-to-buffer copy has to be padded out to COPY_BYTES_PER_ROW_ALIGNMENT.
+		let unpadded_bytes_per_row = 4 * width;
+		let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+		let padded_bytes_per_row = ((unpadded_bytes_per_row + align - 1) / align) * align;
+		let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("render_to_image_readback"),
+			size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+			usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let mut encoder = self
+			.device
+			.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+				label: Some("Render To Image Encoder"),
+			});
+		self.tonemap.draw(&output_view, &mut encoder);
+		encoder.copy_texture_to_buffer(
+			wgpu::ImageCopyTexture {
+				texture: &output,
+				mip_level: 0,
+				origin: wgpu::Origin3d::ZERO,
+				aspect: wgpu::TextureAspect::All,
+			},
+			wgpu::ImageCopyBuffer {
+				buffer: &readback_buffer,
+				layout: wgpu::ImageDataLayout {
+					offset: 0,
+					bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+					rows_per_image: NonZeroU32::new(height),
+				},
+			},
+			texture_size,
+		);
+		self.queue.submit(iter::once(encoder.finish()));
+
+		// Block until the copy is done and the buffer can be read.
+		let buffer_slice = readback_buffer.slice(..);
+		let (map_sender, map_receiver) = std::sync::mpsc::channel();
+		buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+			let _ = map_sender.send(result);
+		});
+		self.device.poll(wgpu::Maintain::Wait);
+		map_receiver
+			.recv()
+			.expect("wgpu dropped a buffer mapping callback without calling it")?;
+
+		let mut image = RgbaImage::new(width, height);
+		{
+			let mapped = buffer_slice.get_mapped_range();
+			for (y, row) in mapped.chunks_exact(padded_bytes_per_row as usize).enumerate() {
+				for (x, pixel) in row[..unpadded_bytes_per_row as usize].chunks_exact(4).enumerate() {
+					let rgba = if swap_red_blue {
+						[pixel[2], pixel[1], pixel[0], pixel[3]]
+					} else {
+						[pixel[0], pixel[1], pixel[2], pixel[3]]
+					};
+					image.put_pixel(x as u32, y as u32, Rgba(rgba));
+				}
+			}
+		}
+		readback_buffer.unmap();
+		Ok(image)
+	}
 
-		let camera_matrix = OPENGL_TO_WGPU_MATRIX * view_projection_matrix;
-		self.camera_uniform.update(camera_matrix);
+	/// Runs the billboard, terrain and line passes from the point of view of camera, and submits them.
+	/// A depth_view of None means "use the window's depth buffer".
+	fn draw_scene(&mut self, 
+			camera: &Camera, 
+			color_view: &wgpu::TextureView,
+			depth_view: Option<&wgpu::TextureView>,
+			ecs_world: &EcsWorld, 
+			clear_color: &Color,
+			secs_since_last_tick: f32) -> Result<(), DrawFrameError> {
+		let depth_view = depth_view.unwrap_or(&self.depth_texture.1);
+		self.camera_uniform.update(camera.build_view_projection_matrix());
 		
 		self.queue.write_buffer(
 			&self.camera_matrix_buffer,
@@ -692,10 +1293,6 @@ impl Renderer {
 			bytemuck::cast_slice(&[self.camera_uniform]),
 		);
 
-		let surface_texture_view = output
-			.texture
-			.create_view(&wgpu::TextureViewDescriptor::default());
-
 		let mut encoder = self
 			.device
 			.create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -709,7 +1306,7 @@ impl Renderer {
 				label: Some("Render Pass"),
 				color_attachments: &[
 					Some(wgpu::RenderPassColorAttachment {
-						view: &surface_texture_view,
+						view: color_view,
 						resolve_target: None,
 						ops: wgpu::Operations {
 							load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -723,7 +1320,7 @@ impl Renderer {
 					}),
 				],
 				depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-					view: &self.depth_texture.1,
+					view: depth_view,
 					depth_ops: Some(wgpu::Operations {
 						load: wgpu::LoadOp::Clear(1.0),
 						store: true,
@@ -736,13 +1333,17 @@ impl Renderer {
 					position, 
 					drawable,
 					scale_maybe,
-					velocity_maybe
+					velocity_maybe,
+					rot_maybe,
+					angular_velocity_maybe
 				)
 			) in ecs_world.query::<
 					(&EntityPos, 
 					&BillboardDrawable,
 					Option<&EntityScale>,
-					Option<&EntityVelocity>)
+					Option<&EntityVelocity>,
+					Option<&EntityRot>,
+					Option<&EntityAngularVelocity>)
 				>().iter() {
 				let texture_maybe = match &drawable.texture_handle {
 					Some(handle) => self.texture_manager.get(*handle),
@@ -793,6 +1394,15 @@ impl Renderer {
 					},
 					None => position.get(),
 				};
+				// Same idea for rotation, if it's turning.
+				let interpolated_rot = match (rot_maybe, angular_velocity_maybe) {
+					(Some(rot), Some(angular_velocity)) => angular_velocity.guess_rotation(rot.get(), secs_since_last_tick),
+					(Some(rot), None) => rot.get(),
+					(None, _) => Quat::IDENTITY,
+				};
+				// Billboards always face the camera, so the only part of the entity's own rotation
+				// which can show is roll - spinning in the billboard's plane.
+				let billboard_spin = Quat::from_rotation_z(interpolated_rot.to_euler(EulerRot::YXZ).2);
 
 				let negated_camera_forward = camera.get_front().neg().normalize();
 				let initial_look_back = Quat::from_rotation_arc(Vec3::new(0.0,0.0,1.0), negated_camera_forward);
@@ -805,7 +1415,8 @@ impl Renderer {
 						let yaw = initial_look_back.to_euler(EulerRot::YXZ).0;
 						Quat::from_euler(EulerRot::YXZ, yaw, 0.0, 0.0)
 					},
-				}.normalize();
+				};
+				let billboard_look_back = (billboard_look_back * billboard_spin).normalize();
 				let model_matrix = match scale_maybe {
 					Some(scale) => {
 						Mat4::from_scale_rotation_translation(
@@ -828,16 +1439,22 @@ impl Renderer {
 				render_pass.draw(0..(UNIT_BILLBOARD.len() as u32), 0..1);
 			}
 		}
-		self.terrain_renderer.draw(&surface_texture_view, 
-			&self.depth_texture.1, 
+		self.terrain_renderer.draw(color_view, 
+			depth_view, 
 			Vec3::ONE,
 			Vec3::ZERO,
 			Quat::IDENTITY, 
+			&camera.frustum_planes(),
 			&self.camera_matrix_bind_group, 
 			&mut encoder)?;
 
+		self.line_renderer.draw(&self.queue,
+			color_view,
+			depth_view,
+			&self.camera_matrix_bind_group,
+			&mut encoder);
+
 		self.queue.submit(iter::once(encoder.finish()));
-		output.present();
 
 		Ok(())
 	}
@@ -851,13 +1468,50 @@ impl Renderer {
 	pub fn get_aspect_ratio(&self) -> f32 { 
 		self.aspect_ratio
 	}
+
+	/// Set which block the player is currently aiming at, to draw a selection highlight around it.
+	/// Pass None to hide the highlight.
+	pub fn get_debug_pipeline_mode(&self) -> DebugPipelineMode {
+		self.terrain_renderer.get_debug_mode()
+	}
+	/// Alternate pipelines get built the first time their mode is picked, and kept around after that.
+	pub fn set_debug_pipeline_mode(&mut self, mode: DebugPipelineMode) {
+		self.terrain_renderer.set_debug_mode(&self.device, mode);
+	}
+	/// Switch to the next DebugPipelineMode and return it.
+	pub fn cycle_debug_pipeline_mode(&mut self) -> DebugPipelineMode {
+		let mode = self.get_debug_pipeline_mode().next();
+		self.set_debug_pipeline_mode(mode);
+		mode
+	}
+
+	pub fn set_block_outline(&mut self, target: Option<TilePos>) {
+		self.block_outline_target = target;
+	}
+
+	/// Multiplier on scene brightness, applied before tonemapping.
+	pub fn set_exposure(&mut self, exposure: f32) {
+		self.tonemap.set_exposure(&self.queue, exposure);
+	}
+
+	/// Change the tonemapping curve, exposure and gamma, e.g. after the client config gets reloaded.
+	pub fn set_tonemap_config(&mut self, config: ToneMapConfig) {
+		self.tonemap.set_config(&self.queue, config);
+	}
+
+	/// Change how the block highlight looks, e.g. after the client config gets reloaded.
+	pub fn set_block_outline_config(&mut self, config: BlockOutlineConfig) {
+		self.block_outline_config = config;
+	}
 	
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+	/// What a headless renderer's final, tonemapped images come out as.
+	pub const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
     
-    fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> (wgpu::Texture, wgpu::TextureView, wgpu::Sampler) {
+    fn create_depth_texture(device: &wgpu::Device, width: u32, height: u32, label: &str) -> (wgpu::Texture, wgpu::TextureView, wgpu::Sampler) {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -894,18 +1548,33 @@ impl Renderer {
 	pub fn ingest_image<P>(&mut self,
 		resource_id: &ResourceId,
 		texture_loader: &mut P)
-			where P: ResourceProvider<InternalImage, Error=LoadImageError> {
-				
-		let diffuse_sampler = wgpu::SamplerDescriptor {
-			address_mode_u: wgpu::AddressMode::Repeat,
-			address_mode_v: wgpu::AddressMode::Repeat,
-			address_mode_w: wgpu::AddressMode::ClampToEdge,
-			mag_filter: wgpu::FilterMode::Nearest,
-			min_filter: wgpu::FilterMode::Nearest,
-			mipmap_filter: wgpu::FilterMode::Nearest,
-			..Default::default()
-		};
-		self.texture_manager.ingest_image_resource(resource_id, &diffuse_sampler, &self.device, &self.queue, &self.texture_bind_group_layout, texture_loader);
+			where P: ResourceLoader<InternalImage, ParseError=LoadImageError> {
+		self.ingest_image_with_sampler(resource_id, &SamplerSpec::default(), true, texture_loader);
+	}
+	/// Like ingest_image(), but lets the caller decide how the texture gets filtered and wrapped, and whether
+	/// it gets mipmaps. Pixel art which should stay crisp at every distance can turn generate_mips off.
+	pub fn ingest_image_with_sampler<P>(&mut self,
+		resource_id: &ResourceId,
+		sampler_spec: &SamplerSpec,
+		generate_mips: bool,
+		texture_loader: &mut P)
+			where P: ResourceLoader<InternalImage, ParseError=LoadImageError> {
+		self.texture_manager.ingest_image_resource(resource_id, sampler_spec, generate_mips, &self.device, &self.queue, &self.texture_bind_group_layout, texture_loader);
+	}
+	/// Ingest an image into a shared array texture with other images of the same size, rather than its own
+	/// texture. Worth it for things like tile textures, where lots of small images get drawn together.
+	pub fn ingest_image_batched<P>(&mut self,
+		resource_id: &ResourceId,
+		texture_loader: &mut P) -> Result<ImageTextureBinding, ArrayTextureError>
+			where P: ResourceLoader<InternalImage, ParseError=LoadImageError> {
+		self.texture_manager.ingest_image_batched(resource_id, &SamplerSpec::default(), &mut self.device, &self.queue, &self.texture_bind_group_layout, texture_loader)
+	}
+	/// Swap in any ingested images which were still loading last time. Cheap when nothing is pending, so call it every frame.
+	pub fn retry_pending_images<P>(&mut self, texture_loader: &mut P)
+			where P: ResourceLoader<InternalImage, ParseError=LoadImageError> {
+		if self.texture_manager.has_pending() {
+			self.texture_manager.retry_pending(&self.device, &self.queue, &self.texture_bind_group_layout, texture_loader);
+		}
 	}
 }
 
@@ -950,3 +1619,48 @@ pub fn generate_error_texture_image(width: u32, height: u32) -> RgbaImage {
 
 	generate_engine_texture_image(width, height, &foreground, &background)
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn mip_chain_for_64x64() {
+		let image = generate_missing_texture_image(64, 64);
+
+		// Actually uploading it needs a graphics adapter, which CI machines may not have.
+		let instance = wgpu::Instance::new(InstanceDescriptor::default());
+		let adapter = match futures::executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())) {
+			Some(adapter) => adapter,
+			None => return,
+		};
+		let (device, queue) = futures::executor::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).unwrap();
+		let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Texture {
+						multisampled: false,
+						view_dimension: wgpu::TextureViewDimension::D2,
+						sample_type: wgpu::TextureSampleType::Float { filterable: true },
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+					count: None,
+				},
+			],
+			label: Some("test_texture_bind_group_layout"),
+		});
+		let sampler = device.create_sampler(&SamplerSpec::SMOOTH.to_descriptor());
+
+		let with_mips = TextureManager::load_image(&image, &sampler, true, &device, &queue, &bind_group_layout);
+		assert_eq!(with_mips.buffer_handle.mip_level_count(), 7);
+		let without_mips = TextureManager::load_image(&image, &sampler, false, &device, &queue, &bind_group_layout);
+		assert_eq!(without_mips.buffer_handle.mip_level_count(), 1);
+	}
+}
@@ -5,14 +5,16 @@ use glam::{Vec3, Quat, Mat4};
 use wgpu::util::DeviceExt;
 use wgpu::{PushConstantRange, ShaderStages, TextureView};
 
+use crate::common::frustum::{is_chunk_visible, FrustumPlanes};
 use super::array_texture::{ArrayTextureLayout, ArrayTexture, ArrayTextureError};
-use super::{load_test_shader, ModelPush};
+use super::{load_test_shader, DebugPipelineMode, ModelPush, SamplerSpec};
 use super::voxel_art::VoxelArtMapper;
-use super::voxel_mesher::{ChunkMesh, MesherState, PackedVertex};
+use super::voxel_mesher::{ChunkMesh, MesherState, MeshStats, MeshingMode, PackedVertex};
 use crate::resource::ResourceProvider;
 use crate::resource::image::{InternalImage, LoadImageError};
 use crate::world::tilespace::{TileSpace, TileSpaceError, world_to_chunk_pos, chunk_to_world_pos};
-//use crate::world::chunk::CHUNK_SIZE;
+use crate::world::chunk::CHUNK_SIZE;
+use crate::common::voxelmath::VoxelSide;
 //use crate::world::tilespace::{world_to_chunk_pos, TileSpaceError, TileSpace};
 use crate::world::{ChunkPos, TilePos, TileId};
 use crate::world::voxelstorage::VoxelSpace;
@@ -50,6 +52,13 @@ struct ChunkTextureBinding {
     tile_array_texture_revision: u64,
 }
 
+/// How many chunks the most recent draw() actually drew, and how many it skipped for being out of view.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct ChunkCullStats {
+    pub drawn: u64,
+    pub culled: u64,
+}
+
 struct BuiltChunk { 
     pub buffer: wgpu::Buffer,
     pub num_verts: u32,
@@ -71,12 +80,28 @@ pub struct TerrainRenderer {
     /// One past the highest texture ID in texture_layouts. Incremented each time we add a new texture layout.
     next_texture_id: u32,
     texture_size: u32,
+    /// How the tile textures get filtered, decided by the TextureQuality setting.
+    sampler_spec: SamplerSpec,
+    /// Kept around to build debug pipelines from, on demand.
+    voxel_shader: wgpu::ShaderModule,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    render_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    debug_mode: DebugPipelineMode,
+    /// Alternate pipelines for anything other than DebugPipelineMode::Normal, built the first time they're needed.
+    debug_pipelines: HashMap<DebugPipelineMode, wgpu::RenderPipeline>,
+    /// Totals over every chunk meshed by the most recent process_remesh() which had anything to do.
+    last_remesh_stats: MeshStats,
+    meshing_mode: MeshingMode,
+    /// Counts from the most recent draw().
+    last_cull_stats: ChunkCullStats,
     
 	render_pipeline: wgpu::RenderPipeline,
 }
 
 impl TerrainRenderer {
     pub fn new(texture_size: u32,
+            sampler_spec: SamplerSpec,
             camera_layout: &wgpu::BindGroupLayout, 
             device: &wgpu::Device,
             render_format: &wgpu::TextureFormat,
@@ -125,22 +150,59 @@ impl TerrainRenderer {
 				}],
 			});
 
-        
-		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+
+		let render_pipeline = Self::create_pipeline(device,
+			&render_pipeline_layout,
+			&voxel_shader,
+			*render_format,
+			*depth_format,
+			DebugPipelineMode::Normal);
+
+        TerrainRenderer {
+            pending_remesh: HashSet::default(),
+            meshed_chunks: HashMap::default(),
+            built_chunks: HashMap::default(),
+            texture_for_chunk: HashMap::default(),
+            texture_layouts: HashMap::default(),
+            texture_bind_group_layout,
+            built_textures: HashMap::default(),
+            next_texture_id: 0,
+            texture_size,
+            sampler_spec,
+            last_remesh_stats: MeshStats::default(),
+            last_cull_stats: ChunkCullStats::default(),
+            meshing_mode: MeshingMode::default(),
+            voxel_shader,
+            render_pipeline_layout,
+            render_format: *render_format,
+            depth_format: *depth_format,
+            debug_mode: DebugPipelineMode::Normal,
+            debug_pipelines: HashMap::default(),
+            render_pipeline,
+        }
+    }
+    fn create_pipeline(device: &wgpu::Device,
+            layout: &wgpu::PipelineLayout,
+            shader: &wgpu::ShaderModule,
+            render_format: wgpu::TextureFormat,
+            depth_format: wgpu::TextureFormat,
+            mode: DebugPipelineMode)
+                -> wgpu::RenderPipeline {
+		device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
 			label: Some("Voxel Render Pipeline"),
-			layout: Some(&render_pipeline_layout),
+			layout: Some(layout),
 			vertex: wgpu::VertexState {
-				module: &voxel_shader,
+				module: shader,
 				entry_point: "vs_main",
 				buffers: &[
 					PackedVertex::desc(),
 				],
 			},
             fragment: Some(wgpu::FragmentState {
-                module: &voxel_shader,
+                module: shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: render_format.clone(),
+                    format: render_format,
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent::REPLACE,
                         alpha: wgpu::BlendComponent::REPLACE,
@@ -152,15 +214,15 @@ impl TerrainRenderer {
 				topology: wgpu::PrimitiveTopology::TriangleList,
 				strip_index_format: None,
 				front_face: wgpu::FrontFace::Ccw,
-				cull_mode: Some(wgpu::Face::Back),
+				cull_mode: mode.cull_mode(),
 				polygon_mode: wgpu::PolygonMode::Fill,
 				unclipped_depth: false,
 				conservative: false,
 			},
 			depth_stencil: Some(wgpu::DepthStencilState {
-				format: *depth_format,
+				format: depth_format,
 				depth_write_enabled: true,
-				depth_compare: wgpu::CompareFunction::Less,
+				depth_compare: mode.depth_compare(),
 				stencil: wgpu::StencilState::default(),
 				bias: wgpu::DepthBiasState::default(),
 			}),
@@ -170,25 +232,55 @@ impl TerrainRenderer {
 				alpha_to_coverage_enabled: false,
 			},
 			multiview: None,
-		});
-
-        TerrainRenderer {
-            pending_remesh: HashSet::default(),
-            meshed_chunks: HashMap::default(),
-            built_chunks: HashMap::default(),
-            texture_for_chunk: HashMap::default(),
-            texture_layouts: HashMap::default(),
-            texture_bind_group_layout,
-            built_textures: HashMap::default(),
-            next_texture_id: 0,
-            texture_size,
-            render_pipeline,
+		})
+    }
+    pub fn get_debug_mode(&self) -> DebugPipelineMode { 
+        self.debug_mode
+    }
+    /// Swap to drawing terrain with (or without) backface culling / depth testing, building the pipeline for it if this is the first time.
+    pub fn set_debug_mode(&mut self, device: &wgpu::Device, mode: DebugPipelineMode) { 
+        if mode != DebugPipelineMode::Normal && !self.debug_pipelines.contains_key(&mode) {
+            let pipeline = Self::create_pipeline(device,
+                &self.render_pipeline_layout,
+                &self.voxel_shader,
+                self.render_format,
+                self.depth_format,
+                mode);
+            self.debug_pipelines.insert(mode, pipeline);
+        }
+        self.debug_mode = mode;
+    }
+    pub fn get_meshing_mode(&self) -> MeshingMode { 
+        self.meshing_mode
+    }
+    /// Switch between naive and greedy meshing. Everything that's already been meshed gets remeshed the new way.
+    pub fn set_meshing_mode(&mut self, mode: MeshingMode) { 
+        if mode != self.meshing_mode {
+            self.meshing_mode = mode;
+            self.pending_remesh.extend(self.meshed_chunks.keys().copied());
         }
     }
     /// Inform this terrain renderer that a block at the given position has changed.
+    /// Tiles on the edge of a chunk also shade the faces next to them in the neighboring chunk, so if that one has
+    /// already been meshed, it gets remeshed too.
     pub fn notify_changed(&mut self, tile_position: &TilePos) { 
-        let chunk_position = world_to_chunk_pos(tile_position);
+        let (chunk_position, local) = tile_position.to_chunk_and_local();
         self.pending_remesh.insert(chunk_position);
+        let last = (CHUNK_SIZE - 1) as u8;
+        for side in VoxelSide::iter_all() {
+            let on_edge = match side {
+                VoxelSide::PosiX => local.x == last,
+                VoxelSide::NegaX => local.x == 0,
+                VoxelSide::PosiY => local.y == last,
+                VoxelSide::NegaY => local.y == 0,
+                VoxelSide::PosiZ => local.z == last,
+                VoxelSide::NegaZ => local.z == 0,
+            };
+            let neighbor = chunk_position.get_neighbor(side);
+            if on_edge && self.meshed_chunks.contains_key(&neighbor) {
+                self.pending_remesh.insert(neighbor);
+            }
+        }
     }
     /// Inform this terrain renderer that a specific chunk needs to be remeshed.
     pub fn notify_chunk_remesh_needed(&mut self, chunk_position: &ChunkPos) {
@@ -247,6 +339,7 @@ impl TerrainRenderer {
         }
         else { 
             let mut did_mesh = false;
+            let mut remesh_stats = MeshStats::default();
             let remesh_list: HashSet<ChunkPos> = self.pending_remesh.drain().collect();
             for chunk_position in remesh_list.iter() { 
                 //let is_new_chunk = !self.gpu_chunks.contains_key(&chunk_position);
@@ -257,10 +350,10 @@ impl TerrainRenderer {
                     self.find_available_texture_array_texture()
                 };
     
-                let chunk = voxel_space.borrow_chunk(chunk_position)?;
+                let neighborhood = voxel_space.borrow_chunk_neighborhood(*chunk_position)?;
     
                 //TODO: Handle case where texture array goes over max
-                let mesher_state = MesherState::prepare_to_mesh(chunk, 
+                let mesher_state = MesherState::prepare_to_mesh_with_neighbors(&neighborhood, 
                     tiles_to_art, 
                     self.texture_layouts
                         .get_mut(&texture_binding.texture_id)
@@ -271,10 +364,11 @@ impl TerrainRenderer {
 
                 //Make sure not to waste bookkeeping pushing all-air chunks through the pipeline. 
                 if mesher_state.needs_draw() { 
-                    let mesh = mesher_state.build_mesh()
+                    let mesh = mesher_state.build_mesh(self.meshing_mode)
                         .map_err(|e| {
                             TerrainRendererError::MeshingError(*chunk_position, format!("{:?}",e))
                         })?;
+                    remesh_stats += mesh.stats;
                         
                     if !mesh.verticies.is_empty() {
                         did_mesh = true;
@@ -284,10 +378,21 @@ impl TerrainRenderer {
                 }
             }
 
+            self.last_remesh_stats = remesh_stats;
             Ok(did_mesh)
         }
     }
 
+    /// Vertex / quad / culled face counts and time spent, summed over every chunk in the last remesh.
+    pub fn last_remesh_stats(&self) -> &MeshStats { 
+        &self.last_remesh_stats
+    }
+
+    /// How many chunks the last draw() drew, and how many it skipped because they were outside the camera's frustum.
+    pub fn last_cull_stats(&self) -> &ChunkCullStats { 
+        &self.last_cull_stats
+    }
+
     /// Takes any of the changed or new chunk meshes made in process_remesh() and makes them available for rendering. 
     pub fn push_to_gpu<TextureSource>(&mut self,
             device: &mut wgpu::Device,
@@ -312,6 +417,7 @@ impl TerrainRenderer {
                 .ok_or(TerrainRendererError::NoTexLayoutForId)?;
             let mut array_texture = ArrayTexture::new(tile_array_texture.clone(),
                 Some(tile_array_texture.get_max_textures()),
+                self.sampler_spec,
                 &self.texture_bind_group_layout,
                 device)?;
 
@@ -324,7 +430,7 @@ impl TerrainRenderer {
 
         // Then, geometry.
         for (position, meshed_chunk) in self.meshed_chunks.drain() { 
-            let ChunkMesh { verticies } = meshed_chunk;
+            let ChunkMesh { verticies, .. } = meshed_chunk;
 
             let chunk_vertex_buffer = device.create_buffer_init(
                 &wgpu::util::BufferInitDescriptor {
@@ -348,6 +454,7 @@ impl TerrainRenderer {
             scale: Vec3,
             translation: Vec3,
             rotation: Quat,
+            frustum: &FrustumPlanes,
             camera_bind_group: &wgpu::BindGroup,
             encoder: &mut wgpu::CommandEncoder) -> Result<(), TerrainRendererError> {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -371,9 +478,19 @@ impl TerrainRenderer {
                 stencil_ops: None,
             }),
         });
-        render_pass.set_pipeline(&self.render_pipeline);
+        let pipeline = match self.debug_mode {
+            DebugPipelineMode::Normal => &self.render_pipeline,
+            mode => self.debug_pipelines.get(&mode).unwrap_or(&self.render_pipeline),
+        };
+        render_pass.set_pipeline(pipeline);
 
+        let mut cull_stats = ChunkCullStats::default();
         for (chunk_pos, mesh) in self.built_chunks.iter() { 
+            if !is_chunk_visible(frustum, chunk_pos, scale, translation, rotation) {
+                cull_stats.culled += 1;
+                continue;
+            }
+            cull_stats.drawn += 1;
             let pos_int = chunk_to_world_pos(&chunk_pos);
             let chunk_origin = Vec3::new(pos_int.x as f32, pos_int.y as f32, pos_int.z as f32);
             let translated_origin = chunk_origin + translation;
@@ -403,6 +520,7 @@ impl TerrainRenderer {
             render_pass.set_vertex_buffer(0, mesh.buffer.slice(..));
             render_pass.draw(0..(mesh.num_verts as u32), 0..1);
         }
+        self.last_cull_stats = cull_stats;
         Ok(())
     }
-}
\ No newline at end of file
+}
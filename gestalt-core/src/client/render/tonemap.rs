@@ -0,0 +1,285 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::{info, warn};
+use wgpu::util::DeviceExt;
+
+use super::load_test_shader;
+use crate::client::client_config::{ToneMapConfig, ToneMapOperator};
+
+/// What the scene gets drawn into when the graphics card lets us, so that brightness above 1.0 survives
+/// until tonemapping.
+pub const HDR_SCENE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapUniform {
+	exposure: f32,
+	gamma_power: f32,
+	operator: u32,
+	_padding: u32,
+}
+impl ToneMapUniform {
+	fn new(config: &ToneMapConfig, surface_is_srgb: bool) -> Self {
+		// Guard against a typo in the config turning the whole screen black or NaN.
+		let gamma = if config.gamma > 0.0 { config.gamma } else { ToneMapConfig::default().gamma };
+		// An sRGB surface does the 2.2-ish encode on its own, so only the difference from that is left for us.
+		let gamma_power = if surface_is_srgb { 2.2 / gamma } else { 1.0 / gamma };
+		Self {
+			exposure: config.exposure.max(0.0),
+			gamma_power,
+			operator: match config.operator {
+				ToneMapOperator::Reinhard => 0,
+				ToneMapOperator::Aces => 1,
+			},
+			_padding: 0,
+		}
+	}
+}
+
+/// Owns the offscreen texture the scene is drawn into, and the fullscreen pass which tonemaps
+/// it onto the window's surface with a configurable exposure and gamma.
+/// If the graphics card can't render to HDR_SCENE_FORMAT, the scene texture falls back to the surface's
+/// own format - exposure and gamma still work, but anything brighter than 1.0 gets clipped before tonemapping.
+pub struct ToneMapPass {
+	scene_format: wgpu::TextureFormat,
+	surface_is_srgb: bool,
+	config: ToneMapConfig,
+	uniform_buffer: wgpu::Buffer,
+	bind_group_layout: wgpu::BindGroupLayout,
+	bind_group: wgpu::BindGroup,
+	render_pipeline: wgpu::RenderPipeline,
+	scene_texture: wgpu::Texture,
+	/// Arc'd so the Renderer can hand it to its own draw methods while still borrowing itself mutably.
+	scene_view: Arc<wgpu::TextureView>,
+}
+
+impl ToneMapPass {
+	/// Picks HDR_SCENE_FORMAT if the adapter can both draw into it and read it back in a shader.
+	pub fn choose_scene_format(adapter: &wgpu::Adapter, surface_format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+		let needed = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+		if adapter
+			.get_texture_format_features(HDR_SCENE_FORMAT)
+			.allowed_usages
+			.contains(needed)
+		{
+			info!("Rendering the scene in HDR ({HDR_SCENE_FORMAT:?}).");
+			HDR_SCENE_FORMAT
+		} else {
+			warn!("This graphics card can't render to {HDR_SCENE_FORMAT:?}, falling back to LDR ({surface_format:?}).");
+			surface_format
+		}
+	}
+
+	pub fn new(device: &wgpu::Device,
+			scene_format: wgpu::TextureFormat,
+			surface_format: wgpu::TextureFormat,
+			width: u32,
+			height: u32,
+			config: ToneMapConfig)
+				-> Self {
+		let surface_is_srgb = surface_format.describe().srgb;
+		let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some("Tonemap Shader"),
+			source: load_test_shader(PathBuf::from("tonemap_shader.wgsl")),
+		});
+
+		let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Tonemap Uniform Buffer"),
+			contents: bytemuck::cast_slice(&[ToneMapUniform::new(&config, surface_is_srgb)]),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					// Read with textureLoad() at the same resolution, so it doesn't need to be filterable.
+					ty: wgpu::BindingType::Texture {
+						multisampled: false,
+						view_dimension: wgpu::TextureViewDimension::D2,
+						sample_type: wgpu::TextureSampleType::Float { filterable: false },
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+			],
+			label: Some("tonemap_bind_group_layout"),
+		});
+
+		let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Tonemap Pipeline Layout"),
+			bind_group_layouts: &[&bind_group_layout],
+			push_constant_ranges: &[],
+		});
+
+		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("Tonemap Render Pipeline"),
+			layout: Some(&render_pipeline_layout),
+			vertex: wgpu::VertexState {
+				module: &shader,
+				entry_point: "vs_main",
+				buffers: &[],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: &shader,
+				entry_point: "fs_main",
+				targets: &[Some(wgpu::ColorTargetState {
+					format: surface_format,
+					blend: Some(wgpu::BlendState::REPLACE),
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+			}),
+			primitive: wgpu::PrimitiveState {
+				topology: wgpu::PrimitiveTopology::TriangleList,
+				strip_index_format: None,
+				front_face: wgpu::FrontFace::Ccw,
+				cull_mode: None,
+				polygon_mode: wgpu::PolygonMode::Fill,
+				unclipped_depth: false,
+				conservative: false,
+			},
+			depth_stencil: None,
+			multisample: wgpu::MultisampleState {
+				count: 1,
+				mask: !0,
+				alpha_to_coverage_enabled: false,
+			},
+			multiview: None,
+		});
+
+		let (scene_texture, scene_view) = Self::create_scene_texture(device, scene_format, width, height);
+		let bind_group = Self::create_bind_group(device, &bind_group_layout, &scene_view, &uniform_buffer);
+
+		Self {
+			scene_format,
+			surface_is_srgb,
+			config,
+			uniform_buffer,
+			bind_group_layout,
+			bind_group,
+			render_pipeline,
+			scene_texture,
+			scene_view: Arc::new(scene_view),
+		}
+	}
+
+	fn create_scene_texture(device: &wgpu::Device,
+			format: wgpu::TextureFormat,
+			width: u32,
+			height: u32)
+				-> (wgpu::Texture, wgpu::TextureView) {
+		let texture = device.create_texture(&wgpu::TextureDescriptor {
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+			label: Some("scene_texture"),
+			view_formats: &[],
+		});
+		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+		(texture, view)
+	}
+
+	fn create_bind_group(device: &wgpu::Device,
+			layout: &wgpu::BindGroupLayout,
+			scene_view: &wgpu::TextureView,
+			uniform_buffer: &wgpu::Buffer)
+				-> wgpu::BindGroup {
+		device.create_bind_group(&wgpu::BindGroupDescriptor {
+			layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(scene_view),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: uniform_buffer.as_entire_binding(),
+				},
+			],
+			label: Some("tonemap_bind_group"),
+		})
+	}
+
+	/// Re-create the scene texture to match a new window size.
+	pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+		let (scene_texture, scene_view) = Self::create_scene_texture(device, self.scene_format, width, height);
+		self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &scene_view, &self.uniform_buffer);
+		self.scene_texture = scene_texture;
+		self.scene_view = Arc::new(scene_view);
+	}
+
+	/// Format every scene pipeline has to be built against.
+	pub fn get_scene_format(&self) -> wgpu::TextureFormat {
+		self.scene_format
+	}
+	pub fn is_hdr(&self) -> bool {
+		self.scene_format == HDR_SCENE_FORMAT
+	}
+	/// Where the scene should be drawn this frame.
+	pub fn get_scene_view(&self) -> Arc<wgpu::TextureView> {
+		self.scene_view.clone()
+	}
+	pub fn get_scene_size(&self) -> (u32, u32) {
+		let size = self.scene_texture.size();
+		(size.width, size.height)
+	}
+
+	pub fn get_config(&self) -> &ToneMapConfig {
+		&self.config
+	}
+	pub fn set_config(&mut self, queue: &wgpu::Queue, config: ToneMapConfig) {
+		self.config = config;
+		queue.write_buffer(
+			&self.uniform_buffer,
+			0,
+			bytemuck::cast_slice(&[ToneMapUniform::new(&self.config, self.surface_is_srgb)]),
+		);
+	}
+	pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: f32) {
+		let config = ToneMapConfig {
+			exposure,
+			..self.config
+		};
+		self.set_config(queue, config);
+	}
+
+	/// Tonemaps whatever was drawn to the scene texture onto surface_view.
+	pub fn draw(&self, surface_view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder) {
+		let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some("Tonemap Render Pass"),
+			color_attachments: &[
+				Some(wgpu::RenderPassColorAttachment {
+					view: surface_view,
+					resolve_target: None,
+					ops: wgpu::Operations {
+						// Every pixel gets overwritten, no need to clear.
+						load: wgpu::LoadOp::Load,
+						store: true,
+					},
+				}),
+			],
+			depth_stencil_attachment: None,
+		});
+		render_pass.set_pipeline(&self.render_pipeline);
+		render_pass.set_bind_group(0, &self.bind_group, &[]);
+		render_pass.draw(0..3, 0..1);
+	}
+}
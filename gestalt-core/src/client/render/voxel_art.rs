@@ -32,6 +32,7 @@ pub struct CubeArt {
     pub textures: CubeTex,
     pub cull_self: bool,   //Do we cull the same material?
     pub cull_others: bool, //Do we cull materials other than this one?
+    pub ao_enabled: bool, //Do neighboring solid tiles darken the corners of this one's faces?
 }
 
 impl CubeArt {
@@ -52,6 +53,7 @@ impl CubeArt {
             textures: CubeTex::Single(*texture),
             cull_self: true,
             cull_others: true,
+            ao_enabled: true,
         }
     }
 }
@@ -63,6 +65,8 @@ pub enum VoxelArtKind {
     Invisible,
     /// Just a bloxel in the strictest sense.
     SimpleCube,
+    /// Two crossed quads, for plants and the like.
+    Cross,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -70,7 +74,10 @@ pub enum VoxelArt {
     /// For air, empty-space, etc, anything that doesn't render. 
     Invisible,
     /// Just a bloxel in the strictest sense.
-    SimpleCube(CubeArt)
+    SimpleCube(CubeArt),
+    /// Two quads crossing diagonally through the voxel in an X shape, for plants and the like.
+    /// Never hides its neighbors' faces, and never gets hidden by them.
+    Cross { texture: ResourceId },
 }
 
 impl VoxelArt {
@@ -78,6 +85,7 @@ impl VoxelArt {
         match self {
             VoxelArt::Invisible => VoxelArtKind::Invisible,
             VoxelArt::SimpleCube(_) => VoxelArtKind::SimpleCube,
+            VoxelArt::Cross { .. } => VoxelArtKind::Cross,
         }
     }
     pub fn all_textures(&self) -> Vec<&ResourceId> { 
@@ -91,6 +99,7 @@ impl VoxelArt {
                     },
                 }
             },
+            VoxelArt::Cross { texture } => vec![texture],
         }
     }
     pub fn simple_solid_block(texture: &ResourceId) -> Self { 
@@ -1,4 +1,6 @@
 use std::error::Error;
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
 
 use log::{error, warn};
 
@@ -12,15 +14,17 @@ use crate::{
     },
     world::{
         chunk::{Chunk, ChunkInner, CHUNK_SIZE},
+        tilespace::ChunkNeighborhood,
         voxelstorage::Voxel,
-        TileId,
+        TileId, VoxelStorage,
     },
 };
 
 use super::array_texture::{ArrayTextureLayout, ArrayTextureError};
 use super::voxel_art::{VoxelArt, CubeArt, CubeTex, VoxelArtMapper};
 use crate::world::chunk::CHUNK_SIZE_CUBED;
-use crate::world::voxelarray;
+use crate::world::{meshing, voxelarray};
+use crate::world::meshing::{CubeArtNotes, FaceAo, OcclusionGrid, MAX_AO, NO_OCCLUSION};
 
 /// A side index and voxel cell represented as [side_idx, x, y, z]
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
@@ -90,16 +94,20 @@ impl IntermediateVertex {
     }
 }
 
-#[repr(transparent)]
+#[repr(C)]
 #[derive(Copy, Clone, Default, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub(super) struct PackedVertex { 
     // 6 bits x, 6 bits y, 6 bits z
-    // 1 bit u, 1 bit v, 12 bits texture id
+    // 12 bits texture id
     vertex_data: u32,
+    // 6 bits u, 6 bits v, 2 bits ambient occlusion
+    // Texture coordinates count voxels rather than going 0 to 1, so that the (repeating) texture
+    // tiles once per voxel across a quad which greedy meshing has stretched over several of them.
+    uv_data: u32,
 }
 
 //Bitmask
-//V. U, tex, Z, Y, X
+//(unused), tex, Z, Y, X
 //b0_0_000000000000_000000_000000_000000
 impl PackedVertex { 
     pub fn set_x(&mut self, value : u32) {
@@ -138,21 +146,45 @@ impl PackedVertex {
         self.vertex_data = self.vertex_data | (val & bitmask); //Set our value
     }
 
+    pub fn set_u(&mut self, value : u32) {
+        let bitmask : u32 = 0b000000_111111;
+        self.uv_data = self.uv_data & (! bitmask);
+        self.uv_data = self.uv_data | (value & bitmask);
+    }
+    pub fn set_v(&mut self, value : u32) {
+        let bitmask : u32 = 0b111111_000000;
+        self.uv_data = self.uv_data & (! bitmask);
+        self.uv_data = self.uv_data | ((value << 6) & bitmask);
+    }
     pub fn set_u_low(&mut self) {
-        let bitmask : u32 = 0b0_1_000000000000_000000_000000_000000;
-        self.vertex_data &= !bitmask;
+        self.set_u(0);
     }
     pub fn set_u_high(&mut self) {
-        let bitmask : u32 = 0b0_1_000000000000_000000_000000_000000;
-        self.vertex_data |= bitmask;
+        self.set_u(1);
     }
     pub fn set_v_low(&mut self) {
-        let bitmask : u32 = 0b1_0_000000000000_000000_000000_000000;
-        self.vertex_data &= !bitmask;
+        self.set_v(0);
     }
     pub fn set_v_high(&mut self) {
-        let bitmask : u32 = 0b1_0_000000000000_000000_000000_000000;
-        self.vertex_data |= bitmask;
+        self.set_v(1);
+    }
+    /// 0 is fully occluded, MAX_AO is not occluded at all.
+    pub fn set_ao(&mut self, value : u8) {
+        let bitmask : u32 = 0b11_000000_000000;
+        self.uv_data = self.uv_data & (! bitmask);
+        self.uv_data = self.uv_data | (((value as u32) << 12) & bitmask);
+    }
+    pub fn get_ao(&self) -> u8 {
+        ((self.uv_data >> 12) & 3) as u8
+    }
+    pub fn get_position(&self) -> (u32, u32, u32) {
+        (self.vertex_data & 63, (self.vertex_data >> 6) & 63, (self.vertex_data >> 12) & 63)
+    }
+    pub fn get_u(&self) -> u32 {
+        self.uv_data & 63
+    }
+    pub fn get_v(&self) -> u32 {
+        (self.uv_data >> 6) & 63
     }
     pub fn new(x: u8, y: u8, z: u8) -> Self { 
         let mut ret = Self::default();
@@ -174,7 +206,7 @@ impl PackedVertex {
 
     pub(in super) fn desc() -> wgpu::VertexBufferLayout<'static> { 
         wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<u32>() as wgpu::BufferAddress,
+            array_stride: std::mem::size_of::<PackedVertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
                 wgpu::VertexAttribute {
@@ -182,6 +214,11 @@ impl PackedVertex {
                     shader_location: 0,
                     format: wgpu::VertexFormat::Uint32,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<u32>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
@@ -282,6 +319,47 @@ const NEGATIVE_Z_FACE: [IntermediateVertex; 6] = [
     POSX_NEGY_NEGZ_VERT,
 ];
 
+/// The two diagonal quads of a VoxelArt::Cross, each once per facing (the back-facing copy is mirrored so
+/// the texture doesn't come out flipped). UVs follow FACE_UVS, same as the cube faces.
+const CROSS_QUADS: [[IntermediateVertex; 6]; 4] = [
+    [
+        NEGX_NEGY_NEGZ_VERT,
+        NEGX_POSY_NEGZ_VERT,
+        POSX_POSY_POSZ_VERT,
+        //-Second triangle
+        POSX_POSY_POSZ_VERT,
+        POSX_NEGY_POSZ_VERT,
+        NEGX_NEGY_NEGZ_VERT,
+    ],
+    [
+        POSX_NEGY_POSZ_VERT,
+        POSX_POSY_POSZ_VERT,
+        NEGX_POSY_NEGZ_VERT,
+        //-Second triangle
+        NEGX_POSY_NEGZ_VERT,
+        NEGX_NEGY_NEGZ_VERT,
+        POSX_NEGY_POSZ_VERT,
+    ],
+    [
+        POSX_NEGY_NEGZ_VERT,
+        POSX_POSY_NEGZ_VERT,
+        NEGX_POSY_POSZ_VERT,
+        //-Second triangle
+        NEGX_POSY_POSZ_VERT,
+        NEGX_NEGY_POSZ_VERT,
+        POSX_NEGY_NEGZ_VERT,
+    ],
+    [
+        NEGX_NEGY_POSZ_VERT,
+        NEGX_POSY_POSZ_VERT,
+        POSX_POSY_NEGZ_VERT,
+        //-Second triangle
+        POSX_POSY_NEGZ_VERT,
+        POSX_NEGY_NEGZ_VERT,
+        NEGX_NEGY_POSZ_VERT,
+    ],
+];
+
 fn get_face_verts(side: VoxelSide) -> [IntermediateVertex; 6] {
     match side {
         VoxelSide::PosiX => POSITIVE_X_FACE,
@@ -327,16 +405,6 @@ pub struct UvCache {
 type ArrayTextureIndex = u16;
 type SidesCache = SidesArray<ArrayTextureIndex>;
 
-#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
-pub(super) struct CubeArtNotes {
-    /// Should *this voxel mesher code* draw this tile?
-    pub visible_this_pass: bool,
-    /// Do we cull the same material? i.e do other tiles with the same ID get culled by this one?
-    pub cull_self: bool,
-    /// Do we cull other materials? i.e do other tiles with different IDs get culled by this one?
-    pub cull_others: bool,
-}
-
 impl From<&VoxelArt> for CubeArtNotes {
     fn from(art: &VoxelArt) -> Self {
         match art {
@@ -345,12 +413,25 @@ impl From<&VoxelArt> for CubeArtNotes {
                     visible_this_pass: true,
                     cull_self: cube.cull_self,
                     cull_others: cube.cull_others,
+                    ao_enabled: cube.ao_enabled,
+                    is_cross: false,
+                }
+            },
+            VoxelArt::Cross { .. } => {
+                CubeArtNotes { 
+                    visible_this_pass: true,
+                    cull_self: false,
+                    cull_others: false,
+                    ao_enabled: false,
+                    is_cross: true,
                 }
             },
             _ => CubeArtNotes {
                 visible_this_pass: false, 
                 cull_self: false,
                 cull_others: false,
+                ao_enabled: false,
+                is_cross: false,
             },
         }
     }
@@ -388,6 +469,7 @@ const VOXEL_ART_MISSING_TEXTURE: VoxelArt = VoxelArt::SimpleCube(
         textures: CubeTex::Single(ID_MISSING_TEXTURE),
         cull_self: true,
         cull_others: true,
+        ao_enabled: true,
     }
 );
 
@@ -421,6 +503,9 @@ fn sides_cache_from_art(
                 new_sides
             },
         }),
+        VoxelArt::Cross { texture } => Some(SidesCache::new_uniform(
+            &(layout.get_or_make_index_for_texture(texture)? as ArrayTextureIndex)
+        )),
     })
 }
 
@@ -528,17 +613,77 @@ impl ArtCacheLarge {
 
 pub(super) type OutputVertex = PackedVertex;
 
+/// Ambient occlusion for every corner of the face on the given side of the voxel at (x, y, z).
+fn face_ao(occluders: &OcclusionGrid, x: u8, y: u8, z: u8, side: VoxelSide) -> FaceAo {
+    let normal_axis = side_axis(side);
+    let mut outside = [x as i32, y as i32, z as i32];
+    outside[normal_axis] += match side.get_sign() {
+        VoxelAxisSign::POSI => 1,
+        VoxelAxisSign::NEGA => -1,
+    };
+    let mut ao = NO_OCCLUSION;
+    for (vert, (u, v)) in get_face_verts(side).iter().zip(FACE_UVS.iter()) {
+        let mut toward = [0i32; 3];
+        for axis in [(normal_axis + 1) % 3, (normal_axis + 2) % 3] {
+            toward[axis] = if vert.position[axis] == 1 { 1 } else { -1 };
+        }
+        ao[(*u + 2 * *v) as usize] = occluders.corner_ao(outside, toward);
+    }
+    ao
+}
+
+/// Does this tile count as solid for ambient occlusion? Tiles with no art get drawn as the missing texture cube, so they do.
+fn art_occludes(art: Option<&VoxelArt>) -> bool {
+    matches!(art, Some(VoxelArt::SimpleCube(_)) | None)
+}
+
+/// How the mesher turns visible voxel faces into quads.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Hash)]
+pub enum MeshingMode {
+    /// One quad per visible face.
+    #[default]
+    Naive,
+    /// Neighboring faces which point the same way and use the same texture get merged into one bigger quad,
+    /// which cuts vertex counts way down on flat terrain.
+    Greedy,
+}
+
+/// How much work went into meshing, and how much geometry came out of it. 
+/// Per-chunk when it comes from a ChunkMesh, or summed over every chunk in a remesh from TerrainRenderer::last_remesh_stats()
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct MeshStats {
+    pub vertices: u64,
+    pub quads: u64,
+    /// Faces which were skipped because a neighboring tile covers them up.
+    pub culled_faces: u64,
+    pub meshing_time: Duration,
+}
+
+impl AddAssign for MeshStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.vertices += rhs.vertices;
+        self.quads += rhs.quads;
+        self.culled_faces += rhs.culled_faces;
+        self.meshing_time += rhs.meshing_time;
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct ChunkMesh {
     pub(super) verticies: Vec<OutputVertex>,
+    pub(super) stats: MeshStats,
 }
 
 impl ChunkMesh { 
     pub fn zero() -> Self { 
         ChunkMesh {
             verticies: Vec::default(),
+            stats: MeshStats::default(),
         }
     }
+    pub fn get_stats(&self) -> &MeshStats { 
+        &self.stats
+    }
 }
 
 pub enum ArtCacheHolder {
@@ -551,6 +696,7 @@ pub struct MesherState<'a> {
     pub art_cache: ArtCacheHolder,
     pub chunk: &'a Chunk<TileId>,
     pub textures_needed: FastHashSet<ResourceId>,
+    pub occluders: OcclusionGrid,
 }
 
 impl<'a> MesherState<'a> {
@@ -643,13 +789,43 @@ impl<'a> MesherState<'a> {
         textures_needed.remove(&ID_MISSING_TEXTURE);
         textures_needed.remove(&ID_PENDING_TEXTURE);
 
+        let mut occluders = OcclusionGrid::new();
+        for i in 0..CHUNK_SIZE_CUBED {
+            let (x, y, z) = voxelarray::chunk_i_to_xyz(i, CHUNK_SIZE);
+            let tile = chunk.get(vpos!(x as u8, y as u8, z as u8))?;
+            occluders.set_solid(x as i32, y as i32, z as i32, art_occludes(tiles_to_art.get_art_for_tile(tile)));
+        }
+
         Ok(Self {
             art_cache: inner,
             chunk,
             textures_needed,
+            occluders,
         })
     }
 
+    /// prepare_to_mesh(), but ambient occlusion also takes the neighboring chunks into account, so that faces along
+    /// the edges of the chunk are shaded correctly. Only face neighbors are available, so voxels which are diagonal
+    /// from this chunk (off two edges at once) count as empty.
+    pub fn prepare_to_mesh_with_neighbors<A: VoxelArtMapper<TileId>>(
+        neighborhood: &ChunkNeighborhood<'a>,
+        tiles_to_art: &A,
+        layout: &mut ArrayTextureLayout,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut state = Self::prepare_to_mesh(neighborhood.center, tiles_to_art, layout)?;
+        let edge = CHUNK_SIZE as i32;
+        for pos in VoxelRange::new(vpos!(-1, -1, -1), vpos!(edge + 1, edge + 1, edge + 1)) {
+            let on_shell = [pos.x, pos.y, pos.z].iter().any(|v| *v == -1 || *v == edge);
+            if !on_shell {
+                continue;
+            }
+            if let Some(tile) = neighborhood.get_local(pos) {
+                state.occluders.set_solid(pos.x, pos.y, pos.z, art_occludes(tiles_to_art.get_art_for_tile(tile)));
+            }
+        }
+        Ok(state)
+    }
+
     /// Do we need to render this at all? Used in order to avoid wasting bookkeeping on all-air chunks.
     pub fn needs_draw(&self) -> bool { 
         match &self.art_cache {
@@ -659,12 +835,15 @@ impl<'a> MesherState<'a> {
         }
     }
 
-    pub fn build_mesh(&self) -> Result<ChunkMesh, Box<dyn Error>> {
-        match &self.art_cache {
-            ArtCacheHolder::Uniform(art_cache) => if art_cache.is_any_visible() { build_mesh(self.chunk, art_cache) } else { Ok(ChunkMesh::zero()) },
-            ArtCacheHolder::Small(art_cache) => build_mesh(self.chunk, art_cache),
-            ArtCacheHolder::Large(art_cache) => build_mesh(self.chunk, art_cache),
-        }
+    pub fn build_mesh(&self, mode: MeshingMode) -> Result<ChunkMesh, Box<dyn Error>> {
+        let start = Instant::now();
+        let mut mesh = match &self.art_cache {
+            ArtCacheHolder::Uniform(art_cache) => if art_cache.is_any_visible() { build_mesh(self.chunk, art_cache, &self.occluders, mode) } else { Ok(ChunkMesh::zero()) },
+            ArtCacheHolder::Small(art_cache) => build_mesh(self.chunk, art_cache, &self.occluders, mode),
+            ArtCacheHolder::Large(art_cache) => build_mesh(self.chunk, art_cache, &self.occluders, mode),
+        }?;
+        mesh.stats.meshing_time = start.elapsed();
+        Ok(mesh)
     }
 }
 
@@ -674,6 +853,7 @@ pub fn make_mesh_completely<A: VoxelArtMapper<TileId>>(
     chunk: &Chunk<TileId>,
     tiles_to_art: &A,
     max_texture_layers: Option<u32>,
+    mode: MeshingMode,
 ) -> Result<(ChunkMesh, ArrayTextureLayout), Box<dyn std::error::Error>> {
     let mut layout = ArrayTextureLayout::new(
         (texture_size,texture_size), 
@@ -681,7 +861,7 @@ pub fn make_mesh_completely<A: VoxelArtMapper<TileId>>(
 
     let state = MesherState::prepare_to_mesh(chunk, tiles_to_art, &mut layout)?;
 
-    Ok((state.build_mesh()?, layout))
+    Ok((state.build_mesh(mode)?, layout))
 }
 
 macro_rules! offset_unroll {
@@ -723,81 +903,329 @@ macro_rules! offset_unroll {
     }};
 }
 
+/// (u, v) for each of the 6 vertices of a face, in the same order as the *_FACE vertex arrays.
+const FACE_UVS: [(u8, u8); 6] = [(0, 1), (0, 0), (1, 0), (1, 0), (1, 1), (0, 1)];
+
+/// Which axis (0 = x, 1 = y, 2 = z) the given side faces along.
+#[inline(always)]
+fn side_axis(side: VoxelSide) -> usize {
+    match side {
+        VoxelSide::PosiX | VoxelSide::NegaX => 0,
+        VoxelSide::PosiY | VoxelSide::NegaY => 1,
+        VoxelSide::PosiZ | VoxelSide::NegaZ => 2,
+    }
+}
+
+/// Emit one quad for side side_index of the voxel at base, stretched to cover extent voxels along each axis
+/// (extent along the side's own axis should be 1). Per-voxel faces are just an extent of [1, 1, 1].
 #[inline]
-fn per_face_step(
-    x: u8,
-    y: u8,
-    z: u8, 
+fn emit_quad(
+    base: [u8; 3],
+    extent: [u8; 3],
     texture_index: u16,
     side_index: u8,
+    ao: FaceAo,
     vertex_buffer: &mut Vec<OutputVertex>,
 ) {
+    let side = VoxelSide::from_id(side_index);
+    let face = get_face_verts(side);
+    // Vertex 1 is at (u 0, v 0), vertex 2 is at (u 1, v 0), and vertex 0 is at (u 0, v 1),
+    // so the axis that changes between them is the one that texture axis runs along.
+    let axis_between = |a: &IntermediateVertex, b: &IntermediateVertex| {
+        (0..3).find(|axis| a.position[*axis] != b.position[*axis]).unwrap()
+    };
+    let u_axis = axis_between(&face[1], &face[2]);
+    let v_axis = axis_between(&face[1], &face[0]);
+
     voxel_side_indicies_unroll!(INDEX, {
-        let side = VoxelSide::from_id(side_index);
-        let mut temp_vert = get_face_verts(side)[INDEX];
+        let mut temp_vert = face[INDEX];
+        for axis in 0..3 {
+            temp_vert.position[axis] = base[axis] + temp_vert.position[axis] * extent[axis];
+        }
 
-        temp_vert.position[0] += x;
-        temp_vert.position[1] += y;
-        temp_vert.position[2] += z;
-        
         let mut packed_vert: PackedVertex = PackedVertex::from(temp_vert);
         packed_vert.set_tex_id(texture_index);
 
-        if (INDEX == 2) || (INDEX == 3) {
-            packed_vert.set_u_high();
-            packed_vert.set_v_low();
-        } else if (INDEX == 0) || (INDEX == 5) {
-            packed_vert.set_u_low();
-            packed_vert.set_v_high();
-        } else if INDEX == 1 {
-            packed_vert.set_u_low();
-            packed_vert.set_v_low();
-        } else if INDEX == 4 {
-            packed_vert.set_u_high();
-            packed_vert.set_v_high();
-        }
+        let (u, v) = FACE_UVS[INDEX];
+        packed_vert.set_u((u * extent[u_axis]) as u32);
+        packed_vert.set_v((v * extent[v_axis]) as u32);
+        packed_vert.set_ao(ao[(u + 2 * v) as usize]);
 
         vertex_buffer.push(packed_vert);
     });
 }
 
+#[inline]
+fn per_face_step(
+    x: u8,
+    y: u8,
+    z: u8, 
+    texture_index: u16,
+    side_index: u8,
+    ao: FaceAo,
+    vertex_buffer: &mut Vec<OutputVertex>,
+) {
+    emit_quad([x, y, z], [1, 1, 1], texture_index, side_index, ao, vertex_buffer);
+}
+
+/// Emit both crossed quads of a VoxelArt::Cross tile. These never merge, so greedy meshing doesn't see them either.
+#[inline]
+fn cross_step(
+    x: u8,
+    y: u8,
+    z: u8,
+    texture_index: u16,
+    vertex_buffer: &mut Vec<OutputVertex>,
+) {
+    for quad in CROSS_QUADS.iter() {
+        for (vert, (u, v)) in quad.iter().zip(FACE_UVS.iter()) {
+            let mut packed_vert = PackedVertex::new(x + vert.get_x(), y + vert.get_y(), z + vert.get_z());
+            packed_vert.set_tex_id(texture_index);
+            packed_vert.set_u(*u as u32);
+            packed_vert.set_v(*v as u32);
+            packed_vert.set_ao(MAX_AO);
+            vertex_buffer.push(packed_vert);
+        }
+    }
+}
+
+/// What greedy meshing needs to know about a visible face to decide whether it can be merged with its neighbor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct FaceKey {
+    texture: ArrayTextureIndex,
+    ao: FaceAo,
+}
+
+impl FaceKey {
+    /// A face which is shaded differently at different corners can't be stretched without smearing the shading.
+    fn can_merge(&self) -> bool {
+        self.ao.iter().all(|v| *v == self.ao[0])
+    }
+}
+
+/// Merge visible faces into as few quads as possible. faces holds the texture and AO of each visible
+/// face (or None), indexed by side index * CHUNK_SIZE_CUBED + chunk index. Each layer of the chunk
+/// is merged separately for each side, by meshing::greedy_rects().
+fn greedy_merge(faces: &[Option<FaceKey>], vertex_buffer: &mut Vec<OutputVertex>) {
+    for side in VoxelSide::iter_all() {
+        let side_index = side.to_id() as usize;
+        let normal_axis = side_axis(side);
+        let axis_a = (normal_axis + 1) % 3;
+        let axis_b = (normal_axis + 2) % 3;
+        for depth in 0..CHUNK_SIZE {
+            let face_at = |a: usize, b: usize| {
+                let mut pos = [0usize; 3];
+                pos[normal_axis] = depth;
+                pos[axis_a] = a;
+                pos[axis_b] = b;
+                faces[side_index * CHUNK_SIZE_CUBED + voxelarray::chunk_xyz_to_i(pos[0], pos[1], pos[2], CHUNK_SIZE)]
+            };
+            meshing::greedy_rects(CHUNK_SIZE, face_at, FaceKey::can_merge, |a, b, width, height, key| {
+                let mut base = [0u8; 3];
+                base[normal_axis] = depth as u8;
+                base[axis_a] = a as u8;
+                base[axis_b] = b as u8;
+                let mut extent = [1u8; 3];
+                extent[axis_a] = width as u8;
+                extent[axis_b] = height as u8;
+                emit_quad(base, extent, key.texture, side_index as u8, key.ao, vertex_buffer);
+            });
+        }
+    }
+}
+
 fn build_mesh<V: Voxel, A: ArtCache>(
     chunk: &Chunk<V>,
     art_cache: &A,
+    occluders: &OcclusionGrid,
+    mode: MeshingMode,
 ) -> Result<ChunkMesh, Box<dyn Error>> {
     let mut vertex_buffer: Vec<OutputVertex> = Vec::new();
+    let mut culled_faces: u64 = 0;
+    // Only used for greedy meshing - faces get collected here and merged once we know all of them.
+    let mut visible_faces: Vec<Option<FaceKey>> = match mode {
+        MeshingMode::Naive => Vec::new(),
+        MeshingMode::Greedy => vec![None; 6 * CHUNK_SIZE_CUBED],
+    };
 
     for i in 0..CHUNK_SIZE_CUBED {
         let tile = chunk.get_raw_i(i);
         if let Some(art) = art_cache.get_mapping(tile) {
             // Skip it if it's air.
-            if art.tile_info.visible_this_pass {
-                offset_unroll!(_SIDE, offset_idx, i, SIDE_INDEX {
+            if art.tile_info.visible_this_pass && art.tile_info.is_cross {
+                let (x, y, z) = voxelarray::chunk_i_to_xyz(i, CHUNK_SIZE);
+                cross_step(x as u8, y as u8, z as u8, art.textures.data[0], &mut vertex_buffer);
+            }
+            else if art.tile_info.visible_this_pass {
+                offset_unroll!(SIDE, offset_idx, i, SIDE_INDEX {
                     let mut cull: bool = false;
                     if let Some(neighbor_idx) = offset_idx {
                         let neighbor_tile = chunk.get_raw_i(neighbor_idx);
                         if let Some(neighbor_art) = art_cache.get_mapping(neighbor_tile) {
-                            cull = neighbor_art.tile_info.visible_this_pass
-                                && ( (art.tile_info.cull_self && (tile == neighbor_tile) )
-                                || (art.tile_info.cull_others && (tile != neighbor_tile) ) );
+                            cull = art.tile_info.is_face_hidden_by(tile, &neighbor_art.tile_info, neighbor_tile);
                         }
                     }
-                    if !cull {
-                        let (x,y,z) = voxelarray::chunk_i_to_xyz(i, CHUNK_SIZE);
+                    if cull {
+                        culled_faces += 1;
+                    }
+                    else {
                         let tex_idx = art.textures.data[SIDE_INDEX];
-                        per_face_step(x as u8,
-                            y as u8,
-                            z as u8,
-                            tex_idx as u16,
-                            SIDE_INDEX as u8,
-                            &mut vertex_buffer);
+                        let (x,y,z) = voxelarray::chunk_i_to_xyz(i, CHUNK_SIZE);
+                        let ao = if art.tile_info.ao_enabled {
+                            face_ao(occluders, x as u8, y as u8, z as u8, SIDE)
+                        } else {
+                            NO_OCCLUSION
+                        };
+                        match mode {
+                            MeshingMode::Naive => {
+                                per_face_step(x as u8,
+                                    y as u8,
+                                    z as u8,
+                                    tex_idx as u16,
+                                    SIDE_INDEX as u8,
+                                    ao,
+                                    &mut vertex_buffer);
+                            }
+                            MeshingMode::Greedy => {
+                                visible_faces[SIDE_INDEX * CHUNK_SIZE_CUBED + i] = Some(FaceKey { texture: tex_idx, ao });
+                            }
+                        }
                     }
                 });
             }
         }
     }
+    if mode == MeshingMode::Greedy {
+        greedy_merge(&visible_faces, &mut vertex_buffer);
+    }
 
+    let vertices = vertex_buffer.len() as u64;
     Ok(ChunkMesh {
         verticies: vertex_buffer,
+        stats: MeshStats {
+            vertices,
+            // Two triangles, not indexed.
+            quads: vertices / 6,
+            culled_faces,
+            // Filled in by the caller, which knows where meshing started.
+            meshing_time: Duration::ZERO,
+        },
     })
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const AIR: TileId = 0;
+    const STONE: TileId = 1;
+
+    /// Sum of the area covered by each quad. UVs count voxels, so that's u * v at the far corner.
+    fn covered_area(mesh: &ChunkMesh) -> u64 {
+        mesh.verticies
+            .chunks(6)
+            .map(|quad| {
+                let u = quad.iter().map(|vert| vert.get_u()).max().unwrap();
+                let v = quad.iter().map(|vert| vert.get_v()).max().unwrap();
+                (u * v) as u64
+            })
+            .sum()
+    }
+
+    #[test]
+    fn greedy_meshing_merges_flat_slab() {
+        let mut chunk: Chunk<TileId> = Chunk::new(AIR);
+        chunk.set_region(VoxelRange::new(vpos!(0, 0, 0), vpos!(16, 1, 16)), STONE).unwrap();
+        let mut art: HashMap<TileId, VoxelArt> = HashMap::new();
+        art.insert(AIR, VoxelArt::Invisible);
+        art.insert(STONE, VoxelArt::simple_solid_block(&ResourceId::from_buf(b"stone")));
+
+        let (naive, _) = make_mesh_completely(16, &chunk, &art, None, MeshingMode::Naive).unwrap();
+        let (greedy, _) = make_mesh_completely(16, &chunk, &art, None, MeshingMode::Greedy).unwrap();
+
+        // Top and bottom, plus the four 16x1 edges.
+        assert_eq!(naive.stats.quads, 16 * 16 * 2 + 16 * 4);
+        assert_eq!(greedy.stats.quads, 6);
+        assert_eq!(covered_area(&naive), naive.stats.quads);
+        assert_eq!(covered_area(&greedy), covered_area(&naive));
+        assert_eq!(greedy.stats.culled_faces, naive.stats.culled_faces);
+
+        // The merged top face spans the whole slab, 1 voxel up.
+        let top = greedy
+            .verticies
+            .chunks(6)
+            .find(|quad| quad.iter().all(|vert| vert.get_position().1 == 1))
+            .unwrap();
+        let xs: Vec<u32> = top.iter().map(|vert| vert.get_position().0).collect();
+        let zs: Vec<u32> = top.iter().map(|vert| vert.get_position().2).collect();
+        assert_eq!((*xs.iter().min().unwrap(), *xs.iter().max().unwrap()), (0, 16));
+        assert_eq!((*zs.iter().min().unwrap(), *zs.iter().max().unwrap()), (0, 16));
+    }
+
+    #[test]
+    fn inside_corner_gets_more_occlusion() {
+        // A floor with two walls meeting in the corner at x = 0, z = 0.
+        let mut chunk: Chunk<TileId> = Chunk::new(AIR);
+        chunk.set_region(VoxelRange::new(vpos!(0, 0, 0), vpos!(8, 1, 8)), STONE).unwrap();
+        chunk.set_region(VoxelRange::new(vpos!(0, 1, 0), vpos!(1, 3, 8)), STONE).unwrap();
+        chunk.set_region(VoxelRange::new(vpos!(0, 1, 0), vpos!(8, 3, 1)), STONE).unwrap();
+        let mut art: HashMap<TileId, VoxelArt> = HashMap::new();
+        art.insert(AIR, VoxelArt::Invisible);
+        art.insert(STONE, VoxelArt::simple_solid_block(&ResourceId::from_buf(b"stone")));
+
+        let top_face_ao = |mesh: &ChunkMesh, x: u32, z: u32| -> Vec<u8> {
+            let quad = mesh
+                .verticies
+                .chunks(6)
+                .find(|quad| {
+                    quad.iter().all(|vert| {
+                        let (vx, vy, vz) = vert.get_position();
+                        vy == 1 && (x..=x + 1).contains(&vx) && (z..=z + 1).contains(&vz)
+                    })
+                })
+                .unwrap();
+            quad.iter().map(|vert| vert.get_ao()).collect()
+        };
+
+        let (mesh, _) = make_mesh_completely(16, &chunk, &art, None, MeshingMode::Naive).unwrap();
+        let corner = top_face_ao(&mesh, 1, 1);
+        let exposed = top_face_ao(&mesh, 5, 5);
+        assert!(exposed.iter().all(|ao| *ao == MAX_AO));
+        assert_eq!(*corner.iter().min().unwrap(), 0);
+        assert!(corner.iter().map(|ao| *ao as u32).sum::<u32>() < exposed.iter().map(|ao| *ao as u32).sum::<u32>());
+
+        // Opting out leaves the corner lit.
+        art.insert(STONE, VoxelArt::SimpleCube(CubeArt {
+            ao_enabled: false,
+            ..CubeArt::simple_solid_block(&ResourceId::from_buf(b"stone"))
+        }));
+        let (mesh, _) = make_mesh_completely(16, &chunk, &art, None, MeshingMode::Naive).unwrap();
+        assert!(top_face_ao(&mesh, 1, 1).iter().all(|ao| *ao == MAX_AO));
+    }
+
+    #[test]
+    fn cross_tiles_neither_cull_nor_get_culled() {
+        const FERN: TileId = 2;
+        let mut chunk: Chunk<TileId> = Chunk::new(AIR);
+        chunk.set(vpos!(6, 5, 5), FERN).unwrap();
+        let mut art: HashMap<TileId, VoxelArt> = HashMap::new();
+        art.insert(AIR, VoxelArt::Invisible);
+        art.insert(STONE, VoxelArt::simple_solid_block(&ResourceId::from_buf(b"stone")));
+        art.insert(FERN, VoxelArt::Cross { texture: ResourceId::from_buf(b"fern") });
+
+        // Two quads, each drawn from both sides.
+        for mode in [MeshingMode::Naive, MeshingMode::Greedy] {
+            let (mesh, _) = make_mesh_completely(16, &chunk, &art, None, mode).unwrap();
+            assert_eq!(mesh.stats.vertices, 4 * 6);
+            assert_eq!(mesh.stats.culled_faces, 0);
+        }
+
+        // Stone right next to it keeps all six of its faces.
+        chunk.set(vpos!(5, 5, 5), STONE).unwrap();
+        let (mesh, _) = make_mesh_completely(16, &chunk, &art, None, MeshingMode::Naive).unwrap();
+        assert_eq!(mesh.stats.quads, 4 + 6);
+        assert_eq!(mesh.stats.culled_faces, 0);
+    }
+}
@@ -0,0 +1,589 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use glam::{Mat4, Vec2, Vec3, EulerRot, Quat};
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+use crate::common::{DegreeAngle, Angle, RadianAngle};
+
+use crate::common::frustum::FrustumPlanes;
+
+/// Which way the camera moves while a movement key is held. Keys map to these through KeyBindings.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum Directions {
+	Left,
+	Right,
+	Up,
+	Down,
+	Forward,
+	Backward,
+}
+
+/// Which keys move the camera which way. Loaded as part of the client config.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+	pub movement: BTreeMap<VirtualKeyCode, Directions>,
+}
+impl KeyBindings {
+	pub fn direction_for(&self, key: VirtualKeyCode) -> Option<Directions> {
+		self.movement.get(&key).copied()
+	}
+}
+impl Default for KeyBindings {
+	fn default() -> Self {
+		Self {
+			movement: BTreeMap::from([
+				(VirtualKeyCode::W, Directions::Forward),
+				(VirtualKeyCode::A, Directions::Left),
+				(VirtualKeyCode::S, Directions::Backward),
+				(VirtualKeyCode::D, Directions::Right),
+				(VirtualKeyCode::R, Directions::Up),
+				(VirtualKeyCode::C, Directions::Down),
+			]),
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Perspective {
+    pub aspect_ratio: f32,
+	/// fov_y in radians
+    pub fov_y: RadianAngle,
+    pub near_clip_z: f32,
+    pub far_clip_z: f32,
+}
+impl Perspective { 
+	pub fn new(aspect_ratio: f32) -> Self { 
+		Self { 
+			aspect_ratio,
+			..Default::default()
+		}
+	}
+	pub fn set_fov_y_degrees(&mut self, fov_y_degrees: f32) { 
+		self.fov_y = RadianAngle::from_degrees(fov_y_degrees)
+	}
+	/// Make a right-handed coordinate system perspective matrix
+	pub fn make_matrix(&self) -> Mat4 {
+		glam::Mat4::perspective_rh(
+			self.fov_y.get_radians(),
+			self.aspect_ratio,
+			self.near_clip_z,
+			self.far_clip_z)
+	}
+}
+
+impl Default for Perspective {
+    fn default() -> Self {
+        Self { 
+			aspect_ratio: 16.0 / 9.0,
+			fov_y: RadianAngle::from_degrees(80.0),
+			near_clip_z: 0.001,
+			far_clip_z: 1000.0 }
+    }
+}
+
+/// A world-space ray, such as one cast out from the camera through the mouse cursor.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraRay {
+	pub origin: Vec3,
+	/// Always normalized.
+	pub direction: Vec3,
+}
+
+/// Describes a transient camera shake, e.g. for an explosion or a hard landing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShakeSpec {
+	/// Peak positional offset, in world units. Rotational shake is scaled from this by
+	/// SHAKE_DEGREES_PER_AMPLITUDE.
+	pub amplitude: f32,
+	/// Oscillations per second.
+	pub frequency: f32,
+	/// Exponential falloff rate, per second. 0.0 means the shake only fades out linearly over its duration.
+	pub decay: f32,
+	pub duration: Duration,
+}
+
+/// How many degrees of yaw / pitch wobble a shake gets per world unit of amplitude.
+pub const SHAKE_DEGREES_PER_AMPLITUDE: f32 = 4.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ActiveShake {
+	spec: ShakeSpec,
+	elapsed: Duration,
+	/// Picked per shake so that overlapping shakes don't move in lockstep.
+	phase: f32,
+}
+
+impl ActiveShake {
+	/// Current strength - starts at amplitude and always reaches exactly 0 at the end of the duration.
+	fn envelope(&self) -> f32 {
+		let duration = self.spec.duration.as_secs_f32();
+		if duration <= 0.0 {
+			return 0.0;
+		}
+		let t = self.elapsed.as_secs_f32().min(duration);
+		self.spec.amplitude * (-self.spec.decay * t).exp() * (1.0 - (t / duration))
+	}
+	/// Returns (positional offset in camera-local right/up/front axes, (yaw, pitch) offset in degrees).
+	fn sample(&self) -> (Vec3, Vec2) {
+		use std::f32::consts::TAU;
+		let strength = self.envelope();
+		let t = self.elapsed.as_secs_f32() * self.spec.frequency * TAU + self.phase;
+		// Slightly different rates per axis so it doesn't just bounce along one line.
+		let position = Vec3::new((t).sin(), (t * 1.31 + 1.7).sin(), (t * 0.73 + 3.1).sin() * 0.5);
+		let rotation = Vec2::new((t * 1.13 + 0.6).sin(), (t * 0.89 + 2.3).sin());
+		(position * strength, rotation * strength * SHAKE_DEGREES_PER_AMPLITUDE)
+	}
+	fn is_finished(&self) -> bool {
+		self.elapsed >= self.spec.duration
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera {
+	position: Vec3,
+	front: Vec3,
+	up: Vec3,
+	right: Vec3,
+	world_up: Vec3,
+
+	yaw: f32,
+	pitch: f32,
+	/// Degrees, positive tilts the top of the view to the right. Stays 0 for ordinary first-person controls.
+	roll: f32,
+	pub speed: f32,
+	pub zoom: f32,
+	/// If set, held movement keys ramp the camera's velocity up and it coasts to a stop afterwards,
+	/// rather than moving at full speed the instant a key goes down. See update_movement().
+	pub smoothing: bool,
+	/// With smoothing, how quickly held keys speed the camera up, in units per second squared.
+	pub acceleration: f32,
+	/// With smoothing, what fraction of its velocity the camera loses per second.
+	pub friction: f32,
+	velocity: Vec3,
+	pub perspective: Perspective,
+
+	shakes: Vec<ActiveShake>,
+	/// Sum of every active shake, in world space. Only ever applied when building the view matrix,
+	/// so anything which uses position / front (aiming, raycasts) is unaffected.
+	shake_offset: Vec3,
+	/// Sum of every active shake's (yaw, pitch), in degrees.
+	shake_rotation: Vec2,
+}
+
+impl Camera {
+	pub fn new(pos: Vec3, aspect_ratio: f32) -> Self {
+		let yaw = 0.0;
+		let pitch = 0.0;
+		let world_up = Vec3::new(0.0, 1.0, 0.0);
+		let front = Camera::calc_front(DegreeAngle(0.0), DegreeAngle(0.0));
+		let (right, up) = Camera::calc_right_up(&front, &world_up, DegreeAngle(0.0));
+
+		Self {
+			position: pos,
+			front,
+			up,
+			right,
+			world_up,
+			yaw,
+			pitch,
+			roll: 0.0,
+			speed: 2.5,
+			zoom: 1.0,
+			smoothing: false,
+			acceleration: 20.0,
+			friction: 8.0,
+			velocity: Vec3::ZERO,
+			perspective: Perspective::new(aspect_ratio),
+			shakes: Vec::new(),
+			shake_offset: Vec3::ZERO,
+			shake_rotation: Vec2::ZERO,
+		}
+	}
+
+	pub fn get_zoom(&self) -> f32 {
+		self.zoom
+	}
+	pub fn get_position(&self) -> &Vec3 {
+		&self.position
+	}
+	pub fn get_front(&self) -> &Vec3 {
+		&self.front
+	}
+	pub fn get_up(&self) -> &Vec3 {
+		&self.up
+	}
+	pub fn get_right(&self) -> &Vec3 {
+		&self.right
+	}
+
+	pub fn get_view_matrix(&self) -> Mat4 {
+		let eye = self.position + self.shake_offset;
+		let (front, up) = if self.shake_rotation == Vec2::ZERO {
+			(self.front, self.up)
+		} else {
+			let front = Camera::calc_front(
+				DegreeAngle(self.yaw + self.shake_rotation.x),
+				DegreeAngle(self.pitch + self.shake_rotation.y),
+			);
+			let (_, up) = Camera::calc_right_up(&front, &self.world_up, self.get_roll());
+			(front, up)
+		};
+		glam::Mat4::look_at_rh(eye, /*center*/ eye + front, up)
+	}
+
+	/// Start a new camera shake. Shakes stack - each one adds to the others and fades out on its own.
+	pub fn add_shake(&mut self, spec: ShakeSpec) {
+		// Golden-ratio spacing keeps consecutive shakes well out of phase with each other.
+		let phase = (self.shakes.len() as f32 * 0.618_034 * std::f32::consts::TAU) % std::f32::consts::TAU;
+		self.shakes.push(ActiveShake {
+			spec,
+			elapsed: Duration::ZERO,
+			phase,
+		});
+	}
+
+	/// Advance every active shake by elapsed_time. Call this once per frame.
+	pub fn update_shake(&mut self, elapsed_time: Duration) {
+		let mut offset = Vec3::ZERO;
+		let mut rotation = Vec2::ZERO;
+		for shake in self.shakes.iter_mut() {
+			shake.elapsed += elapsed_time;
+			let (local_offset, local_rotation) = shake.sample();
+			offset += (self.right * local_offset.x) + (self.up * local_offset.y) + (self.front * local_offset.z);
+			rotation += local_rotation;
+		}
+		self.shakes.retain(|shake| !shake.is_finished());
+		if self.shakes.is_empty() {
+			offset = Vec3::ZERO;
+			rotation = Vec2::ZERO;
+		}
+		self.shake_offset = offset;
+		self.shake_rotation = rotation;
+	}
+
+	/// How far the view is currently displaced from get_position() by shaking.
+	pub fn get_shake_offset(&self) -> Vec3 {
+		self.shake_offset
+	}
+	pub fn is_shaking(&self) -> bool {
+		!self.shakes.is_empty()
+	}
+
+	pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) { 
+		self.perspective.aspect_ratio = aspect_ratio;
+	}
+
+	fn direction_vector(&self, direction: Directions) -> Vec3 {
+		match direction {
+			Directions::Forward => self.front,
+			Directions::Left => -self.right,
+			Directions::Right => self.right,
+			Directions::Up => self.up,
+			Directions::Down => -self.up,
+			Directions::Backward => -self.front,
+		}
+	}
+
+	pub fn key_interact(&mut self, direction: Directions, time_elapsed: Duration) {
+		self.position += self.direction_vector(direction) * self.speed * (time_elapsed.as_secs_f64() as f32);
+	}
+
+	/// Move the camera for one frame, given every movement key currently held. Without smoothing, this is
+	/// just key_interact() for each of them. With smoothing, held keys accelerate the camera (up to speed)
+	/// and friction slows it down again, so it needs calling every frame, even when nothing is held.
+	pub fn update_movement<I: IntoIterator<Item = Directions>>(&mut self, held: I, time_elapsed: Duration) {
+		if !self.smoothing {
+			self.velocity = Vec3::ZERO;
+			for direction in held {
+				self.key_interact(direction, time_elapsed);
+			}
+			return;
+		}
+		let seconds = time_elapsed.as_secs_f32();
+		let wish = held
+			.into_iter()
+			.fold(Vec3::ZERO, |sum, direction| sum + self.direction_vector(direction))
+			.normalize_or_zero();
+		self.velocity += wish * self.acceleration * seconds;
+		self.velocity *= (1.0 - (self.friction * seconds)).max(0.0);
+		self.velocity = self.velocity.clamp_length_max(self.speed);
+		self.position += self.velocity * seconds;
+	}
+
+	pub fn get_velocity(&self) -> Vec3 {
+		self.velocity
+	}
+
+	pub fn update_orientation(&mut self) {
+		self.front = Camera::calc_front(self.get_yaw(), self.get_pitch());
+		(self.right, self.up) = Camera::calc_right_up(&self.front, &self.world_up, self.get_roll());
+	}
+	pub fn mouse_interact(&mut self, dx: f32, dy: f32) {
+		self.yaw = self.yaw - dx;
+		self.pitch = (self.pitch - dy).max(-89.0).min(89.0);
+		self.update_orientation();
+	}
+
+	pub fn get_yaw(&self) -> DegreeAngle { 
+		DegreeAngle(self.yaw)
+	}
+	pub fn get_pitch(&self) -> DegreeAngle { 
+		DegreeAngle(self.pitch)
+	}
+	pub fn get_roll(&self) -> DegreeAngle { 
+		DegreeAngle(self.roll)
+	}
+	pub fn set_roll<A: Angle>(&mut self, roll: A) {
+		self.roll = roll.get_degrees() % 360.0;
+		self.update_orientation();
+	}
+	pub fn add_roll<A: Angle>(&mut self, delta: A) {
+		self.set_roll(DegreeAngle(self.roll + delta.get_degrees()));
+	}
+
+	pub fn scroll_wheel_interact(&mut self, delta: f32) {
+		let new_zoom = (self.zoom + delta).max(1.0).min(55.0);
+		//println!("Scroll {} + delta {} => {}", self.zoom, delta, new_zoom );
+		self.zoom = new_zoom;
+	}
+
+	fn calc_front<A: Angle>(yaw: A, pitch: A) -> Vec3 {
+		const COORDINATE_SYSTEM_FORWARD: Vec3 = Vec3::new(0.0, 0.0, -1.0);
+		let yaw = yaw.get_radians() % (2.0 * std::f32::consts::PI);
+		let pitch = pitch.get_radians() % (2.0 * std::f32::consts::PI);
+		let quat = Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0); 
+		quat.mul_vec3(COORDINATE_SYSTEM_FORWARD).normalize()
+	}
+
+	fn calc_right(front: &Vec3, world_up: &Vec3) -> Vec3 {
+		front.cross(*world_up).normalize()
+	}
+
+	fn calc_up(right: &Vec3, front: &Vec3) -> Vec3 {
+		right.cross(*front).normalize()
+		//Vec3::new(0.0, 1.0, 0.0)
+	}
+
+	/// Right and up vectors for a camera looking along front, turned about front by roll.
+	fn calc_right_up<A: Angle>(front: &Vec3, world_up: &Vec3, roll: A) -> (Vec3, Vec3) {
+		let right = Camera::calc_right(front, world_up);
+		let up = Camera::calc_up(&right, front);
+		if roll.get_radians() == 0.0 {
+			return (right, up);
+		}
+		let rotation = Quat::from_axis_angle(*front, roll.get_radians());
+		(rotation.mul_vec3(right).normalize(), rotation.mul_vec3(up).normalize())
+	}
+
+    /// What the renderer uploads to the camera uniform. perspective_rh() already maps depth to 0..1 the way wgpu
+    /// expects, so this needs no further remapping.
+    pub fn build_view_projection_matrix(&self) -> glam::Mat4 {
+        let view = self.get_view_matrix();
+        let proj = self.perspective.make_matrix();
+
+        return proj * view;
+    }
+
+	/// What this camera can see, for culling. Built from the same matrix as the camera uniform, shake and all.
+	pub fn frustum_planes(&self) -> FrustumPlanes {
+		FrustumPlanes::from_view_projection(self.build_view_projection_matrix())
+	}
+
+	/// Project a world-space point to screen-space pixel coordinates, with (0, 0) in the top-left
+	/// corner of the viewport. Returns None if the point is behind the near clip plane.
+	pub fn world_to_screen(&self, point: Vec3, viewport: (u32, u32)) -> Option<Vec2> {
+		let clip = self.build_view_projection_matrix() * point.extend(1.0);
+		// For a perspective projection, w is the view-space distance along the camera's front.
+		if clip.w < self.perspective.near_clip_z {
+			return None;
+		}
+		let ndc = Vec2::new(clip.x / clip.w, clip.y / clip.w);
+		let (width, height) = (viewport.0 as f32, viewport.1 as f32);
+		Some(Vec2::new(
+			(ndc.x + 1.0) * 0.5 * width,
+			(1.0 - ndc.y) * 0.5 * height,
+		))
+	}
+
+	/// Inverse of world_to_screen(): produces a world-space ray starting at the camera
+	/// and passing through the given screen-space pixel coordinates.
+	pub fn screen_to_ray(&self, screen: Vec2, viewport: (u32, u32)) -> CameraRay {
+		let (width, height) = (viewport.0 as f32, viewport.1 as f32);
+		let ndc_x = (screen.x / width) * 2.0 - 1.0;
+		let ndc_y = 1.0 - (screen.y / height) * 2.0;
+		// Work it out from the camera's own axes rather than inverting the view-projection matrix - with the near
+		// and far planes as far apart as they are, unprojecting through the inverse loses most of the precision.
+		let half_height = (self.perspective.fov_y.get_radians() * 0.5).tan();
+		let half_width = half_height * self.perspective.aspect_ratio;
+		let direction = self.front + (self.right * ndc_x * half_width) + (self.up * ndc_y * half_height);
+		CameraRay {
+			origin: self.position,
+			direction: direction.normalize(),
+		}
+	}
+
+	/// Ray cast straight out from the middle of the screen, i.e. where the crosshair is.
+	pub fn center_ray(&self) -> CameraRay {
+		let viewport = (1000, (1000.0 / self.perspective.aspect_ratio) as u32);
+		self.screen_to_ray(Vec2::new(viewport.0 as f32 * 0.5, viewport.1 as f32 * 0.5), viewport)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn screen_center_round_trips_to_forward_ray() {
+		let viewport = (1280, 720);
+		let mut camera = Camera::new(Vec3::new(3.0, 12.0, -5.0), 1280.0 / 720.0);
+		camera.mouse_interact(37.0, -20.0);
+
+		let center = Vec2::new(640.0, 360.0);
+		let ray = camera.screen_to_ray(center, viewport);
+		assert_eq!(ray.origin, *camera.get_position());
+		assert!(ray.direction.abs_diff_eq(*camera.get_front(), 0.0001));
+
+		let ahead = *camera.get_position() + (*camera.get_front() * 10.0);
+		let projected = camera.world_to_screen(ahead, viewport).unwrap();
+		assert!(projected.abs_diff_eq(center, 0.01));
+
+		// Off to one side too, not just dead center.
+		let corner = Vec2::new(100.0, 50.0);
+		let corner_ray = camera.screen_to_ray(corner, viewport);
+		let along_ray = corner_ray.origin + (corner_ray.direction * 10.0);
+		assert!(camera.world_to_screen(along_ray, viewport).unwrap().abs_diff_eq(corner, 0.05));
+
+		let behind = *camera.get_position() - (*camera.get_front() * 10.0);
+		assert!(camera.world_to_screen(behind, viewport).is_none());
+	}
+
+	#[test]
+	fn roll_turns_up_vector() {
+		let mut camera = Camera::new(Vec3::new(0.0, 5.0, 0.0), 16.0 / 9.0);
+		let unrolled_view = camera.get_view_matrix();
+		let unrolled_right = *camera.get_right();
+		assert!(camera.get_up().abs_diff_eq(Vec3::Y, 0.0001));
+
+		camera.set_roll(DegreeAngle(90.0));
+		// Rolled a quarter turn to the right, "up" is where "right" used to be.
+		assert!(camera.get_up().abs_diff_eq(unrolled_right, 0.0001));
+		assert!(camera.get_right().abs_diff_eq(-Vec3::Y, 0.0001));
+		assert!(camera.get_front().abs_diff_eq(Vec3::NEG_Z, 0.0001));
+		// And the view matrix agrees: the camera's up is straight up on screen.
+		let view = camera.get_view_matrix();
+		assert!(view.transform_vector3(*camera.get_up()).abs_diff_eq(Vec3::Y, 0.0001));
+
+		camera.add_roll(DegreeAngle(-90.0));
+		assert_eq!(camera.get_roll(), DegreeAngle(0.0));
+		assert!(camera.get_view_matrix().abs_diff_eq(unrolled_view, 0.0001));
+	}
+
+	#[test]
+	fn remapped_keys_move_the_camera() {
+		let defaults = KeyBindings::default();
+		assert_eq!(defaults.direction_for(VirtualKeyCode::W), Some(Directions::Forward));
+		assert_eq!(defaults.direction_for(VirtualKeyCode::Up), None);
+
+		let file = r#"(
+			movement: {
+				Up: Forward,
+				Down: Backward,
+			},
+		)"#;
+		let bindings: KeyBindings = ron::from_str(file).unwrap();
+		assert_eq!(bindings.direction_for(VirtualKeyCode::Up), Some(Directions::Forward));
+		assert_eq!(bindings.direction_for(VirtualKeyCode::Down), Some(Directions::Backward));
+		// Rebinding replaces the whole layout rather than adding to it.
+		assert_eq!(bindings.direction_for(VirtualKeyCode::W), None);
+		// Leaving the section out entirely gets you the defaults.
+		assert_eq!(ron::from_str::<KeyBindings>("()").unwrap(), defaults);
+
+		// Held keys go through the bindings to actually move the camera.
+		let mut camera = Camera::new(Vec3::ZERO, 16.0 / 9.0);
+		let held = [VirtualKeyCode::Up, VirtualKeyCode::W].into_iter().filter_map(|key| bindings.direction_for(key));
+		camera.update_movement(held, Duration::from_secs(1));
+		assert!(camera.get_position().abs_diff_eq(*camera.get_front() * camera.speed, 0.0001));
+
+		let written = ron::to_string(&bindings).unwrap();
+		assert_eq!(ron::from_str::<KeyBindings>(&written).unwrap(), bindings);
+	}
+
+	#[test]
+	fn smoothed_movement_ramps_up_and_coasts() {
+		let mut camera = Camera::new(Vec3::ZERO, 16.0 / 9.0);
+		camera.smoothing = true;
+		let frame = Duration::from_millis(16);
+
+		let mut last_speed = 0.0;
+		for _ in 0..5 {
+			camera.update_movement([Directions::Forward], frame);
+			let speed = camera.get_velocity().length();
+			assert!(speed > last_speed);
+			assert!(speed <= camera.speed);
+			last_speed = speed;
+		}
+		assert!(camera.get_velocity().normalize().abs_diff_eq(*camera.get_front(), 0.0001));
+
+		// Let go - it keeps drifting forward, slower every frame.
+		for _ in 0..5 {
+			let before = *camera.get_position();
+			camera.update_movement([], frame);
+			let speed = camera.get_velocity().length();
+			assert!(speed < last_speed);
+			assert!((*camera.get_position() - before).dot(*camera.get_front()) > 0.0);
+			last_speed = speed;
+		}
+		for _ in 0..120 {
+			camera.update_movement([], frame);
+		}
+		assert!(camera.get_velocity().length() < 0.001);
+
+		// Without smoothing, there's no velocity to speak of.
+		camera.smoothing = false;
+		let before = *camera.get_position();
+		camera.update_movement([Directions::Forward], Duration::from_secs(1));
+		assert!((*camera.get_position() - before).abs_diff_eq(*camera.get_front() * camera.speed, 0.0001));
+		assert_eq!(camera.get_velocity(), Vec3::ZERO);
+	}
+
+	#[test]
+	fn shake_fades_back_to_rest() {
+		let mut camera = Camera::new(Vec3::new(1.0, 2.0, 3.0), 16.0 / 9.0);
+		let resting_view = camera.get_view_matrix();
+		let frame = Duration::from_millis(16);
+		camera.add_shake(ShakeSpec {
+			amplitude: 0.5,
+			frequency: 12.0,
+			decay: 3.0,
+			duration: Duration::from_millis(500),
+		});
+		camera.add_shake(ShakeSpec {
+			amplitude: 0.2,
+			frequency: 5.0,
+			decay: 0.0,
+			duration: Duration::from_millis(800),
+		});
+
+		camera.update_shake(frame * 2);
+		assert!(camera.is_shaking());
+		assert!(camera.get_shake_offset().length() > 0.0);
+		assert!(camera.get_shake_offset().length() <= (0.5 + 0.2) * 1.5);
+		// Shaking moves the view, but never the logical camera.
+		assert_eq!(*camera.get_position(), Vec3::new(1.0, 2.0, 3.0));
+		assert_ne!(camera.get_view_matrix(), resting_view);
+
+		// The first shake ends, the second keeps going on its own.
+		camera.update_shake(Duration::from_millis(500));
+		assert!(camera.is_shaking());
+
+		camera.update_shake(Duration::from_millis(300));
+		assert!(!camera.is_shaking());
+		assert_eq!(camera.get_shake_offset(), Vec3::ZERO);
+		assert_eq!(camera.get_view_matrix(), resting_view);
+	}
+}
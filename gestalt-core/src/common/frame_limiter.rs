@@ -0,0 +1,87 @@
+//! Optional frame rate cap, for players who would rather save power (or fan noise) than draw frames
+//! their monitor can't show anyway.
+
+use std::time::{Duration, Instant};
+
+/// Paces frames to at most max_fps per second by sleeping out whatever is left of each frame's budget.
+/// Frame deadlines are spaced evenly from one another rather than from whenever the last frame happened
+/// to end, so oversleeping by a little on one frame gets made up on the next rather than accumulating.
+pub struct FrameLimiter {
+	max_fps: u32,
+	next_deadline: Option<Instant>,
+}
+
+impl FrameLimiter {
+	/// A max_fps of 0 means no cap.
+	pub fn new(max_fps: u32) -> Self {
+		Self {
+			max_fps,
+			next_deadline: None,
+		}
+	}
+
+	pub fn set_max_fps(&mut self, max_fps: u32) {
+		if max_fps != self.max_fps {
+			self.max_fps = max_fps;
+			self.next_deadline = None;
+		}
+	}
+
+	pub fn get_max_fps(&self) -> u32 {
+		self.max_fps
+	}
+
+	fn frame_budget(&self) -> Option<Duration> {
+		(self.max_fps > 0).then(|| Duration::from_secs(1) / self.max_fps)
+	}
+
+	/// Works out how long to wait, as of now, before starting the next frame - and moves on to that frame.
+	pub fn next_wait(&mut self, now: Instant) -> Duration {
+		let Some(budget) = self.frame_budget() else {
+			return Duration::ZERO;
+		};
+		let deadline = match self.next_deadline {
+			// If we've fallen more than a whole frame behind (a hitch, or the window was being dragged around),
+			// start counting again from now rather than rushing out a burst of frames to catch up.
+			Some(deadline) if deadline + budget >= now => deadline,
+			_ => now,
+		};
+		self.next_deadline = Some(deadline + budget);
+		deadline.saturating_duration_since(now)
+	}
+
+	/// Call at the end of each frame. Returns immediately if there is no cap.
+	pub fn wait_for_next_frame(&mut self) {
+		let wait = self.next_wait(Instant::now());
+		if !wait.is_zero() {
+			std::thread::sleep(wait);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn frame_limiter_paces_without_drift() {
+		let mut limiter = FrameLimiter::new(0);
+		let start = Instant::now();
+		assert_eq!(limiter.next_wait(start), Duration::ZERO);
+
+		limiter.set_max_fps(50);
+		let budget = Duration::from_millis(20);
+		// First frame goes right away.
+		assert_eq!(limiter.next_wait(start), Duration::ZERO);
+		// A frame which took 5ms waits out the other 15.
+		assert_eq!(limiter.next_wait(start + Duration::from_millis(5)), Duration::from_millis(15));
+		// If that sleep ran 3ms long and the next frame took 5ms, the wait after it is 3ms shorter to make up for it.
+		assert_eq!(limiter.next_wait(start + budget + Duration::from_millis(8)), Duration::from_millis(12));
+		assert_eq!(limiter.next_wait(start + (budget * 3)), Duration::ZERO);
+
+		// After a long hitch, pacing starts over from there instead of trying to catch up.
+		let later = start + Duration::from_secs(2);
+		assert_eq!(limiter.next_wait(later), Duration::ZERO);
+		assert_eq!(limiter.next_wait(later + Duration::from_millis(1)), Duration::from_millis(19));
+	}
+}
@@ -0,0 +1,150 @@
+//! View frustum planes, for skipping anything the camera can't possibly see.
+
+use glam::{Mat4, Quat, Vec3, Vec4};
+
+use crate::world::chunk::CHUNK_SIZE;
+use crate::world::tilespace::chunk_to_world_pos;
+use crate::world::ChunkPos;
+
+/// The six planes bounding what a camera can see. Each plane is stored as (normal.xyz, distance),
+/// with the normal pointing into the frustum, so a point is on the visible side when normal.dot(point) + distance >= 0.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrustumPlanes {
+	pub left: Vec4,
+	pub right: Vec4,
+	pub bottom: Vec4,
+	pub top: Vec4,
+	pub near: Vec4,
+	pub far: Vec4,
+}
+
+impl FrustumPlanes {
+	/// Extract the planes from a combined view-projection matrix (Gribb & Hartmann, "Fast Extraction of Viewing
+	/// Frustum Planes from the World-View-Projection Matrix"). Expects wgpu's clip space, where depth goes 0 to 1,
+	/// which is what glam's perspective_rh() (and so Camera::build_view_projection_matrix()) already gives you.
+	pub fn from_view_projection(matrix: Mat4) -> Self {
+		let row_x = matrix.row(0);
+		let row_y = matrix.row(1);
+		let row_z = matrix.row(2);
+		let row_w = matrix.row(3);
+		Self {
+			left: normalize_plane(row_w + row_x),
+			right: normalize_plane(row_w - row_x),
+			bottom: normalize_plane(row_w + row_y),
+			top: normalize_plane(row_w - row_y),
+			near: normalize_plane(row_z),
+			far: normalize_plane(row_w - row_z),
+		}
+	}
+
+	pub fn planes(&self) -> [Vec4; 6] {
+		[self.left, self.right, self.bottom, self.top, self.near, self.far]
+	}
+
+	pub fn contains_point(&self, point: Vec3) -> bool {
+		self.planes().iter().all(|plane| plane.truncate().dot(point) + plane.w >= 0.0)
+	}
+}
+
+fn normalize_plane(plane: Vec4) -> Vec4 {
+	plane / plane.truncate().length()
+}
+
+/// Does the axis-aligned box from min to max overlap the frustum at all? Conservative - a box near a corner of
+/// the frustum can pass without actually being visible, but a box which fails is definitely out of view.
+pub fn aabb_frustum_intersection(frustum: &FrustumPlanes, min: Vec3, max: Vec3) -> bool {
+	frustum.planes().iter().all(|plane| {
+		let normal = plane.truncate();
+		// The corner of the box furthest along the plane's normal. If even that one is outside, they all are.
+		let furthest = Vec3::select(normal.cmpge(Vec3::ZERO), max, min);
+		normal.dot(furthest) + plane.w >= 0.0
+	})
+}
+
+/// World-space bounds of the chunk at chunk_pos, once the whole terrain has been scaled, rotated and moved by the given transform.
+fn chunk_world_aabb(chunk_pos: &ChunkPos, scale: Vec3, translation: Vec3, rotation: Quat) -> (Vec3, Vec3) {
+	let pos_int = chunk_to_world_pos(chunk_pos);
+	let chunk_origin = Vec3::new(pos_int.x as f32, pos_int.y as f32, pos_int.z as f32);
+	let model_matrix = Mat4::from_scale_rotation_translation(scale, rotation, chunk_origin + translation);
+	let size = CHUNK_SIZE as f32;
+	let mut min = Vec3::splat(f32::MAX);
+	let mut max = Vec3::splat(f32::MIN);
+	for corner in 0..8 {
+		let local = Vec3::new(
+			(corner & 1) as f32 * size,
+			((corner >> 1) & 1) as f32 * size,
+			((corner >> 2) & 1) as f32 * size,
+		);
+		let world = model_matrix.transform_point3(local);
+		min = min.min(world);
+		max = max.max(world);
+	}
+	(min, max)
+}
+
+/// Could any of the chunk at chunk_pos be in view?
+pub fn is_chunk_visible(frustum: &FrustumPlanes, chunk_pos: &ChunkPos, scale: Vec3, translation: Vec3, rotation: Quat) -> bool {
+	let (min, max) = chunk_world_aabb(chunk_pos, scale, translation, rotation);
+	aabb_frustum_intersection(frustum, min, max)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::common::camera::Camera;
+
+	/// Camera at the origin looking down -Z, 90 degree fov both ways, clipping from 1 to 100.
+	/// At depth d, the visible area runs from -d to d along x and y.
+	fn test_frustum() -> FrustumPlanes {
+		let projection = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 100.0);
+		FrustumPlanes::from_view_projection(projection)
+	}
+
+	#[test]
+	fn points_either_side_of_each_plane() {
+		let frustum = test_frustum();
+		for plane in frustum.planes() {
+			assert!((plane.truncate().length() - 1.0).abs() < 0.0001);
+		}
+		assert!(frustum.contains_point(Vec3::new(0.0, 0.0, -10.0)));
+
+		let cases = [
+			// (just inside, just outside)
+			(Vec3::new(-9.9, 0.0, -10.0), Vec3::new(-10.1, 0.0, -10.0)), // Left
+			(Vec3::new(9.9, 0.0, -10.0), Vec3::new(10.1, 0.0, -10.0)),   // Right
+			(Vec3::new(0.0, -9.9, -10.0), Vec3::new(0.0, -10.1, -10.0)), // Bottom
+			(Vec3::new(0.0, 9.9, -10.0), Vec3::new(0.0, 10.1, -10.0)),   // Top
+			(Vec3::new(0.0, 0.0, -1.01), Vec3::new(0.0, 0.0, -0.99)),    // Near
+			(Vec3::new(0.0, 0.0, -99.9), Vec3::new(0.0, 0.0, -100.1)),   // Far
+		];
+		for (inside, outside) in cases {
+			assert!(frustum.contains_point(inside), "{inside} should be inside");
+			assert!(!frustum.contains_point(outside), "{outside} should be outside");
+			let tiny = Vec3::splat(0.001);
+			assert!(aabb_frustum_intersection(&frustum, inside - tiny, inside + tiny));
+			assert!(!aabb_frustum_intersection(&frustum, outside - tiny, outside + tiny));
+			// A box stretching from one to the other crosses the plane, so it's partly visible.
+			assert!(aabb_frustum_intersection(&frustum, inside.min(outside), inside.max(outside)));
+		}
+		assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, 10.0)));
+	}
+
+	#[test]
+	fn chunks_behind_camera_are_culled() {
+		// Default orientation looks down -Z.
+		let camera = Camera::new(Vec3::new(16.0, 16.0, 0.5), 16.0 / 9.0);
+		let frustum = camera.frustum_planes();
+		let in_front: ChunkPos = vpos!(0, 0, -2);
+		let behind: ChunkPos = vpos!(0, 0, 2);
+		let high_above: ChunkPos = vpos!(0, 20, -2);
+
+		assert!(is_chunk_visible(&frustum, &in_front, Vec3::ONE, Vec3::ZERO, Quat::IDENTITY));
+		assert!(!is_chunk_visible(&frustum, &behind, Vec3::ONE, Vec3::ZERO, Quat::IDENTITY));
+		assert!(!is_chunk_visible(&frustum, &high_above, Vec3::ONE, Vec3::ZERO, Quat::IDENTITY));
+		// The chunk the camera is sitting inside is always drawn.
+		assert!(is_chunk_visible(&frustum, &vpos!(0, 0, 0), Vec3::ONE, Vec3::ZERO, Quat::IDENTITY));
+
+		// Moving the whole terrain past the camera brings the chunk behind into view.
+		assert!(is_chunk_visible(&frustum, &behind, Vec3::ONE, Vec3::new(0.0, 0.0, -128.0), Quat::IDENTITY));
+	}
+}
@@ -2,17 +2,27 @@
 pub struct GrowableBuf {
 	inner: Vec<u8>,
 	maximum: usize,
+	overflowed: bool,
 }
 impl GrowableBuf {
 	pub fn new(underlying_buffer: Vec<u8>, maximum: usize) -> Self {
 		Self {
 			inner: underlying_buffer,
 			maximum,
+			overflowed: false,
 		}
 	}
 	pub fn into_inner(self) -> Vec<u8> {
 		self.inner
 	}
+	pub fn get_maximum(&self) -> usize {
+		self.maximum
+	}
+	/// Has a write ever been refused for going over the maximum size?
+	/// Lets callers tell that apart from other failures, whatever those got wrapped in on the way out.
+	pub fn overflowed(&self) -> bool {
+		self.overflowed
+	}
 }
 
 impl std::io::Write for GrowableBuf {
@@ -20,6 +30,7 @@ impl std::io::Write for GrowableBuf {
 		let current_len = self.inner.len();
 		let buf_len = buf.len();
 		if current_len + buf_len > self.maximum {
+			self.overflowed = true;
 			Err( std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Ran out of space in a growable buffer - max size is {} and we tried to add {} bytes to a buffer which contains {}", self.maximum, buf_len, current_len)))
 		} else {
 			self.inner.extend_from_slice(buf);
@@ -32,3 +43,20 @@ impl std::io::Write for GrowableBuf {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use std::io::Write;
+
+	use super::*;
+
+	#[test]
+	fn growable_buf_flags_overflow() {
+		let mut buf = GrowableBuf::new(vec![0u8; 2], 4);
+		buf.write_all(&[1, 2]).unwrap();
+		assert!(!buf.overflowed());
+		assert!(buf.write_all(&[3]).is_err());
+		assert!(buf.overflowed());
+		assert_eq!(buf.into_inner(), vec![0, 0, 1, 2]);
+	}
+}
@@ -7,7 +7,7 @@ use argon2::Argon2;
 use base64::engine::general_purpose::URL_SAFE as BASE_64;
 use base64::Engine;
 use rand::Rng;
-use rand_core::CryptoRngCore;
+use rand_core::{CryptoRngCore, RngCore};
 use serde_with_macros::serde_as;
 use signature::{Signer, Verifier};
 
@@ -69,7 +69,8 @@ impl PublicKey {
 		message: &[u8],
 		signature: &[u8; 64],
 	) -> Result<(), SignatureError> {
-		let converted_key: ed25519_dalek::VerifyingKey = self.into();
+		// Keys can come from peers, so a key which isn't a valid curve point is a failed check rather than a panic.
+		let converted_key = ed25519_dalek::VerifyingKey::from_bytes(&self.0)?;
 		let converted_signature = ed25519_dalek::Signature::from_bytes(signature);
 		converted_key.verify(message, &converted_signature)
 	}
@@ -139,13 +140,26 @@ pub struct IdentityKeyPair {
 	pub private: PrivateKey,
 }
 
+/// Test-only key generation. These are compiled out of non-test builds, and must never be
+/// used to make a real identity - the seeded variant in particular is trivially guessable.
 impl IdentityKeyPair {
+	/// Generates a fresh random key pair.
 	#[cfg(test)]
 	pub fn generate_for_tests() -> Self {
 		let mut rng = rand_core::OsRng::default();
 		let keys_dalek = ed25519_dalek::SigningKey::generate(&mut rng);
 		(&keys_dalek).into()
 	}
+
+	/// Generates the same key pair every time for the same seed, so tests can rely on fixed identities.
+	#[cfg(test)]
+	pub fn generate_for_tests_seeded(seed: u64) -> Self {
+		use sha2::Digest;
+		// Hash the seed rather than using a seeded RNG, so the keys don't change if rand's algorithms do.
+		let secret: [u8; PRIVATE_KEY_LENGTH] = sha2::Sha512_256::digest(seed.to_le_bytes()).into();
+		let keys_dalek = ed25519_dalek::SigningKey::from_bytes(&secret);
+		(&keys_dalek).into()
+	}
 }
 
 impl From<&IdentityKeyPair> for ed25519_dalek::SigningKey {
@@ -275,6 +289,12 @@ pub enum KeyPairLoadError {
 	#[error("Hasher produced no output")]
 	NoHashOut,
 }
+impl KeyPairLoadError {
+	/// Did this fail because the user gave us the wrong passphrase (or none), rather than because the key file is broken?
+	pub fn is_wrong_passphrase(&self) -> bool {
+		matches!(self, KeyPairLoadError::FailedDecryption(_) | KeyPairLoadError::NoPassphrase)
+	}
+}
 impl From<aes_gcm::Error> for KeyPairLoadError {
 	fn from(value: aes_gcm::Error) -> Self {
 		KeyPairLoadError::FailedDecryption(value)
@@ -354,22 +374,191 @@ impl VersionedKeyFile {
 	}
 }
 
+/// Passphrases shorter than this are refused when generating keys. A blank passphrase is still allowed, and means "no passphrase".
+pub const MIN_PASSPHRASE_LEN: usize = 4;
+
+/// Rough guess at how hard a passphrase would be to brute-force, used to warn users about weak ones.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PassphraseStrength {
+	/// Shorter than MIN_PASSPHRASE_LEN.
+	TooShort,
+	Weak,
+	Fair,
+	Strong,
+}
+
+impl PassphraseStrength {
+	/// Estimated entropy below this is Weak.
+	pub const FAIR_BITS: f64 = 40.0;
+	/// Estimated entropy at or above this is Strong.
+	pub const STRONG_BITS: f64 = 70.0;
+
+	/// Estimates entropy as length * log2(size of the character classes used). This overestimates
+	/// dictionary words and repeated characters, so treat it as an upper bound.
+	pub fn entropy_bits(passphrase: &str) -> f64 {
+		let mut pool_size: u32 = 0;
+		if passphrase.chars().any(|c| c.is_ascii_lowercase()) {
+			pool_size += 26;
+		}
+		if passphrase.chars().any(|c| c.is_ascii_uppercase()) {
+			pool_size += 26;
+		}
+		if passphrase.chars().any(|c| c.is_ascii_digit()) {
+			pool_size += 10;
+		}
+		if passphrase.chars().any(|c| c.is_ascii_punctuation() || c == ' ') {
+			pool_size += 33;
+		}
+		if passphrase.chars().any(|c| !c.is_ascii()) {
+			pool_size += 100;
+		}
+		if pool_size == 0 {
+			return 0.0;
+		}
+		passphrase.chars().count() as f64 * (pool_size as f64).log2()
+	}
+
+	pub fn estimate(passphrase: &str) -> Self {
+		if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+			return PassphraseStrength::TooShort;
+		}
+		let bits = Self::entropy_bits(passphrase);
+		if bits < Self::FAIR_BITS {
+			PassphraseStrength::Weak
+		} else if bits < Self::STRONG_BITS {
+			PassphraseStrength::Fair
+		} else {
+			PassphraseStrength::Strong
+		}
+	}
+}
+
+/// Problems finding, reading, or generating the local identity, worded so they can be shown to the user directly.
+#[derive(thiserror::Error, Debug)]
+pub enum IdentityError {
+	#[error("The cryptography backend failed to initialize ({0}). Your platform may be unsupported.")]
+	CryptoBackend(rand_core::Error),
+	#[error("Could not access identity key file {0}: {1}. Check that Gestalt has permission to read and write it.")]
+	Io(PathBuf, std::io::Error),
+	#[error("Identity key file {0} could not be parsed and may be corrupted: {1}")]
+	Parse(PathBuf, toml::de::Error),
+	#[error("Identity key file {0} is a directory. Move or delete it so a key file can be created there.")]
+	KeyPathIsDirectory(PathBuf),
+	#[error("Environment variable {0} could not be used: {1}")]
+	EnvVarInvalid(&'static str, String),
+	#[error("Could not read the identity key given in {IDENTITY_KEY_ENV_VAR}: {0}")]
+	EnvKey(KeyPairLoadError),
+}
+
+/// If set, this node's identity key is taken from here instead of from a key file, e.g. for servers
+/// running in containers. Holds either the entire contents of a key file (which may be passphrase-encrypted)
+/// or just the private key in URL-safe base 64.
+///
+/// Keys from the environment are never written to disk. That also makes them easier to leak than a key file,
+/// though: any process running as the same user can read another's environment (/proc/PID/environ on Linux),
+/// child processes inherit it, and container runtimes and orchestrators will happily show it to anyone who can
+/// inspect the container. Prefer a secrets mechanism which injects the variable only at launch, and prefer an
+/// encrypted key file's contents plus IDENTITY_PASSPHRASE_ENV_VAR over a bare private key where that's available.
+pub const IDENTITY_KEY_ENV_VAR: &str = "GESTALT_IDENTITY_KEY";
+/// Passphrase for an encrypted key, whether that came from IDENTITY_KEY_ENV_VAR or the key file -
+/// so that encrypted keys can be loaded without anyone typing at a prompt. Carries all the same risks.
+pub const IDENTITY_PASSPHRASE_ENV_VAR: &str = "GESTALT_IDENTITY_PASSPHRASE";
+
+/// Reads one of the identity environment variables. Unset (or blank) is Ok(None).
+pub fn read_identity_env_var(name: &'static str) -> Result<Option<String>, IdentityError> {
+	match std::env::var(name) {
+		Ok(value) if value.trim().is_empty() => Ok(None),
+		Ok(value) => Ok(Some(value)),
+		Err(std::env::VarError::NotPresent) => Ok(None),
+		Err(std::env::VarError::NotUnicode(_)) => {
+			Err(IdentityError::EnvVarInvalid(name, String::from("not valid unicode")))
+		}
+	}
+}
+
+/// Parses an identity key in either of the forms IDENTITY_KEY_ENV_VAR accepts.
+pub fn identity_from_key_string(
+	key: &str,
+	passphrase: Option<&str>,
+) -> Result<IdentityKeyPair, IdentityError> {
+	let key = key.trim();
+	if let Ok(key_file) = toml::from_str::<VersionedKeyFile>(key) {
+		return match passphrase {
+			Some(passphrase) => try_read_with_env_passphrase(&key_file, passphrase),
+			None => key_file.try_read(None),
+		}
+		.map_err(IdentityError::EnvKey);
+	}
+	let bytes = BASE_64.decode(key).map_err(|e| {
+		IdentityError::EnvVarInvalid(
+			IDENTITY_KEY_ENV_VAR,
+			format!("not a key file, and not a base 64 private key either ({e})"),
+		)
+	})?;
+	if bytes.len() != PRIVATE_KEY_LENGTH {
+		return Err(IdentityError::EnvKey(KeyPairLoadError::WrongLengthPrivate(bytes.len())));
+	}
+	let mut secret = [0u8; PRIVATE_KEY_LENGTH];
+	secret.copy_from_slice(&bytes);
+	let keys_dalek = ed25519_dalek::SigningKey::from_bytes(&secret);
+	Ok((&keys_dalek).into())
+}
+
+/// Decrypts a key file with a passphrase which came from IDENTITY_PASSPHRASE_ENV_VAR. Key files made at the
+/// interactive prompt have the newline from pressing enter as part of their passphrase, so this also tries
+/// the passphrase with each kind of line ending on the end.
+pub fn try_read_with_env_passphrase(
+	key_file: &VersionedKeyFile,
+	passphrase: &str,
+) -> Result<IdentityKeyPair, KeyPairLoadError> {
+	let mut result = Err(KeyPairLoadError::NoPassphrase);
+	for ending in ["", "\n", "\r\n"] {
+		result = key_file.clone().try_read(Some(&format!("{passphrase}{ending}")));
+		match &result {
+			Err(e) if e.is_wrong_passphrase() => continue,
+			_ => break,
+		}
+	}
+	result
+}
+
+/// Loads our identity from IDENTITY_KEY_ENV_VAR (and IDENTITY_PASSPHRASE_ENV_VAR, if the key is encrypted).
+/// Returns Ok(None) if IDENTITY_KEY_ENV_VAR isn't set, meaning the key file should be used instead.
+pub fn load_identity_from_env() -> Result<Option<IdentityKeyPair>, IdentityError> {
+	let Some(key) = read_identity_env_var(IDENTITY_KEY_ENV_VAR)? else {
+		return Ok(None);
+	};
+	let passphrase = read_identity_env_var(IDENTITY_PASSPHRASE_ENV_VAR)?;
+	identity_from_key_string(&key, passphrase.as_deref()).map(Some)
+}
+
+/// Make sure the operating system can give us secure random numbers before we try to make or use any keys.
+pub fn check_crypto_backend() -> Result<(), IdentityError> {
+	let mut buf = [0u8; 32];
+	rand_core::OsRng::default()
+		.try_fill_bytes(&mut buf)
+		.map_err(IdentityError::CryptoBackend)
+}
+
 /// Returns false if key files exist already and true if they don't exist yet and need to be made.
-pub fn do_keys_need_generating(keys_directory: PathBuf, expected_filename: &str) -> bool {
+pub fn do_keys_need_generating(
+	keys_directory: PathBuf,
+	expected_filename: &str,
+) -> Result<bool, IdentityError> {
 	if !keys_directory.exists() {
-		fs::create_dir_all(keys_directory).unwrap();
-		return true;
+		fs::create_dir_all(&keys_directory).map_err(|e| IdentityError::Io(keys_directory, e))?;
+		return Ok(true);
 	}
 	let key_path = keys_directory.join(PathBuf::from(expected_filename));
 
 	if !key_path.exists() {
-		return true;
+		return Ok(true);
 	} else if key_path.is_dir() {
-		panic!("Public key file {} cannot be a directory!", key_path.display());
+		return Err(IdentityError::KeyPathIsDirectory(key_path));
 	}
 
 	// All sanity checks passed, keys exist so we don't need to generate them.
-	false
+	Ok(false)
 }
 
 pub fn generate_local_keys(
@@ -415,7 +604,7 @@ pub fn gen_and_save_keys(
 	// Set up paths
 	let keys_directory = PathBuf::from(keys_directory);
 	if !keys_directory.exists() {
-		fs::create_dir_all(&keys_directory).unwrap();
+		fs::create_dir_all(&keys_directory)?;
 	}
 	let key_path = keys_directory.join(PathBuf::from(keys_filename));
 
@@ -444,12 +633,81 @@ pub fn gen_and_save_keys(
 pub fn load_keyfile(
 	keys_directory: PathBuf,
 	keys_filename: &str,
-) -> Result<VersionedKeyFile, Box<dyn std::error::Error>> {
+) -> Result<VersionedKeyFile, IdentityError> {
 	let keys_directory = PathBuf::from(keys_directory);
 	let key_path = keys_directory.join(PathBuf::from(keys_filename));
 
-	let keyfile_string = fs::read_to_string(key_path)?;
+	let keyfile_string =
+		fs::read_to_string(&key_path).map_err(|e| IdentityError::Io(key_path.clone(), e))?;
 
-	let keyfile: VersionedKeyFile = toml::from_str(&keyfile_string)?;
+	let keyfile: VersionedKeyFile =
+		toml::from_str(&keyfile_string).map_err(|e| IdentityError::Parse(key_path, e))?;
 	Ok(keyfile)
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn passphrase_strength() {
+		assert_eq!(PassphraseStrength::estimate(""), PassphraseStrength::TooShort);
+		assert_eq!(PassphraseStrength::estimate("abc"), PassphraseStrength::TooShort);
+		assert_eq!(PassphraseStrength::estimate("abcd"), PassphraseStrength::Weak);
+		assert_eq!(PassphraseStrength::estimate("password"), PassphraseStrength::Weak);
+		assert_eq!(PassphraseStrength::estimate("hunter2hunter"), PassphraseStrength::Fair);
+		assert_eq!(
+			PassphraseStrength::estimate("correct horse battery staple"),
+			PassphraseStrength::Strong
+		);
+		// Length is counted in characters, not bytes.
+		assert_eq!(PassphraseStrength::estimate("ééé"), PassphraseStrength::TooShort);
+	}
+
+	#[test]
+	fn identity_from_env_strings() {
+		let (keys, key_file) = generate_local_keys(Some("hunter2hunter")).unwrap();
+		let key_file_string = toml::to_string_pretty(&key_file).unwrap();
+		assert_eq!(identity_from_key_string(&key_file_string, Some("hunter2hunter")).unwrap(), keys);
+		match identity_from_key_string(&key_file_string, Some("hunter3hunter")) {
+			Err(IdentityError::EnvKey(e)) => assert!(e.is_wrong_passphrase()),
+			other => panic!("Expected a wrong passphrase error, got {other:?}"),
+		}
+		assert!(matches!(
+			identity_from_key_string(&key_file_string, None),
+			Err(IdentityError::EnvKey(KeyPairLoadError::NoPassphrase))
+		));
+
+		// As if the passphrase had been typed at the prompt.
+		let (prompt_keys, prompt_key_file) = generate_local_keys(Some("hunter2hunter\n")).unwrap();
+		let prompt_key_file_string = toml::to_string_pretty(&prompt_key_file).unwrap();
+		assert_eq!(identity_from_key_string(&prompt_key_file_string, Some("hunter2hunter")).unwrap(), prompt_keys);
+
+		let bare_key = BASE_64.encode(keys.private.get_bytes());
+		assert_eq!(identity_from_key_string(&format!("{bare_key}\n"), None).unwrap(), keys);
+
+		assert!(matches!(
+			identity_from_key_string("this is not a key", None),
+			Err(IdentityError::EnvVarInvalid(IDENTITY_KEY_ENV_VAR, _))
+		));
+		assert!(matches!(
+			identity_from_key_string(&BASE_64.encode([0u8; 16]), None),
+			Err(IdentityError::EnvKey(KeyPairLoadError::WrongLengthPrivate(16)))
+		));
+	}
+
+	#[test]
+	fn seeded_test_identity() {
+		let first = IdentityKeyPair::generate_for_tests_seeded(1234);
+		let again = IdentityKeyPair::generate_for_tests_seeded(1234);
+		let other = IdentityKeyPair::generate_for_tests_seeded(1235);
+		assert_eq!(first, again);
+		assert_eq!(first.public.to_base64(), again.public.to_base64());
+		assert_ne!(first.public, other.public);
+
+		// Should still be a usable key pair.
+		let message = b"Hello!";
+		let signature = first.sign(message).unwrap();
+		assert!(first.public.verify_signature(message, &signature.to_bytes()).is_ok());
+	}
+}
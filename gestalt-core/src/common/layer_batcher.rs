@@ -0,0 +1,104 @@
+use std::hash::Hash;
+
+use super::{new_fast_hash_map, FastHashMap};
+
+struct Batch<K> {
+	size: (u32, u32),
+	layers: FastHashMap<K, u32>,
+	next_layer: u32,
+}
+
+/// Decides which batch, and which layer of it, each image goes into. Images of the same size share a batch until
+/// it's full, so drawing lots of them doesn't need a bind group switch per image. The first reserved_layers layers
+/// of every batch are left alone for builtins (e.g. the missing / pending textures), so new images start after those.
+/// This only plans where things go - whatever owns the actual array textures does the uploading.
+pub struct LayerBatcher<K> {
+	batches: Vec<Batch<K>>,
+	/// Layers per batch, including the reserved ones.
+	max_layers: u32,
+	reserved_layers: u32,
+}
+
+impl<K: Hash + Eq + Clone> LayerBatcher<K> {
+	/// max_layers gets bumped up to reserved_layers + 1 if it's lower, so every batch has room for at least one image.
+	pub fn new(max_layers: u32, reserved_layers: u32) -> Self {
+		Self {
+			batches: Vec::new(),
+			max_layers: max_layers.max(reserved_layers + 1),
+			reserved_layers,
+		}
+	}
+	/// Returns (batch, layer) for this image, picking a spot for it if it doesn't have one yet.
+	pub fn place(&mut self, key: &K, size: (u32, u32)) -> (u32, u32) {
+		for (batch, existing) in self.batches.iter().enumerate() {
+			if existing.size == size {
+				if let Some(layer) = existing.layers.get(key) {
+					return (batch as u32, *layer);
+				}
+			}
+		}
+		let max_layers = self.max_layers;
+		let batch = match self.batches.iter().position(|batch| batch.size == size && batch.next_layer < max_layers) {
+			Some(batch) => batch,
+			None => {
+				self.batches.push(Batch {
+					size,
+					layers: new_fast_hash_map(),
+					next_layer: self.reserved_layers,
+				});
+				self.batches.len() - 1
+			}
+		};
+		let chosen = &mut self.batches[batch];
+		let layer = chosen.next_layer;
+		chosen.next_layer += 1;
+		chosen.layers.insert(key.clone(), layer);
+		(batch as u32, layer)
+	}
+	/// Pixel width and height of every image in this batch.
+	pub fn get_batch_size(&self, batch: u32) -> Option<(u32, u32)> {
+		self.batches.get(batch as usize).map(|batch| batch.size)
+	}
+	/// How many layers of this batch are taken, counting the reserved ones.
+	pub fn get_layer_count(&self, batch: u32) -> Option<u32> {
+		self.batches.get(batch as usize).map(|batch| batch.next_layer)
+	}
+	pub fn get_max_layers(&self) -> u32 {
+		self.max_layers
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn same_size_tiles_share_an_array_texture() {
+		// Two reserved layers, like the missing / pending textures in an ArrayTextureLayout.
+		let mut batcher: LayerBatcher<u32> = LayerBatcher::new(8, 2);
+		let tiles: Vec<u32> = (0..4).collect();
+
+		let placed: Vec<(u32, u32)> = tiles.iter().map(|tile| batcher.place(tile, (32, 32))).collect();
+		assert!(placed.iter().all(|(batch, _)| *batch == 0));
+		let mut layers: Vec<u32> = placed.iter().map(|(_, layer)| *layer).collect();
+		layers.sort();
+		layers.dedup();
+		assert_eq!(layers.len(), tiles.len());
+		// The reserved layers stay put.
+		assert!(layers.iter().all(|layer| *layer >= 2));
+
+		// Asking again gives the same answer.
+		assert_eq!(batcher.place(&tiles[2], (32, 32)), placed[2]);
+
+		// Different sizes can't share.
+		assert_eq!(batcher.place(&100, (64, 64)).0, 1);
+		assert_eq!(batcher.get_batch_size(1), Some((64, 64)));
+
+		// Once the first array is full, 32x32 tiles spill over into a new one.
+		let more: Vec<(u32, u32)> = (10..13).map(|i| batcher.place(&i, (32, 32))).collect();
+		assert_eq!(more[0].0, 0);
+		assert_eq!(more[1].0, 0);
+		assert_eq!(more[2], (2, 2));
+		assert_eq!(batcher.get_layer_count(0), Some(8));
+	}
+}
@@ -1,5 +1,6 @@
 use std::any::Any;
 use std::collections::hash_map::Entry;
+use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
@@ -76,11 +77,68 @@ where
 	fn recv_wait(&mut self) -> impl Future<Output = Result<T, RecvError>> + '_;
 }
 
+/// Lets recv_wait_batched() share one implementation between receiver types.
+trait BatchBuffered<T>
+where
+	T: Message,
+{
+	/// Messages collected by a recv_wait_batched() call that got cancelled before it could return them.
+	fn batch_buf(&mut self) -> &mut VecDeque<T>;
+	/// Error which ended the last batch early, to return once batch_buf is empty.
+	fn pending_error(&mut self) -> &mut Option<RecvError>;
+	/// Receive from the underlying channel, ignoring batch_buf.
+	fn recv_unbuffered(&mut self) -> impl Future<Output = Result<T, RecvError>> + '_;
+}
+
+/// Waits for at least one message, then keeps collecting until we have `max` messages or `max_wait`
+/// has elapsed. Cancel-safe: anything collected before the future is dropped stays in batch_buf
+/// and is handed out by the next receive call. Never returns more than `max` messages (or 1, if `max` is 0) -
+/// if a cancelled call with a bigger `max` left more than that behind, the rest wait for the next call.
+/// An error partway through a batch ends it early. The error itself comes back once everything received before it
+/// has been handed out, so e.g. a broadcast receiver's Lagged count isn't lost.
+async fn recv_wait_batched_impl<T, R>(
+	receiver: &mut R,
+	max: usize,
+	max_wait: Duration,
+) -> Result<Vec<T>, RecvError>
+where
+	T: Message,
+	R: BatchBuffered<T>,
+{
+	let max = max.max(1);
+	if receiver.batch_buf().is_empty() {
+		if let Some(e) = receiver.pending_error().take() {
+			return Err(e);
+		}
+		let first = receiver.recv_unbuffered().await?;
+		receiver.batch_buf().push_back(first);
+	}
+	let deadline = Instant::now() + max_wait;
+	while receiver.batch_buf().len() < max {
+		match tokio::time::timeout_at(deadline, receiver.recv_unbuffered()).await {
+			Ok(Ok(message)) => receiver.batch_buf().push_back(message),
+			Ok(Err(e)) => {
+				trace!("Error while collecting a batch of messages, returning the batch early: {e}");
+				*receiver.pending_error() = Some(e);
+				break;
+			}
+			// Timed out.
+			Err(_) => break,
+		}
+	}
+	let batch_buf = receiver.batch_buf();
+	let count = batch_buf.len().min(max);
+	Ok(batch_buf.drain(..count).collect())
+}
+
 pub struct BroadcastReceiver<T>
 where
 	T: Message + Clone,
 {
 	pub(in crate::common::message) inner: UnderlyingBroadcastReceiver<T>,
+	pub(in crate::common::message) batch_buf: VecDeque<T>,
+	/// An error hit partway through collecting a batch, held back until the messages received before it are handed out.
+	pub(in crate::common::message) pending_error: Option<RecvError>,
 }
 
 impl<T> BroadcastReceiver<T>
@@ -88,16 +146,42 @@ where
 	T: Message + Clone,
 {
 	pub fn new(to_wrap: tokio::sync::broadcast::Receiver<T>) -> Self {
-		BroadcastReceiver { inner: to_wrap }
+		BroadcastReceiver {
+			inner: to_wrap,
+			batch_buf: VecDeque::new(),
+			pending_error: None,
+		}
 	}
 
 	pub fn resubscribe(&self) -> Self {
 		BroadcastReceiver {
 			inner: self.inner.resubscribe(),
+			batch_buf: VecDeque::new(),
+			pending_error: None,
 		}
 	}
 
+	/// Waits for at least one message, then collects up to `max` messages or until `max_wait`
+	/// elapses, whichever comes first. Cancel-safe, so it can be used in `tokio::select!`.
+	pub async fn recv_wait_batched(
+		&mut self,
+		max: usize,
+		max_wait: Duration,
+	) -> Result<Vec<T>, RecvError> {
+		recv_wait_batched_impl(self, max, max_wait).await
+	}
+
 	async fn recv_wait_inner(&mut self) -> Result<T, RecvError> {
+		if let Some(message) = self.batch_buf.pop_front() {
+			return Ok(message);
+		}
+		if let Some(e) = self.pending_error.take() {
+			return Err(e);
+		}
+		self.recv_unbuffered_inner().await
+	}
+
+	async fn recv_unbuffered_inner(&mut self) -> Result<T, RecvError> {
 		self.inner
 			.recv()
 			.map_err(|e| match e {
@@ -108,12 +192,33 @@ where
 	}
 }
 
+impl<T> BatchBuffered<T> for BroadcastReceiver<T>
+where
+	T: Message + Clone,
+{
+	fn batch_buf(&mut self) -> &mut VecDeque<T> {
+		&mut self.batch_buf
+	}
+	fn pending_error(&mut self) -> &mut Option<RecvError> {
+		&mut self.pending_error
+	}
+	fn recv_unbuffered(&mut self) -> impl Future<Output = Result<T, RecvError>> + '_ {
+		self.recv_unbuffered_inner()
+	}
+}
+
 impl<T> MessageReceiver<T> for BroadcastReceiver<T>
 where
 	T: Message + Clone,
 {
 	/// Nonblockingly polls for new messages, returning an empty vector if the channel is empty.  
 	fn recv_poll(&mut self) -> Result<Option<T>, RecvError> {
+		if let Some(message) = self.batch_buf.pop_front() {
+			return Ok(Some(message));
+		}
+		if let Some(e) = self.pending_error.take() {
+			return Err(e);
+		}
 		match self.inner.try_recv() {
 			Ok(val) => Ok(Some(val)),
 			Err(err) => match err {
@@ -184,6 +289,11 @@ where
 
 	/// Send one message to every domain, excluding the domain 'exclude'
 	fn send_to_all_except(&self, message: T, exclude: &D) -> Result<(), SendError>;
+
+	/// Send one message to every domain for which filter returns true
+	fn send_to_matching<F>(&self, message: T, filter: F) -> Result<(), SendError>
+	where
+		F: Fn(&D) -> bool;
 }
 
 impl<T> MessageSender<T> for BroadcastSender<T>
@@ -246,6 +356,14 @@ where
 {
 	fn take_receiver(&self) -> Result<Self::Receiver, DomainSubscribeErr<String>>;
 }
+/// A TakeReceiver where the receiver goes back into the channel when it's dropped,
+/// so that a consumer which gets restarted can take it again.
+pub trait ResetTakeReceiver<T> : TakeReceiver<T>
+where
+	T: Message,
+{
+	fn reset_and_take(&self) -> Result<Self::Receiver, DomainSubscribeErr<String>>;
+}
 
 pub trait MpmcChannel<T: Message>: SenderSubscribe<T> + ReceiverChannel<T> {}
 impl<T, U> MpmcChannel<T> for U
@@ -270,7 +388,9 @@ where
 	T: Message,
 {
 	// Does not need a mutex because you can clone it without mut.
-	sender: BroadcastSender<T>,
+	// Behind an Arc so that the channel registry can hold a weak reference to it.
+	sender: Arc<BroadcastSender<T>>,
+	capacity: usize,
 
 	/// It's a bad idea to just have a copy of a broadcast::Receiver around forever,
 	/// because then the channel will be perpetually full even when it doesn't need to be.
@@ -290,7 +410,8 @@ where
 	pub fn new(capacity: usize) -> Self {
 		let (sender, receiver) = tokio::sync::broadcast::channel(capacity);
 		BroadcastChannel {
-			sender,
+			sender: Arc::new(sender),
+			capacity,
 			retained_receiver: Arc::new(ChannelMutex::new(Some(receiver))),
 		}
 	}
@@ -305,6 +426,7 @@ where
 	fn clone(&self) -> Self {
 		Self {
 			sender: self.sender.clone(),
+			capacity: self.capacity,
 			retained_receiver: self.retained_receiver.clone(),
 		}
 	}
@@ -336,7 +458,7 @@ where
 	T: Message + Clone,
 {
 	fn sender_subscribe(&self) -> BroadcastSender<T> {
-		self.sender.clone()
+		BroadcastSender::clone(&self.sender)
 	}
 }
 impl<T> ReceiverChannel<T> for BroadcastChannel<T> where T: Message + Clone,
@@ -385,12 +507,21 @@ where
 
 pub type MpscSenderInner<T> = tokio::sync::mpsc::Sender<T>;
 type UnderlyingMpscReceiver<T> = mpsc::Receiver<T>;
+/// An MpscReceiver waiting in its channel to be taken, along with any messages it had buffered but not handed out.
+type RetainedMpscReceiver<T> = (UnderlyingMpscReceiver<T>, VecDeque<T>);
 
 pub struct MpscReceiver<T>
 where
 	T: Message,
 {
-	pub(in crate::common::message) inner: UnderlyingMpscReceiver<T>,
+	/// Only ever None while this is being dropped.
+	pub(in crate::common::message) inner: Option<UnderlyingMpscReceiver<T>>,
+	/// If this receiver was taken with reset_and_take(), this is the slot in the channel
+	/// that it (and batch_buf) goes back into when it's dropped, so that it can be taken again.
+	pub(in crate::common::message) return_to: Option<Arc<ChannelMutex<Option<RetainedMpscReceiver<T>>>>>,
+	pub(in crate::common::message) batch_buf: VecDeque<T>,
+	/// An error hit partway through collecting a batch, held back until the messages received before it are handed out.
+	pub(in crate::common::message) pending_error: Option<RecvError>,
 }
 
 impl<T> MpscReceiver<T>
@@ -398,11 +529,55 @@ where
 	T: Message,
 {
 	pub fn new(to_wrap: tokio::sync::mpsc::Receiver<T>) -> Self {
-		MpscReceiver { inner: to_wrap }
+		MpscReceiver {
+			inner: Some(to_wrap),
+			return_to: None,
+			batch_buf: VecDeque::new(),
+			pending_error: None,
+		}
+	}
+
+	/// Waits for at least one message, then collects up to `max` messages or until `max_wait`
+	/// elapses, whichever comes first. Cancel-safe, so it can be used in `tokio::select!`.
+	pub async fn recv_wait_batched(
+		&mut self,
+		max: usize,
+		max_wait: Duration,
+	) -> Result<Vec<T>, RecvError> {
+		recv_wait_batched_impl(self, max, max_wait).await
 	}
 
 	async fn recv_wait_inner(&mut self) -> Result<T, RecvError> {
-		self.inner.recv().await.ok_or(RecvError::NoSenders)
+		if let Some(message) = self.batch_buf.pop_front() {
+			return Ok(message);
+		}
+		if let Some(e) = self.pending_error.take() {
+			return Err(e);
+		}
+		self.recv_unbuffered_inner().await
+	}
+
+	async fn recv_unbuffered_inner(&mut self) -> Result<T, RecvError> {
+		self.inner_mut().recv().await.ok_or(RecvError::NoSenders)
+	}
+
+	fn inner_mut(&mut self) -> &mut UnderlyingMpscReceiver<T> {
+		self.inner.as_mut().expect("MpscReceiver's inner receiver is only taken when it's dropped")
+	}
+}
+
+impl<T> BatchBuffered<T> for MpscReceiver<T>
+where
+	T: Message,
+{
+	fn batch_buf(&mut self) -> &mut VecDeque<T> {
+		&mut self.batch_buf
+	}
+	fn pending_error(&mut self) -> &mut Option<RecvError> {
+		&mut self.pending_error
+	}
+	fn recv_unbuffered(&mut self) -> impl Future<Output = Result<T, RecvError>> + '_ {
+		self.recv_unbuffered_inner()
 	}
 }
 
@@ -412,7 +587,13 @@ where
 {
 	/// Nonblockingly polls for new messages, returning an empty vector if the channel is empty.  
 	fn recv_poll(&mut self) -> Result<Option<T>, RecvError> {
-		match self.inner.try_recv() {
+		if let Some(message) = self.batch_buf.pop_front() {
+			return Ok(Some(message));
+		}
+		if let Some(e) = self.pending_error.take() {
+			return Err(e);
+		}
+		match self.inner_mut().try_recv() {
 			Ok(val) => Ok(Some(val)),
 			Err(e) => match e {
 				MpscTryRecvError::Empty => Ok(None),
@@ -431,6 +612,17 @@ where
 		self.recv_wait_inner()
 	}
 }
+
+impl<T> Drop for MpscReceiver<T>
+where
+	T: Message,
+{
+	fn drop(&mut self) {
+		if let (Some(slot), Some(inner)) = (self.return_to.take(), self.inner.take()) {
+			*slot.lock() = Some((inner, std::mem::take(&mut self.batch_buf)));
+		}
+	}
+}
 pub struct MpscSender<T> where T: Message {
 	pub(super) inner: MpscSenderInner<T>,
 }
@@ -468,7 +660,7 @@ where
 	sender: MpscSender<T>,
 
 	/// This will be taken once and only once.
-	retained_receiver: Arc<ChannelMutex<Option<UnderlyingMpscReceiver<T>>>>,
+	retained_receiver: Arc<ChannelMutex<Option<RetainedMpscReceiver<T>>>>,
 }
 impl<T> MpscChannel<T>
 where
@@ -480,14 +672,34 @@ where
 		let (sender, receiver) = tokio::sync::mpsc::channel(capacity);
 		MpscChannel {
 			sender: MpscSender { inner: sender },
-			retained_receiver: Arc::new(ChannelMutex::new(Some(receiver))),
+			retained_receiver: Arc::new(ChannelMutex::new(Some((receiver, VecDeque::new())))),
 		}
 	}
 
 	/// Attempt to take the single consumer in this multi-producer single-consumer message channel.
 	pub fn take_receiver(&self) -> Option<MpscReceiver<T>> {
 		let mut inner_receiver = self.retained_receiver.lock();
-		inner_receiver.take().map(|r| MpscReceiver::new(r))
+		inner_receiver.take().map(|(r, batch_buf)| MpscReceiver {
+			inner: Some(r),
+			return_to: None,
+			batch_buf,
+			pending_error: None,
+		})
+	}
+
+	/// Like take_receiver(), except that when the receiver this returns is dropped
+	/// (for example, because the task consuming it crashed), it is handed back to
+	/// this channel rather than closing it. This means it can be taken again by a
+	/// restarted consumer, and senders can keep sending in the meantime.
+	/// Returns None if a receiver is still out there somewhere.
+	pub fn reset_and_take(&self) -> Option<MpscReceiver<T>> {
+		let mut inner_receiver = self.retained_receiver.lock();
+		inner_receiver.take().map(|(r, batch_buf)| MpscReceiver {
+			inner: Some(r),
+			return_to: Some(self.retained_receiver.clone()),
+			batch_buf,
+			pending_error: None,
+		})
 	}
 }
 
@@ -545,6 +757,11 @@ impl<T> TakeReceiver<T> for MpscChannel<T> where T: Message {
 		MpscChannel::take_receiver(self).ok_or(DomainSubscribeErr::TakeTakenReceiver(String::from("<NoDomain>")))
 	}
 }
+impl<T> ResetTakeReceiver<T> for MpscChannel<T> where T: Message {
+	fn reset_and_take(&self) -> Result<Self::Receiver, DomainSubscribeErr<String>> {
+		MpscChannel::reset_and_take(self).ok_or(DomainSubscribeErr::TakeTakenReceiver(String::from("<NoDomain>")))
+	}
+}
 
 //Note that sending directly on a channel rather than subscribing a sender will always be slower than getting a sender for bulk operations.
 impl<T, R> MessageSender<T> for MpscChannel<R>
@@ -566,6 +783,43 @@ where
 	}
 }
 
+impl<T> ChannelStats for BroadcastChannel<T>
+where
+	T: Message + Send + 'static,
+{
+	fn queued_len(&self) -> usize {
+		self.sender.len()
+	}
+	fn capacity(&self) -> usize {
+		self.capacity
+	}
+	fn stats_probe(&self) -> ChannelStatsProbe {
+		let sender = Arc::downgrade(&self.sender);
+		let capacity = self.capacity;
+		Box::new(move || sender.upgrade().map(|s| (s.len(), capacity)))
+	}
+}
+
+impl<T> ChannelStats for MpscChannel<T>
+where
+	T: Message + Send + 'static,
+{
+	fn queued_len(&self) -> usize {
+		self.sender.inner.max_capacity() - self.sender.inner.capacity()
+	}
+	fn capacity(&self) -> usize {
+		self.sender.inner.max_capacity()
+	}
+	fn stats_probe(&self) -> ChannelStatsProbe {
+		let sender = self.sender.inner.downgrade();
+		Box::new(move || {
+			sender
+				.upgrade()
+				.map(|s| (s.max_capacity() - s.capacity(), s.max_capacity()))
+		})
+	}
+}
+
 impl<T> From<MpscChannel<T>> for MpscSender<T> where T: Message {
 	fn from(value: MpscChannel<T>) -> Self {
 		value.sender_subscribe()
@@ -670,6 +924,18 @@ where
 		}
 		Ok(())
 	}
+
+	fn send_to_matching<F>(&self, message: T, filter: F) -> Result<(), SendError>
+	where
+		F: Fn(&D) -> bool,
+	{
+		for (domain, chan) in self.channels.lock().iter() {
+			if filter(domain) {
+				chan.send(message.clone())?;
+			}
+		}
+		Ok(())
+	}
 }
 #[derive(Clone)]
 pub struct DomainMultiChannel<T, D, C>
@@ -763,6 +1029,21 @@ where
 			.map_err(|_e| DomainSubscribeErr::TakeTakenReceiver(domain.clone()))
 	}
 }
+impl<T, D, C> DomainMultiChannel<T, D, C>
+where
+	T: Message,
+	D: ChannelDomain,
+	C: ResetTakeReceiver<T>,
+{
+	pub fn reset_and_take(&self, domain: &D) -> Result<C::Receiver, DomainSubscribeErr<D>> {
+		self.channels
+			.lock()
+			.get_mut(domain)
+			.ok_or_else(|| DomainSubscribeErr::NoDomain(domain.clone()))?
+			.reset_and_take()
+			.map_err(|_e| DomainSubscribeErr::TakeTakenReceiver(domain.clone()))
+	}
+}
 
 impl<T, D, C> DomainMessageSender<T, D> for DomainMultiChannel<T, D, C>
 where
@@ -795,6 +1076,18 @@ where
 		}
 		Ok(())
 	}
+
+	fn send_to_matching<F>(&self, message: T, filter: F) -> Result<(), SendError>
+	where
+		F: Fn(&D) -> bool,
+	{
+		for (domain, chan) in self.channels.lock().iter() {
+			if filter(domain) {
+				chan.send(message.clone())?;
+			}
+		}
+		Ok(())
+	}
 }
 
 impl<T, D, R, C> MessageSender<T> for DomainMultiChannel<R, D, C>
@@ -827,6 +1120,34 @@ where
 	}
 }
 
+impl<T, D, C> ChannelStats for DomainMultiChannel<T, D, C>
+where
+	T: Message + 'static,
+	D: ChannelDomain,
+	C: ChannelStats + Send + 'static,
+{
+	/// Total across every domain.
+	fn queued_len(&self) -> usize {
+		self.channels.lock().values().map(|c| c.queued_len()).sum()
+	}
+	/// Total across every domain.
+	fn capacity(&self) -> usize {
+		self.channels.lock().values().map(|c| c.capacity()).sum()
+	}
+	fn stats_probe(&self) -> ChannelStatsProbe {
+		let channels = Arc::downgrade(&self.channels);
+		Box::new(move || {
+			channels.upgrade().map(|chans| {
+				let lock = chans.lock();
+				(
+					lock.values().map(|c| c.queued_len()).sum(),
+					lock.values().map(|c| c.capacity()).sum(),
+				)
+			})
+		})
+	}
+}
+
 impl<T, D, C> SenderChannel<T> for DomainMultiChannel<T, D, C>
 where
 	T: Message + Clone,
@@ -903,6 +1224,23 @@ where
 		self.take_receiver(domain)
 	}
 }
+pub trait DomainResetTakeReceiver<T, D> : DomainTakeReceiver<T, D>
+where
+	T: Message, D: ChannelDomain
+{
+	fn reset_and_take_domain(&self, domain: &D) -> Result<Self::Receiver, DomainSubscribeErr<D>>;
+}
+
+impl<T, D, C> DomainResetTakeReceiver<T, D> for DomainMultiChannel<T,D,C>
+where
+	T: Message + Clone,
+	D: ChannelDomain,
+	C: ResetTakeReceiver<T>,
+{
+	fn reset_and_take_domain(&self, domain: &D) -> Result<Self::Receiver, DomainSubscribeErr<D>> {
+		self.reset_and_take(domain)
+	}
+}
 
 #[derive(thiserror::Error, Debug, Clone)]
 pub enum GlobalChannelError {
@@ -951,6 +1289,98 @@ impl ChannelCapacityConf {
 	}
 }
 
+/// Something which can check on a channel's (queued, capacity) later without keeping it alive.
+/// Returns None once the channel is gone.
+pub type ChannelStatsProbe = Box<dyn Fn() -> Option<(usize, usize)> + Send + Sync>;
+
+/// Any channel which can report how backed-up it is.
+pub trait ChannelStats {
+	/// How many messages are waiting in this channel right now?
+	fn queued_len(&self) -> usize;
+	fn capacity(&self) -> usize;
+	fn stats_probe(&self) -> ChannelStatsProbe;
+}
+
+#[derive(Clone, Debug)]
+pub struct ChannelStat {
+	pub name: &'static str,
+	pub message_type: &'static str,
+	pub queued: usize,
+	pub capacity: usize,
+}
+
+struct RegisteredChannel {
+	name: &'static str,
+	message_type: &'static str,
+	probe: ChannelStatsProbe,
+}
+
+struct RegisteredChannels {
+	entries: Vec<RegisteredChannel>,
+	/// Dropped channels get cleaned out when registering would take entries past this, so a process which
+	/// makes lots of short-lived channels but never takes a snapshot doesn't keep all of them around.
+	prune_at: usize,
+}
+
+impl RegisteredChannels {
+	const MIN_PRUNE_AT: usize = 64;
+
+	fn prune(&mut self) {
+		self.entries.retain(|entry| (entry.probe)().is_some());
+		// Doubling keeps the cost of pruning on register amortized, however many channels are alive.
+		self.prune_at = (self.entries.len() * 2).max(Self::MIN_PRUNE_AT);
+	}
+}
+
+static CHANNEL_REGISTRY: ChannelMutex<RegisteredChannels> = parking_lot::const_mutex(RegisteredChannels {
+	entries: Vec::new(),
+	prune_at: RegisteredChannels::MIN_PRUNE_AT,
+});
+
+/// Central place to look at how full every channel built through the ChannelSet system is,
+/// for diagnosing backpressure.
+pub struct ChannelRegistry;
+
+impl ChannelRegistry {
+	pub fn register<C>(channel: &C::Channel) where C: StaticChannelAtom, C::Channel: ChannelStats {
+		let mut registry = CHANNEL_REGISTRY.lock();
+		if registry.entries.len() >= registry.prune_at {
+			registry.prune();
+		}
+		registry.entries.push(RegisteredChannel {
+			name: C::get_static_name(),
+			message_type: C::get_static_msg_ty(),
+			probe: channel.stats_probe(),
+		});
+	}
+	/// Current stats for every live registered channel. Channels that have been dropped get cleaned up here.
+	pub fn snapshot() -> Vec<ChannelStat> {
+		let mut result = Vec::new();
+		CHANNEL_REGISTRY.lock().entries.retain(|entry| match (entry.probe)() {
+			Some((queued, capacity)) => {
+				result.push(ChannelStat {
+					name: entry.name,
+					message_type: entry.message_type,
+					queued,
+					capacity,
+				});
+				true
+			}
+			None => false,
+		});
+		result
+	}
+	/// Dump the current snapshot to the log, e.g. periodically or when the engine stalls.
+	pub fn log_snapshot() {
+		for stat in Self::snapshot() {
+			info!(
+				"Channel {} ({}): {}/{} messages queued",
+				stat.name, stat.message_type, stat.queued, stat.capacity
+			);
+		}
+	}
+}
+
 // A few *very universal* channels are allowed to be globals.
 global_channel!(BroadcastChannel, START_QUIT, (), 1);
 global_channel!(BroadcastChannel, READY_FOR_QUIT, (), 4096);
@@ -1117,6 +1547,13 @@ pub trait StaticDomainTakeReceiver<C> where C: StaticDomainChannelAtom, C::Chann
 	fn take_receiver(&self, domain: &C::Domain) -> Result<<<C as StaticChannelAtom>::Channel as ReceiverChannel<C::Message>>::Receiver, DomainSubscribeErr<C::Domain>>;
 }
 
+pub trait StaticResetTakeReceiver<C> where C: StaticChannelAtom, C::Channel: ReceiverChannel<C::Message> { 
+	fn reset_and_take(&self) -> Result< <<C as StaticChannelAtom>::Channel as ReceiverChannel<C::Message>>::Receiver, DomainSubscribeErr<String> >;
+}
+pub trait StaticDomainResetTakeReceiver<C> where C: StaticDomainChannelAtom, C::Channel: DomainResetTakeReceiver<C::Message, C::Domain> { 
+	fn reset_and_take(&self, domain: &C::Domain) -> Result<<<C as StaticChannelAtom>::Channel as ReceiverChannel<C::Message>>::Receiver, DomainSubscribeErr<C::Domain>>;
+}
+
 // Such gnarly type signatures are allowed only when needed for advanced procmacro shenanigans.
 impl<T, C> StaticSenderSubscribe<C> for T where T: HasChannel<C>,
 	C: StaticChannelAtom,
@@ -1161,6 +1598,20 @@ impl<T, C> StaticDomainTakeReceiver<C> for T where T: HasChannel<C>,
 		self.get_channel().take_receiver_domain(domain)
 	}
 }
+impl<T, C> StaticResetTakeReceiver<C> for T where T: HasChannel<C>,
+	C: StaticChannelAtom,
+	C::Channel: ResetTakeReceiver<C::Message>, {
+	fn reset_and_take(&self) -> Result< <<C as StaticChannelAtom>::Channel as ReceiverChannel<C::Message>>::Receiver, DomainSubscribeErr<String> > {
+		self.get_channel().reset_and_take()
+	}
+}
+impl<T, C> StaticDomainResetTakeReceiver<C> for T where T: HasChannel<C>,
+	C: StaticDomainChannelAtom,
+	C::Channel: DomainResetTakeReceiver<C::Message, C::Domain> {
+	fn reset_and_take(&self, domain: &C::Domain) -> Result<<<C as StaticChannelAtom>::Channel as ReceiverChannel<<C as StaticChannelAtom>::Message>>::Receiver, DomainSubscribeErr<<C as StaticDomainChannelAtom>::Domain>> {
+		self.get_channel().reset_and_take_domain(domain)
+	}
+}
 
 pub trait ChannelSet {
 	type StaticBuilder;
@@ -1437,4 +1888,264 @@ pub mod test {
 		assert_eq!(client_b.foo.recv_poll(), Ok(None));
 
 	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn channel_set_new_channel_init() {
+		static_channel_atom!(FreshMpsc, MpscChannel<u32>, u32, 8);
+		static_channel_atom!(FreshDomains, DomainMultiChannel<u32, NodeIdentity, BroadcastChannel<u32>>, u32, NodeIdentity, 8);
+
+		#[derive(ChannelSet)]
+		struct FreshChannels {
+			#[channel(FreshMpsc, new_channel)]
+			pub mpsc: MpscChannel<u32>,
+			#[channel(FreshDomains, new_channel)]
+			pub domains: DomainMultiChannel<u32, NodeIdentity, BroadcastChannel<u32>>,
+		}
+
+		// Capacity comes from the builder's configuration if it's set there, and from the atom if it isn't.
+		let mut builder = SubsetBuilder::new(());
+		builder.capacity_conf.set::<FreshDomains>(32);
+		let first = FreshChannels::new(builder);
+		assert_eq!(first.domains.get_capacity(), 32);
+
+		let second = FreshChannels::new(SubsetBuilder::new(()));
+		assert_eq!(second.domains.get_capacity(), FreshDomains::DEFAULT_CAPACITY);
+
+		// Each new() call should produce a brand new channel rather than sharing one.
+		let mut first_receiver = first.mpsc.take_receiver().unwrap();
+		let mut second_receiver = second.mpsc.take_receiver().unwrap();
+		first.mpsc.send(1u32).unwrap();
+		assert_eq!(first_receiver.recv_poll(), Ok(Some(1)));
+		assert_eq!(second_receiver.recv_poll(), Ok(None));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn channel_set_mixed_subset() {
+		static_channel_atom!(MixedBroadcast, BroadcastChannel<u32>, u32, 16);
+		static_channel_atom!(MixedDomains, DomainMultiChannel<String, NodeIdentity, BroadcastChannel<String>>, String, NodeIdentity, 16);
+
+		#[derive(ChannelSet)]
+		struct MixedParent {
+			#[channel(MixedBroadcast, new_channel)]
+			pub broadcast: BroadcastChannel<u32>,
+			#[channel(MixedDomains, new_channel)]
+			pub per_player: DomainMultiChannel<String, NodeIdentity, BroadcastChannel<String>>,
+		}
+		#[derive(ChannelSet)]
+		struct MixedChild {
+			#[sender(MixedBroadcast)]
+			pub to_everyone: BroadcastSender<u32>,
+			#[receiver(MixedBroadcast)]
+			pub from_everyone: BroadcastReceiver<u32>,
+			#[receiver(MixedDomains, domain = "player")]
+			pub from_player: BroadcastReceiver<String>,
+			// Non-channel fields get carried through the builder.
+			pub label: String,
+		}
+
+		let parent = MixedParent::new(SubsetBuilder::new(()));
+		let player_keys = IdentityKeyPair::generate_for_tests();
+
+		// Subscribing to a domain that has not been initialized should fail rather than panic.
+		let no_domain: Result<MixedChild, _> = parent.build_subset(SubsetBuilder::new(MixedChildFields {
+			player_domain: player_keys.public.clone(),
+			label: String::from("Laurence"),
+		}));
+		assert!(matches!(no_domain, Err(DomainSubscribeErr::NoDomain(_))));
+
+		parent.per_player.init_domain(player_keys.public.clone()).unwrap();
+		let mut child: MixedChild = parent.build_subset(SubsetBuilder::new(MixedChildFields {
+			player_domain: player_keys.public.clone(),
+			label: String::from("Laurence"),
+		})).unwrap();
+		assert_eq!(child.label, String::from("Laurence"));
+
+		// Parent to subset.
+		parent.broadcast.send(7u32).unwrap();
+		assert_eq!(child.from_everyone.recv_wait().await.unwrap(), 7);
+
+		// Subset's sender to subset's receiver, through the parent's channel.
+		child.to_everyone.send(9).unwrap();
+		assert_eq!(child.from_everyone.recv_wait().await.unwrap(), 9);
+
+		// Parent to subset, in a domain.
+		let message = String::from("Hello, player!");
+		parent.per_player.send_to(message.clone(), &player_keys.public).unwrap();
+		assert_eq!(child.from_player.recv_wait().await.unwrap(), message);
+		assert_eq!(child.from_player.recv_poll(), Ok(None));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn channel_set_reusable_take_receiver() {
+		static_channel_atom!(RestartableMpsc, MpscChannel<u32>, u32, 16);
+
+		#[derive(ChannelSet)]
+		struct SupervisorChannels {
+			#[channel(RestartableMpsc, new_channel)]
+			pub work: MpscChannel<u32>,
+		}
+		#[derive(ChannelSet)]
+		struct WorkerChannels {
+			#[take_receiver(RestartableMpsc, reusable)]
+			pub work: MpscReceiver<u32>,
+		}
+		#[derive(ChannelSet)]
+		struct ProducerChannels {
+			#[sender(RestartableMpsc)]
+			pub work: MpscSender<u32>,
+		}
+
+		let supervisor = SupervisorChannels::new(SubsetBuilder::new(()));
+		let producer: ProducerChannels = supervisor.build_subset(SubsetBuilder::new(())).unwrap();
+
+		let mut worker: WorkerChannels = supervisor.build_subset(SubsetBuilder::new(())).unwrap();
+		// Only one receiver at a time.
+		let second_worker: Result<WorkerChannels, _> = supervisor.build_subset(SubsetBuilder::new(()));
+		assert!(matches!(second_worker, Err(DomainSubscribeErr::TakeTakenReceiver(_))));
+
+		producer.work.send(1).unwrap();
+		assert_eq!(worker.work.recv_wait().await.unwrap(), 1);
+
+		// Our worker "crashes" with a message still in flight.
+		producer.work.send(2).unwrap();
+		drop(worker);
+		// The channel stays open for senders while nobody is receiving.
+		producer.work.send(3).unwrap();
+
+		let mut restarted_worker: WorkerChannels = supervisor.build_subset(SubsetBuilder::new(())).unwrap();
+		assert_eq!(restarted_worker.work.recv_wait().await.unwrap(), 2);
+		assert_eq!(restarted_worker.work.recv_wait().await.unwrap(), 3);
+		assert_eq!(restarted_worker.work.recv_poll(), Ok(None));
+
+		// Messages a cancelled batch had already pulled off the channel come back with the receiver.
+		producer.work.send(4).unwrap();
+		producer.work.send(5).unwrap();
+		let cancelled = tokio::time::timeout(
+			Duration::from_millis(10),
+			restarted_worker.work.recv_wait_batched(100, Duration::from_secs(5)),
+		)
+		.await;
+		assert!(cancelled.is_err());
+		drop(restarted_worker);
+		let mut third_worker: WorkerChannels = supervisor.build_subset(SubsetBuilder::new(())).unwrap();
+		assert_eq!(third_worker.work.recv_wait_batched(100, Duration::from_millis(10)).await.unwrap(), vec![4, 5]);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn channel_registry_snapshot() {
+		static_channel_atom!(RegistryTestMpsc, MpscChannel<u32>, u32, 16);
+
+		#[derive(ChannelSet)]
+		struct RegistryTestChannels {
+			#[channel(RegistryTestMpsc, new_channel)]
+			pub mpsc: MpscChannel<u32>,
+		}
+
+		let find_stat = || {
+			ChannelRegistry::snapshot()
+				.into_iter()
+				.find(|stat| stat.name == "RegistryTestMpsc")
+		};
+
+		let channels = RegistryTestChannels::new(SubsetBuilder::new(()));
+		let stat = find_stat().unwrap();
+		assert_eq!(stat.queued, 0);
+		assert_eq!(stat.capacity, 16);
+
+		channels.mpsc.send(1u32).unwrap();
+		channels.mpsc.send(2u32).unwrap();
+		assert_eq!(find_stat().unwrap().queued, 2);
+
+		// Dropped channels should fall out of the registry.
+		drop(channels);
+		assert!(find_stat().is_none());
+
+		// Even if nobody ever takes a snapshot.
+		for _ in 0..1000 {
+			drop(RegistryTestChannels::new(SubsetBuilder::new(())));
+		}
+		assert!(CHANNEL_REGISTRY.lock().entries.len() < 1000);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn recv_wait_batched() {
+		let channel: MpscChannel<u32> = MpscChannel::new(64);
+		let mut receiver = channel.take_receiver().unwrap();
+		let sender = channel.sender_subscribe();
+
+		// Stops at max.
+		for i in 0..10 {
+			sender.send(i).unwrap();
+		}
+		let batch = receiver.recv_wait_batched(4, Duration::from_secs(5)).await.unwrap();
+		assert_eq!(batch, vec![0, 1, 2, 3]);
+
+		// Stops at max_wait if fewer than max messages are available.
+		let batch = receiver.recv_wait_batched(100, Duration::from_millis(10)).await.unwrap();
+		assert_eq!(batch, vec![4, 5, 6, 7, 8, 9]);
+
+		// Cancelling partway through a batch must not lose the messages collected so far.
+		sender.send(10).unwrap();
+		let cancelled = tokio::time::timeout(
+			Duration::from_millis(10),
+			receiver.recv_wait_batched(100, Duration::from_secs(5)),
+		)
+		.await;
+		assert!(cancelled.is_err());
+		sender.send(11).unwrap();
+		assert_eq!(receiver.recv_wait().await.unwrap(), 10);
+		let batch = receiver.recv_wait_batched(100, Duration::from_millis(10)).await.unwrap();
+		assert_eq!(batch, vec![11]);
+
+		// Leftovers from a cancelled call with a bigger max still only come out max at a time.
+		for i in 20..25 {
+			sender.send(i).unwrap();
+		}
+		let cancelled = tokio::time::timeout(
+			Duration::from_millis(10),
+			receiver.recv_wait_batched(100, Duration::from_secs(5)),
+		)
+		.await;
+		assert!(cancelled.is_err());
+		assert_eq!(receiver.recv_wait_batched(2, Duration::from_secs(5)).await.unwrap(), vec![20, 21]);
+		assert_eq!(receiver.recv_wait_batched(2, Duration::from_secs(5)).await.unwrap(), vec![22, 23]);
+		assert_eq!(receiver.recv_poll().unwrap(), Some(24));
+
+		// A batch that was already started gets handed over once the senders go away.
+		sender.send(12).unwrap();
+		drop(sender);
+		drop(channel);
+		let batch = receiver.recv_wait_batched(100, Duration::from_secs(5)).await.unwrap();
+		assert_eq!(batch, vec![12]);
+		assert_eq!(receiver.recv_wait_batched(100, Duration::from_secs(5)).await, Err(RecvError::NoSenders));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn recv_wait_batched_keeps_lag() {
+		let channel: BroadcastChannel<u32> = BroadcastChannel::new(2);
+		let sender = channel.sender_subscribe();
+		let mut receiver = channel.receiver_subscribe();
+
+		// Lagging before anything has been collected is reported right away.
+		for i in 0..4 {
+			sender.send(i).unwrap();
+		}
+		assert_eq!(receiver.recv_wait_batched(100, Duration::from_millis(10)).await, Err(RecvError::Lagged(2)));
+		assert_eq!(receiver.recv_wait_batched(100, Duration::from_millis(10)).await.unwrap(), vec![2, 3]);
+
+		// Lagging partway through a batch hands over the batch first, then the lag.
+		sender.send(10).unwrap();
+		let cancelled = tokio::time::timeout(
+			Duration::from_millis(10),
+			receiver.recv_wait_batched(100, Duration::from_secs(5)),
+		)
+		.await;
+		assert!(cancelled.is_err());
+		for i in 11..14 {
+			sender.send(i).unwrap();
+		}
+		assert_eq!(receiver.recv_wait_batched(100, Duration::from_millis(10)).await.unwrap(), vec![10]);
+		assert_eq!(receiver.recv_wait_batched(100, Duration::from_millis(10)).await, Err(RecvError::Lagged(1)));
+		assert_eq!(receiver.recv_wait_batched(100, Duration::from_millis(10)).await.unwrap(), vec![12, 13]);
+	}
 }
@@ -1,10 +1,14 @@
 pub mod growable_buffer;
+pub mod layer_batcher;
 pub mod identity;
 #[macro_use]
 pub mod message;
 #[macro_use]
 pub mod voxelmath;
+pub mod camera;
 pub mod directories;
+pub mod frame_limiter;
+pub mod frustum;
 
 use core::str;
 use std::{
@@ -77,6 +81,7 @@ impl Angle for DegreeAngle {
 	}
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Color {
 	/// Red
 	pub r: u8,
@@ -89,8 +94,44 @@ impl Color {
 	pub fn to_normalized_float(&self) -> (f32, f32, f32) {
 		(self.r as f32 / 255.0, self.g as f32 / 255.0, self.b as f32 / 255.0)
 	}
+	/// Hue is in degrees (wraps around, so 360 is red again), saturation and value go from 0 to 1.
+	pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+		let hue = hue.rem_euclid(360.0);
+		let saturation = saturation.clamp(0.0, 1.0);
+		let value = value.clamp(0.0, 1.0);
+
+		let chroma = value * saturation;
+		let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+		let (r, g, b) = match (hue / 60.0) as u32 {
+			0 => (chroma, x, 0.0),
+			1 => (x, chroma, 0.0),
+			2 => (0.0, chroma, x),
+			3 => (0.0, x, chroma),
+			4 => (x, 0.0, chroma),
+			_ => (chroma, 0.0, x),
+		};
+		let m = value - chroma;
+		let to_byte = |channel: f32| ((channel + m) * 255.0).round() as u8;
+		Color {
+			r: to_byte(r),
+			g: to_byte(g),
+			b: to_byte(b),
+		}
+	}
+	/// Linear interpolation between this color (t = 0) and the other one (t = 1), per channel.
+	/// t gets clamped to 0..=1, so the endpoints come back exactly.
+	pub fn lerp(&self, other: &Color, t: f32) -> Self {
+		let t = t.clamp(0.0, 1.0);
+		let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+		Color {
+			r: mix(self.r, other.r),
+			g: mix(self.g, other.g),
+			b: mix(self.b, other.b),
+		}
+	}
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ColorAlpha {
 	pub color: Color,
 	/// Transparency
@@ -209,3 +250,27 @@ impl FixedString {
 		Self([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]])
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn color_from_hsv() {
+		assert_eq!(Color::from_hsv(0.0, 1.0, 1.0), Color { r: 255, g: 0, b: 0 });
+		assert_eq!(Color::from_hsv(120.0, 1.0, 1.0), Color { r: 0, g: 255, b: 0 });
+		assert_eq!(Color::from_hsv(360.0 + 240.0, 1.0, 1.0), Color { r: 0, g: 0, b: 255 });
+		// Sky blue-ish.
+		assert_eq!(Color::from_hsv(200.0, 0.5, 0.8), Color { r: 102, g: 170, b: 204 });
+		assert_eq!(Color::from_hsv(42.0, 0.0, 0.5), Color { r: 128, g: 128, b: 128 });
+	}
+
+	#[test]
+	fn color_lerp_endpoints() {
+		let night = Color { r: 5, g: 10, b: 40 };
+		let day = Color { r: 135, g: 206, b: 235 };
+		assert_eq!(night.lerp(&day, 0.0), night);
+		assert_eq!(night.lerp(&day, 1.0), day);
+		assert_eq!(night.lerp(&day, 0.5), Color { r: 70, g: 108, b: 138 });
+	}
+}
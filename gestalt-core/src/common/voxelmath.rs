@@ -9,6 +9,7 @@ use std::fmt;
 use std::marker::Copy;
 
 use std::ops::Add;
+use std::ops::Mul;
 use std::ops::Sub;
 
 use std::convert::From;
@@ -211,6 +212,22 @@ where
 	}
 }
 
+/// Scalar multiplication.
+impl<T> Mul<T> for VoxelPos<T>
+where
+	T: VoxelCoord + Mul<Output = T>,
+{
+	type Output = VoxelPos<T>;
+	#[inline]
+	fn mul(self, scalar: T) -> VoxelPos<T> {
+		VoxelPos {
+			x: self.x * scalar,
+			y: self.y * scalar,
+			z: self.z * scalar,
+		}
+	}
+}
+
 impl<T> fmt::Display for VoxelPos<T>
 where
 	T: VoxelCoord + fmt::Display,
@@ -241,9 +258,11 @@ where
 	}
 }
 
+/// Shorthand for building a VoxelPos, e.g. `vpos!(1, 2, 3)`.
+#[macro_export]
 macro_rules! vpos {
 	($x:expr, $y:expr, $z:expr) => {
-		VoxelPos {
+		$crate::common::voxelmath::VoxelPos {
 			x: $x,
 			y: $y,
 			z: $z,
@@ -497,6 +516,43 @@ where
 	}
 }
 
+impl VoxelRange<i32> {
+	/// Splits this range up along chunk boundaries, for things which need to work on a region one chunk at a time.
+	/// Each yielded range lies entirely within one chunk - ranges at the edges get clipped down to the part that
+	/// overlaps this range, so they can be smaller than a whole chunk. Chunks are visited in the same x, y, z order
+	/// as iterating over a VoxelRange.
+	pub fn chunked(&self, chunk_size: u32) -> impl Iterator<Item = VoxelRange<i32>> {
+		assert!(chunk_size > 0, "VoxelRange::chunked() needs a nonzero chunk size.");
+		let size = chunk_size as i32;
+		let range = self.get_validated();
+		// Which chunk the first and last voxel along each axis land in. div_euclid() rounds towards negative infinity,
+		// so -1 is in chunk -1 rather than chunk 0.
+		let empty = range.lower.x == range.upper.x || range.lower.y == range.upper.y || range.lower.z == range.upper.z;
+		let chunks_along = move |lower: i32, upper: i32| {
+			if empty {
+				0..0
+			} else {
+				lower.div_euclid(size)..((upper - 1).div_euclid(size) + 1)
+			}
+		};
+		let clip = move |chunk: i32, lower: i32, upper: i32| (cmp::max(chunk * size, lower), cmp::min((chunk + 1) * size, upper));
+
+		chunks_along(range.lower.x, range.upper.x).flat_map(move |cx| {
+			chunks_along(range.lower.y, range.upper.y).flat_map(move |cy| {
+				chunks_along(range.lower.z, range.upper.z).map(move |cz| {
+					let (lower_x, upper_x) = clip(cx, range.lower.x, range.upper.x);
+					let (lower_y, upper_y) = clip(cy, range.lower.y, range.upper.y);
+					let (lower_z, upper_z) = clip(cz, range.lower.z, range.upper.z);
+					VoxelRange {
+						lower: vpos!(lower_x, lower_y, lower_z),
+						upper: vpos!(upper_x, upper_y, upper_z),
+					}
+				})
+			})
+		})
+	}
+}
+
 pub trait VoxelRangeUnsigner<S: ToUnsigned<U> + VoxelCoord, U: ToSigned<S> + VoxelCoord> {
 	type MARKERHACK;
 	/// Take a position in "world" space and return an offset from self.lower, telling you how far the point is from our origin.
@@ -789,6 +845,25 @@ pub mod axis {
 			}
 		}
 
+		/// Turn this direction around the Y axis by some number of quarter turns.
+		/// Each quarter turn takes +X to +Z, +Z to -X, -X to -Z and -Z to +X. Up and down stay put.
+		#[must_use]
+		pub const fn rotated_y(&self, quarter_turns: u8) -> VoxelSide {
+			let mut side = *self;
+			let mut i = 0;
+			while i < quarter_turns % 4 {
+				side = match side {
+					VoxelSide::PosiX => VoxelSide::PosiZ,
+					VoxelSide::PosiZ => VoxelSide::NegaX,
+					VoxelSide::NegaX => VoxelSide::NegaZ,
+					VoxelSide::NegaZ => VoxelSide::PosiX,
+					other => other,
+				};
+				i += 1;
+			}
+			side
+		}
+
 		/// If you are looking straight at this side (assuming no roll), what direction is its' local 2D positive-X direction?
 		#[allow(dead_code)]
 		#[must_use]
@@ -1218,6 +1293,9 @@ pub struct VoxelRaycast {
 	t_delta: glam::Vec3, //How far along do we need to move for the length of that movement to equal the width of a voxel?
 	step_dir: VoxelPos<i32>, //Values are only 1 or -1, to determine the sign of the direction the ray is traveling.
 	last_direction: VoxelAxis,
+	origin: glam::Vec3,
+	direction: glam::Vec3,
+	t_entered: f32, //The t value at which the ray crossed into the voxel at pos. 0 until the first step.
 }
 
 /*
@@ -1227,6 +1305,7 @@ impl VoxelRaycast {
 	#[inline]
 	#[allow(dead_code)]
 	fn step_x(&mut self) {
+		self.t_entered = self.t_max.x;
 		self.pos.x += self.step_dir.x;
 		self.t_max.x += self.t_delta.x;
 		self.last_direction = VoxelAxis::X; //We will correct the sign on this in a get function, rather than in the loop.
@@ -1234,6 +1313,7 @@ impl VoxelRaycast {
 	#[inline]
 	#[allow(dead_code)]
 	fn step_y(&mut self) {
+		self.t_entered = self.t_max.y;
 		self.pos.y += self.step_dir.y;
 		self.t_max.y += self.t_delta.y;
 		self.last_direction = VoxelAxis::Y; //We will correct the sign on this in a get function, rather than in the loop.
@@ -1241,6 +1321,7 @@ impl VoxelRaycast {
 	#[inline]
 	#[allow(dead_code)]
 	fn step_z(&mut self) {
+		self.t_entered = self.t_max.z;
 		self.pos.z += self.step_dir.z;
 		self.t_max.z += self.t_delta.z;
 		self.last_direction = VoxelAxis::Z; //We will correct the sign on this in a get function, rather than in the loop.
@@ -1248,11 +1329,12 @@ impl VoxelRaycast {
 	#[inline]
 	#[allow(dead_code)]
 	pub fn step(&mut self) {
-		if (self.t_max.x < self.t_max.y) && (self.t_max.x < self.t_max.z) {
+		// Ties (the ray crossing an edge or corner exactly) still have to go somewhere, or the ray gets stuck.
+		if (self.t_max.x <= self.t_max.y) && (self.t_max.x <= self.t_max.z) {
 			self.step_x();
-		} else if (self.t_max.y < self.t_max.x) && (self.t_max.y < self.t_max.z) {
+		} else if self.t_max.y <= self.t_max.z {
 			self.step_y();
-		} else if (self.t_max.z < self.t_max.x) && (self.t_max.z < self.t_max.y) {
+		} else {
 			self.step_z();
 		}
 	}
@@ -1327,7 +1409,12 @@ impl VoxelRaycast {
 		}*/
 
 		//Distance along the ray to the next voxel from our origin
-		let next_voxel_boundary = voxel_origin + step_dir;
+		//Going in the negative direction, the next boundary is this voxel's own lower face.
+		let next_voxel_boundary = VoxelPos {
+			x: voxel_origin.x + step_dir.x.max(0),
+			y: voxel_origin.y + step_dir.y.max(0),
+			z: voxel_origin.z + step_dir.z.max(0),
+		};
 
 		//Set up our t_max - distances to next cell
 		let mut t_max: glam::Vec3 = glam::Vec3::new(0.0, 0.0, 0.0);
@@ -1372,8 +1459,36 @@ impl VoxelRaycast {
 			t_delta,
 			step_dir,
 			last_direction: VoxelAxis::Z,
+			origin,
+			direction,
+			t_entered: 0.0,
 		}
 	}
+	/// The exact point where the ray entered the voxel at `pos`.
+	/// Before the first step, this is just the ray's origin.
+	pub fn hit_point(&self) -> glam::Vec3 {
+		self.origin + self.direction * self.t_entered
+	}
+	/// How far the ray has gone from its origin to hit_point(), in world units
+	/// (regardless of whether the direction passed in was normalized).
+	pub fn distance_travelled(&self) -> f32 {
+		self.t_entered * self.direction.length()
+	}
+	/// Step along the ray until is_hit() returns true for a voxel, returning that voxel and the side the ray came in through.
+	/// Gives up and returns None once the ray has gone further than max_distance, or right away if the
+	/// direction is zero-length (the ray would never get anywhere).
+	pub fn cast_until(&mut self, max_distance: f32, mut is_hit: impl FnMut(VoxelPos<i32>) -> bool) -> Option<(VoxelPos<i32>, VoxelSide)> {
+		if self.direction == glam::Vec3::ZERO {
+			return None;
+		}
+		while self.distance_travelled() <= max_distance {
+			if is_hit(self.pos) {
+				return Some((self.pos, self.hit_side()));
+			}
+			self.step();
+		}
+		None
+	}
 	pub fn hit_side(&self) -> VoxelSide {
 		match self.last_direction {
 			VoxelAxis::X => {
@@ -1461,6 +1576,17 @@ where
 			data: self,
 		}
 	}
+
+	/// Whatever was on a side ends up on that side's VoxelSide::rotated_y(), so that e.g. the
+	/// textures of a block turn along with the block.
+	#[must_use]
+	pub fn rotated_y(&self, quarter_turns: u8) -> Self {
+		let mut rotated = self.clone();
+		for side in VoxelSide::iter_all() {
+			rotated.set(self.get(side).clone(), side.rotated_y(quarter_turns));
+		}
+		rotated
+	}
 }
 
 impl<T> Default for SidesArray<T>
@@ -1723,3 +1849,113 @@ fn test_contains() {
 		assert!(ran.contains(i));
 	}
 }
+
+#[test]
+fn test_voxel_pos_arithmetic() {
+	let a: VoxelPos<i32> = vpos!(1, -2, 3);
+	let b: VoxelPos<i32> = vpos!(-4, 5, 0);
+	assert_eq!(a + b, vpos!(-3, 3, 3));
+	assert_eq!(a - b, vpos!(5, -7, 3));
+	assert_eq!(a * 4, vpos!(4, -8, 12));
+	assert_eq!(a * -1, vpos!(-1, 2, -3));
+	assert_eq!((a + b) - b, a);
+}
+
+#[test]
+fn sides_array_rotated_y() {
+	let sides = SidesArray::new(1, 2, 3, 4, 5, 6);
+	let turned = sides.rotated_y(1);
+	assert_eq!(*turned.get(VoxelSide::PosiZ), 1);
+	assert_eq!(*turned.get(VoxelSide::NegaX), 3);
+	assert_eq!(*turned.get(VoxelSide::PosiY), 2);
+	assert_eq!(*turned.get(VoxelSide::NegaY), 5);
+	assert_eq!(sides.rotated_y(4), sides);
+	assert_eq!(turned.rotated_y(3), sides);
+}
+
+#[test]
+fn chunked_range_aligned() {
+	let range: VoxelRange<i32> = VoxelRange::new(vpos!(0, 0, 0), vpos!(64, 32, 32));
+	let pieces: Vec<VoxelRange<i32>> = range.chunked(32).collect();
+	assert_eq!(
+		pieces,
+		vec![
+			VoxelRange::new(vpos!(0, 0, 0), vpos!(32, 32, 32)),
+			VoxelRange::new(vpos!(32, 0, 0), vpos!(64, 32, 32)),
+		]
+	);
+}
+
+#[test]
+fn chunked_range_unaligned() {
+	let range: VoxelRange<i32> = VoxelRange::new(vpos!(5, 0, 30), vpos!(40, 16, 33));
+	let pieces: Vec<VoxelRange<i32>> = range.chunked(32).collect();
+	assert_eq!(
+		pieces,
+		vec![
+			VoxelRange::new(vpos!(5, 0, 30), vpos!(32, 16, 32)),
+			VoxelRange::new(vpos!(5, 0, 32), vpos!(32, 16, 33)),
+			VoxelRange::new(vpos!(32, 0, 30), vpos!(40, 16, 32)),
+			VoxelRange::new(vpos!(32, 0, 32), vpos!(40, 16, 33)),
+		]
+	);
+	// A range with nothing in it has no chunks either.
+	let empty: VoxelRange<i32> = VoxelRange::new(vpos!(3, 3, 3), vpos!(3, 10, 10));
+	assert_eq!(empty.chunked(32).count(), 0);
+}
+
+#[test]
+fn chunked_range_across_origin() {
+	let range: VoxelRange<i32> = VoxelRange::new(vpos!(-20, -1, -16), vpos!(20, 1, 16));
+	let pieces: Vec<VoxelRange<i32>> = range.chunked(16).collect();
+	// 4 chunks along x (-32..-16, -16..0, 0..16, 16..32), 2 along y, 2 along z.
+	assert_eq!(pieces.len(), 4 * 2 * 2);
+	assert_eq!(pieces[0], VoxelRange::new(vpos!(-20, -1, -16), vpos!(-16, 0, 0)));
+	assert_eq!(*pieces.last().unwrap(), VoxelRange::new(vpos!(16, 0, 0), vpos!(20, 1, 16)));
+	// Every voxel of the original range shows up in exactly one piece, and each piece stays inside one chunk.
+	let mut covered = 0;
+	for piece in pieces.iter() {
+		let size = piece.get_size();
+		covered += size.x * size.y * size.z;
+		for pos in *piece {
+			assert!(range.contains(pos));
+		}
+		let last = piece.upper - vpos!(1, 1, 1);
+		assert_eq!(piece.lower.x.div_euclid(16), last.x.div_euclid(16));
+		assert_eq!(piece.lower.y.div_euclid(16), last.y.div_euclid(16));
+		assert_eq!(piece.lower.z.div_euclid(16), last.z.div_euclid(16));
+	}
+	assert_eq!(covered, 40 * 2 * 32);
+}
+
+#[test]
+fn raycast_hit_point_on_face() {
+	let origin = glam::Vec3::new(0.5, 0.5, 0.5);
+	let direction = glam::Vec3::new(1.0, 0.25, 0.0).normalize();
+	let mut raycast = VoxelRaycast::new(origin, direction);
+	assert_eq!(raycast.distance_travelled(), 0.0);
+	while raycast.pos != vpos!(3, 1, 0) {
+		raycast.step();
+	}
+	assert_eq!(raycast.hit_side(), VoxelSide::NegaX);
+	let hit = raycast.hit_point();
+	// Entering (3, 1, 0) through its -X face means the hit lies on the plane x = 3.
+	assert!((hit.x - 3.0).abs() < 0.0001);
+	assert!((hit.y - (0.5 + 2.5 * 0.25)).abs() < 0.0001);
+	assert!((hit.z - 0.5).abs() < 0.0001);
+	assert!((raycast.distance_travelled() - (hit - origin).length()).abs() < 0.0001);
+}
+
+#[test]
+fn raycast_into_empty_space_gives_up() {
+	let mut raycast = VoxelRaycast::new(glam::Vec3::new(0.5, 10.5, 0.5), glam::Vec3::new(0.3, 1.0, -0.2));
+	assert_eq!(raycast.cast_until(64.0, |_| false), None);
+	assert!(raycast.distance_travelled() > 64.0);
+
+	// A ray which can't go anywhere shouldn't spin forever either.
+	let mut stuck = VoxelRaycast::new(glam::Vec3::new(0.5, 0.5, 0.5), glam::Vec3::ZERO);
+	assert_eq!(stuck.cast_until(64.0, |_| false), None);
+
+	let mut raycast = VoxelRaycast::new(glam::Vec3::new(0.5, 0.5, 0.5), glam::Vec3::new(1.0, 0.0, 0.0));
+	assert_eq!(raycast.cast_until(64.0, |pos| pos.x == 5), Some((vpos!(5, 0, 0), VoxelSide::NegaX)));
+}
@@ -0,0 +1,169 @@
+//! Keeping entities from moving through solid terrain.
+
+use glam::Vec3;
+
+use super::{tick_rotation_system, EcsWorld, EntityPos, EntityVec3, EntityVelocity, LastPos};
+use crate::common::voxelmath::VoxelPos;
+use crate::world::tilespace::TileSpace;
+use crate::world::{TickLength, TileId, VoxelStorage};
+
+/// Keeps an axis-aligned box exactly touching a voxel face from counting as overlapping the voxel.
+const FACE_EPSILON: f32 = 0.0001;
+
+/// Entities with one of these bump into solid voxels rather than passing through them,
+/// when moved by tick_movement_system_with_world().
+#[derive(Clone, Debug)]
+pub struct EntityCollider {
+	/// The collision box goes this far out from EntityPos in each direction.
+	pub half_extents: EntityVec3,
+	/// Tiles which can be moved through, e.g. air. Everything else is solid.
+	pub passable: Vec<TileId>,
+}
+impl EntityCollider {
+	pub fn new(half_extents: EntityVec3, passable: Vec<TileId>) -> Self {
+		Self {
+			half_extents,
+			passable,
+		}
+	}
+	/// Unloaded terrain counts as solid, so nothing falls out of the world while its chunks load.
+	fn is_solid(&self, tiles: &TileSpace, pos: VoxelPos<i32>) -> bool {
+		match tiles.get(pos) {
+			Ok(tile) => !self.passable.contains(tile),
+			Err(_) => true,
+		}
+	}
+}
+
+/// How much of a move of delta along one axis the box from min to max can actually make before running
+/// into a solid voxel. Sweeps every layer of voxels the leading face of the box passes through.
+fn sweep_axis(collider: &EntityCollider, tiles: &TileSpace, min: Vec3, max: Vec3, axis: usize, delta: f32) -> f32 {
+	if delta == 0.0 {
+		return 0.0;
+	}
+	let (other_a, other_b) = ((axis + 1) % 3, (axis + 2) % 3);
+	let range_a = ((min[other_a] + FACE_EPSILON).floor() as i32)..=((max[other_a] - FACE_EPSILON).floor() as i32);
+	let range_b = ((min[other_b] + FACE_EPSILON).floor() as i32)..=((max[other_b] - FACE_EPSILON).floor() as i32);
+	let layer_is_solid = |layer: i32| {
+		range_a.clone().any(|a| {
+			range_b.clone().any(|b| {
+				let mut pos = [0i32; 3];
+				pos[axis] = layer;
+				pos[other_a] = a;
+				pos[other_b] = b;
+				collider.is_solid(tiles, vpos!(pos[0], pos[1], pos[2]))
+			})
+		})
+	};
+
+	if delta > 0.0 {
+		let leading = max[axis];
+		let first = (leading - FACE_EPSILON).floor() as i32 + 1;
+		let last = (leading + delta - FACE_EPSILON).floor() as i32;
+		for layer in first..=last {
+			if layer_is_solid(layer) {
+				return (layer as f32 - leading).max(0.0);
+			}
+		}
+	} else {
+		let leading = min[axis];
+		let first = (leading + FACE_EPSILON).floor() as i32 - 1;
+		let last = (leading + delta + FACE_EPSILON).floor() as i32;
+		for layer in (last..=first).rev() {
+			if layer_is_solid(layer) {
+				return ((layer + 1) as f32 - leading).min(0.0);
+			}
+		}
+	}
+	delta
+}
+
+/// Move the collider's box from position by motion, one axis at a time, stopping each axis at the first solid
+/// voxel in the way. Returns where it ends up and which axes got blocked.
+pub fn sweep_collider(collider: &EntityCollider, tiles: &TileSpace, position: EntityVec3, motion: EntityVec3) -> (EntityVec3, [bool; 3]) {
+	let mut position = position;
+	let mut blocked = [false; 3];
+	for axis in 0..3 {
+		let allowed = sweep_axis(collider,
+			tiles,
+			position - collider.half_extents,
+			position + collider.half_extents,
+			axis,
+			motion[axis]);
+		blocked[axis] = allowed != motion[axis];
+		position[axis] += allowed;
+	}
+	(position, blocked)
+}
+
+/// Like tick_movement_system(), except entities with an EntityCollider stop at solid voxels in tiles.
+/// Whichever axes they got blocked on have their velocity zeroed. Entities without a collider move as usual.
+pub fn tick_movement_system_with_world(world: &mut EcsWorld, tiles: &TileSpace, seconds_per_tick: TickLength) {
+	for (_entity, (position, velocity, collider, last_pos_maybe)) in
+		world.query_mut::<(&mut EntityPos, &mut EntityVelocity, &EntityCollider, Option<&mut LastPos>)>()
+	{
+		if let Some(previous) = last_pos_maybe {
+			previous.pos = position.get();
+		}
+		let motion = velocity.get_motion_per_second() * seconds_per_tick.get();
+		let (new_position, blocked) = sweep_collider(collider, tiles, position.get(), motion);
+		position.set(new_position);
+		let mut motion_per_second = velocity.get_motion_per_second();
+		for axis in 0..3 {
+			if blocked[axis] {
+				motion_per_second[axis] = 0.0;
+			}
+		}
+		velocity.set_motion_per_second(motion_per_second);
+	}
+	for (_entity, (position, velocity, last_pos_maybe)) in world
+		.query_mut::<(&mut EntityPos, &EntityVelocity, Option<&mut LastPos>)>()
+		.without::<&EntityCollider>()
+	{
+		if let Some(previous) = last_pos_maybe {
+			previous.pos = position.get();
+		}
+		velocity.apply_tick(position, seconds_per_tick);
+	}
+	tick_rotation_system(world, seconds_per_tick);
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::world::chunk::Chunk;
+
+	#[test]
+	fn entity_stops_at_solid_face() {
+		const AIR: TileId = 0;
+		const STONE: TileId = 1;
+		let mut tiles = TileSpace::new();
+		tiles.ingest_loaded_chunk(vpos!(0, 0, 0), Chunk::new(AIR)).unwrap();
+		tiles.set(vpos!(5, 1, 1), STONE).unwrap();
+
+		let mut world = EcsWorld::new();
+		let tick = TickLength::from_tps(10.0);
+		let collider = EntityCollider::new(EntityVec3::splat(0.25), vec![AIR]);
+		let mover = world.spawn((
+			EntityPos::new(EntityVec3::new(1.5, 1.5, 1.5)),
+			EntityVelocity::new(EntityVec3::new(10.0, 0.0, 0.0)),
+			collider,
+		));
+		let ghost = world.spawn((
+			EntityPos::new(EntityVec3::new(1.5, 1.5, 1.5)),
+			EntityVelocity::new(EntityVec3::new(10.0, 0.0, 0.0)),
+		));
+
+		// One voxel per tick, so it gets there in a few.
+		for _ in 0..6 {
+			tick_movement_system_with_world(&mut world, &tiles, tick);
+		}
+		let stopped_at = world.get::<&EntityPos>(mover).unwrap().get();
+		assert!(stopped_at.abs_diff_eq(EntityVec3::new(5.0 - 0.25, 1.5, 1.5), 0.0001), "{stopped_at}");
+		assert_eq!(world.get::<&EntityVelocity>(mover).unwrap().get_motion_per_second(), EntityVec3::ZERO);
+
+		// Without a collider, it goes straight through.
+		let ghost_pos = world.get::<&EntityPos>(ghost).unwrap().get();
+		assert!(ghost_pos.abs_diff_eq(EntityVec3::new(7.5, 1.5, 1.5), 0.0001), "{ghost_pos}");
+	}
+}
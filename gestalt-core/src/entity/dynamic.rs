@@ -0,0 +1,129 @@
+//! Components which aren't Rust types, so scripts and mods can attach their own data (e.g. "health") to
+//! entities without a recompile. Each one is a named, MessagePack-encoded blob living in an entity's
+//! DynamicComponents - as far as hecs is concerned, that's the only component involved.
+
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::EcsWorld;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DynamicComponentError {
+	#[error("entity {0:?} does not exist")]
+	NoSuchEntity(hecs::Entity),
+	#[error("could not encode dynamic component \"{0}\": {1}")]
+	Encode(String, rmp_serde::encode::Error),
+	#[error("could not decode dynamic component \"{0}\": {1}")]
+	Decode(String, rmp_serde::decode::Error),
+}
+
+/// Every dynamic component on one entity, by name.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DynamicComponents {
+	components: HashMap<String, Vec<u8>>,
+}
+impl DynamicComponents {
+	pub fn get_raw(&self, name: &str) -> Option<&[u8]> {
+		self.components.get(name).map(|value| value.as_slice())
+	}
+	pub fn set_raw(&mut self, name: &str, value: Vec<u8>) {
+		self.components.insert(name.to_string(), value);
+	}
+	pub fn remove_raw(&mut self, name: &str) -> Option<Vec<u8>> {
+		self.components.remove(name)
+	}
+	pub fn names(&self) -> impl Iterator<Item = &str> {
+		self.components.keys().map(|name| name.as_str())
+	}
+	pub fn is_empty(&self) -> bool {
+		self.components.is_empty()
+	}
+}
+
+/// Getting and setting dynamic components straight through the EcsWorld.
+pub trait DynamicComponentAccess {
+	/// Store the raw bytes of a dynamic component, replacing any previous value under that name.
+	fn set_dynamic_raw(&mut self, entity: hecs::Entity, name: &str, value: Vec<u8>) -> Result<(), DynamicComponentError>;
+	/// Ok(None) if the entity exists but doesn't have this component.
+	fn get_dynamic_raw(&self, entity: hecs::Entity, name: &str) -> Result<Option<Vec<u8>>, DynamicComponentError>;
+	fn remove_dynamic(&mut self, entity: hecs::Entity, name: &str) -> Result<Option<Vec<u8>>, DynamicComponentError>;
+
+	fn set_dynamic<T: Serialize>(&mut self, entity: hecs::Entity, name: &str, value: &T) -> Result<(), DynamicComponentError> {
+		let bytes = rmp_serde::to_vec(value).map_err(|e| DynamicComponentError::Encode(name.to_string(), e))?;
+		self.set_dynamic_raw(entity, name, bytes)
+	}
+	fn get_dynamic<T: DeserializeOwned>(&self, entity: hecs::Entity, name: &str) -> Result<Option<T>, DynamicComponentError> {
+		match self.get_dynamic_raw(entity, name)? {
+			Some(bytes) => rmp_serde::from_slice(&bytes)
+				.map(Some)
+				.map_err(|e| DynamicComponentError::Decode(name.to_string(), e)),
+			None => Ok(None),
+		}
+	}
+}
+
+impl DynamicComponentAccess for EcsWorld {
+	fn set_dynamic_raw(&mut self, entity: hecs::Entity, name: &str, value: Vec<u8>) -> Result<(), DynamicComponentError> {
+		if !self.contains(entity) {
+			return Err(DynamicComponentError::NoSuchEntity(entity));
+		}
+		if let Ok(mut components) = self.get::<&mut DynamicComponents>(entity) {
+			components.set_raw(name, value);
+			return Ok(());
+		}
+		let mut components = DynamicComponents::default();
+		components.set_raw(name, value);
+		self.insert_one(entity, components)
+			.map_err(|_| DynamicComponentError::NoSuchEntity(entity))
+	}
+	fn get_dynamic_raw(&self, entity: hecs::Entity, name: &str) -> Result<Option<Vec<u8>>, DynamicComponentError> {
+		match self.get::<&DynamicComponents>(entity) {
+			Ok(components) => Ok(components.get_raw(name).map(|value| value.to_vec())),
+			Err(hecs::ComponentError::MissingComponent(_)) => Ok(None),
+			Err(hecs::ComponentError::NoSuchEntity) => Err(DynamicComponentError::NoSuchEntity(entity)),
+		}
+	}
+	fn remove_dynamic(&mut self, entity: hecs::Entity, name: &str) -> Result<Option<Vec<u8>>, DynamicComponentError> {
+		match self.get::<&mut DynamicComponents>(entity) {
+			Ok(mut components) => Ok(components.remove_raw(name)),
+			Err(hecs::ComponentError::MissingComponent(_)) => Ok(None),
+			Err(hecs::ComponentError::NoSuchEntity) => Err(DynamicComponentError::NoSuchEntity(entity)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::entity::EntityPos;
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize)]
+	struct Health {
+		current: u32,
+		max: u32,
+	}
+
+	#[test]
+	fn attach_and_read_back_dynamic_component() {
+		let mut world = EcsWorld::new();
+		let entity = world.spawn((EntityPos::default(),));
+		assert!(world.get_dynamic::<Health>(entity, "health").unwrap().is_none());
+
+		world.set_dynamic(entity, "health", &Health { current: 7, max: 10 }).unwrap();
+		world.set_dynamic(entity, "name", &"Bob".to_string()).unwrap();
+		assert_eq!(world.get_dynamic::<Health>(entity, "health").unwrap(), Some(Health { current: 7, max: 10 }));
+		assert_eq!(world.get_dynamic::<String>(entity, "name").unwrap().as_deref(), Some("Bob"));
+		// Ordinary components are untouched.
+		assert!(world.get::<&EntityPos>(entity).is_ok());
+
+		world.set_dynamic(entity, "health", &Health { current: 3, max: 10 }).unwrap();
+		assert_eq!(world.get_dynamic::<Health>(entity, "health").unwrap().unwrap().current, 3);
+		assert!(world.remove_dynamic(entity, "health").unwrap().is_some());
+		assert!(world.get_dynamic::<Health>(entity, "health").unwrap().is_none());
+
+		world.despawn(entity).unwrap();
+		assert!(matches!(world.set_dynamic(entity, "health", &1u32), Err(DynamicComponentError::NoSuchEntity(_))));
+	}
+}
@@ -1,6 +1,20 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
 use glam::{EulerRot, Quat};
+use log::warn;
+use serde::{Deserialize, Serialize};
 pub use hecs::World as EcsWorld;
 
+pub mod collision;
+pub mod dynamic;
+pub mod persist;
+pub mod schedule;
+pub use collision::{tick_movement_system_with_world, EntityCollider};
+pub use dynamic::{DynamicComponentAccess, DynamicComponents};
+pub use persist::{ComponentSaveRegistry, EcsWorldPersistence};
+pub use schedule::{SystemOrdering, SystemSchedule};
+
 use crate::{
 	common::{Angle, RadianAngle},
 	world::TickLength,
@@ -8,7 +22,7 @@ use crate::{
 pub type EntityCoord = f32;
 pub type EntityVec3 = glam::f32::Vec3;
 
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct EntityPos {
 	pos: EntityVec3,
 }
@@ -30,7 +44,7 @@ impl EntityPos {
 }
 
 /// Represents the position as of the previous server tick.
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LastPos {
 	pub pos: EntityVec3,
 }
@@ -40,7 +54,7 @@ impl LastPos {
 	}
 }
 
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct EntityRot {
 	rot: Quat,
 }
@@ -95,7 +109,7 @@ impl EntityRot {
 	}
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct EntityScale {
 	scale: EntityVec3,
 }
@@ -123,7 +137,7 @@ impl Default for EntityScale {
 	}
 }
 
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct EntityVelocity {
 	motion_per_second: EntityVec3,
 }
@@ -134,6 +148,9 @@ impl EntityVelocity {
 	pub fn get_motion_per_second(&self) -> EntityVec3 {
 		self.motion_per_second
 	}
+	pub fn set_motion_per_second(&mut self, motion_per_second: EntityVec3) {
+		self.motion_per_second = motion_per_second
+	}
 	pub fn apply_tick(&self, to_move: &mut EntityPos, seconds_per_tick: TickLength) {
 		to_move.move_by(self.get_motion_per_second() * seconds_per_tick.get())
 	}
@@ -147,6 +164,49 @@ impl EntityVelocity {
 	}
 }
 
+/// How fast an entity is turning - the rotational counterpart to EntityVelocity.
+/// Assumes less than half a turn per second, since anything more is ambiguous as a single quaternion.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntityAngularVelocity {
+	rotation_per_second: Quat,
+}
+impl EntityAngularVelocity {
+	pub fn new(rotation_per_second: Quat) -> Self {
+		Self { rotation_per_second: rotation_per_second.normalize() }
+	}
+	pub fn new_from_euler<A: Angle>(yaw: A, pitch: A, roll: A) -> Self {
+		Self::new(Quat::from_euler(
+			EulerRot::YXZ,
+			yaw.get_radians(),
+			pitch.get_radians(),
+			roll.get_radians(),
+		))
+	}
+	pub fn get_rotation_per_second(&self) -> Quat {
+		self.rotation_per_second
+	}
+	/// How far this turns something in the given number of seconds.
+	pub fn rotation_over(&self, seconds: f32) -> Quat {
+		Quat::IDENTITY.slerp(self.rotation_per_second, seconds)
+	}
+	pub fn apply_tick(&self, to_turn: &mut EntityRot, seconds_per_tick: TickLength) {
+		to_turn.set((self.rotation_over(seconds_per_tick.get()) * to_turn.get()).normalize())
+	}
+	/// Guess the orientation some time after the last tick, by slerping from where it was toward where
+	/// it'll be a second later. The rotational version of the render path's movement guess.
+	pub fn guess_rotation(&self, rot: Quat, secs_since_last_tick: f32) -> Quat {
+		let one_second_later = (self.rotation_per_second * rot).normalize();
+		rot.slerp(one_second_later, secs_since_last_tick)
+	}
+}
+impl Default for EntityAngularVelocity {
+	fn default() -> Self {
+		Self {
+			rotation_per_second: Quat::IDENTITY,
+		}
+	}
+}
+
 pub fn tick_movement_system(world: &mut EcsWorld, seconds_per_tick: TickLength) {
 	for (_entity, (position, velocity, last_pos_maybe)) in
 		world.query_mut::<(&mut EntityPos, &EntityVelocity, Option<&mut LastPos>)>()
@@ -157,4 +217,236 @@ pub fn tick_movement_system(world: &mut EcsWorld, seconds_per_tick: TickLength)
 		}
 		velocity.apply_tick(position, seconds_per_tick);
 	}
+	tick_rotation_system(world, seconds_per_tick);
+}
+
+/// Turns everything with an EntityAngularVelocity. Part of tick_movement_system().
+pub fn tick_rotation_system(world: &mut EcsWorld, seconds_per_tick: TickLength) {
+	for (_entity, (rotation, angular_velocity)) in
+		world.query_mut::<(&mut EntityRot, &EntityAngularVelocity)>()
+	{
+		angular_velocity.apply_tick(rotation, seconds_per_tick);
+	}
+}
+
+/// Entities with one of these get despawned once it runs out, e.g. projectiles and particles.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntityLifetime {
+	pub remaining: Duration,
+}
+impl EntityLifetime {
+	pub fn new(remaining: Duration) -> Self {
+		Self { remaining }
+	}
+}
+
+/// Counts every EntityLifetime down by one tick, and despawns the entities whose lifetimes run out.
+/// Despawning waits until the query is finished, so nothing gets removed out from under it.
+/// Components (drawables included) are dropped along with the entity. The renderer keeps no per-entity
+/// state of its own, but the despawned entities are returned for anything else which does.
+pub fn tick_lifetime_system(world: &mut EcsWorld, seconds_per_tick: TickLength) -> Vec<hecs::Entity> {
+	let tick = seconds_per_tick.get_duration();
+	let mut expired = Vec::new();
+	for (entity, lifetime) in world.query_mut::<&mut EntityLifetime>() {
+		lifetime.remaining = lifetime.remaining.saturating_sub(tick);
+		if lifetime.remaining.is_zero() {
+			expired.push(entity);
+		}
+	}
+	for entity in expired.iter() {
+		// Only fails if it's already gone, which is fine.
+		let _ = world.despawn(*entity);
+	}
+	expired
+}
+
+/// Attaches this entity to another one, e.g. a held item or a turret on a vehicle. The entity's
+/// EntityPos / EntityRot then get worked out from its parent's by tick_transform_hierarchy_system(),
+/// using its EntityLocalTransform (or sitting right on the parent, if it has none).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EntityParent(pub hecs::Entity);
+
+/// Offset of a child entity relative to its EntityParent, in the parent's space.
+#[derive(Copy, Clone, Debug)]
+pub struct EntityLocalTransform {
+	pub pos: EntityVec3,
+	pub rot: Quat,
+}
+impl EntityLocalTransform {
+	pub fn new(pos: EntityVec3, rot: Quat) -> Self {
+		Self { pos, rot }
+	}
+	/// Where something at this offset from a parent at (parent_pos, parent_rot) ends up in world space.
+	pub fn apply_to_parent(&self, parent_pos: EntityVec3, parent_rot: Quat) -> (EntityVec3, Quat) {
+		(parent_pos + parent_rot.mul_vec3(self.pos), (parent_rot * self.rot).normalize())
+	}
+}
+impl Default for EntityLocalTransform {
+	fn default() -> Self {
+		Self {
+			pos: EntityVec3::ZERO,
+			rot: Quat::IDENTITY,
+		}
+	}
+}
+
+/// Sets the world-space EntityPos and EntityRot of every entity with an EntityParent, from its parent's
+/// (already resolved) world transform plus its own EntityLocalTransform. Parents are always resolved before
+/// their children, however deep the hierarchy goes.
+/// Links which can't be followed get removed, leaving that entity wherever it was: one link out of any cycle
+/// of parents, and any link to a parent which has been despawned.
+pub fn tick_transform_hierarchy_system(world: &mut EcsWorld, _seconds_per_tick: TickLength) {
+	let links: HashMap<hecs::Entity, (hecs::Entity, EntityLocalTransform)> = world
+		.query_mut::<(&EntityParent, Option<&EntityLocalTransform>)>()
+		.into_iter()
+		.map(|(entity, (parent, local))| (entity, (parent.0, local.copied().unwrap_or_default())))
+		.collect();
+	if links.is_empty() {
+		return;
+	}
+
+	let mut resolved: HashMap<hecs::Entity, (EntityVec3, Quat)> = HashMap::with_capacity(links.len());
+	let mut broken: HashSet<hecs::Entity> = HashSet::new();
+	for start in links.keys() {
+		// Walk up towards the root, until we hit something whose world transform is already known.
+		let mut chain: Vec<hecs::Entity> = Vec::new();
+		let mut on_chain: HashSet<hecs::Entity> = HashSet::new();
+		let mut current = *start;
+		let root_transform = loop {
+			if let Some(transform) = resolved.get(&current) {
+				break *transform;
+			}
+			let parent = match links.get(&current) {
+				Some((parent, _)) if !broken.contains(&current) => *parent,
+				// Not a child of anything, so its own components are already in world space.
+				_ => {
+					let pos = world.get::<&EntityPos>(current).map(|pos| pos.get()).unwrap_or_default();
+					let rot = world.get::<&EntityRot>(current).map(|rot| rot.get()).unwrap_or_default();
+					break (pos, rot);
+				}
+			};
+			chain.push(current);
+			on_chain.insert(current);
+			if on_chain.contains(&parent) {
+				warn!("Entity {current:?} is part of a cycle of parents, detaching it from {parent:?}.");
+				broken.insert(current);
+				chain.pop();
+				continue;
+			}
+			if !world.contains(parent) {
+				warn!("Entity {current:?} has a parent ({parent:?}) which no longer exists, detaching it.");
+				broken.insert(current);
+				chain.pop();
+				continue;
+			}
+			current = parent;
+		};
+
+		// Then back down, composing transforms as we go.
+		let (mut pos, mut rot) = root_transform;
+		for entity in chain.iter().rev() {
+			let (_, local) = links[entity];
+			(pos, rot) = local.apply_to_parent(pos, rot);
+			resolved.insert(*entity, (pos, rot));
+		}
+	}
+
+	for entity in broken {
+		let _ = world.remove_one::<EntityParent>(entity);
+	}
+	for (entity, (pos, rot)) in resolved {
+		// Children are free to not have their own position or rotation yet.
+		let _ = world.insert(entity, (EntityPos::new(pos), EntityRot::new(rot)));
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn lifetime_despawns_entities() {
+		let mut world = EcsWorld::new();
+		let tick = TickLength::from_tps(10.0);
+		let short = world.spawn((EntityPos::default(), EntityLifetime::new(Duration::from_millis(250))));
+		let long = world.spawn((EntityPos::default(), EntityLifetime::new(Duration::from_secs(10))));
+		let forever = world.spawn((EntityPos::default(),));
+
+		assert!(tick_lifetime_system(&mut world, tick).is_empty());
+		assert!(tick_lifetime_system(&mut world, tick).is_empty());
+		assert!(world.contains(short));
+		assert_eq!(tick_lifetime_system(&mut world, tick), vec![short]);
+		assert!(!world.contains(short));
+
+		for _ in 0..10 {
+			tick_lifetime_system(&mut world, tick);
+		}
+		assert!(world.contains(long));
+		assert!(world.contains(forever));
+		assert!(world.get::<&EntityLifetime>(long).unwrap().remaining < Duration::from_secs(9));
+	}
+
+	#[test]
+	fn rotation_guess_is_a_slerp() {
+		let start = Quat::from_rotation_y(0.3);
+		let spin = EntityAngularVelocity::new(Quat::from_rotation_z(1.0));
+		let end = Quat::from_rotation_z(1.0) * start;
+
+		// Slerp by hand: sin((1-t)θ)/sin(θ) * a + sin(tθ)/sin(θ) * b, where cos(θ) = a·b.
+		let theta = start.dot(end).clamp(-1.0, 1.0).acos();
+		let (a, b) = (start * (0.5 * theta).sin() / theta.sin(), end * (0.5 * theta).sin() / theta.sin());
+		let manual = a + b;
+
+		let halfway = spin.guess_rotation(start, 0.5);
+		assert!(halfway.abs_diff_eq(manual, 0.0001), "{halfway} != {manual}");
+		// Same place a tick of that length would have put it.
+		let mut rot = EntityRot::new(start);
+		spin.apply_tick(&mut rot, TickLength::from_tps(2.0));
+		assert!(rot.get().abs_diff_eq(halfway, 0.0001));
+	}
+
+	#[test]
+	fn children_follow_parents() {
+		let mut world = EcsWorld::new();
+		let quarter_turn = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+		let root = world.spawn((EntityPos::new(EntityVec3::new(10.0, 0.0, 0.0)), EntityRot::new(quarter_turn)));
+		let child = world.spawn((
+			EntityParent(root),
+			EntityLocalTransform::new(EntityVec3::new(1.0, 0.0, 0.0), Quat::IDENTITY),
+		));
+		let grandchild = world.spawn((
+			EntityParent(child),
+			EntityLocalTransform::new(EntityVec3::new(1.0, 2.0, 0.0), Quat::IDENTITY),
+		));
+
+		tick_transform_hierarchy_system(&mut world, TickLength::default());
+		let child_pos = world.get::<&EntityPos>(child).unwrap().get();
+		assert!(child_pos.abs_diff_eq(EntityVec3::new(10.0, 0.0, -1.0), 0.0001));
+		let grandchild_pos = world.get::<&EntityPos>(grandchild).unwrap().get();
+		assert!(grandchild_pos.abs_diff_eq(EntityVec3::new(10.0, 2.0, -2.0), 0.0001));
+		assert!(world.get::<&EntityRot>(grandchild).unwrap().get().abs_diff_eq(quarter_turn, 0.0001));
+
+		// Moving the root drags everything along next tick.
+		world.get::<&mut EntityPos>(root).unwrap().move_by(EntityVec3::new(0.0, 5.0, 0.0));
+		tick_transform_hierarchy_system(&mut world, TickLength::default());
+		let grandchild_pos = world.get::<&EntityPos>(grandchild).unwrap().get();
+		assert!(grandchild_pos.abs_diff_eq(EntityVec3::new(10.0, 7.0, -2.0), 0.0001));
+	}
+
+	#[test]
+	fn parent_cycles_get_broken() {
+		let mut world = EcsWorld::new();
+		let a = world.spawn((EntityPos::new(EntityVec3::new(1.0, 0.0, 0.0)),));
+		let b = world.spawn((EntityPos::new(EntityVec3::new(0.0, 1.0, 0.0)),));
+		world.insert_one(a, EntityParent(b)).unwrap();
+		world.insert_one(b, EntityParent(a)).unwrap();
+
+		tick_transform_hierarchy_system(&mut world, TickLength::default());
+		let still_attached = [a, b].iter().filter(|entity| world.get::<&EntityParent>(**entity).is_ok()).count();
+		assert_eq!(still_attached, 1);
+		// Whichever one is left attached sits right on the other.
+		let a_pos = world.get::<&EntityPos>(a).unwrap().get();
+		let b_pos = world.get::<&EntityPos>(b).unwrap().get();
+		assert_eq!(a_pos, b_pos);
+	}
 }
@@ -0,0 +1,236 @@
+//! Saving every entity in an EcsWorld, and loading them back again.
+//!
+//! hecs can't tell us how to serialize an arbitrary component, so only component types which have been
+//! registered in a ComponentSaveRegistry get saved. Anything else is skipped with a warning.
+
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use hecs::{Component, EntityBuilder, EntityRef};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::{
+	DynamicComponents, EcsWorld, EntityAngularVelocity, EntityLifetime, EntityPos, EntityRot, EntityScale,
+	EntityVelocity, LastPos,
+};
+
+pub const ECS_SAVE_VERSION: u32 = 1;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EcsSaveError {
+	#[error("could not encode saved entities: {0}")]
+	Encode(#[from] rmp_serde::encode::Error),
+	#[error("could not decode saved entities: {0}")]
+	Decode(#[from] rmp_serde::decode::Error),
+	#[error("could not decode saved component \"{0}\": {1}")]
+	DecodeComponent(String, rmp_serde::decode::Error),
+	#[error("saved entities are from version {0} of the format, and we only understand up to {1}")]
+	UnsupportedVersion(u32, u32),
+}
+
+type SaveComponentFn = fn(&EntityRef) -> Option<Result<Vec<u8>, rmp_serde::encode::Error>>;
+type LoadComponentFn = fn(&[u8], &mut EntityBuilder) -> Result<(), rmp_serde::decode::Error>;
+
+struct SaveableComponent {
+	name: &'static str,
+	type_id: TypeId,
+	save: SaveComponentFn,
+	load: LoadComponentFn,
+}
+
+fn save_component<T: Component + Serialize>(entity: &EntityRef) -> Option<Result<Vec<u8>, rmp_serde::encode::Error>> {
+	entity.get::<&T>().map(|component| rmp_serde::to_vec(&*component))
+}
+fn load_component<T: Component + DeserializeOwned>(bytes: &[u8], builder: &mut EntityBuilder) -> Result<(), rmp_serde::decode::Error> {
+	let component: T = rmp_serde::from_slice(bytes)?;
+	builder.add(component);
+	Ok(())
+}
+
+/// Which component types get saved, and the names they're saved under. Names have to stay the same
+/// between saving and loading - the Rust type names aren't used, so types can be renamed freely.
+pub struct ComponentSaveRegistry {
+	components: Vec<SaveableComponent>,
+}
+
+impl ComponentSaveRegistry {
+	/// A registry which knows about no components at all.
+	pub fn new() -> Self {
+		Self { components: Vec::new() }
+	}
+	/// A registry which knows about every serializable component defined in the entity module.
+	/// Components defined elsewhere aren't included - e.g. the client's BillboardDrawable gets added by
+	/// client::render::drawable::register_saveable_components(), since the entity module can't depend on rendering.
+	pub fn with_core_components() -> Self {
+		let mut registry = Self::new();
+		registry.register::<EntityPos>("pos");
+		registry.register::<EntityRot>("rot");
+		registry.register::<EntityScale>("scale");
+		registry.register::<EntityVelocity>("velocity");
+		registry.register::<EntityAngularVelocity>("angular_velocity");
+		registry.register::<LastPos>("last_pos");
+		registry.register::<EntityLifetime>("lifetime");
+		registry.register::<DynamicComponents>("dynamic");
+		registry
+	}
+	/// Registering the same name twice replaces the earlier registration.
+	pub fn register<T: Component + Serialize + DeserializeOwned>(&mut self, name: &'static str) {
+		self.components.retain(|component| component.name != name);
+		self.components.push(SaveableComponent {
+			name,
+			type_id: TypeId::of::<T>(),
+			save: save_component::<T>,
+			load: load_component::<T>,
+		});
+	}
+	fn get_by_name(&self, name: &str) -> Option<&SaveableComponent> {
+		self.components.iter().find(|component| component.name == name)
+	}
+	fn knows_type(&self, type_id: TypeId) -> bool {
+		self.components.iter().any(|component| component.type_id == type_id)
+	}
+}
+
+impl Default for ComponentSaveRegistry {
+	fn default() -> Self {
+		Self::with_core_components()
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntity {
+	/// hecs::Entity::to_bits(), so the entity can come back under the same ID.
+	id: u64,
+	components: Vec<(String, Vec<u8>)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEcsWorld {
+	version: u32,
+	entities: Vec<SavedEntity>,
+}
+
+/// Write out every entity in the world, along with every component of theirs the registry knows about.
+pub fn save_ecs_world<W: Write>(world: &EcsWorld, registry: &ComponentSaveRegistry, writer: &mut W) -> Result<(), EcsSaveError> {
+	let mut unknown_types: HashSet<TypeId> = HashSet::new();
+	let mut entities = Vec::with_capacity(world.len() as usize);
+	for entity in world.iter() {
+		let mut components = Vec::new();
+		for saveable in registry.components.iter() {
+			if let Some(bytes) = (saveable.save)(&entity) {
+				components.push((saveable.name.to_string(), bytes?));
+			}
+		}
+		unknown_types.extend(entity.component_types().filter(|type_id| !registry.knows_type(*type_id)));
+		entities.push(SavedEntity {
+			id: entity.entity().to_bits().get(),
+			components,
+		});
+	}
+	if !unknown_types.is_empty() {
+		warn!("Skipped {} component type(s) while saving entities, since they aren't registered as saveable.", unknown_types.len());
+	}
+	let saved = SavedEcsWorld {
+		version: ECS_SAVE_VERSION,
+		entities,
+	};
+	rmp_serde::encode::write(writer, &saved)?;
+	Ok(())
+}
+
+/// Read entities written by save_ecs_world() into a new world. Entities keep the IDs they were saved with.
+/// Components the registry doesn't know are skipped with a warning.
+pub fn load_ecs_world<R: Read>(registry: &ComponentSaveRegistry, reader: &mut R) -> Result<EcsWorld, EcsSaveError> {
+	let saved: SavedEcsWorld = rmp_serde::from_read(reader)?;
+	if saved.version > ECS_SAVE_VERSION {
+		return Err(EcsSaveError::UnsupportedVersion(saved.version, ECS_SAVE_VERSION));
+	}
+	let mut world = EcsWorld::new();
+	let mut builder = EntityBuilder::new();
+	for saved_entity in saved.entities {
+		for (name, bytes) in saved_entity.components.iter() {
+			match registry.get_by_name(name) {
+				Some(saveable) => (saveable.load)(bytes, &mut builder)
+					.map_err(|e| EcsSaveError::DecodeComponent(name.clone(), e))?,
+				None => warn!("Skipping saved component \"{name}\", since it isn't registered as saveable."),
+			}
+		}
+		match hecs::Entity::from_bits(saved_entity.id) {
+			Some(entity) => world.spawn_at(entity, builder.build()),
+			None => {
+				warn!("Saved entity ID {} is invalid, spawning it under a new ID.", saved_entity.id);
+				world.spawn(builder.build());
+			}
+		}
+	}
+	Ok(world)
+}
+
+/// save() and load() for EcsWorld itself, using the core components. Use save_ecs_world() and load_ecs_world()
+/// directly to save other components too.
+pub trait EcsWorldPersistence: Sized {
+	fn save<W: Write>(&self, writer: &mut W) -> Result<(), EcsSaveError>;
+	fn load<R: Read>(reader: &mut R) -> Result<Self, EcsSaveError>;
+}
+
+impl EcsWorldPersistence for EcsWorld {
+	fn save<W: Write>(&self, writer: &mut W) -> Result<(), EcsSaveError> {
+		save_ecs_world(self, &ComponentSaveRegistry::with_core_components(), writer)
+	}
+	fn load<R: Read>(reader: &mut R) -> Result<Self, EcsSaveError> {
+		load_ecs_world(&ComponentSaveRegistry::with_core_components(), reader)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::entity::{DynamicComponentAccess, EntityLifetime, EntityVec3};
+	use glam::Quat;
+	use std::time::Duration;
+
+	/// Never registered, so it should get left behind.
+	#[derive(Debug)]
+	struct NotSaveable;
+
+	#[test]
+	fn save_and_load_round_trip() {
+		let mut world = EcsWorld::new();
+		let first = world.spawn((
+			EntityPos::new(EntityVec3::new(1.0, 2.0, 3.0)),
+			EntityRot::new(Quat::from_rotation_y(0.5)),
+			EntityScale::new(EntityVec3::splat(2.0)),
+		));
+		let second = world.spawn((
+			EntityPos::new(EntityVec3::new(-4.0, 0.0, 9.5)),
+			EntityVelocity::new(EntityVec3::new(0.0, 0.0, 0.5)),
+			EntityLifetime::new(Duration::from_millis(2500)),
+			NotSaveable,
+		));
+		world.set_dynamic(second, "health", &42u32).unwrap();
+		// Leave a gap in the entity IDs, to check they're kept rather than renumbered.
+		let gone = world.spawn((EntityPos::default(),));
+		world.despawn(gone).unwrap();
+		let third = world.spawn((EntityPos::default(),));
+
+		let mut buffer = Vec::new();
+		world.save(&mut buffer).unwrap();
+		let loaded = EcsWorld::load(&mut buffer.as_slice()).unwrap();
+
+		assert_eq!(loaded.len(), 3);
+		assert_eq!(*loaded.get::<&EntityPos>(first).unwrap(), *world.get::<&EntityPos>(first).unwrap());
+		assert_eq!(*loaded.get::<&EntityRot>(first).unwrap(), *world.get::<&EntityRot>(first).unwrap());
+		assert_eq!(*loaded.get::<&EntityScale>(first).unwrap(), *world.get::<&EntityScale>(first).unwrap());
+		assert_eq!(*loaded.get::<&EntityPos>(second).unwrap(), *world.get::<&EntityPos>(second).unwrap());
+		assert_eq!(*loaded.get::<&EntityVelocity>(second).unwrap(), *world.get::<&EntityVelocity>(second).unwrap());
+		assert_eq!(loaded.get_dynamic::<u32>(second, "health").unwrap(), Some(42));
+		// Otherwise a saved projectile would never expire once loaded.
+		assert_eq!(*loaded.get::<&EntityLifetime>(second).unwrap(), *world.get::<&EntityLifetime>(second).unwrap());
+		assert!(loaded.get::<&NotSaveable>(second).is_err());
+		assert!(loaded.contains(third));
+		assert!(loaded.get::<&EntityVelocity>(first).is_err());
+	}
+}
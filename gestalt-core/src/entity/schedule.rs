@@ -0,0 +1,232 @@
+//! Runs the game's ECS systems once per tick, in an order which is spelled out explicitly
+//! rather than being whatever order the calls happen to be written in the tick loop.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::EcsWorld;
+use crate::world::TickLength;
+
+pub type SystemFn = Box<dyn FnMut(&mut EcsWorld, TickLength) + Send>;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+	#[error("a system named \"{0}\" has already been added to this schedule")]
+	DuplicateSystem(&'static str),
+	#[error("no system named \"{0}\" has been added to this schedule")]
+	UnknownSystem(&'static str),
+	#[error("system \"{0}\" can't be ordered against \"{1}\", since that would make them depend on each other")]
+	Cycle(&'static str, &'static str),
+}
+
+/// Where a newly-added system has to run relative to systems already in the schedule.
+/// Systems with no constraints between them run in the order they were added.
+#[derive(Clone, Debug, Default)]
+pub struct SystemOrdering {
+	before: Vec<&'static str>,
+	after: Vec<&'static str>,
+}
+
+impl SystemOrdering {
+	pub fn before(mut self, system: &'static str) -> Self {
+		self.before.push(system);
+		self
+	}
+	pub fn after(mut self, system: &'static str) -> Self {
+		self.after.push(system);
+		self
+	}
+}
+
+struct ScheduledSystem {
+	name: &'static str,
+	enabled: bool,
+	system: SystemFn,
+}
+
+/// A list of systems to run each tick. Systems are run one after another on the calling thread.
+#[derive(Default)]
+pub struct SystemSchedule {
+	systems: Vec<ScheduledSystem>,
+	/// (first, second) pairs of indices into systems, meaning first has to run before second.
+	constraints: Vec<(usize, usize)>,
+	/// Indices into systems, in the order they will run.
+	order: Vec<usize>,
+}
+
+impl SystemSchedule {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn index_of(&self, name: &'static str) -> Result<usize, ScheduleError> {
+		self.systems
+			.iter()
+			.position(|scheduled| scheduled.name == name)
+			.ok_or(ScheduleError::UnknownSystem(name))
+	}
+
+	/// Add a system which runs after every system added before it (unless something added later says otherwise).
+	pub fn add_system<F>(&mut self, name: &'static str, system: F) -> Result<(), ScheduleError>
+	where
+		F: FnMut(&mut EcsWorld, TickLength) + Send + 'static,
+	{
+		self.add_system_ordered(name, SystemOrdering::default(), system)
+	}
+
+	/// Add a system which has to run before and/or after particular systems already in the schedule.
+	/// On error, the schedule is left exactly as it was.
+	pub fn add_system_ordered<F>(
+		&mut self,
+		name: &'static str,
+		ordering: SystemOrdering,
+		system: F,
+	) -> Result<(), ScheduleError>
+	where
+		F: FnMut(&mut EcsWorld, TickLength) + Send + 'static,
+	{
+		if self.index_of(name).is_ok() {
+			return Err(ScheduleError::DuplicateSystem(name));
+		}
+		let new_index = self.systems.len();
+		let mut new_constraints = Vec::with_capacity(ordering.before.len() + ordering.after.len());
+		for other in ordering.before {
+			new_constraints.push((new_index, self.index_of(other)?));
+		}
+		for other in ordering.after {
+			new_constraints.push((self.index_of(other)?, new_index));
+		}
+
+		let constraints_len = self.constraints.len();
+		self.constraints.extend(new_constraints);
+		match Self::sort(new_index + 1, &self.constraints) {
+			Ok(order) => {
+				self.order = order;
+				self.systems.push(ScheduledSystem {
+					name,
+					enabled: true,
+					system: Box::new(system),
+				});
+				Ok(())
+			}
+			Err(stuck) => {
+				self.constraints.truncate(constraints_len);
+				let stuck_name = if stuck == new_index { name } else { self.systems[stuck].name };
+				Err(ScheduleError::Cycle(name, stuck_name))
+			}
+		}
+	}
+
+	/// Topological sort which, whenever it has a choice, picks whichever system was added first.
+	/// On failure, returns one of the systems caught up in a cycle.
+	fn sort(count: usize, constraints: &[(usize, usize)]) -> Result<Vec<usize>, usize> {
+		let mut waiting_on = vec![0usize; count];
+		for (_, second) in constraints {
+			waiting_on[*second] += 1;
+		}
+		let mut ready: BinaryHeap<Reverse<usize>> = (0..count)
+			.filter(|index| waiting_on[*index] == 0)
+			.map(Reverse)
+			.collect();
+		let mut order = Vec::with_capacity(count);
+		while let Some(Reverse(next)) = ready.pop() {
+			order.push(next);
+			for (_, second) in constraints.iter().filter(|(first, _)| *first == next) {
+				waiting_on[*second] -= 1;
+				if waiting_on[*second] == 0 {
+					ready.push(Reverse(*second));
+				}
+			}
+		}
+		if order.len() == count {
+			Ok(order)
+		} else {
+			Err((0..count).find(|index| waiting_on[*index] != 0).unwrap())
+		}
+	}
+
+	/// Turn a system on or off. Disabled systems keep their place in the order, they just get skipped.
+	pub fn set_enabled(&mut self, name: &'static str, enabled: bool) -> Result<(), ScheduleError> {
+		let index = self.index_of(name)?;
+		self.systems[index].enabled = enabled;
+		Ok(())
+	}
+
+	/// None if there is no system by that name.
+	pub fn is_enabled(&self, name: &'static str) -> Option<bool> {
+		self.index_of(name).ok().map(|index| self.systems[index].enabled)
+	}
+
+	/// Names of every system, in the order they run (including disabled ones).
+	pub fn get_order(&self) -> Vec<&'static str> {
+		self.order.iter().map(|index| self.systems[*index].name).collect()
+	}
+
+	/// Run every enabled system once, in order.
+	pub fn run(&mut self, world: &mut EcsWorld, tick_length: TickLength) {
+		for index in self.order.iter() {
+			let scheduled = &mut self.systems[*index];
+			if scheduled.enabled {
+				(scheduled.system)(world, tick_length);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::{Arc, Mutex};
+
+	use super::*;
+
+	#[test]
+	fn systems_run_in_declared_order() {
+		let ran: Arc<Mutex<Vec<&'static str>>> = Arc::new(Mutex::new(Vec::new()));
+		let logger = |name: &'static str| {
+			let ran = ran.clone();
+			move |_: &mut EcsWorld, _: TickLength| ran.lock().unwrap().push(name)
+		};
+
+		let mut schedule = SystemSchedule::new();
+		schedule.add_system("movement", logger("movement")).unwrap();
+		schedule.add_system("lifetime", logger("lifetime")).unwrap();
+		schedule
+			.add_system_ordered("collision", SystemOrdering::default().after("movement").before("lifetime"), logger("collision"))
+			.unwrap();
+		schedule
+			.add_system_ordered("input", SystemOrdering::default().before("movement"), logger("input"))
+			.unwrap();
+		assert_eq!(schedule.get_order(), vec!["input", "movement", "collision", "lifetime"]);
+
+		let mut world = EcsWorld::new();
+		schedule.run(&mut world, TickLength::default());
+		assert_eq!(*ran.lock().unwrap(), vec!["input", "movement", "collision", "lifetime"]);
+
+		ran.lock().unwrap().clear();
+		schedule.set_enabled("collision", false).unwrap();
+		assert_eq!(schedule.is_enabled("collision"), Some(false));
+		schedule.run(&mut world, TickLength::default());
+		assert_eq!(*ran.lock().unwrap(), vec!["input", "movement", "lifetime"]);
+	}
+
+	#[test]
+	fn bad_orderings_are_rejected() {
+		let mut schedule = SystemSchedule::new();
+		schedule.add_system("a", |_, _| {}).unwrap();
+		schedule.add_system_ordered("b", SystemOrdering::default().after("a"), |_, _| {}).unwrap();
+
+		assert_eq!(schedule.add_system("a", |_, _| {}), Err(ScheduleError::DuplicateSystem("a")));
+		assert_eq!(
+			schedule.add_system_ordered("c", SystemOrdering::default().after("z"), |_, _| {}),
+			Err(ScheduleError::UnknownSystem("z"))
+		);
+		assert!(matches!(
+			schedule.add_system_ordered("c", SystemOrdering::default().after("b").before("a"), |_, _| {}),
+			Err(ScheduleError::Cycle("c", _))
+		));
+		// Failed additions leave nothing behind.
+		assert_eq!(schedule.get_order(), vec!["a", "b"]);
+		schedule.add_system_ordered("c", SystemOrdering::default().before("a"), |_, _| {}).unwrap();
+		assert_eq!(schedule.get_order(), vec!["c", "a", "b"]);
+	}
+}
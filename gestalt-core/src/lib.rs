@@ -0,0 +1,127 @@
+//! Voxel social-art-space "game" you can have some fun in.
+//! This is the engine itself - the gestalt binary (main.rs) is a thin launcher on top of it.
+#![allow(incomplete_features)]
+#![feature(extract_if)]
+#![feature(str_from_raw_parts)]
+#![feature(string_remove_matches)]
+#![feature(generic_const_exprs)]
+#![feature(int_roundings)]
+#![feature(inherent_associated_types)]
+#![feature(array_try_from_fn)]
+#![feature(trivial_bounds)]
+#![allow(clippy::large_enum_variant)]
+
+#[macro_use]
+pub mod common;
+pub mod main_channels;
+pub use common::message;
+pub use crate::main_channels::*;
+use semver::Version;
+
+#[macro_use]
+pub mod net;
+
+#[macro_use]
+pub mod resource;
+
+pub mod admin_console;
+
+pub mod client;
+pub mod entity;
+pub mod message_types;
+pub mod script;
+pub mod server;
+pub mod world;
+
+use std::sync::Arc;
+
+use log::warn;
+
+use common::{identity::{NodeIdentity, PublicKey}, message::*};
+
+pub const ENGINE_VERSION: Version = Version::new(0,0,1);
+
+/// Decides whether to go ahead with a handshake when a peer shows up with a protocol key we haven't pinned for them.
+pub trait KeyChangeApprover: Send + Sync {
+	/// `old_pinned` is the key we had on record for this peer, if the caller knows it.
+	fn approve(&self, ident: &NodeIdentity, old_pinned: Option<&PublicKey>) -> bool;
+}
+
+/// Approves every key change. This was the only behavior before approvers were pluggable.
+pub struct DefaultApprover;
+
+impl KeyChangeApprover for DefaultApprover {
+	fn approve(&self, ident: &NodeIdentity, _old_pinned: Option<&PublicKey>) -> bool {
+		warn!(
+			"Protocol key has changed for peer {:?} - most likely this is the same user \n\
+		connecting with a new device, but it's possible it's an attempt to impersonate them.",
+			ident.to_base64()
+		);
+		//Approve implicitly.
+		//When GUI is a thing, we want this to generate a popup for clients.
+		true
+	}
+}
+
+pub async fn protocol_key_change_approver(
+	mut receiver: BroadcastReceiver<NodeIdentity>,
+	sender: BroadcastSender<(NodeIdentity, bool)>,
+	approver: Arc<dyn KeyChangeApprover>,
+) {
+	loop {
+		match receiver.recv_wait().await {
+			Ok(ident) => {
+				// Mismatch reports only carry the identity, so there's no old key to hand over.
+				let approved = approver.approve(&ident, None);
+				if !approved {
+					warn!("Rejected a new protocol key for peer {}.", ident.to_base64());
+				}
+				sender.send((ident.clone(), approved)).unwrap();
+			}
+			Err(e) => panic!("Protocol key change approver channel died: {:?}", e),
+		}
+	}
+}
+
+pub fn init_channels() -> MainChannelSet { 
+	let conf = ChannelCapacityConf::new(); 
+	MainChannelSet::new(&conf)
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::Duration;
+
+	use super::*;
+	use common::identity::IdentityKeyPair;
+
+	struct RejectingApprover;
+
+	impl KeyChangeApprover for RejectingApprover {
+		fn approve(&self, _ident: &NodeIdentity, _old_pinned: Option<&PublicKey>) -> bool {
+			false
+		}
+	}
+
+	#[tokio::test]
+	async fn rejected_key_change_is_reported_back() {
+		let report_channel: BroadcastChannel<NodeIdentity> = BroadcastChannel::new(16);
+		let approve_channel: BroadcastChannel<(NodeIdentity, bool)> = BroadcastChannel::new(16);
+		let mut replies = approve_channel.receiver_subscribe();
+		let approver_task = tokio::spawn(protocol_key_change_approver(
+			report_channel.receiver_subscribe(),
+			approve_channel.sender_subscribe(),
+			Arc::new(RejectingApprover),
+		));
+
+		let ident = IdentityKeyPair::generate_for_tests().public;
+		report_channel.sender_subscribe().send(ident.clone()).unwrap();
+		let reply = tokio::time::timeout(Duration::from_secs(5), replies.recv_wait())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(reply, (ident, false));
+
+		approver_task.abort();
+	}
+}
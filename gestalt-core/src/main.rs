@@ -1,41 +1,12 @@
-//! Voxel social-art-space "game" you can have some fun in.
-#![allow(incomplete_features)]
-#![feature(extract_if)]
-#![feature(str_from_raw_parts)]
-#![feature(string_remove_matches)]
-#![feature(generic_const_exprs)]
-#![feature(int_roundings)]
-#![feature(inherent_associated_types)]
-#![feature(array_try_from_fn)]
-#![feature(trivial_bounds)]
-#![allow(clippy::large_enum_variant)]
-
-#[macro_use]
-pub mod common;
-pub mod main_channels;
-use clap::Parser;
-pub use common::message;
-use net::{generated::get_netmsg_table, NetMsg, PacketIntermediary};
-pub use crate::main_channels::*;
-use semver::Version;
-
-#[macro_use]
-pub mod net;
-
-#[macro_use]
-pub mod resource;
+//! Launcher for the Gestalt engine - parses arguments, loads identity keys, and starts a client or server.
 
-//pub mod client;
-pub mod entity;
-pub mod message_types;
-pub mod script;
-pub mod server;
-pub mod world;
+use clap::Parser;
 
 use std::{
 	io::Write,
 	net::{IpAddr, Ipv6Addr, SocketAddr},
 	path::PathBuf,
+	sync::Arc,
 	time::Duration,
 };
 
@@ -44,53 +15,35 @@ use simplelog::{
 	ColorChoice, CombinedLogger, ConfigBuilder, TermLogger, TerminalMode, WriteLogger,
 };
 
-use common::{
-	identity::{do_keys_need_generating, gen_and_save_keys, load_keyfile, NodeIdentity},
-	message::*
-};
-
-use crate::{
-	message::QuitReceiver,
+use gestalt_core::{
+	admin_console::AdminConsole,
+	common::identity::{
+		check_crypto_backend, do_keys_need_generating, gen_and_save_keys, load_identity_from_env, load_keyfile,
+		read_identity_env_var, try_read_with_env_passphrase, IdentityKeyPair, KeyPairLoadError, PassphraseStrength, IDENTITY_KEY_ENV_VAR,
+		IDENTITY_PASSPHRASE_ENV_VAR, MIN_PASSPHRASE_LEN,
+	},
+	init_channels,
+	message::{self, BuildSubset, MessageReceiverAsync, QuitReceiver, ReceiverSubscribe, SenderSubscribe, SubsetBuilder},
 	message_types::{
-		voxel::{VoxelChangeAnnounce, VoxelChangeRequest},
-		JoinAnnounce, JoinDefaultEntry,
+		voxel::VoxelChangeRequest,
+		JoinAnnounce,
 	},
 	net::{
 		default_protocol_store_dir,
-		preprotocol::{launch_preprotocol_listener, preprotocol_connect_to_server},
+		generated::get_netmsg_table,
+		preprotocol::{launch_preprotocol_listener, preprotocol_connect_to_server, reconnect_on_timeout},
 		reliable_udp::LaminarConfig,
-		NetworkSystem, SelfNetworkRole,
+		NetworkSystem, SelfNetworkRole, DEFAULT_MAX_MESSAGE_SIZE,
+	},
+	protocol_key_change_approver, server, DefaultApprover,
+	world::{
+		fsworldstorage,
+		worldgen::{NoiseWorldGen, WorldGenQueue},
+		WorldId,
 	},
+	ENGINE_VERSION,
 };
 
-pub const ENGINE_VERSION: Version = Version::new(0,0,1);
-
-pub async fn protocol_key_change_approver(
-	mut receiver: BroadcastReceiver<NodeIdentity>,
-	sender: BroadcastSender<(NodeIdentity, bool)>,
-) {
-	loop {
-		match receiver.recv_wait().await {
-			Ok(ident) => {
-				warn!(
-					"Protocol key has changed for peer {:?} - most likely this is the same user \n\
-				connecting with a new device, but it's possible it's an attempt to impersonate them.",
-					ident.to_base64()
-				);
-				//Approve implicitly.
-				//When GUI is a thing, we want this to generate a popup for clients.
-				sender.send((ident.clone(), true)).unwrap();
-			}
-			Err(e) => panic!("Protocol key change approver channel died: {:?}", e),
-		}
-	}
-}
-
-pub fn init_channels() -> MainChannelSet { 
-	let conf = ChannelCapacityConf::new(); 
-	MainChannelSet::new(&conf)
-}
-
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
@@ -104,6 +57,90 @@ struct Args {
 	verbose: bool,
 }
 
+/// Prints a prompt and reads one line from stdin, including its newline. Returns an empty string if stdin is closed.
+fn prompt_line(prompt: &str) -> Result<String, std::io::Error> {
+	print!("{prompt}");
+	std::io::stdout().flush()?;
+	let mut input = String::new();
+	std::io::stdin().read_line(&mut input)?;
+	Ok(input)
+}
+
+/// Loads our identity key pair, generating it first if there isn't one yet.
+/// Prompts on the command line for passphrases, and asks again if the passphrase was wrong.
+/// If the key or passphrase are given in environment variables (see IDENTITY_KEY_ENV_VAR), those are
+/// used instead, without prompting.
+fn load_identity(key_dir: PathBuf, keyfile_name: &str) -> Result<IdentityKeyPair, Box<dyn std::error::Error>> {
+	check_crypto_backend()?;
+	if let Some(keys) = load_identity_from_env()? {
+		info!("Using the identity key from {IDENTITY_KEY_ENV_VAR}.");
+		return Ok(keys);
+	}
+	let env_passphrase = read_identity_env_var(IDENTITY_PASSPHRASE_ENV_VAR)?;
+	if do_keys_need_generating(key_dir.clone(), keyfile_name)? {
+		println!("No identity keys found, generating identity keys.");
+		println!("Optionally enter a passphrase.");
+		println!("Minimum length is {MIN_PASSPHRASE_LEN} characters.");
+		println!("WARNING: If you forget your passphrase, this will be impossible to recover!");
+		println!("Leave this blank if you do not want to use a passphrase.");
+		let passphrase = 'passphrase: loop {
+			let input = prompt_line("Enter your passphrase: ")?;
+			let entered = input.trim_end_matches(['\r', '\n']);
+			if entered.is_empty() {
+				break None;
+			}
+			match PassphraseStrength::estimate(entered) {
+				PassphraseStrength::TooShort => {
+					println!("Passphrase must be at least {MIN_PASSPHRASE_LEN} characters long. Please try again.");
+					continue;
+				}
+				PassphraseStrength::Weak => {
+					println!("Warning: this passphrase is weak. A longer one mixing letters, numbers and symbols would be much harder to guess.");
+				}
+				PassphraseStrength::Fair | PassphraseStrength::Strong => {}
+			}
+			// Only the confirmation gets re-typed on a mismatch, unless it's left blank to start over.
+			loop {
+				let confirm = prompt_line("Confirm your passphrase (leave blank to choose a different one): ")?;
+				if confirm == input {
+					break 'passphrase Some(input);
+				} else if confirm.trim_end_matches(['\r', '\n']).is_empty() {
+					continue 'passphrase;
+				} else {
+					println!("The confirmation does not match the passphrase you entered. Please type the confirmation again.");
+				}
+			}
+		};
+
+		gen_and_save_keys(passphrase.as_deref(), key_dir, keyfile_name)
+	} else {
+		let key_file = load_keyfile(key_dir, keyfile_name)?;
+		if !key_file.needs_passphrase() {
+			return Ok(key_file.try_read(None)?);
+		}
+		if let Some(passphrase) = env_passphrase {
+			// Nobody is at a prompt to try again, so a wrong passphrase here is just an error.
+			info!("Decrypting the identity key with the passphrase from {IDENTITY_PASSPHRASE_ENV_VAR}.");
+			return Ok(try_read_with_env_passphrase(&key_file, &passphrase)?);
+		}
+		println!("Your identity key is encrypted. Please enter your passphrase.");
+		loop {
+			let input = prompt_line("Passphrase: ")?;
+			if input.is_empty() {
+				// Stdin is closed, nobody is going to type the right passphrase.
+				return Err(Box::new(KeyPairLoadError::NoPassphrase));
+			}
+			match key_file.clone().try_read(Some(input.as_str())) {
+				Ok(keys) => return Ok(keys),
+				Err(e) if e.is_wrong_passphrase() => {
+					println!("Incorrect passphrase, please try again.");
+				}
+				Err(e) => return Err(Box::new(e)),
+			}
+		}
+	}
+}
+
 #[allow(unused_must_use)]
 fn main() {
 	// Announce the engine launching, for our command-line friends.
@@ -159,62 +196,20 @@ fn main() {
 	let key_dir = PathBuf::from("keys/");
 	let keyfile_name = "identity_key";
 	// Load our identity key pair. Right now this will be the same on both client and server - that will change later.
-	// Using environment variables here might also be a good move.
-	let keys = if do_keys_need_generating(key_dir.clone(), keyfile_name) {
-		println!("No identity keys found, generating identity keys.");
-		println!("Optionally enter a passphrase.");
-		println!("Minimum length is 4 characters.");
-		println!("WARNING: If you forget your passphrase, this will be impossible to recover!");
-		println!("Leave this blank if you do not want to use a passphrase.");
-		let mut input = String::new();
-		loop { 
-			print!("Enter your passphrase: ");
-			std::io::stdout().flush().unwrap();
-
-			std::io::stdin()
-				.read_line(&mut input)
-				.expect("Error reading from STDIN");
-			print!("Confirm your passphrase: ");
-			std::io::stdout().flush().unwrap();
-			let mut confirm = String::new();
-			std::io::stdin()
-				.read_line(&mut confirm)
-				.expect("Error reading from STDIN");
-			if confirm == input { 
-				break;
-			} else {
-				println!("Passphrases do not match! Please try again.");
-				input = String::new();
-			}
+	let keys = match load_identity(key_dir, keyfile_name) {
+		Ok(keys) => keys,
+		Err(e) => {
+			error!("Could not load identity keys: {e}");
+			eprintln!("Could not load identity keys: {e}");
+			std::process::exit(1);
 		}
-
-		// If it's 1 char, that's a newline or a \0
-		let passphrase = if input.chars().count() > 1 {
-			Some(input.as_str())
-		} else {
-			None
-		};
-
-		gen_and_save_keys(passphrase, key_dir.clone(), keyfile_name).unwrap()
-	} else {
-		let key_file = load_keyfile(key_dir.clone(), keyfile_name).unwrap();
-		let passphrase = if key_file.needs_passphrase() {
-			println!("Your identity key is encrypted. Please enter your passphrase.");
-			print!("Passphrase: ");
-			std::io::stdout().flush().unwrap();
-
-			let mut input = String::new();
-			std::io::stdin()
-				.read_line(&mut input)
-				.expect("Error reading from STDIN");
-			Some(input)
-		} else {
-			None
-		};
-		key_file
-			.try_read(passphrase.as_ref().map(|v| v.as_str()))
-			.unwrap()
 	};
+	// Don't hand our key to anything we launch later.
+	// SAFETY: No other threads have been started yet - the async runtime gets built below.
+	unsafe {
+		std::env::remove_var(IDENTITY_KEY_ENV_VAR);
+		std::env::remove_var(IDENTITY_PASSPHRASE_ENV_VAR);
+	}
 
 	info!("Identity keys loaded! Initializing engine...");
 
@@ -235,6 +230,7 @@ fn main() {
 	async_runtime.spawn(protocol_key_change_approver(
 		channels.net_channels.key_mismatch_reporter.receiver_subscribe(),
 		channels.net_channels.key_mismatch_approver.sender_subscribe(),
+		Arc::new(DefaultApprover),
 	));
 
 	let mut laminar_config = LaminarConfig::default();
@@ -267,21 +263,21 @@ fn main() {
 		));
 
 		info!("Spawning network system task.");
-		let keys_for_net = keys.clone();
 		let net_channels = channels.net_channels.build_subset(SubsetBuilder::new(())).unwrap();
-		let net_system_join_handle = async_runtime.spawn(async move {
-			let mut sys = NetworkSystem::new(
+		let mut net_system = async_runtime
+			.block_on(NetworkSystem::new(
 				SelfNetworkRole::Server,
 				udp_address,
-				keys_for_net,
+				keys,
 				laminar_config,
+				DEFAULT_MAX_MESSAGE_SIZE,
 				Duration::from_millis(25),
-				net_channels
-			)
-			.await
+				net_channels,
+			))
 			.unwrap();
-			sys.run().await
-		});
+		// For the admin console's list command.
+		let connected_peers = net_system.connected_peers_handle();
+		let net_system_join_handle = async_runtime.spawn(async move { net_system.run().await });
 
 		//let test_world_range: VoxelRange<i32> = VoxelRange{upper: vpos!(3,3,3), lower: vpos!(-2,-2,-2) };
 		//let mut world_space = TileSpace::new();
@@ -297,59 +293,43 @@ fn main() {
 		//let world_id = get_lobby_world_id(&keys.public);
 		//load_or_generate_dev_world(&mut world_space, &world_id, test_world_range, None).unwrap();
 
-		info!("Launching server mainloop.");
-		let mut total_changes: Vec<VoxelChangeAnnounce> = Vec::new();
-		let net_channels = channels.net_channels.clone();
-		async_runtime.block_on(async move {
-			let mut quit_receiver = QuitReceiver::new();
-			let mut voxel_from_client =
-				net_channels.net_msg_inbound.receiver_typed::<VoxelChangeAnnounce>().unwrap();
-			let mut joins_to_server =
-				net_channels.net_msg_inbound.receiver_typed::<JoinDefaultEntry>().unwrap();
-			let net_msg_broadcast = net_channels.net_msg_outbound.sender_subscribe_all();
-			loop {
-				tokio::select! {
-					voxel_events_maybe = voxel_from_client.recv_wait() => {
-						if let Ok(voxel_events) = voxel_events_maybe {
-							for (ident, event) in voxel_events {
-								//world_space.set(event.pos, event.new_tile).unwrap();
-								info!("Received {:?} from {}", &event, ident.to_base64());
-								let announce: VoxelChangeAnnounce = event.into();
-								net_msg_broadcast.send_to_all_except(vec![announce.clone().construct_packet().unwrap()], &ident).unwrap();
-								total_changes.push(announce);
-							}
-						}
-					}
-					join_event_maybe = joins_to_server.recv_wait() => {
-						if let Ok(events) = join_event_maybe {
-							for (ident, event) in events {
-								info!("User {} has joined with display name {}", ident.to_base64(), &event.display_name);
-								let announce = JoinAnnounce {
-									display_name: event.display_name,
-									identity: ident,
-								};
-								net_msg_broadcast.send_to_all_except(vec![announce.clone().construct_packet().unwrap()], &ident).unwrap();
-								info!("Sending all previous changes to the newly-joined user.");
-
-								let sender_to_new_join = net_channels.net_msg_outbound.sender_subscribe_domain(&ident).unwrap();
-								sender_to_new_join.send(
-									total_changes
-										.iter()
-										.map(|ev| ev.construct_packet().unwrap())
-										.collect::<Vec<PacketIntermediary>>()
-								).unwrap();
-							}
-						}
-					}
-					quit_ready_indicator = quit_receiver.wait_for_quit() => {
-						quit_ready_indicator.notify_ready();
-						break;
-					}
-				}
+		let server_config = server::load_server_config().unwrap();
+		let lobby_world_uuid = match fsworldstorage::load_or_create_lobby_world_uuid(&server_config.world_dir) {
+			Ok(uuid) => uuid,
+			Err(e) => {
+				error!("Could not load or create the lobby world's ID: {e}");
+				std::process::exit(1);
 			}
-		});
-		message::quit_game(Duration::from_secs(10));
+		};
+		let world = server::HostedWorld::new(
+			WorldId {
+				uuid: lobby_world_uuid,
+				host: keys.public,
+			},
+			server_config.world_dir.clone(),
+		);
+
+		info!("Spawning admin console task.");
+		async_runtime.spawn(AdminConsole::new(channels.admin_commands.sender_subscribe()).run_stdin());
+
+		let worldgen = WorldGenQueue::new(NoiseWorldGen::for_world(&world.world_id), async_runtime.handle().clone());
+
+		info!("Launching server mainloop.");
+		async_runtime.block_on(server::run_server_mainloop(
+			channels.net_channels.clone(),
+			connected_peers,
+			world,
+			worldgen,
+			server_config,
+			channels.admin_commands.take_receiver().unwrap(),
+		));
+		// The main loop returns on its own for an admin stop command, so the rest of the engine still needs telling.
+		if let Err(e) = async_runtime.block_on(message::quit_game(Duration::from_secs(10))) {
+			error!("Could not tell the rest of the engine to quit: {e:?}");
+		}
 		async_runtime.block_on(net_system_join_handle);
+		// The admin console is most likely still blocked reading stdin, which would otherwise hold up exit.
+		async_runtime.shutdown_timeout(Duration::from_secs(1));
 	} else if let Some(raw_addr) = {
 		if program_args.join {
 			program_args.addr
@@ -373,6 +353,7 @@ fn main() {
 				address,
 				keys_for_net,
 				laminar_config,
+				DEFAULT_MAX_MESSAGE_SIZE,
 				Duration::from_millis(25),
 				net_channels
 			)
@@ -380,6 +361,15 @@ fn main() {
 			.unwrap();
 			sys.run().await
 		});
+		async_runtime.spawn(reconnect_on_timeout(
+			keys.clone(),
+			Duration::new(5, 0),
+			Duration::from_secs(2),
+			5,
+			protocol_store_dir.clone(),
+			channels.net_channels.reconnect_requested.receiver_subscribe(),
+			channels.net_channels.build_subset(SubsetBuilder::new(())).unwrap(),
+		));
 		async_runtime
 			.block_on(preprotocol_connect_to_server(
 				keys,
@@ -439,6 +429,9 @@ fn main() {
 		);*/
 	} else {
 		info!("Launching as stand-alone.");
+		// Single-player still talks over NetMsgs, it just sends them to itself.
+		let mut net_system = NetworkSystem::new_loopback(keys.clone(), channels.net_channels.build_subset(SubsetBuilder::new(())).unwrap());
+		async_runtime.spawn(async move { net_system.run().await });
 		let mut voxel_event_receiver = channels.net_channels.net_msg_inbound.receiver_typed::<VoxelChangeRequest>().unwrap();
 
 		async_runtime.spawn(async move {
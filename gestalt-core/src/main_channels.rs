@@ -1,18 +1,24 @@
 use gestalt_proc_macros::ChannelSet;
 
+use crate::admin_console::{AdminCommands, AdminRequest};
 use crate::net::net_channels::EngineNetChannels;
 
-use crate::ChannelCapacityConf;
+use crate::{ChannelCapacityConf, ChannelRegistry, MpscChannel};
 
 #[derive(ChannelSet)]
 pub struct MainChannelSet {
 	pub net_channels: EngineNetChannels,
+	/// Admin console to server main loop.
+	pub admin_commands: MpscChannel<AdminRequest>,
 }
 
 impl MainChannelSet {
     pub fn new(conf: &ChannelCapacityConf) -> Self {
+        let admin_commands = MpscChannel::new(conf.get_or_default::<AdminCommands>());
+        ChannelRegistry::register::<AdminCommands>(&admin_commands);
         Self {
-            net_channels: EngineNetChannels::new(conf)
+            net_channels: EngineNetChannels::new(conf),
+            admin_commands,
         }
     }
 }
\ No newline at end of file
@@ -11,6 +11,16 @@ pub struct JoinDefaultEntry {
 	pub display_name: String,
 }
 
+// Client to server. Where the player is and how far they can see, so the server knows which changes
+// they need to hear about. Sent often, and only the newest one matters.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[netmsg(10, ClientToServer, UnreliableSequenced)]
+pub struct PlayerPositionReport {
+	pub position: glam::Vec3,
+	/// In chunks. The server may cap this.
+	pub view_distance: u32,
+}
+
 // Server to client. Let you know somebody joined!
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[netmsg(9, ServerToClient, ReliableOrdered)]
@@ -1,7 +1,15 @@
 use gestalt_proc_macros::netmsg;
 use serde::{Deserialize, Serialize};
 
-use crate::{common::voxelmath::VoxelPos, world::TileId};
+use crate::{
+	common::voxelmath::VoxelPos,
+	world::{
+		chunk::{Chunk, ChunkIoError},
+		voxelarray::VoxelArrayError,
+		voxelstorage::VoxelStorage,
+		ChunkPos, LocalTilePos, TileId,
+	},
+};
 
 /// Usually client-to-server.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -27,3 +35,151 @@ impl Into<VoxelChangeAnnounce> for VoxelChangeRequest {
 		}
 	}
 }
+
+/// Server to client. A batch of changes to one chunk, coalesced into one message.
+/// If a client's copy of the chunk isn't at base_revision, it has missed something
+/// and should ask for the whole chunk with a ChunkDataRequest.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[netmsg(42, ServerToClient, ReliableOrdered)]
+pub struct ChunkDelta {
+	pub chunk: ChunkPos,
+	pub edits: Vec<(LocalTilePos, TileId)>,
+	/// Server's revision of this chunk before these edits.
+	pub base_revision: u64,
+	/// Server's revision of this chunk after these edits.
+	pub revision: u64,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ChunkDeltaError {
+	#[error("Chunk {chunk} is at revision {found} but the delta applies to revision {expected}, so an update was missed")]
+	RevisionGap { chunk: ChunkPos, expected: u64, found: u64 },
+	#[error("Could not apply an edit from a chunk delta: {0}")]
+	Edit(#[from] VoxelArrayError<u8>),
+}
+
+impl ChunkDelta {
+	/// Applies the edits to our copy of the chunk, and brings its revision in line with the server's.
+	/// Leaves the chunk untouched if there's a revision gap.
+	pub fn apply_to(&self, chunk: &mut Chunk<TileId>) -> Result<(), ChunkDeltaError> {
+		if chunk.revision != self.base_revision {
+			return Err(ChunkDeltaError::RevisionGap {
+				chunk: self.chunk,
+				expected: self.base_revision,
+				found: chunk.revision,
+			});
+		}
+		for (pos, tile) in self.edits.iter() {
+			chunk.set(*pos, *tile)?;
+		}
+		chunk.revision = self.revision;
+		Ok(())
+	}
+}
+
+/// Server to client. The whole chunk, for when a client doesn't have it or has fallen behind.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[netmsg(43, ServerToClient, ReliableOrdered)]
+pub struct ChunkData {
+	pub chunk: ChunkPos,
+	/// Chunk::write_chunk() encoding, which carries the revision along with it.
+	pub data: Vec<u8>,
+}
+
+impl ChunkData {
+	pub fn new(pos: ChunkPos, chunk: &Chunk<TileId>) -> Result<Self, ChunkIoError> {
+		let mut data = Vec::new();
+		chunk.write_chunk(&mut data)?;
+		Ok(Self { chunk: pos, data })
+	}
+	pub fn decode(&self) -> Result<Chunk<TileId>, ChunkIoError> {
+		Chunk::read_chunk(&mut &self.data[..])
+	}
+}
+
+/// Client to server. Please send me this whole chunk (as a ChunkData).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[netmsg(44, ClientToServer, ReliableOrdered)]
+pub struct ChunkDataRequest {
+	pub chunk: ChunkPos,
+}
+
+/// Rough size, in bytes, of one edit in an encoded ChunkDelta - three coordinates and a tile.
+pub const CHUNK_DELTA_BYTES_PER_EDIT: usize = 3 + std::mem::size_of::<TileId>() + 2;
+
+/// A delta with this many edits or fewer always gets sent as a delta, without checking how big the full chunk would be.
+pub const CHUNK_DELTA_ALWAYS_SEND_EDITS: usize = 32;
+
+/// Either a ChunkDelta or, if that would be bigger than the chunk itself, a ChunkData.
+#[derive(Clone, Debug)]
+pub enum ChunkUpdate {
+	Delta(ChunkDelta),
+	Full(ChunkData),
+}
+
+impl ChunkUpdate {
+	/// chunk is the server's copy, with the edits already applied.
+	pub fn choose(delta: ChunkDelta, chunk: &Chunk<TileId>) -> Result<Self, ChunkIoError> {
+		if delta.edits.len() <= CHUNK_DELTA_ALWAYS_SEND_EDITS {
+			return Ok(ChunkUpdate::Delta(delta));
+		}
+		let full = ChunkData::new(delta.chunk, chunk)?;
+		if delta.edits.len() * CHUNK_DELTA_BYTES_PER_EDIT > full.data.len() {
+			Ok(ChunkUpdate::Full(full))
+		} else {
+			Ok(ChunkUpdate::Delta(delta))
+		}
+	}
+}
+
+#[test]
+fn chunk_delta_apply_and_gap() {
+	let mut server_chunk: Chunk<TileId> = Chunk::new(0);
+	let mut client_chunk: Chunk<TileId> = Chunk::new(0);
+
+	let edits: Vec<(LocalTilePos, TileId)> = vec![(vpos!(1, 2, 3), 5), (vpos!(4, 5, 6), 7), (vpos!(1, 2, 3), 8)];
+	let base_revision = server_chunk.revision;
+	for (pos, tile) in edits.iter() {
+		server_chunk.set(*pos, *tile).unwrap();
+	}
+	let delta = ChunkDelta {
+		chunk: vpos!(0, 0, 0),
+		edits,
+		base_revision,
+		revision: server_chunk.revision,
+	};
+	delta.apply_to(&mut client_chunk).unwrap();
+	assert_eq!(client_chunk.revision, server_chunk.revision);
+	assert_eq!(*client_chunk.get(vpos!(1, 2, 3)).unwrap(), 8);
+	assert_eq!(*client_chunk.get(vpos!(4, 5, 6)).unwrap(), 7);
+
+	// Same delta again is stale now.
+	assert!(matches!(delta.apply_to(&mut client_chunk), Err(ChunkDeltaError::RevisionGap { .. })));
+
+	// And a full resend catches us up regardless.
+	let full = ChunkData::new(vpos!(0, 0, 0), &server_chunk).unwrap();
+	let resent = full.decode().unwrap();
+	assert_eq!(resent.revision, server_chunk.revision);
+	assert_eq!(*resent.get(vpos!(4, 5, 6)).unwrap(), 7);
+}
+
+#[test]
+fn chunk_update_falls_back_to_full() {
+	use crate::world::chunk::CHUNK_SIZE;
+
+	let mut chunk: Chunk<TileId> = Chunk::new(0);
+	let mut edits = Vec::new();
+	for x in 0..CHUNK_SIZE as u8 {
+		for y in 0..CHUNK_SIZE as u8 {
+			for z in 0..CHUNK_SIZE as u8 {
+				chunk.set(vpos!(x, y, z), 1).unwrap();
+				edits.push((vpos!(x, y, z), 1));
+			}
+		}
+	}
+	let big_delta = ChunkDelta { chunk: vpos!(0, 0, 0), edits, base_revision: 0, revision: chunk.revision };
+	assert!(matches!(ChunkUpdate::choose(big_delta, &chunk).unwrap(), ChunkUpdate::Full(_)));
+
+	let small_delta = ChunkDelta { chunk: vpos!(0, 0, 0), edits: vec![(vpos!(0, 0, 0), 1)], base_revision: 0, revision: 1 };
+	assert!(matches!(ChunkUpdate::choose(small_delta, &chunk).unwrap(), ChunkUpdate::Delta(_)));
+}
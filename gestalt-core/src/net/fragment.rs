@@ -0,0 +1,428 @@
+//! Splits NetMsgs which are too big for one datagram into ReliableOrdered pieces, and puts them back together
+//! on the other end before the rest of the session ever sees them.
+//!
+//! On the wire, a fragment is the reserved FRAGMENT_RESERVED NetMsgId, followed by a header of
+//! (varint msg_id, varint sequence, u16 fragment_index, u16 fragment_count), followed by a slice of the original
+//! packet's payload. The sequence is counted up by the sending session for every message it splits, so two messages
+//! of the same type in flight at once don't get mixed together.
+//! The original payload still starts with its own NetMsgId, so a reassembled message decodes like any other.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use laminar::OrderingGuarantee;
+
+use super::netmsg::{read_netmsg_id, PacketGuarantees, FRAGMENT_RESERVED};
+use super::{NetMsgId, PacketIntermediary};
+
+/// How much of the original payload goes into each fragment. Kept well under Laminar's own fragment size,
+/// so each of our fragments goes out as a single datagram.
+pub const FRAGMENT_BODY_SIZE: usize = 1024;
+/// Largest message we'll split up (or put back together) is FRAGMENT_BODY_SIZE * MAX_FRAGMENTS bytes.
+pub const MAX_FRAGMENTS: u16 = 4096;
+/// How many messages one peer can have partway through arriving at once.
+pub const MAX_PENDING_MESSAGES: usize = 32;
+/// How much memory one peer's partially-received messages can take up, counting each message's fragment slots.
+pub const MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+/// A partially-received message gets thrown away if none of its fragments have shown up for this long.
+pub const DEFAULT_FRAGMENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const FRAGMENT_COUNTER_LEN: usize = std::mem::size_of::<u16>();
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum FragmentError {
+	#[error("Fragment header was cut off.")]
+	Truncated,
+	#[error("A NetMsg which is {0} bytes long would take more than {MAX_FRAGMENTS} fragments to send.")]
+	TooManyFragments(usize),
+	#[error("Fragment {0} of {1} for NetMsg type {2} is not a valid fragment index / count.")]
+	BadIndex(u16, u16, NetMsgId),
+	#[error("Got a fragment of NetMsg type {0} claiming there are {1} fragments, but earlier fragments said there were {2}.")]
+	CountMismatch(NetMsgId, u16, u16),
+	#[error("Got a fragment of NetMsg type {1} for message {0}, but earlier fragments of that message were of type {2}.")]
+	IdMismatch(u32, NetMsgId, NetMsgId),
+	#[error("Already waiting on {MAX_PENDING_MESSAGES} partially-received messages from this peer, so a new one of NetMsg type {0} was dropped.")]
+	TooManyPending(NetMsgId),
+	#[error("Partially-received messages from this peer would take up more than {MAX_PENDING_BYTES} bytes, so one of NetMsg type {0} was dropped.")]
+	TooManyPendingBytes(NetMsgId),
+	#[error("Could not read the NetMsg ID of a message being fragmented.")]
+	NoMessageId,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FragmentHeader {
+	/// NetMsgId of the message this is a piece of.
+	pub msg_id: NetMsgId,
+	/// Which of the sender's fragmented messages this is a piece of.
+	pub sequence: u32,
+	pub fragment_index: u16,
+	pub fragment_count: u16,
+}
+
+/// Cut a packet into fragments which are each at most FRAGMENT_BODY_SIZE bytes of the original payload.
+/// Fragments are always ReliableOrdered, and stay on whichever stream the original packet was on.
+/// sequence needs to be different for every message a session splits up, at least until the peer has had time to
+/// finish (or give up on) older ones.
+pub fn split_packet(packet: &PacketIntermediary, sequence: u32) -> Result<Vec<PacketIntermediary>, FragmentError> {
+	let (msg_id, _) = read_netmsg_id(&packet.payload).ok_or(FragmentError::NoMessageId)?;
+	let fragment_count = packet.payload.len().div_ceil(FRAGMENT_BODY_SIZE);
+	if fragment_count > MAX_FRAGMENTS as usize {
+		return Err(FragmentError::TooManyFragments(packet.payload.len()));
+	}
+	let fragment_count = fragment_count as u16;
+
+	let mut header_start: Vec<u8> = vu64::encode(FRAGMENT_RESERVED as u64).as_ref().to_vec();
+	header_start.extend_from_slice(vu64::encode(msg_id as u64).as_ref());
+	header_start.extend_from_slice(vu64::encode(sequence as u64).as_ref());
+
+	Ok(packet
+		.payload
+		.chunks(FRAGMENT_BODY_SIZE)
+		.enumerate()
+		.map(|(fragment_index, body)| {
+			let mut payload = Vec::with_capacity(header_start.len() + FRAGMENT_COUNTER_LEN * 2 + body.len());
+			payload.extend_from_slice(&header_start);
+			payload.extend_from_slice(&(fragment_index as u16).to_le_bytes());
+			payload.extend_from_slice(&fragment_count.to_le_bytes());
+			payload.extend_from_slice(body);
+			PacketIntermediary {
+				guarantees: PacketGuarantees::ReliableOrdered,
+				stream: packet.stream,
+				priority: packet.priority,
+				payload,
+			}
+		})
+		.collect())
+}
+
+fn read_sequence(bytes: &[u8]) -> Option<(u32, usize)> {
+	let first_byte = *bytes.first()?;
+	let len = vu64::decoded_len(first_byte) as usize;
+	if bytes.len() < len {
+		return None;
+	}
+	let sequence = vu64::decode_with_length(len as u8, &bytes[0..len]).ok()?;
+	Some((u32::try_from(sequence).ok()?, len))
+}
+
+/// Read a fragment's header. Takes everything after the FRAGMENT_RESERVED NetMsgId,
+/// and returns the header along with this fragment's slice of the original payload.
+pub fn parse_fragment(body: &[u8]) -> Result<(FragmentHeader, &[u8]), FragmentError> {
+	let (msg_id, mut cursor) = read_netmsg_id(body).ok_or(FragmentError::Truncated)?;
+	let (sequence, sequence_len) = read_sequence(&body[cursor..]).ok_or(FragmentError::Truncated)?;
+	cursor += sequence_len;
+	if body.len() < cursor + FRAGMENT_COUNTER_LEN * 2 {
+		return Err(FragmentError::Truncated);
+	}
+	let fragment_index = u16::from_le_bytes([body[cursor], body[cursor + 1]]);
+	cursor += FRAGMENT_COUNTER_LEN;
+	let fragment_count = u16::from_le_bytes([body[cursor], body[cursor + 1]]);
+	cursor += FRAGMENT_COUNTER_LEN;
+	if fragment_count == 0 || fragment_count > MAX_FRAGMENTS || fragment_index >= fragment_count {
+		return Err(FragmentError::BadIndex(fragment_index, fragment_count, msg_id));
+	}
+	Ok((
+		FragmentHeader {
+			msg_id,
+			sequence,
+			fragment_index,
+			fragment_count,
+		},
+		&body[cursor..],
+	))
+}
+
+/// Which stream a received packet came in on, for telling apart fragments of different messages.
+pub fn packet_stream(packet: &laminar::Packet) -> Option<u8> {
+	match packet.order_guarantee() {
+		OrderingGuarantee::Ordered(stream) | OrderingGuarantee::Sequenced(stream) => stream,
+		OrderingGuarantee::None => None,
+	}
+}
+
+/// Fragments of one message come in on the same stream and share a sequence number.
+type PartialMessageKey = (Option<u8>, u32);
+
+struct PartialMessage {
+	msg_id: NetMsgId,
+	fragments: Vec<Option<Vec<u8>>>,
+	received: u16,
+	/// Counted against MAX_PENDING_BYTES - the fragment slots, plus every body received so far.
+	bytes: usize,
+	last_received: Instant,
+}
+
+/// Collects fragments until a whole message has arrived. There is one of these per Session,
+/// so partial messages are already separated out by peer - within a peer, they're keyed by stream and sequence.
+pub struct Reassembler {
+	in_progress: HashMap<PartialMessageKey, PartialMessage>,
+	/// Sum of the bytes of everything in in_progress.
+	pending_bytes: usize,
+	timeout: Duration,
+}
+
+impl Reassembler {
+	pub fn new(timeout: Duration) -> Self {
+		Self {
+			in_progress: HashMap::new(),
+			pending_bytes: 0,
+			timeout,
+		}
+	}
+
+	/// Returns the original payload once the last fragment of a message has arrived. stream is whichever stream the
+	/// fragment came in on (see packet_stream()). A message which would go over MAX_PENDING_MESSAGES or
+	/// MAX_PENDING_BYTES is dropped, along with whatever of it had already arrived.
+	pub fn ingest(
+		&mut self,
+		stream: Option<u8>,
+		header: FragmentHeader,
+		body: &[u8],
+		time: Instant,
+	) -> Result<Option<Vec<u8>>, FragmentError> {
+		let FragmentHeader {
+			msg_id,
+			sequence,
+			fragment_index,
+			fragment_count,
+		} = header;
+		let key = (stream, sequence);
+		if !self.in_progress.contains_key(&key) {
+			if self.in_progress.len() >= MAX_PENDING_MESSAGES {
+				return Err(FragmentError::TooManyPending(msg_id));
+			}
+			let slots_bytes = fragment_count as usize * std::mem::size_of::<Option<Vec<u8>>>();
+			if self.pending_bytes + slots_bytes > MAX_PENDING_BYTES {
+				return Err(FragmentError::TooManyPendingBytes(msg_id));
+			}
+			self.pending_bytes += slots_bytes;
+			self.in_progress.insert(
+				key,
+				PartialMessage {
+					msg_id,
+					fragments: vec![None; fragment_count as usize],
+					received: 0,
+					bytes: slots_bytes,
+					last_received: time,
+				},
+			);
+		}
+		let partial = self.in_progress.get_mut(&key).unwrap();
+		if partial.msg_id != msg_id {
+			let expected = partial.msg_id;
+			self.remove(&key);
+			return Err(FragmentError::IdMismatch(sequence, msg_id, expected));
+		}
+		if partial.fragments.len() != fragment_count as usize {
+			let expected = partial.fragments.len() as u16;
+			self.remove(&key);
+			return Err(FragmentError::CountMismatch(msg_id, fragment_count, expected));
+		}
+		partial.last_received = time;
+		if partial.fragments[fragment_index as usize].is_none() {
+			if self.pending_bytes + body.len() > MAX_PENDING_BYTES {
+				self.remove(&key);
+				return Err(FragmentError::TooManyPendingBytes(msg_id));
+			}
+			partial.fragments[fragment_index as usize] = Some(body.to_vec());
+			partial.received += 1;
+			partial.bytes += body.len();
+			self.pending_bytes += body.len();
+		}
+		if partial.received < fragment_count {
+			return Ok(None);
+		}
+		let finished = self.remove(&key).unwrap();
+		Ok(Some(finished.fragments.into_iter().flatten().flatten().collect()))
+	}
+
+	fn remove(&mut self, key: &PartialMessageKey) -> Option<PartialMessage> {
+		let removed = self.in_progress.remove(key)?;
+		self.pending_bytes -= removed.bytes;
+		Some(removed)
+	}
+
+	/// Throw away any partial messages which have gone quiet for longer than the timeout.
+	/// Returns the NetMsgIds of the messages which were dropped.
+	pub fn expire(&mut self, time: Instant) -> Vec<NetMsgId> {
+		let timeout = self.timeout;
+		let mut expired = Vec::new();
+		let mut freed = 0;
+		self.in_progress.retain(|_, partial| {
+			let keep = time.saturating_duration_since(partial.last_received) <= timeout;
+			if !keep {
+				expired.push(partial.msg_id);
+				freed += partial.bytes;
+			}
+			keep
+		});
+		self.pending_bytes -= freed;
+		expired
+	}
+
+	/// How many messages are partway through arriving.
+	pub fn pending(&self) -> usize {
+		self.in_progress.len()
+	}
+
+	/// How many bytes the messages partway through arriving are taking up, as counted against MAX_PENDING_BYTES.
+	pub fn pending_bytes(&self) -> usize {
+		self.pending_bytes
+	}
+}
+
+impl Default for Reassembler {
+	fn default() -> Self {
+		Self::new(DEFAULT_FRAGMENT_TIMEOUT)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::SocketAddr;
+
+	use super::*;
+	use crate::net::reliable_udp::{LaminarConfig, LaminarConnectionManager, DEFAULT_MAX_STREAMS};
+	use crate::net::test::TestNetMsg;
+	use crate::net::NetMsg;
+
+	#[test]
+	fn large_message_round_trip() {
+		let sender_addr: SocketAddr = "127.0.0.1:3227".parse().unwrap();
+		let receiver_addr: SocketAddr = "127.0.0.1:3228".parse().unwrap();
+		let config = LaminarConfig::default();
+		let now = Instant::now();
+		let mut sender = LaminarConnectionManager::new(receiver_addr, &config, DEFAULT_MAX_STREAMS, now);
+		let mut receiver = LaminarConnectionManager::new(sender_addr, &config, DEFAULT_MAX_STREAMS, now);
+
+		let message: String = (0..64 * 1024).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+		let original = TestNetMsg { message: message.clone() }.construct_packet().unwrap();
+		let fragments = split_packet(&original, 0).unwrap();
+		assert_eq!(fragments.len(), original.payload.len().div_ceil(FRAGMENT_BODY_SIZE));
+
+		sender
+			.process_outbound(fragments.into_iter().map(|fragment| fragment.make_full_packet(receiver_addr)), now)
+			.unwrap();
+		let on_the_wire: Vec<(SocketAddr, Vec<u8>)> = sender.empty_outbox();
+		receiver
+			.process_inbound(on_the_wire.into_iter().map(|(_, bytes)| bytes), now)
+			.unwrap();
+
+		let mut reassembler = Reassembler::default();
+		let mut reassembled = None;
+		let received: Vec<laminar::SocketEvent> = receiver.empty_inbox();
+		for event in received {
+			if let laminar::SocketEvent::Packet(packet) = event {
+				let (id, id_len) = read_netmsg_id(packet.payload()).unwrap();
+				assert_eq!(id, FRAGMENT_RESERVED);
+				let (header, body) = parse_fragment(&packet.payload()[id_len..]).unwrap();
+				assert_eq!(header.msg_id, TestNetMsg::net_msg_id());
+				if let Some(payload) = reassembler.ingest(packet_stream(&packet), header, body, now).unwrap() {
+					reassembled = Some(payload);
+				}
+			}
+		}
+		let reassembled = reassembled.unwrap();
+		assert_eq!(reassembled, original.payload);
+		assert_eq!(reassembler.pending(), 0);
+		assert_eq!(reassembler.pending_bytes(), 0);
+
+		let (id, id_len) = read_netmsg_id(&reassembled).unwrap();
+		assert_eq!(id, TestNetMsg::net_msg_id());
+		let decoded: TestNetMsg = rmp_serde::from_read(&reassembled[id_len..]).unwrap();
+		assert_eq!(decoded.message, message);
+	}
+
+	#[test]
+	fn missing_fragments_time_out() {
+		let original = TestNetMsg { message: "B".repeat(FRAGMENT_BODY_SIZE * 3) }.construct_packet().unwrap();
+		let fragments = split_packet(&original, 0).unwrap();
+		let start = Instant::now();
+		let mut reassembler = Reassembler::new(Duration::from_secs(1));
+
+		// Everything but the last one shows up.
+		for fragment in &fragments[..fragments.len() - 1] {
+			let (header, body) = header_and_body(fragment);
+			assert_eq!(reassembler.ingest(None, header, body, start).unwrap(), None);
+		}
+		assert!(reassembler.expire(start + Duration::from_millis(500)).is_empty());
+		assert_eq!(reassembler.expire(start + Duration::from_secs(2)), vec![TestNetMsg::net_msg_id()]);
+		assert_eq!(reassembler.pending(), 0);
+		assert_eq!(reassembler.pending_bytes(), 0);
+
+		assert_eq!(parse_fragment(&[0x05]), Err(FragmentError::Truncated));
+	}
+
+	fn header_and_body(fragment: &PacketIntermediary) -> (FragmentHeader, &[u8]) {
+		let (_, id_len) = read_netmsg_id(&fragment.payload).unwrap();
+		parse_fragment(&fragment.payload[id_len..]).unwrap()
+	}
+
+	#[test]
+	fn same_type_messages_interleave() {
+		let first = TestNetMsg { message: "C".repeat(FRAGMENT_BODY_SIZE * 2) }.construct_packet().unwrap();
+		let second = TestNetMsg { message: "D".repeat(FRAGMENT_BODY_SIZE * 2) }.construct_packet().unwrap();
+		let first_fragments = split_packet(&first, 7).unwrap();
+		let second_fragments = split_packet(&second, 8).unwrap();
+		let now = Instant::now();
+		let mut reassembler = Reassembler::default();
+
+		// Two messages of the same type, with their fragments arriving alternately.
+		let mut finished = Vec::new();
+		for (a, b) in first_fragments.iter().zip(second_fragments.iter()) {
+			for fragment in [a, b] {
+				let (header, body) = header_and_body(fragment);
+				finished.extend(reassembler.ingest(Some(1), header, body, now).unwrap());
+			}
+		}
+		assert_eq!(finished, vec![first.payload.clone(), second.payload.clone()]);
+
+		// The same sequence on a different stream is a different message.
+		let (header, body) = header_and_body(&first_fragments[0]);
+		assert_eq!(reassembler.ingest(Some(1), header, body, now).unwrap(), None);
+		assert_eq!(reassembler.ingest(Some(2), header, body, now).unwrap(), None);
+		assert_eq!(reassembler.pending(), 2);
+	}
+
+	#[test]
+	fn pending_messages_are_capped() {
+		let now = Instant::now();
+		let mut reassembler = Reassembler::default();
+		let header = |sequence: u32, fragment_count: u16| FragmentHeader {
+			msg_id: TestNetMsg::net_msg_id(),
+			sequence,
+			fragment_index: 0,
+			fragment_count,
+		};
+
+		// A peer starting lots of messages and never finishing any of them.
+		for sequence in 0..MAX_PENDING_MESSAGES as u32 {
+			assert_eq!(reassembler.ingest(None, header(sequence, 2), &[0], now), Ok(None));
+		}
+		assert_eq!(
+			reassembler.ingest(None, header(MAX_PENDING_MESSAGES as u32, 2), &[0], now),
+			Err(FragmentError::TooManyPending(TestNetMsg::net_msg_id()))
+		);
+		// Messages which already started can still finish.
+		let last = FragmentHeader { fragment_index: 1, ..header(0, 2) };
+		assert_eq!(reassembler.ingest(None, last, &[1], now), Ok(Some(vec![0, 1])));
+		assert_eq!(reassembler.pending(), MAX_PENDING_MESSAGES - 1);
+
+		// Or a few messages claiming to be huge, sending full fragments for all but the last piece of each.
+		let mut reassembler = Reassembler::default();
+		let body = vec![0u8; FRAGMENT_BODY_SIZE];
+		let per_message = MAX_FRAGMENTS as usize - 1;
+		let mut result = Ok(None);
+		let mut sent = 0;
+		while result.is_ok() {
+			let sequence = (sent / per_message) as u32;
+			let index = (sent % per_message) as u16;
+			let fragment = FragmentHeader { fragment_index: index, ..header(sequence, MAX_FRAGMENTS) };
+			result = reassembler.ingest(None, fragment, &body, now);
+			sent += 1;
+		}
+		assert_eq!(result, Err(FragmentError::TooManyPendingBytes(TestNetMsg::net_msg_id())));
+		assert!(reassembler.pending_bytes() <= MAX_PENDING_BYTES);
+		assert!(sent * FRAGMENT_BODY_SIZE > MAX_PENDING_BYTES / 2);
+	}
+}
@@ -251,41 +251,25 @@ async fn load_validate_noise_peer_key(
 	) -> Result<(), HandshakeError> {
 		trace!("Storing new noise keys for unfamiliar peer {:?}", peer_identity);
 
-		let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(false)
-            .open(&peer_filepath).await
-            .map_err(| e| {
-                let peer_print = peer_identity.to_base64();
-                error!("Unable to store peer public key for {} due to IO error: {:?}. A session with this peer cannot be initialized.", peer_print, &e);
-                HandshakeError::ProtocolStoreIoError(e)
-            })?;
 		let new_keys_struct = PeerKeyFile {
 			keys: HashSet::from([received_noise_key]),
 		};
-		let writebuf = rmp_serde::to_vec_named(&new_keys_struct)
-            .map_err(| e| {
-                let peer_print = peer_identity.to_base64();
-                error!("Unable to store peer public key for {} due to IO error: {:?}. A session with this peer cannot be initialized.", peer_print, &e);
-                HandshakeError::ProtocolStoreEncodeError(e)
-            })?;
-		file.write_all(&writebuf)
-			.await
-			.map_err(HandshakeError::ProtocolStoreIoError)?;
-		file.flush()
-			.await
-			.map_err(HandshakeError::ProtocolStoreIoError)?;
-		Ok(())
+		write_peer_key_file(peer_filepath, new_keys_struct).await.map_err(|e| {
+			let peer_print = peer_identity.to_base64();
+			error!("Unable to store peer public key for {} due to an error: {:?}. A session with this peer cannot be initialized.", peer_print, &e);
+			e
+		})
 	}
-	async fn write_changed_peer_key_file(
+	/// Writes to a temporary file and then renames it over the old one, so a crash partway through
+	/// can't leave a truncated key file behind (which would look like a key change next time).
+	async fn write_peer_key_file(
 		peer_filepath: PathBuf,
 		keys: PeerKeyFile,
 	) -> Result<(), HandshakeError> {
 		// Convert to msgpack
 		let writebuf =
 			rmp_serde::to_vec_named(&keys).map_err(HandshakeError::ProtocolStoreEncodeError)?;
-		let temp_filepath = peer_filepath.with_extension(".pending");
+		let temp_filepath = peer_filepath.with_extension("pending");
 
 		let mut file = OpenOptions::new()
 			.create(true)
@@ -298,21 +282,15 @@ async fn load_validate_noise_peer_key(
 		file.write_all(&writebuf)
 			.await
 			.map_err(HandshakeError::ProtocolStoreIoError)?;
-		file.flush()
+		file.sync_all()
 			.await
 			.map_err(HandshakeError::ProtocolStoreIoError)?;
+		drop(file);
 
-		// Swap our pending file in
-		let bkfile = peer_filepath.with_extension(".bk");
-		tokio::fs::rename(&peer_filepath, &bkfile)
-			.await
-			.map_err(HandshakeError::ProtocolStoreIoError)?;
+		// Swap our pending file in. Renaming over an existing file replaces it in one step.
 		tokio::fs::rename(&temp_filepath, &peer_filepath)
 			.await
 			.map_err(HandshakeError::ProtocolStoreIoError)?;
-		tokio::fs::remove_file(bkfile)
-			.await
-			.map_err(HandshakeError::ProtocolStoreIoError)?;
 
 		Ok(())
 	}
@@ -339,7 +317,7 @@ async fn load_validate_noise_peer_key(
 								key_struct.keys.insert(received_noise_key);
 
 								let write_change_resl =
-									write_changed_peer_key_file(peer_filepath, key_struct).await;
+									write_peer_key_file(peer_filepath, key_struct).await;
 								if let Err(e) = write_change_resl {
 									// This one is special - we have a new key, the user approved it, but also we cannot
 									// write it to the file for some reason. So, log that we cannot write it, but allow
@@ -1274,6 +1252,51 @@ mod test {
 			.unwrap_err();
 	}
 
+	#[tokio::test]
+	async fn changed_peer_key_is_reported() {
+		let noise_dir = tempfile::tempdir().unwrap();
+		let peer_identity = IdentityKeyPair::generate_for_tests().public;
+		let builder: snow::Builder<'_> = snow::Builder::new(NOISE_PARAMS.clone());
+		let first_key: [u8; 32] = builder.generate_keypair().unwrap().public.try_into().unwrap();
+		let second_key: [u8; 32] = builder.generate_keypair().unwrap().public.try_into().unwrap();
+
+		let mismatch_report_channel = BroadcastChannel::new(1024);
+		let mismatch_approve_channel = BroadcastChannel::new(1024);
+		let mut mismatch_reports = mismatch_report_channel.receiver_subscribe();
+
+		// First contact pins the key, and seeing it again is fine - neither should ask for approval.
+		for _ in 0..2 {
+			load_validate_noise_peer_key(
+				PathBuf::from(noise_dir.path()),
+				peer_identity.clone(),
+				first_key,
+				mismatch_report_channel.sender_subscribe(),
+				mismatch_approve_channel.receiver_subscribe(),
+			)
+			.await
+			.unwrap();
+		}
+		assert!(peer_dir(&PathBuf::from(noise_dir.path())).join(peer_identity.to_base64()).exists());
+
+		// Same identity reconnects with a different key, and gets turned down.
+		let approve_sender = mismatch_approve_channel.sender_subscribe();
+		let rejecter = tokio::spawn(async move {
+			let reported = mismatch_reports.recv_wait().await.unwrap();
+			approve_sender.send((reported.clone(), false)).unwrap();
+			reported
+		});
+		let result = load_validate_noise_peer_key(
+			PathBuf::from(noise_dir.path()),
+			peer_identity.clone(),
+			second_key,
+			mismatch_report_channel.sender_subscribe(),
+			mismatch_approve_channel.receiver_subscribe(),
+		)
+		.await;
+		assert!(matches!(result, Err(HandshakeError::IdentityChanged(_))));
+		assert_eq!(rejecter.await.unwrap(), peer_identity);
+	}
+
 	#[tokio::test]
 	async fn handshake_state_machine_test() {
 		let noise_dir = tempfile::tempdir().unwrap();
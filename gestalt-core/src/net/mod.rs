@@ -15,15 +15,20 @@ use net_channels::NetSystemChannels;
 use net_channels::OutboundRawPackets;
 use net_channels::SessionChannelsFields;
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use parking_lot::RwLock;
+use semver::Version;
 use snow::StatelessTransportState;
 use tokio::net::UdpSocket;
 use tokio::task::JoinHandle;
 
 use crate::common::identity::IdentityKeyPair;
 use crate::common::identity::NodeIdentity;
+use crate::common::{new_fast_hash_set, FastHashSet};
 use crate::message::MessageSender;
 use crate::message::QuitReceiver;
+use crate::BroadcastSender;
 use crate::BuildSubset;
 use crate::DomainMessageSender;
 use crate::MessageReceiver;
@@ -32,6 +37,7 @@ use crate::MpscReceiver;
 
 use base64::engine::general_purpose::URL_SAFE as BASE_64;
 
+pub mod fragment;
 pub mod handshake;
 pub mod net_channels;
 #[macro_use]
@@ -40,6 +46,7 @@ pub mod generated;
 pub mod preprotocol;
 pub mod reliable_udp;
 pub mod session;
+pub mod simulated_transport;
 
 pub use netmsg::InboundNetMsg;
 pub use netmsg::NetMsg;
@@ -51,14 +58,21 @@ pub use netmsg::PacketIntermediary;
 pub use netmsg::SelfNetworkRole;
 pub use netmsg::DISCONNECT_RESERVED;
 
+use self::net_channels::InboundNetMsgs;
+use self::net_channels::OutboundNetMsgReceiver;
+use self::netmsg::describe_netmsg_id;
+use self::netmsg::read_netmsg_id;
 use self::netmsg::CiphertextEnvelope;
 use self::netmsg::OuterEnvelopeError;
 use self::reliable_udp::*;
 use self::session::*;
+use self::simulated_transport::{NetSocket, SimulatedConditions, SimulatedTransport};
 
 pub type MessageCounter = u32;
 
-const MAX_MESSAGE_SIZE: usize = 8192;
+/// Size of the buffers packets get read into and written out of, and so the biggest encoded OuterEnvelope we'll send.
+/// NetMsg payloads get up to netmsg::MAX_WIRE_OVERHEAD bytes less than this.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 8192;
 
 /// Which directory holds temporary network protocol data?
 /// I.e. Noise protocol keys, cached knowledge of "this identity is at this IP," etc.
@@ -78,6 +92,8 @@ pub struct SuccessfulConnect {
 	pub peer_identity: NodeIdentity,
 	pub peer_address: SocketAddr,
 	pub peer_role: NetworkRole,
+	/// Engine version the peer reported during the pre-protocol, if any. Used to gate netmsgs.
+	pub peer_engine_version: Option<Version>,
 	pub transport_cryptography: StatelessTransportState,
 	pub transport_counter: u32,
 }
@@ -97,6 +113,7 @@ impl SuccessfulConnect {
 pub struct ConnectAnnounce {
 	pub peer_identity: NodeIdentity,
 	pub peer_role: NetworkRole,
+	pub peer_engine_version: Option<Version>,
 }
 
 impl From<&SuccessfulConnect> for ConnectAnnounce {
@@ -104,6 +121,7 @@ impl From<&SuccessfulConnect> for ConnectAnnounce {
 		ConnectAnnounce { 
 			peer_identity: value.peer_identity.clone(),
 			peer_role: value.peer_role.clone(),
+			peer_engine_version: value.peer_engine_version.clone(),
 		}
 	}
 }
@@ -114,6 +132,67 @@ pub struct DisconnectAnnounce {
 	pub peer_role: NetworkRole,
 }
 
+/// Traffic over one session during the last SESSION_STATS_INTERVAL, published by the session itself.
+/// Byte counts are of ciphertext, i.e. roughly what actually went over the wire.
+#[derive(Clone, Debug)]
+pub struct SessionStats {
+	pub peer: NodeIdentity,
+	pub bytes_in: u64,
+	pub bytes_out: u64,
+	pub packets_in_flight: u16,
+	/// How long these counts were accumulated over - divide by this to get a rate.
+	pub interval: Duration,
+}
+
+/// Sent by a client's network system when its session with a server timed out (rather than being
+/// closed on purpose). Messages the engine sends to this peer are held on to until a new session
+/// comes up, so whoever receives this should run the pre-protocol against peer_address again.
+#[derive(Clone, Debug)]
+pub struct ReconnectRequested {
+	pub peer_identity: NodeIdentity,
+	pub peer_address: SocketAddr,
+}
+
+/// Asks a server's network system to end its session(s) with a peer. See NetworkSystem::kick_peer().
+#[derive(Clone, Debug)]
+pub struct KickPeer {
+	pub peer_identity: NodeIdentity,
+	/// Also refuse any new sessions with this peer from now on.
+	pub ban: bool,
+}
+
+/// Shared view of who the network system currently has a session with. Cheap to clone, so async tasks
+/// which don't own the NetworkSystem (e.g. admin commands) can hold on to one. See NetworkSystem::connected_peers_handle().
+#[derive(Clone, Debug, Default)]
+pub struct ConnectedPeers {
+	inner: Arc<RwLock<FastHashSet<NodeIdentity>>>,
+}
+
+impl ConnectedPeers {
+	/// Copy of the set as of right now, in no particular order.
+	pub fn snapshot(&self) -> Vec<NodeIdentity> {
+		self.inner.read().iter().cloned().collect()
+	}
+	pub fn contains(&self, peer: &NodeIdentity) -> bool {
+		self.inner.read().contains(peer)
+	}
+	pub fn len(&self) -> usize {
+		self.inner.read().len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.inner.read().is_empty()
+	}
+	fn insert(&self, peer: NodeIdentity) {
+		self.inner.write().insert(peer);
+	}
+	fn remove(&self, peer: &NodeIdentity) {
+		self.inner.write().remove(peer);
+	}
+	fn clear(&self) {
+		self.inner.write().clear();
+	}
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum NetworkError {
 	#[error("Error encountered encoding or decoding an outer envelope: {0:?}")]
@@ -124,11 +203,59 @@ pub enum NetworkError {
 	NoNewConnectionsChannel,
 }
 
+/// Which IP stacks a server's UDP socket listens on. See NetworkSystem::new_with_bind_mode().
+///
+/// Platform caveats:
+/// * DualStack relies on IPv4-mapped IPv6 addresses. OpenBSD doesn't support those at all, so binding fails there.
+/// * Without an explicit mode, whether `::` also accepts IPv4 is up to the OS - on by default on most Linux
+///   systems (the net.ipv6.bindv6only sysctl), off by default on Windows and the BSDs.
+/// * On a DualStack socket, IPv4 peers show up with addresses like `::ffff:127.0.0.1`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BindMode {
+	/// Needs an IPv4 address.
+	V4Only,
+	/// Needs an IPv6 address. Sets IPV6_V6ONLY, so IPv4 clients can't connect.
+	V6Only,
+	/// Needs an IPv6 address (usually `::`). Clears IPV6_V6ONLY, so IPv4 clients can connect too.
+	DualStack,
+}
+
+/// Binds a UDP socket with the IPV6_V6ONLY option set according to `bind_mode`.
+pub fn bind_udp_socket(address: SocketAddr, bind_mode: BindMode) -> std::io::Result<UdpSocket> {
+	use socket2::{Domain, Protocol, Socket, Type};
+	let domain = match (bind_mode, address) {
+		(BindMode::V4Only, SocketAddr::V4(_)) => Domain::IPV4,
+		(BindMode::V6Only | BindMode::DualStack, SocketAddr::V6(_)) => Domain::IPV6,
+		_ => {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!("Cannot bind {address} with bind mode {bind_mode:?}"),
+			))
+		}
+	};
+	let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+	match bind_mode {
+		BindMode::V4Only => {}
+		BindMode::V6Only => socket.set_only_v6(true)?,
+		BindMode::DualStack => socket.set_only_v6(false)?,
+	}
+	// Tokio needs this, and won't set it for us.
+	socket.set_nonblocking(true)?;
+	socket.bind(&address.into())?;
+	UdpSocket::from_std(socket.into())
+}
+
 pub struct NetworkSystem {
 	pub our_role: SelfNetworkRole,
-	socket: UdpSocket,
+	socket: NetSocket,
 	pub local_identity: IdentityKeyPair,
 	pub laminar_config: LaminarConfig,
+	/// NetMsgs sent on a stream at or past this get rejected. See reliable_udp::DEFAULT_MAX_STREAMS.
+	pub max_streams: u8,
+	/// NetMsgs which serialize to more than this get fragmented, or dropped (with an error logged) if fragment_large_messages is off.
+	max_message_size: usize,
+	/// See fragment.rs - on by default.
+	pub fragment_large_messages: bool,
 	pub session_tick_interval: Duration,
 	/// Used by servers to hold on to client info until we can ascertain their new port number (the TCP port number from preprotocol/handshake got dropped)
 	anticipated_clients: HashMap<PartialSessionName, SuccessfulConnect>,
@@ -140,6 +267,14 @@ pub struct NetworkSystem {
 	/// Taken from channels.session_to_socket for convenience.
 	kill_from_session: MpscReceiver<(session::FullSessionName, Vec<session::SessionLayerError>)>,
 	session_to_identity: HashMap<FullSessionName, NodeIdentity>,
+	/// Kept in sync with session_to_identity.
+	connected_peers: ConnectedPeers,
+	/// Role each connected peer reported, for announcing their disconnection. Kept in sync with connected_peers.
+	peer_roles: HashMap<NodeIdentity, NetworkRole>,
+	/// Peers we refuse to set up a session with. Only lasts as long as this NetworkSystem does.
+	banned_peers: FastHashSet<NodeIdentity>,
+	/// Only set for network systems made with new_loopback(), until run() takes it.
+	loopback_outbound: Option<OutboundNetMsgReceiver>,
 	join_handles: Vec<JoinHandle<()>>,
 }
 
@@ -149,35 +284,181 @@ impl NetworkSystem {
 		address: SocketAddr,
 		local_identity: IdentityKeyPair,
 		laminar_config: LaminarConfig,
+		max_message_size: usize,
 		session_tick_interval: Duration,
 		channels: NetSystemChannels,
 	) -> Result<Self, std::io::Error> {
 		
 		let socket = match our_role {
 			SelfNetworkRole::Server => UdpSocket::bind(address).await?,
-			SelfNetworkRole::Client => {
-				match address.is_ipv6() {
-					true => UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))).await?,
-					false => UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?,
-				}
-			}
+			SelfNetworkRole::Client => Self::bind_client_socket(address).await?,
 		};
-
-		Ok(Self {
+		Ok(Self::from_socket(our_role, socket, local_identity, laminar_config, max_message_size, session_tick_interval, channels))
+	}
+	/// Like new(), but a server's socket gets bound according to `bind_mode` rather than leaving IPv4/IPv6
+	/// handling up to the OS. Clients ignore `bind_mode`.
+	pub async fn new_with_bind_mode(
+		our_role: SelfNetworkRole,
+		address: SocketAddr,
+		bind_mode: BindMode,
+		local_identity: IdentityKeyPair,
+		laminar_config: LaminarConfig,
+		max_message_size: usize,
+		session_tick_interval: Duration,
+		channels: NetSystemChannels,
+	) -> Result<Self, std::io::Error> {
+		let socket = match our_role {
+			SelfNetworkRole::Server => bind_udp_socket(address, bind_mode)?,
+			SelfNetworkRole::Client => Self::bind_client_socket(address).await?,
+		};
+		Ok(Self::from_socket(our_role, socket, local_identity, laminar_config, max_message_size, session_tick_interval, channels))
+	}
+	/// Clients take whatever port they're given, on the same IP version as the server.
+	async fn bind_client_socket(server_address: SocketAddr) -> Result<UdpSocket, std::io::Error> {
+		match server_address.is_ipv6() {
+			true => UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))).await,
+			false => UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await,
+		}
+	}
+	fn from_socket(
+		our_role: SelfNetworkRole,
+		socket: UdpSocket,
+		local_identity: IdentityKeyPair,
+		laminar_config: LaminarConfig,
+		max_message_size: usize,
+		session_tick_interval: Duration,
+		channels: NetSystemChannels,
+	) -> Self {
+		Self {
 			our_role,
-			socket,
+			socket: NetSocket::Udp(socket),
 			local_identity,
 			laminar_config,
+			max_streams: DEFAULT_MAX_STREAMS,
+			max_message_size,
+			fragment_large_messages: true,
 			session_tick_interval,
 			anticipated_clients: HashMap::default(),
-			recv_buf: vec![0u8; MAX_MESSAGE_SIZE],
-			send_buf: vec![0u8; MAX_MESSAGE_SIZE],
+			recv_buf: vec![0u8; max_message_size],
+			send_buf: vec![0u8; max_message_size],
 			push_receiver: channels.session_to_socket.take_receiver().unwrap(),
 			kill_from_session: channels.kill_from_session.take_receiver().unwrap(),
 			channels,
 			session_to_identity: HashMap::default(),
+			connected_peers: ConnectedPeers::default(),
+			peer_roles: HashMap::default(),
+			banned_peers: new_fast_hash_set(),
+			loopback_outbound: None,
 			join_handles: Vec::default(),
-		})
+		}
+	}
+	/// In-process stand-in for a network connection, for single-player and tests. NetMsgs sent to our own
+	/// identity come straight back out of net_msg_inbound, with no socket, encryption or Laminar involved.
+	/// We're both ends of the "connection", so every NetMsg type is accepted regardless of sidedness.
+	pub fn new_loopback(local_identity: IdentityKeyPair, channels: NetSystemChannels) -> Self {
+		// Register up front rather than in run(), so receivers can subscribe before it starts.
+		for id in generated::get_netmsg_table().keys() {
+			let _ = channels.net_msg_inbound.init_domain(*id);
+		}
+		let loopback_outbound = channels
+			.net_msg_outbound
+			.register_peer(local_identity.public.clone())
+			.expect("Loopback peer should not already be registered on a fresh channel set");
+		let connected_peers = ConnectedPeers::default();
+		connected_peers.insert(local_identity.public.clone());
+
+		Self {
+			our_role: SelfNetworkRole::Server,
+			socket: NetSocket::Loopback,
+			local_identity,
+			laminar_config: LaminarConfig::default(),
+			max_streams: DEFAULT_MAX_STREAMS,
+			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+			fragment_large_messages: false,
+			session_tick_interval: Duration::ZERO,
+			anticipated_clients: HashMap::default(),
+			recv_buf: Vec::new(),
+			send_buf: Vec::new(),
+			push_receiver: channels.session_to_socket.take_receiver().unwrap(),
+			kill_from_session: channels.kill_from_session.take_receiver().unwrap(),
+			channels,
+			session_to_identity: HashMap::default(),
+			connected_peers,
+			peer_roles: HashMap::default(),
+			banned_peers: new_fast_hash_set(),
+			loopback_outbound: Some(loopback_outbound),
+			join_handles: Vec::default(),
+		}
+	}
+	/// Runs every packet this node receives through a SimulatedTransport, for testing how sessions
+	/// cope with loss, duplication, reordering and latency. Not something to turn on outside of tests.
+	pub fn with_simulated_conditions(mut self, conditions: SimulatedConditions) -> Self {
+		warn!("Network system is simulating bad network conditions: {conditions:?}");
+		self.socket = match self.socket {
+			NetSocket::Udp(socket) => NetSocket::Simulated(SimulatedTransport::new(socket, conditions)),
+			NetSocket::Simulated(transport) => NetSocket::Simulated(transport.with_conditions(conditions)),
+			NetSocket::Loopback => NetSocket::Loopback,
+		};
+		self
+	}
+	/// Everyone we have a session with right now.
+	pub fn connected_peers(&self) -> Vec<NodeIdentity> {
+		self.connected_peers.snapshot()
+	}
+	/// Handle which stays up to date as sessions come and go, for use outside of the network system's task.
+	pub fn connected_peers_handle(&self) -> ConnectedPeers {
+		self.connected_peers.clone()
+	}
+	/// Ends every session we have with this peer. They get a DisconnectMsg first, so they know it was
+	/// deliberate rather than timing out (and trying to reconnect). Returns false if they weren't connected.
+	pub fn kick_peer(&mut self, identity: &NodeIdentity) -> bool {
+		let sessions: Vec<FullSessionName> = self
+			.session_to_identity
+			.iter()
+			.filter(|(_, ident)| *ident == identity)
+			.map(|(session, _)| session.clone())
+			.collect();
+		if sessions.is_empty() {
+			return false;
+		}
+		info!("Kicking peer {}", identity.to_base64());
+		if let Err(e) = self.channels.net_msg_outbound.send_to(vec![DisconnectMsg{}.construct_packet().unwrap()], identity) {
+			warn!("Could not tell {} they are being kicked: {e:?}", identity.to_base64());
+		}
+		for session in sessions {
+			let _ = self.channels.system_kill_session.send_to((), &session);
+			self.forget_session(&session);
+			self.channels.drop_peer(&session, identity);
+		}
+		true
+	}
+	/// Kicks the peer if they're connected, and refuses any sessions with them from here on out.
+	pub fn ban_peer(&mut self, identity: NodeIdentity) -> bool {
+		let was_connected = self.kick_peer(&identity);
+		self.banned_peers.insert(identity);
+		was_connected
+	}
+	/// Returns false if they weren't banned.
+	pub fn unban_peer(&mut self, identity: &NodeIdentity) -> bool {
+		self.banned_peers.remove(identity)
+	}
+	pub fn is_banned(&self, identity: &NodeIdentity) -> bool {
+		self.banned_peers.contains(identity)
+	}
+	/// Stop tracking a session. The peer only leaves connected_peers once none of its sessions are left
+	/// (a reconnecting client can briefly have two), at which point the rest of the engine gets a DisconnectAnnounce.
+	fn forget_session(&mut self, session: &FullSessionName) -> Option<NodeIdentity> {
+		let ident = self.session_to_identity.remove(session)?;
+		if !self.session_to_identity.values().any(|other| other == &ident) {
+			self.connected_peers.remove(&ident);
+			let peer_role = self.peer_roles.remove(&ident).unwrap_or(NetworkRole::Unknown);
+			// Nobody listening for disconnections is fine.
+			let _ = self.channels.announce_disconnection.send(DisconnectAnnounce {
+				peer_identity: ident.clone(),
+				peer_role,
+			});
+		}
+		Some(ident)
 	}
 	pub async fn add_new_session(
 		&mut self,
@@ -189,7 +470,12 @@ impl NetworkSystem {
 			&actual_address.peer_address,
 			&connection.transport_counter
 		);
+		if self.is_banned(&connection.peer_identity) {
+			warn!("Refusing a session with banned peer {}", connection.peer_identity.to_base64());
+			return Ok(());
+		}
 		let peer_role = connection.peer_role.clone();
+		let peer_engine_version = connection.peer_engine_version.clone();
 		self.channels.init_peer(actual_address.clone(), connection.peer_identity.clone());
 		let system_kill_session = self.channels.system_kill_session.take_receiver(&actual_address).unwrap();
 		//Communication with the rest of the engine.
@@ -203,6 +489,9 @@ impl NetworkSystem {
 			Ok(channels) => {
 				let peer_identity = connection.peer_identity.clone();
 				trace!("Sender channel successfully registered for {}", peer_identity.to_base64());
+				self.session_to_identity.insert(actual_address.clone(), peer_identity.clone());
+				self.connected_peers.insert(peer_identity.clone());
+				self.peer_roles.insert(peer_identity.clone(), peer_role);
 				// Construct the session
 				let mut session = Session::new(
 					self.local_identity.clone(),
@@ -210,6 +499,9 @@ impl NetworkSystem {
 					actual_address.peer_address,
 					connection,
 					self.laminar_config.clone(),
+					self.max_streams,
+					self.max_message_size,
+					self.fragment_large_messages,
 					Instant::now(),
 					channels,
 				);
@@ -238,6 +530,7 @@ impl NetworkSystem {
 				self.channels.announce_connection.send(ConnectAnnounce {
 					peer_identity,
 					peer_role,
+					peer_engine_version,
 				}).unwrap();
 			}
 			Err(e) => {
@@ -277,6 +570,8 @@ impl NetworkSystem {
 			info!("Terminating session with peer {ident:#?}");
 			self.channels.system_kill_session.send_to((), session).unwrap();
 		}
+		self.connected_peers.clear();
+		self.peer_roles.clear();
 		tokio::time::sleep(Duration::from_millis(10)).await;
 		for jh in &self.join_handles {
 			jh.abort();
@@ -284,6 +579,36 @@ impl NetworkSystem {
 		}
 		info!("Network system should be safe to shut down.");
 	}
+	/// Tears down our side of a session which has ended. If we're a client and the session timed out,
+	/// the peer's outbound channel is kept around and a ReconnectRequested goes out instead.
+	fn handle_session_kill(&mut self, session_kill: FullSessionName, errors: Vec<SessionLayerError>) {
+		let ident = match self.forget_session(&session_kill) {
+			Some(ident) => ident,
+			None => {
+				trace!("Session {session_kill:?} was already closed.");
+				return;
+			}
+		};
+		if self.our_role == SelfNetworkRole::Client && errors.iter().any(|e| e.is_timeout()) {
+			warn!("Connection to {} timed out, requesting a reconnect.", ident.to_base64());
+			self.channels.drop_session(&session_kill);
+			let request = ReconnectRequested {
+				peer_identity: ident,
+				peer_address: session_kill.peer_address,
+			};
+			if let Err(e) = self.channels.announce_reconnect.send(request) {
+				error!("Could not request a reconnect to {}: {e:?}", session_kill.peer_address);
+			}
+			return;
+		}
+		if errors.is_empty() {
+			info!("Closing connection for a session with {:?}.", &ident);
+		}
+		else {
+			info!("Closing connection for a session with {:?}, due to errors: {:?}", &ident, errors);
+		}
+		self.channels.drop_peer(&session_kill, &ident);
+	}
 	pub async fn wait_for_ready(&mut self) -> Result<(), NetworkError> {
 		match (self.our_role, self.session_to_identity.len()) {
 			// We're a client (i.e. not listening) and have no connections yet,
@@ -314,7 +639,68 @@ impl NetworkSystem {
 			_ => Ok(()),
 		}
 	}
+	async fn run_loopback(&mut self, mut outbound: OutboundNetMsgReceiver) {
+		let local = self.local_identity.public.clone();
+		info!("Network system running as a loopback for {}.", local.to_base64());
+		let _ = self.channels.announce_connection.send(ConnectAnnounce {
+			peer_identity: local.clone(),
+			peer_role: self.our_role.into(),
+			peer_engine_version: Some(crate::ENGINE_VERSION),
+		});
+
+		let mut inbound_senders: HashMap<NetMsgId, BroadcastSender<InboundNetMsgs>> = HashMap::new();
+		let mut quit_reciever = QuitReceiver::new();
+		loop {
+			tokio::select! {
+				outbound_maybe = outbound.recv_wait() => {
+					let packets = match outbound_maybe {
+						Ok(packets) => packets,
+						Err(e) => {
+							error!("Loopback outbound channel closed: {e}");
+							break;
+						}
+					};
+					let mut by_type: HashMap<NetMsgId, InboundNetMsgs> = HashMap::new();
+					for packet in packets {
+						match read_netmsg_id(&packet.payload) {
+							// Nothing to disconnect from.
+							Some((DISCONNECT_RESERVED, _)) => {}
+							Some((message_type_id, id_len)) => by_type.entry(message_type_id).or_default().push(InboundNetMsg {
+								message_type_id,
+								payload: packet.payload[id_len..].to_vec(),
+								peer_identity: local.clone(),
+							}),
+							None => error!("Could not read the NetMsg ID of a looped-back packet."),
+						}
+					}
+					for (message_type_id, messages) in by_type {
+						if !inbound_senders.contains_key(&message_type_id) {
+							match self.channels.net_msg_inbound.sender_subscribe(&message_type_id) {
+								Ok(sender) => {
+									inbound_senders.insert(message_type_id, sender);
+								}
+								Err(_) => {
+									warn!("Dropping looped-back NetMsg(s) of unregistered type {}", describe_netmsg_id(message_type_id));
+									continue;
+								}
+							}
+						}
+						// Nobody listening for this type is fine.
+						let _ = inbound_senders[&message_type_id].send(messages);
+					}
+				}
+				quit_ready_indicator = quit_reciever.wait_for_quit() => {
+					info!("Shutting down loopback network system.");
+					quit_ready_indicator.notify_ready();
+					break;
+				}
+			}
+		}
+	}
 	pub async fn run(&mut self) {
+		if let Some(outbound) = self.loopback_outbound.take() {
+			return self.run_loopback(outbound).await;
+		}
 		trace!(
 			"Initializing network subsystem for {:?}, which is a {:?}.",
 			self.local_identity.public.to_base64(),
@@ -339,6 +725,8 @@ impl NetworkSystem {
 		);
 
 		let mut quit_reciever = QuitReceiver::new();
+		// Goes false if nothing can send kick requests anymore.
+		let mut kick_requests_open = true;
 
 		//If we are a client, make sure there's at least one session going before polling for anything.
 		//Otherwise silly things will happen, like attempting to receive on a channel that doesn't exist.
@@ -359,12 +747,16 @@ impl NetworkSystem {
 
 					let session_name = connection.get_full_session_name();
 
-					if self.our_role == SelfNetworkRole::Server {
+					if self.is_banned(&connection.peer_identity) {
+						warn!("Ignoring a connection from banned peer {}", connection.peer_identity.to_base64());
+					}
+					else if self.our_role == SelfNetworkRole::Server {
 						trace!("Adding anticipated client entry for session {:?}", &BASE_64.encode(connection.session_id));
 						self.channels.net_msg_outbound.init_peer(connection.peer_identity.clone());
+						// Canonical, so an IPv4 client matches up whether or not the UDP socket is dual-stack.
 						self.anticipated_clients.insert( PartialSessionName{
 							session_id: connection.session_id.clone(),
-							peer_address: connection.peer_address.ip(),
+							peer_address: connection.peer_address.ip().to_canonical(),
 						}, connection);
 					}
 					else {
@@ -396,7 +788,7 @@ impl NetworkSystem {
 											if self.our_role == SelfNetworkRole::Server {
 												// Reconstruct the partial session name so we can do a lookup with it.
 												let partial_session_name = PartialSessionName {
-													peer_address: peer_address.ip(),
+													peer_address: peer_address.ip().to_canonical(),
 													session_id: session_name.session_id,
 												};
 												//Did we have an anticipated client with this partial session name?
@@ -462,10 +854,9 @@ impl NetworkSystem {
 									Err(e) => { 
 										error!("Error encountered while sending to a socket for {:?}: {e:#?}\nClosing connection.", message.session);
 										let _ = self.channels.system_kill_session.send_to((), &message.session);
-										if let Some(ident) = self.session_to_identity.get(&message.session) {
+										if let Some(ident) = self.forget_session(&message.session) {
 											self.channels.drop_peer(&message.session, &ident);
 										}
-										let _ = self.session_to_identity.remove(&message.session);
 									}
 								}
 							},
@@ -476,15 +867,21 @@ impl NetworkSystem {
 				// Has one of our sessions failed or disconnected?
 				kill_maybe = (&mut self.kill_from_session).recv_wait() => {
 					if let Ok((session_kill, errors)) = kill_maybe {
-						let ident = self.session_to_identity.get(&session_kill).unwrap().clone();
-						if errors.is_empty() {
-							info!("Closing connection for a session with {:?}.", &ident);
-						}
-						else {
-							info!("Closing connection for a session with {:?}, due to errors: {:?}", &ident, errors);
+						self.handle_session_kill(session_kill, errors);
+					}
+				}
+				kick_maybe = (&mut self.channels.kick_requests).recv_wait(), if kick_requests_open => {
+					match kick_maybe {
+						Ok(KickPeer { peer_identity, ban }) => {
+							let was_connected = match ban {
+								true => self.ban_peer(peer_identity.clone()),
+								false => self.kick_peer(&peer_identity),
+							};
+							if !was_connected {
+								info!("Asked to kick {}, who is not connected.", peer_identity.to_base64());
+							}
 						}
-						self.channels.drop_peer(&session_kill, &ident);
-						let _ = self.session_to_identity.remove(&session_kill);
+						Err(_) => kick_requests_open = false,
 					}
 				}
 				quit_ready_indicator = quit_reciever.wait_for_quit() => {
@@ -499,7 +896,7 @@ impl NetworkSystem {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
 	use std::net::IpAddr;
 	use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
@@ -514,6 +911,7 @@ use std::net::Ipv6Addr;
 	use crate::ChannelCapacityConf;
 	use crate::DomainSenderSubscribe;
 use crate::SubsetBuilder;
+	use super::netmsg::{MessagePriority, OutboundQueue, StreamSelector};
 	use super::preprotocol::launch_preprotocol_listener;
 	use super::preprotocol::preprotocol_connect_to_server;
 	use super::*;
@@ -525,7 +923,7 @@ use crate::SubsetBuilder;
 	use serde::Serialize;
 	use simplelog::TermLogger;
 
-	async fn find_available_udp_port(range: std::ops::Range<u16>) -> Option<u16> {
+	pub(crate) async fn find_available_udp_port(range: std::ops::Range<u16>) -> Option<u16> {
 		for i in range {
 			match UdpSocket::bind((Ipv6Addr::LOCALHOST, i)).await {
 				Ok(_) => return Some(i),
@@ -540,11 +938,422 @@ use crate::SubsetBuilder;
 	pub(crate) struct TestNetMsg {
 		pub message: String,
 	}
+
+	#[derive(Clone, Serialize, Deserialize, Debug)]
+	#[netmsg(1338, Common, ReliableOrdered, since = "0.2.0")]
+	pub(crate) struct TestNewerNetMsg {
+		pub message: String,
+	}
+
+	/// Stand-in for movement-sync style messages, where only the newest one matters.
+	#[derive(Clone, Serialize, Deserialize, Debug)]
+	#[netmsg(1339, Common, UnreliableSequenced)]
+	pub(crate) struct TestSequencedNetMsg {
+		pub position: [f32; 3],
+	}
+
+	#[derive(Clone, Serialize, Deserialize, Debug)]
+	#[netmsg(name = "TestNamedNetMsg", Common, ReliableOrdered)]
+	pub(crate) struct TestNamedNetMsg {
+		pub message: String,
+	}
+
+	const TEST_HIGH_STREAM: u8 = 200;
+
+	#[derive(Clone, Serialize, Deserialize, Debug)]
+	#[netmsg(1340, Common, ReliableOrdered, TEST_HIGH_STREAM)]
+	pub(crate) struct TestHighStreamNetMsg {
+		pub message: String,
+	}
+
+	#[derive(Clone, Serialize, Deserialize, Debug)]
+	#[netmsg(1341, Common, ReliableOrdered, 2, High)]
+	pub(crate) struct TestUrgentNetMsg {
+		pub message: String,
+	}
+
 	lazy_static! {
 		/// Used to keep tests which use real network i/o from clobbering eachother.
 		pub static ref NET_TEST_MUTEX: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
 	}
 
+	/// A client and a server Session which have already been through a Noise handshake with eachother,
+	/// with no sockets or network system involved. Whatever one session pushes toward its socket comes out
+	/// of its outbox, and can be handed straight to the other with deliver().
+	pub(crate) struct SessionPair {
+		pub client: Session,
+		pub server: Session,
+		pub client_outbox: MpscReceiver<OutboundRawPackets>,
+		/// Nothing reads this yet, but the server's acks need somewhere to go.
+		_server_outbox: MpscReceiver<OutboundRawPackets>,
+		/// Where the server session hands finished NetMsgs to the rest of the engine.
+		pub server_channels: EngineNetChannels,
+		/// The sessions' channels are subsets of these, so they need to stick around.
+		_client_channels: EngineNetChannels,
+		_netsys_channels: Vec<NetSystemChannels>,
+	}
+
+	impl SessionPair {
+		pub fn new(max_streams: u8, max_message_size: usize, fragment_large_messages: bool) -> Self {
+			let client_addr: SocketAddr = "127.0.0.1:3230".parse().unwrap();
+			let server_addr: SocketAddr = "127.0.0.1:3231".parse().unwrap();
+			let client_keys = IdentityKeyPair::generate_for_tests();
+			let server_keys = IdentityKeyPair::generate_for_tests();
+
+			let builder = snow::Builder::new(handshake::NOISE_PARAMS.clone());
+			let client_noise_keys = builder.generate_keypair().unwrap();
+			let server_noise_keys = builder.generate_keypair().unwrap();
+			let mut initiator = snow::Builder::new(handshake::NOISE_PARAMS.clone())
+				.local_private_key(&client_noise_keys.private)
+				.build_initiator()
+				.unwrap();
+			let mut responder = snow::Builder::new(handshake::NOISE_PARAMS.clone())
+				.local_private_key(&server_noise_keys.private)
+				.build_responder()
+				.unwrap();
+			let mut message = [0u8; 1024];
+			let mut payload = [0u8; 1024];
+			for _ in 0..3 {
+				let (writer, reader) = if initiator.is_my_turn() { (&mut initiator, &mut responder) } else { (&mut responder, &mut initiator) };
+				let len = writer.write_message(&[], &mut message).unwrap();
+				reader.read_message(&message[..len], &mut payload).unwrap();
+			}
+			let session_id = [1, 2, 3, 4];
+
+			let mut netsys_channels_kept = Vec::new();
+			let mut make_session = |local_keys: &IdentityKeyPair, local_role, peer_keys: &IdentityKeyPair, peer_address, peer_role, transport| {
+				let engine_channels = EngineNetChannels::new(&ChannelCapacityConf::new());
+				for id in generated::get_netmsg_table().keys() {
+					let _ = engine_channels.net_msg_inbound.init_domain(*id);
+				}
+				let netsys_channels: NetSystemChannels = engine_channels.build_subset(SubsetBuilder::new(())).unwrap();
+				let session_name = FullSessionName { peer_address, session_id };
+				netsys_channels.init_peer(session_name.clone(), peer_keys.public.clone());
+				let session_channels = netsys_channels
+					.build_subset(SessionChannelsFields {
+						session_domain: session_name,
+						peer_identity_domain: peer_keys.public.clone(),
+					}.into())
+					.unwrap();
+				let outbox = netsys_channels.session_to_socket.take_receiver().unwrap();
+				let session = Session::new(
+					local_keys.clone(),
+					local_role,
+					peer_address,
+					SuccessfulConnect {
+						session_id,
+						peer_identity: peer_keys.public.clone(),
+						peer_address,
+						peer_role,
+						peer_engine_version: None,
+						transport_cryptography: transport,
+						transport_counter: 1,
+					},
+					LaminarConfig::default(),
+					max_streams,
+					max_message_size,
+					fragment_large_messages,
+					Instant::now(),
+					session_channels,
+				);
+				netsys_channels_kept.push(netsys_channels);
+				(session, outbox, engine_channels)
+			};
+			let (client, client_outbox, client_channels) = make_session(
+				&client_keys,
+				SelfNetworkRole::Client,
+				&server_keys,
+				server_addr,
+				NetworkRole::Server,
+				initiator.into_stateless_transport_mode().unwrap(),
+			);
+			let (server, server_outbox, server_channels) = make_session(
+				&server_keys,
+				SelfNetworkRole::Server,
+				&client_keys,
+				client_addr,
+				NetworkRole::Client,
+				responder.into_stateless_transport_mode().unwrap(),
+			);
+			Self {
+				client,
+				server,
+				client_outbox,
+				_server_outbox: server_outbox,
+				server_channels,
+				_client_channels: client_channels,
+				_netsys_channels: netsys_channels_kept,
+			}
+		}
+
+		/// Everything the client has pushed toward its socket so far, fed into the server. Returns the server's errors.
+		pub fn deliver_to_server(&mut self, time: Instant) -> Vec<SessionLayerError> {
+			let envelopes = drain_outbox(&mut self.client_outbox);
+			self.server.ingest_packets(envelopes, time)
+		}
+	}
+
+	/// Everything a session has pushed toward its socket, as the other side's socket would have decoded it.
+	pub(crate) fn drain_outbox(outbox: &mut MpscReceiver<OutboundRawPackets>) -> Vec<CiphertextEnvelope> {
+		let mut envelopes = Vec::new();
+		while let Some(batch) = outbox.recv_poll().unwrap() {
+			envelopes.extend(batch.into_iter().map(|envelope| CiphertextEnvelope { session: envelope.session, body: envelope.body }));
+		}
+		envelopes
+	}
+
+	#[test]
+	fn outbound_queue_orders_by_priority() {
+		assert_eq!(TestNetMsg::net_msg_priority(), MessagePriority::Normal);
+		assert_eq!(TestUrgentNetMsg::net_msg_priority(), MessagePriority::High);
+		assert_eq!(TestUrgentNetMsg::net_msg_stream(), StreamSelector::Specific(2));
+
+		let normal = |i: usize| TestNetMsg { message: format!("normal {i}") }.construct_packet().unwrap();
+		let urgent = |i: usize| TestUrgentNetMsg { message: format!("urgent {i}") }.construct_packet().unwrap();
+		let mut low_same_stream = TestNetMsg { message: String::from("low") }.construct_packet().unwrap();
+		low_same_stream.priority = MessagePriority::Low;
+		let mut low_elsewhere = TestNetMsg { message: String::from("low elsewhere") }.construct_packet().unwrap();
+		low_elsewhere.priority = MessagePriority::Low;
+		low_elsewhere.stream = StreamSelector::Specific(3);
+
+		// Everything that came in during one tick.
+		let mut queue = OutboundQueue::new();
+		queue.push(low_elsewhere.clone());
+		queue.push(normal(0));
+		queue.push(urgent(0));
+		queue.extend([low_same_stream.clone(), normal(1), urgent(1)]);
+		assert_eq!(queue.len(), 6);
+
+		// The urgent stream jumps ahead, but normal(1) still has to wait behind the low-priority packet sent
+		// before it on the same stream. The two low ones are on different streams, so push order decides.
+		let drained: Vec<Vec<u8>> = queue.drain().into_iter().map(|packet| packet.payload).collect();
+		let expected: Vec<Vec<u8>> = vec![urgent(0), urgent(1), normal(0), low_elsewhere, low_same_stream, normal(1)]
+			.into_iter()
+			.map(|packet| packet.payload)
+			.collect();
+		assert_eq!(drained, expected);
+		assert!(queue.is_empty());
+	}
+
+	#[test]
+	fn netmsg_since_gating() {
+		assert_eq!(TestNetMsg::net_msg_since(), None);
+		assert_eq!(TestNewerNetMsg::net_msg_since(), Some(Version::new(0, 2, 0)));
+		assert!(TestNetMsg::net_msg_type().supported_by(None));
+
+		let newer = generated::get_netmsg_table().get(&TestNewerNetMsg::net_msg_id()).unwrap();
+		assert_eq!(newer.since, Some(Version::new(0, 2, 0)));
+		assert!(!newer.supported_by(None));
+		assert!(!newer.supported_by(Some(&Version::new(0, 1, 9))));
+		assert!(newer.supported_by(Some(&Version::new(0, 2, 0))));
+		assert!(newer.supported_by(Some(&Version::new(1, 0, 0))));
+	}
+
+	#[test]
+	fn netmsg_id_from_name() {
+		// Pinned, since changing how names are hashed changes what goes on the wire.
+		assert_eq!(TestNamedNetMsg::net_msg_id(), 0x8694_82be);
+		// The runtime version has to agree with what the macro worked out.
+		assert_eq!(netmsg::netmsg_id_from_name("TestNamedNetMsg"), TestNamedNetMsg::net_msg_id());
+		assert_ne!(netmsg::netmsg_id_from_name("TestNamedNetMsg2") & netmsg::NAMED_NETMSG_ID_BIT, 0);
+		let named = generated::get_netmsg_table().get(&TestNamedNetMsg::net_msg_id()).unwrap();
+		assert_eq!(named.name, "TestNamedNetMsg");
+	}
+
+	#[test]
+	fn netmsg_errors_name_the_message() {
+		use crate::message_types::voxel::VoxelChangeAnnounce;
+		use netmsg::{MessagePriority, PacketGuarantees};
+
+		assert!(SelfNetworkRole::Server.should_we_ingest(&netmsg::MessageSidedness::ClientToServer));
+		assert!(!SelfNetworkRole::Server.should_we_ingest(&VoxelChangeAnnounce::net_msg_sidedness()));
+
+		// The session only ever looks at the ID before deciding it can't take a message, so the body can be anything.
+		let raw_packet = |id: NetMsgId| {
+			let mut payload = vu64::encode(id as u64).as_ref().to_vec();
+			payload.extend_from_slice(b"body");
+			PacketIntermediary {
+				guarantees: PacketGuarantees::ReliableOrdered,
+				stream: StreamSelector::Any,
+				priority: MessagePriority::Normal,
+				payload,
+			}
+		};
+		let mut pair = SessionPair::new(DEFAULT_MAX_STREAMS, DEFAULT_MAX_MESSAGE_SIZE, false);
+		let server_addr = pair.client.peer_address;
+		// Clients don't get to announce voxel changes, and nobody has heard of 0x7fff_fff0.
+		pair.client
+			.process_outbound([raw_packet(VoxelChangeAnnounce::net_msg_id()), raw_packet(0x7fff_fff0)].map(|packet| packet.make_full_packet(server_addr)), Instant::now())
+			.unwrap();
+		let errors: Vec<String> = pair.deliver_to_server(Instant::now()).iter().map(|e| e.to_string()).collect();
+		assert_eq!(errors.len(), 2, "{errors:?}");
+		assert!(errors.iter().any(|e| e.contains("41 (VoxelChangeAnnounce)") && e.contains("ServerToClient")), "{errors:?}");
+		assert!(errors.iter().any(|e| e.contains("2147483632 (unknown to this build)")), "{errors:?}");
+	}
+
+	#[test]
+	fn unregistered_netmsg_id_falls_back() {
+		assert_eq!(netmsg::try_netmsg_name(TestNamedNetMsg::net_msg_id()), Some("TestNamedNetMsg"));
+
+		let unregistered = netmsg::netmsg_id_from_name("NobodyDeclaredThis");
+		assert_eq!(netmsg::try_netmsg_name(unregistered), None);
+		assert_eq!(describe_netmsg_id(unregistered), format!("{unregistered:#010x} (unknown to this build)"));
+		assert_eq!(describe_netmsg_id(0x7fff_fff0), "2147483632 (unknown to this build)");
+	}
+
+	#[test]
+	fn netmsg_out_of_range_stream() {
+		let too_far = TestHighStreamNetMsg { message: String::from("Too far down the river.") };
+		let fine = TestNetMsg { message: String::from("Close enough.") };
+
+		let mut pair = SessionPair::new(16, DEFAULT_MAX_MESSAGE_SIZE, false);
+		let mut server_receiver = pair.server_channels.net_msg_inbound.receiver_typed::<TestNetMsg>().unwrap();
+		let server_addr = pair.client.peer_address;
+		let batch = [too_far.construct_packet().unwrap(), fine.construct_packet().unwrap()].map(|packet| packet.make_full_packet(server_addr));
+		match pair.client.process_outbound(batch, Instant::now()) {
+			Err(error @ SessionLayerError::InvalidStream(id, stream)) => {
+				assert_eq!((id, stream), (TestHighStreamNetMsg::net_msg_id(), TEST_HIGH_STREAM));
+				assert!(error.to_string().contains("1340 (TestHighStreamNetMsg) on stream 200"));
+			}
+			other => panic!("Expected InvalidStream, got {other:?}"),
+		}
+		// The rest of the batch still went out.
+		assert!(pair.deliver_to_server(Instant::now()).is_empty());
+		let received = server_receiver.recv_poll().unwrap().unwrap();
+		assert_eq!(received.len(), 1);
+		assert_eq!(received[0].1.message, fine.message);
+
+		// With the default maximum, that stream is fine.
+		let mut pair = SessionPair::new(DEFAULT_MAX_STREAMS, DEFAULT_MAX_MESSAGE_SIZE, false);
+		let server_addr = pair.client.peer_address;
+		pair.client.process_outbound([too_far.construct_packet().unwrap().make_full_packet(server_addr)], Instant::now()).unwrap();
+	}
+
+	#[tokio::test]
+	async fn netmsg_batches_count_messages() {
+		let mut pair = SessionPair::new(DEFAULT_MAX_STREAMS, DEFAULT_MAX_MESSAGE_SIZE, false);
+		let mut server_receiver = pair.server_channels.net_msg_inbound.receiver_typed::<TestNetMsg>().unwrap();
+		let server_addr = pair.client.peer_address;
+		let packets: Vec<laminar::Packet> = (0..5)
+			.map(|i| TestNetMsg { message: format!("Message {i}") }.construct_packet().unwrap().make_full_packet(server_addr))
+			.collect();
+		pair.client.process_outbound(packets, Instant::now()).unwrap();
+		// All five arrive in the same inbound batch, but max still means messages.
+		assert!(pair.deliver_to_server(Instant::now()).is_empty());
+		let mut received = Vec::new();
+		for expected_len in [2, 2, 1] {
+			let batch = server_receiver.recv_wait_batched(2, Duration::from_millis(10)).await.unwrap();
+			assert_eq!(batch.len(), expected_len);
+			received.extend(batch.into_iter().map(|(_, message)| message.message));
+		}
+		assert_eq!(received, (0..5).map(|i| format!("Message {i}")).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn netmsg_too_large() {
+		let small = TestNetMsg { message: String::from("Fits.") }.construct_packet().unwrap();
+		assert!(check_message_size(&small, DEFAULT_MAX_MESSAGE_SIZE).is_ok());
+
+		// The biggest payload we allow still fits in the send buffer after Laminar, encryption and the OuterEnvelope.
+		let mut pair = SessionPair::new(DEFAULT_MAX_STREAMS, DEFAULT_MAX_MESSAGE_SIZE, false);
+		let server_addr = pair.client.peer_address;
+		let mut largest = small.clone();
+		largest.payload.resize(netmsg::max_payload_size(DEFAULT_MAX_MESSAGE_SIZE), b'A');
+		let to_send = pair.client.split_oversized(vec![largest]);
+		assert_eq!(to_send.len(), 1);
+		pair.client.process_outbound(to_send.into_iter().map(|packet| packet.make_full_packet(server_addr)), Instant::now()).unwrap();
+		let envelopes = drain_outbox(&mut pair.client_outbox);
+		assert!(!envelopes.is_empty());
+		let mut send_buf = vec![0u8; DEFAULT_MAX_MESSAGE_SIZE];
+		for envelope in envelopes {
+			let envelope = OuterEnvelope { session: envelope.session, body: envelope.body };
+			assert!(envelope.encode(&mut send_buf).is_ok());
+		}
+		// One byte more gets dropped rather than sent.
+		let mut too_large = small.clone();
+		too_large.payload.resize(netmsg::max_payload_size(DEFAULT_MAX_MESSAGE_SIZE) + 1, b'A');
+		assert!(check_message_size(&too_large, DEFAULT_MAX_MESSAGE_SIZE).is_err());
+		assert!(pair.client.split_oversized(vec![too_large]).is_empty());
+
+		let huge = TestNetMsg { message: "A".repeat(DEFAULT_MAX_MESSAGE_SIZE * 2) }.construct_packet().unwrap();
+		match check_message_size(&huge, DEFAULT_MAX_MESSAGE_SIZE) {
+			Err(SessionLayerError::MessageTooLarge(id, len)) => {
+				assert_eq!(id, TestNetMsg::net_msg_id());
+				assert_eq!(len, huge.payload.len());
+			}
+			other => panic!("Expected MessageTooLarge, got {other:?}"),
+		}
+		// Same message is fine if we're told to allow it.
+		assert!(check_message_size(&huge, DEFAULT_MAX_MESSAGE_SIZE * 4).is_ok());
+	}
+
+	#[test]
+	fn netmsg_unreliable_sequenced() {
+		use netmsg::PacketGuarantees;
+
+		assert_eq!(TestSequencedNetMsg::net_msg_guarantees(), PacketGuarantees::UnreliableSequenced);
+		let packet = TestSequencedNetMsg { position: [1.0, 2.0, 3.0] }.construct_packet().unwrap();
+		assert_eq!(packet.guarantees, PacketGuarantees::UnreliableSequenced);
+	}
+
+	#[tokio::test]
+	async fn client_timeout_requests_reconnect() {
+		use crate::message::DomainResetTakeReceiver;
+
+		let engine_channels = EngineNetChannels::new(&ChannelCapacityConf::new());
+		let mut reconnect_requests = engine_channels.reconnect_requested.receiver_subscribe();
+		let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 54133);
+		let mut sys = NetworkSystem::new(
+			SelfNetworkRole::Client,
+			server_addr,
+			IdentityKeyPair::generate_for_tests(),
+			LaminarConfig::default(),
+			DEFAULT_MAX_MESSAGE_SIZE,
+			Duration::from_millis(50),
+			engine_channels.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		let server_identity = IdentityKeyPair::generate_for_tests().public;
+
+		// Stand-in for add_new_session(), minus the handshake.
+		let first_session = FullSessionName { peer_address: server_addr, session_id: [1, 2, 3, 4] };
+		sys.channels.init_peer(first_session.clone(), server_identity.clone());
+		sys.session_to_identity.insert(first_session.clone(), server_identity.clone());
+		let session_outbound = engine_channels.net_msg_outbound.reset_and_take_domain(&server_identity).unwrap();
+
+		let engine_sender = engine_channels.net_msg_outbound.sender_subscribe_domain(&server_identity).unwrap();
+		let before = TestNetMsg { message: String::from("Before") }.construct_packet().unwrap();
+		engine_sender.send(before.clone()).unwrap();
+
+		// The session goes quiet and gives up.
+		drop(session_outbound);
+		sys.handle_session_kill(first_session.clone(), vec![SessionLayerError::LaminarTimeout(server_addr)]);
+
+		let request = reconnect_requests.recv_wait().await.unwrap();
+		assert_eq!(request.peer_identity, server_identity);
+		assert_eq!(request.peer_address, server_addr);
+		assert!(sys.channels.raw_to_session.sender_subscribe(&first_session).is_err());
+
+		// The engine doesn't have to know anything happened.
+		let during = TestNetMsg { message: String::from("During") }.construct_packet().unwrap();
+		engine_sender.send(during.clone()).unwrap();
+
+		// Reconnected.
+		let second_session = FullSessionName { peer_address: server_addr, session_id: [5, 6, 7, 8] };
+		sys.channels.init_peer(second_session.clone(), server_identity.clone());
+		sys.session_to_identity.insert(second_session.clone(), server_identity.clone());
+		let mut session_outbound = engine_channels.net_msg_outbound.reset_and_take_domain(&server_identity).unwrap();
+		assert_eq!(session_outbound.recv_wait().await.unwrap()[0].payload, before.payload);
+		assert_eq!(session_outbound.recv_wait().await.unwrap()[0].payload, during.payload);
+
+		// Leaving on purpose is not a reason to reconnect.
+		drop(session_outbound);
+		sys.handle_session_kill(second_session, vec![]);
+		assert!(engine_channels.net_msg_outbound.sender_subscribe_domain(&server_identity).is_err());
+		assert!(matches!(reconnect_requests.recv_poll(), Ok(None)));
+	}
+
 	#[tokio::test]
 	//#[ignore] //Ignored until cause of GH Actions test flakiness can be ascertained.
 	async fn session_with_localhost() {
@@ -596,6 +1405,7 @@ use crate::SubsetBuilder;
 				server_socket_addr,
 				server_key_pair.clone(),
 				LaminarConfig::default(),
+				DEFAULT_MAX_MESSAGE_SIZE,
 				Duration::from_millis(50),
 				subset,
 			)
@@ -620,6 +1430,7 @@ use crate::SubsetBuilder;
 				SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
 				client_key_pair.clone(),
 				LaminarConfig::default(),
+				DEFAULT_MAX_MESSAGE_SIZE,
 				Duration::from_millis(50),
 				netsys_channels
 			)
@@ -646,6 +1457,7 @@ use crate::SubsetBuilder;
 		info!("Client connected to peer {:?} with role, {:?}", &connected_peer.peer_identity, &connected_peer.peer_role);
 
 		let post_handshake = tokio::time::Instant::now();
+		let mut client_stats_receiver = client_channel_set.session_stats.receiver_subscribe();
 
 		let client_net_send = client_channel_set.net_msg_outbound.sender_subscribe_domain(&connected_peer.peer_identity).unwrap();
 		client_net_send.send(
@@ -697,6 +1509,20 @@ use crate::SubsetBuilder;
 			assert_eq!(out.message, test_reply.message);
 		}
 
+		// Both directions have seen traffic by now, so a stats report should show some of it.
+		let stats = tokio::time::timeout(SESSION_STATS_INTERVAL * 5, async {
+			loop {
+				let stats = client_stats_receiver.recv_wait().await.unwrap();
+				if stats.bytes_in > 0 && stats.bytes_out > 0 {
+					break stats;
+				}
+			}
+		})
+		.await
+		.unwrap();
+		assert_eq!(stats.peer, server_key_pair.public);
+		assert!(stats.interval >= SESSION_STATS_INTERVAL);
+
 		info!("All behavior between the end of init&handshake, and the beginning of shutdown, took {:?}", post_handshake.elapsed());
 
 		quit_game(Duration::from_millis(50)).await.unwrap();
@@ -708,4 +1534,333 @@ use crate::SubsetBuilder;
 
 		drop(mutex_guard);
 	}
+
+	#[tokio::test]
+	async fn loopback_delivers_netmsgs() {
+		let channel_set = EngineNetChannels::new(&ChannelCapacityConf::new());
+		let key_pair = IdentityKeyPair::generate_for_tests();
+		let mut sys = NetworkSystem::new_loopback(key_pair.clone(), channel_set.build_subset(SubsetBuilder::new(())).unwrap());
+		assert_eq!(sys.connected_peers(), vec![key_pair.public.clone()]);
+
+		let mut test_receiver = channel_set.net_msg_inbound.receiver_typed::<TestNetMsg>().unwrap();
+		let join_handle = tokio::spawn(async move { sys.run().await });
+
+		let test = TestNetMsg {
+			message: String::from("Hello, me!"),
+		};
+		let sender = channel_set.net_msg_outbound.sender_subscribe_domain(&key_pair.public).unwrap();
+		sender.send(test.construct_packet().unwrap()).unwrap();
+
+		let out = tokio::time::timeout(Duration::from_secs(5), test_receiver.recv_wait())
+			.await
+			.unwrap()
+			.unwrap();
+		let (peer_ident, out) = out.first().unwrap().clone();
+		assert_eq!(peer_ident, key_pair.public);
+		assert_eq!(out.message, test.message);
+
+		join_handle.abort();
+		let _ = join_handle.await;
+	}
+
+	#[tokio::test]
+	async fn server_lists_connected_peers() {
+		let mutex_guard = NET_TEST_MUTEX.lock().await;
+
+		let server_channel_set = EngineNetChannels::new(&ChannelCapacityConf::new());
+		let protocol_dir = tempfile::tempdir().unwrap();
+		let server_key_pair = IdentityKeyPair::generate_for_tests();
+		tokio::spawn(approver_no_mismatch(server_channel_set.key_mismatch_reporter.receiver_subscribe(), server_channel_set.key_mismatch_approver.sender_subscribe()));
+
+		let port = find_available_udp_port(54534..54934).await.unwrap_or(8081);
+		let server_socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+
+		let mut server = NetworkSystem::new(
+			SelfNetworkRole::Server,
+			server_socket_addr,
+			server_key_pair.clone(),
+			LaminarConfig::default(),
+			DEFAULT_MAX_MESSAGE_SIZE,
+			Duration::from_millis(50),
+			server_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		let server_peers = server.connected_peers_handle();
+		assert!(server.connected_peers().is_empty());
+		let mut join_handles = vec![tokio::spawn(async move { server.run().await })];
+		join_handles.push(tokio::spawn(launch_preprotocol_listener(
+			server_key_pair.clone(),
+			Some(server_socket_addr),
+			port,
+			PathBuf::from(protocol_dir.path()),
+			server_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+		)));
+		// Let the listener bind before anyone tries to connect to it.
+		tokio::task::yield_now().await;
+
+		let mut client_identities = Vec::new();
+		// The clients' sessions end if their channels go away, so hang on to them until the test is done.
+		let mut client_channel_sets = Vec::new();
+		for _ in 0..2 {
+			let client_channel_set = EngineNetChannels::new(&ChannelCapacityConf::new());
+			let client_key_pair = IdentityKeyPair::generate_for_tests();
+			tokio::spawn(approver_no_mismatch(client_channel_set.key_mismatch_reporter.receiver_subscribe(), client_channel_set.key_mismatch_approver.sender_subscribe()));
+			let mut client = NetworkSystem::new(
+				SelfNetworkRole::Client,
+				server_socket_addr,
+				client_key_pair.clone(),
+				LaminarConfig::default(),
+				DEFAULT_MAX_MESSAGE_SIZE,
+				Duration::from_millis(50),
+				client_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+			)
+			.await
+			.unwrap();
+			join_handles.push(tokio::spawn(async move { client.run().await }));
+
+			let mut connected = client_channel_set.peer_connected.receiver_subscribe();
+			preprotocol_connect_to_server(
+				client_key_pair.clone(),
+				server_socket_addr,
+				Duration::new(5, 0),
+				PathBuf::from(protocol_dir.path()),
+				client_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+			)
+			.await
+			.unwrap();
+			let connected = tokio::time::timeout(Duration::from_secs(5), connected.recv_wait()).await.unwrap().unwrap();
+			assert_eq!(connected.peer_identity, server_key_pair.public);
+			client_identities.push(client_key_pair.public);
+			client_channel_sets.push(client_channel_set);
+		}
+
+		// The server adds a client's session once it hears from it over UDP, which can lag the handshake a little.
+		tokio::time::timeout(Duration::from_secs(5), async {
+			while server_peers.len() < client_identities.len() {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await
+		.unwrap();
+		let listed = server_peers.snapshot();
+		assert_eq!(listed.len(), 2);
+		for identity in &client_identities {
+			assert!(server_peers.contains(identity));
+			assert!(listed.contains(identity));
+		}
+
+		for join_handle in join_handles {
+			join_handle.abort();
+			let _ = join_handle.await;
+		}
+		drop(mutex_guard);
+	}
+
+	#[tokio::test]
+	async fn v4_client_reaches_dual_stack_server() {
+		let mutex_guard = NET_TEST_MUTEX.lock().await;
+
+		let server_channel_set = EngineNetChannels::new(&ChannelCapacityConf::new());
+		let client_channel_set = EngineNetChannels::new(&ChannelCapacityConf::new());
+		let protocol_dir = tempfile::tempdir().unwrap();
+		let server_key_pair = IdentityKeyPair::generate_for_tests();
+		let client_key_pair = IdentityKeyPair::generate_for_tests();
+		tokio::spawn(approver_no_mismatch(server_channel_set.key_mismatch_reporter.receiver_subscribe(), server_channel_set.key_mismatch_approver.sender_subscribe()));
+		tokio::spawn(approver_no_mismatch(client_channel_set.key_mismatch_reporter.receiver_subscribe(), client_channel_set.key_mismatch_approver.sender_subscribe()));
+
+		let port = find_available_udp_port(55334..55734).await.unwrap_or(8083);
+		// UDP on every IPv6 address (and, being dual-stack, IPv4 too). The client only ever uses IPv4.
+		let server_udp_addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+		let server_v4_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+
+		let mut server = NetworkSystem::new_with_bind_mode(
+			SelfNetworkRole::Server,
+			server_udp_addr,
+			BindMode::DualStack,
+			server_key_pair.clone(),
+			LaminarConfig::default(),
+			DEFAULT_MAX_MESSAGE_SIZE,
+			Duration::from_millis(50),
+			server_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		let server_peers = server.connected_peers_handle();
+		let mut client = NetworkSystem::new(
+			SelfNetworkRole::Client,
+			server_v4_addr,
+			client_key_pair.clone(),
+			LaminarConfig::default(),
+			DEFAULT_MAX_MESSAGE_SIZE,
+			Duration::from_millis(50),
+			client_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		let join_handles = vec![
+			tokio::spawn(async move { server.run().await }),
+			tokio::spawn(launch_preprotocol_listener(
+				server_key_pair.clone(),
+				Some(server_v4_addr),
+				port,
+				PathBuf::from(protocol_dir.path()),
+				server_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+			)),
+			tokio::spawn(async move { client.run().await }),
+		];
+		// Let the listener bind before anyone tries to connect to it.
+		tokio::task::yield_now().await;
+
+		// run() registers NetMsg domains once it starts, which might not have happened yet.
+		let _ = server_channel_set.net_msg_inbound.init_domain(TestNetMsg::net_msg_id());
+		let mut server_test_receiver = server_channel_set.net_msg_inbound.receiver_typed::<TestNetMsg>().unwrap();
+		let mut connected = client_channel_set.peer_connected.receiver_subscribe();
+		preprotocol_connect_to_server(
+			client_key_pair.clone(),
+			server_v4_addr,
+			Duration::new(5, 0),
+			PathBuf::from(protocol_dir.path()),
+			client_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		tokio::time::timeout(Duration::from_secs(5), connected.recv_wait()).await.unwrap().unwrap();
+
+		let test = TestNetMsg {
+			message: String::from("Hello over IPv4!"),
+		};
+		client_channel_set
+			.net_msg_outbound
+			.sender_subscribe_domain(&server_key_pair.public)
+			.unwrap()
+			.send(test.construct_packet().unwrap())
+			.unwrap();
+		let out = tokio::time::timeout(Duration::from_secs(5), server_test_receiver.recv_wait())
+			.await
+			.unwrap()
+			.unwrap();
+		let (peer_ident, out) = out.first().unwrap().clone();
+		assert_eq!(peer_ident, client_key_pair.public);
+		assert_eq!(out.message, test.message);
+		assert!(server_peers.contains(&client_key_pair.public));
+
+		for join_handle in join_handles {
+			join_handle.abort();
+			let _ = join_handle.await;
+		}
+		drop(mutex_guard);
+	}
+
+	#[test]
+	fn bind_mode_rejects_mismatched_address() {
+		let v4: SocketAddr = "127.0.0.1:0".parse().unwrap();
+		let v6: SocketAddr = "[::1]:0".parse().unwrap();
+		assert!(bind_udp_socket(v6, BindMode::V4Only).is_err());
+		assert!(bind_udp_socket(v4, BindMode::V6Only).is_err());
+		assert!(bind_udp_socket(v4, BindMode::DualStack).is_err());
+	}
+
+	#[tokio::test]
+	async fn kicked_peer_session_ends() {
+		let mutex_guard = NET_TEST_MUTEX.lock().await;
+
+		let server_channel_set = EngineNetChannels::new(&ChannelCapacityConf::new());
+		let client_channel_set = EngineNetChannels::new(&ChannelCapacityConf::new());
+		let protocol_dir = tempfile::tempdir().unwrap();
+		let server_key_pair = IdentityKeyPair::generate_for_tests();
+		let client_key_pair = IdentityKeyPair::generate_for_tests();
+		tokio::spawn(approver_no_mismatch(server_channel_set.key_mismatch_reporter.receiver_subscribe(), server_channel_set.key_mismatch_approver.sender_subscribe()));
+		tokio::spawn(approver_no_mismatch(client_channel_set.key_mismatch_reporter.receiver_subscribe(), client_channel_set.key_mismatch_approver.sender_subscribe()));
+
+		let port = find_available_udp_port(54934..55334).await.unwrap_or(8082);
+		let server_socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+
+		let mut server = NetworkSystem::new(
+			SelfNetworkRole::Server,
+			server_socket_addr,
+			server_key_pair.clone(),
+			LaminarConfig::default(),
+			DEFAULT_MAX_MESSAGE_SIZE,
+			Duration::from_millis(50),
+			server_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		let server_peers = server.connected_peers_handle();
+		let mut client = NetworkSystem::new(
+			SelfNetworkRole::Client,
+			server_socket_addr,
+			client_key_pair.clone(),
+			LaminarConfig::default(),
+			DEFAULT_MAX_MESSAGE_SIZE,
+			Duration::from_millis(50),
+			client_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		let client_peers = client.connected_peers_handle();
+		let mut server_disconnects = server_channel_set.peer_disconnected.receiver_subscribe();
+		let mut client_disconnects = client_channel_set.peer_disconnected.receiver_subscribe();
+		let join_handles = vec![
+			tokio::spawn(async move { server.run().await }),
+			tokio::spawn(launch_preprotocol_listener(
+				server_key_pair.clone(),
+				Some(server_socket_addr),
+				port,
+				PathBuf::from(protocol_dir.path()),
+				server_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+			)),
+			tokio::spawn(async move { client.run().await }),
+		];
+		// Let the listener bind before anyone tries to connect to it.
+		tokio::task::yield_now().await;
+
+		preprotocol_connect_to_server(
+			client_key_pair.clone(),
+			server_socket_addr,
+			Duration::new(5, 0),
+			PathBuf::from(protocol_dir.path()),
+			client_channel_set.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		tokio::time::timeout(Duration::from_secs(5), async {
+			while !server_peers.contains(&client_key_pair.public) {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await
+		.unwrap();
+		assert!(client_peers.contains(&server_key_pair.public));
+
+		server_channel_set.kick_peer.sender_subscribe().send(KickPeer {
+			peer_identity: client_key_pair.public.clone(),
+			ban: false,
+		}).unwrap();
+
+		// The client's session only goes away once it has gotten the DisconnectMsg and stopped looping.
+		tokio::time::timeout(Duration::from_secs(5), async {
+			while !client_peers.is_empty() {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await
+		.unwrap();
+		assert!(!server_peers.contains(&client_key_pair.public));
+
+		// Both ends let the rest of the engine know, e.g. so the server can forget the client's player.
+		let server_disconnect = tokio::time::timeout(Duration::from_secs(5), server_disconnects.recv_wait()).await.unwrap().unwrap();
+		assert_eq!(server_disconnect.peer_identity, client_key_pair.public);
+		assert_eq!(server_disconnect.peer_role, NetworkRole::Client);
+		let client_disconnect = tokio::time::timeout(Duration::from_secs(5), client_disconnects.recv_wait()).await.unwrap().unwrap();
+		assert_eq!(client_disconnect.peer_identity, server_key_pair.public);
+		assert_eq!(client_disconnect.peer_role, NetworkRole::Server);
+
+		for join_handle in join_handles {
+			join_handle.abort();
+			let _ = join_handle.await;
+		}
+		drop(mutex_guard);
+	}
 }
@@ -1,13 +1,15 @@
+use std::collections::VecDeque;
 use std::marker::PhantomData;
+use std::time::Duration;
 
 use gestalt_proc_macros::ChannelSet;
 use log::warn;
 
 use crate::{
-	common::identity::NodeIdentity, message::{MessageSender, MpscSender, SendError}, BroadcastChannel, BroadcastReceiver, BroadcastSender, ChannelCapacityConf, ChannelInit, DomainMessageSender, DomainMultiChannel, DomainSenderSubscribe, DomainSubscribeErr, DomainTakeReceiver, MessageReceiver, MessageReceiverAsync, MpscChannel, MpscReceiver, MultiDomainSender, NewDomainErr, ReceiverChannel, SenderChannel, StaticChannelAtom
+	common::identity::NodeIdentity, message::{DomainResetTakeReceiver, MessageSender, MpscSender, RecvError, SendError}, BroadcastChannel, BroadcastReceiver, BroadcastSender, ChannelCapacityConf, ChannelInit, ChannelRegistry, ChannelStats, ChannelStatsProbe, DomainMessageSender, DomainMultiChannel, DomainSenderSubscribe, DomainSubscribeErr, DomainTakeReceiver, MessageReceiver, MessageReceiverAsync, MpscChannel, MpscReceiver, MultiDomainSender, NewDomainErr, ReceiverChannel, SenderChannel, StaticChannelAtom
 };
 
-use super::{netmsg::{CiphertextEnvelope, NetMsgRecvError}, ConnectAnnounce, FullSessionName, InboundNetMsg, NetMsg, NetMsgDomain, NetMsgId, OuterEnvelope, PacketIntermediary, SessionLayerError, SuccessfulConnect};
+use super::{netmsg::{CiphertextEnvelope, NetMsgRecvError}, ConnectAnnounce, DisconnectAnnounce, FullSessionName, InboundNetMsg, KickPeer, NetMsg, NetMsgDomain, NetMsgId, OuterEnvelope, PacketIntermediary, ReconnectRequested, SessionLayerError, SessionStats, SuccessfulConnect};
 
 pub type OutboundNetMsgs = Vec<PacketIntermediary>;
 pub(super) type NetInnerSender = MpscSender<OutboundNetMsgs>;
@@ -34,13 +36,7 @@ impl NetMsgSender {
 			.map_err(|_e| SendError::NoReceivers)
 	}
 	fn encode_packet<R>(message: R) -> Result<PacketIntermediary, crate::message::SendError> where R: NetMsg { 
-		message.construct_packet().map_err(|e| {
-			SendError::Encode(format!(
-				"Could not convert packet of type {} into a packet intermediary: {:?}",
-				R::net_msg_name(),
-				e
-			))
-		})
+		message.construct_packet().map_err(|e| SendError::Encode(e.to_string()))
 	}
 
 	pub fn send_one<R>(&self, message: R) -> Result<(), crate::message::SendError> where R: NetMsg {
@@ -135,6 +131,18 @@ impl NetSendChannel {
 	//}
 }
 
+impl ChannelStats for NetSendChannel {
+	fn queued_len(&self) -> usize {
+		self.inner.queued_len()
+	}
+	fn capacity(&self) -> usize {
+		self.inner.capacity()
+	}
+	fn stats_probe(&self) -> ChannelStatsProbe {
+		self.inner.stats_probe()
+	}
+}
+
 impl ReceiverChannel<OutboundNetMsgs> for NetSendChannel {
 	type Receiver = OutboundNetMsgReceiver;
 }
@@ -155,6 +163,12 @@ impl DomainTakeReceiver<OutboundNetMsgs, NodeIdentity> for NetSendChannel {
 	}
 }
 
+impl DomainResetTakeReceiver<OutboundNetMsgs, NodeIdentity> for NetSendChannel {
+	fn reset_and_take_domain(&self, domain: &NodeIdentity) -> Result<Self::Receiver, DomainSubscribeErr<NodeIdentity>> {
+		self.inner.reset_and_take(domain)
+	}
+}
+
 impl DomainMessageSender<OutboundNetMsgs, NodeIdentity> for NetSendChannel {
 	fn send_to(&self, message: OutboundNetMsgs, domain: &NodeIdentity) -> Result<(), SendError> {
 		self.inner.send_to(message, domain)
@@ -167,6 +181,13 @@ impl DomainMessageSender<OutboundNetMsgs, NodeIdentity> for NetSendChannel {
 	fn send_to_all_except(&self, message: OutboundNetMsgs, exclude: &NodeIdentity) -> Result<(), SendError> {
 		self.inner.send_to_all_except(message, exclude)
 	}
+
+	fn send_to_matching<F>(&self, message: OutboundNetMsgs, filter: F) -> Result<(), SendError>
+	where
+		F: Fn(&NodeIdentity) -> bool,
+	{
+		self.inner.send_to_matching(message, filter)
+	}
 }
 
 pub type InboundNetMsgs = Vec<InboundNetMsg>;
@@ -180,6 +201,10 @@ impl InboundNetChannel {
 
 pub struct NetMsgReceiver<T> where T: NetMsg { 
 	inner: BroadcastReceiver<InboundNetMsgs>,
+	/// Messages received by recv_wait_batched() but not handed out yet, because of its limit or because it got cancelled.
+	leftover: VecDeque<InboundNetMsg>,
+	/// Error which ended a recv_wait_batched() early, returned once leftover has been handed out.
+	pending_error: Option<RecvError>,
 	_marker: PhantomData<T>,
 }
 
@@ -188,6 +213,8 @@ impl<T: NetMsg> NetMsgReceiver<T> {
 		Ok(
 			Self { 
 				inner: channel.receiver_subscribe(&T::net_msg_id())?,
+				leftover: VecDeque::new(),
+				pending_error: None,
 				_marker: PhantomData,
 			}
 		)
@@ -217,16 +244,57 @@ impl<T: NetMsg> NetMsgReceiver<T> {
 		Ok(output)
 	}
 
+	/// Anything recv_wait_batched() left behind, or the error it stopped on.
+	fn take_leftover(&mut self) -> Option<Result<Vec<InboundNetMsg>, RecvError>> {
+		if !self.leftover.is_empty() {
+			return Some(Ok(self.leftover.drain(..).collect()));
+		}
+		self.pending_error.take().map(Err)
+	}
+
 	pub async fn recv_wait(&mut self) -> Result<Vec<(NodeIdentity, T)>, NetMsgRecvError> {
+		if let Some(leftover) = self.take_leftover() {
+			return Self::decode(leftover?);
+		}
 		Self::decode(self.inner.recv_wait().await?)
 	}
 
+	/// Like recv_wait(), but keeps collecting until `max` messages have arrived or `max_wait` has elapsed, and returns
+	/// them all at once. Never returns more than `max` messages (or 1, if `max` is 0) - the rest wait for the next call.
+	/// Cancel-safe. Like BroadcastReceiver::recv_wait_batched(), an error partway through comes back on the next call.
+	pub async fn recv_wait_batched(&mut self, max: usize, max_wait: Duration) -> Result<Vec<(NodeIdentity, T)>, NetMsgRecvError> {
+		let max = max.max(1);
+		if self.leftover.is_empty() {
+			if let Some(e) = self.pending_error.take() {
+				return Err(e.into());
+			}
+			let first = self.inner.recv_wait().await?;
+			self.leftover.extend(first);
+		}
+		let deadline = tokio::time::Instant::now() + max_wait;
+		while self.leftover.len() < max {
+			match tokio::time::timeout_at(deadline, self.inner.recv_wait()).await {
+				Ok(Ok(batch)) => self.leftover.extend(batch),
+				Ok(Err(e)) => {
+					self.pending_error = Some(e);
+					break;
+				}
+				// Timed out.
+				Err(_) => break,
+			}
+		}
+		let count = self.leftover.len().min(max);
+		Self::decode(self.leftover.drain(..count).collect())
+	}
+
 	pub fn resubscribe<U>(&self) -> NetMsgReceiver<U>
 	where
 		U: NetMsg,
 	{
 		NetMsgReceiver {
 			inner: self.inner.resubscribe(),
+			leftover: VecDeque::new(),
+			pending_error: None,
 			_marker: PhantomData::default(),
 		}
 	}
@@ -234,7 +302,10 @@ impl<T: NetMsg> NetMsgReceiver<T> {
 
 impl<T> MessageReceiver<Vec<(NodeIdentity, T)>> for NetMsgReceiver<T> where T: NetMsg + std::fmt::Debug {
 	fn recv_poll(&mut self) -> Result<Option<Vec<(NodeIdentity, T)>>, crate::RecvError> {
-		let resl = self.inner.recv_poll()?;
+		let resl = match self.take_leftover() {
+			Some(leftover) => Some(leftover?),
+			None => self.inner.recv_poll()?,
+		};
 		let messages = if let Some(messages) = resl { messages } else { return Ok(None) };
 		Self::decode(messages)
 			.map(|v| Some(v))
@@ -248,7 +319,11 @@ static_channel_atom!(NetMsgInbound, InboundNetChannel, InboundNetMsgs, NetMsgDom
 static_channel_atom!(ConnectInternal, MpscChannel<SuccessfulConnect>, SuccessfulConnect, 4096);
 
 static_channel_atom!(ConnectionReady, BroadcastChannel<ConnectAnnounce>, ConnectAnnounce, 4096);
-static_channel_atom!(DisconnectAnnounce, BroadcastChannel<ConnectAnnounce>, ConnectAnnounce, 4096);
+static_channel_atom!(ConnectionEnded, BroadcastChannel<DisconnectAnnounce>, DisconnectAnnounce, 4096);
+static_channel_atom!(ReconnectRequest, BroadcastChannel<ReconnectRequested>, ReconnectRequested, 64);
+static_channel_atom!(SessionStatsReport, BroadcastChannel<SessionStats>, SessionStats, 1024);
+
+static_channel_atom!(PeerKick, MpscChannel<KickPeer>, KickPeer, 64);
 
 pub type OutboundRawPackets = Vec<OuterEnvelope>;
 pub type OutboundPacketChannel = MpscChannel<OutboundRawPackets>;
@@ -271,6 +346,18 @@ pub struct EngineNetChannels {
 	pub connect_internal: <ConnectInternal as StaticChannelAtom>::Channel,
 	#[channel(ConnectionReady)]
 	pub peer_connected: <ConnectionReady as StaticChannelAtom>::Channel,
+	/// Sent once the last session with a peer is gone, however it ended.
+	#[channel(ConnectionEnded)]
+	pub peer_disconnected: <ConnectionEnded as StaticChannelAtom>::Channel,
+	/// Client-only, sent when a session with a server times out.
+	#[channel(ReconnectRequest)]
+	pub reconnect_requested: <ReconnectRequest as StaticChannelAtom>::Channel,
+	/// Per-session traffic, published every SESSION_STATS_INTERVAL.
+	#[channel(SessionStatsReport)]
+	pub session_stats: <SessionStatsReport as StaticChannelAtom>::Channel,
+	/// Server-only, used by the game (e.g. admin commands) to boot or ban a peer.
+	#[channel(PeerKick)]
+	pub kick_peer: <PeerKick as StaticChannelAtom>::Channel,
 	#[channel(ProtocolKeyMismatchReporter)]
 	pub key_mismatch_reporter: <ProtocolKeyMismatchReporter as StaticChannelAtom>::Channel,
 	#[channel(ProtocolKeyMismatchApprover)]
@@ -279,14 +366,29 @@ pub struct EngineNetChannels {
 // TODO: Do some more proc macro nonsense but around init this time so this isn't so boilerplatey.
 impl EngineNetChannels {
 	pub fn new(conf: &ChannelCapacityConf) -> Self {
-		Self {
+		let channels = Self {
 			net_msg_outbound: NetSendChannel::new(conf.get_or_default::<NetMsgOutbound>()),
 			net_msg_inbound: InboundNetChannel::new(conf.get_or_default::<NetMsgInbound>()),
 			connect_internal: MpscChannel::new(conf.get_or_default::<ConnectInternal>()),
 			peer_connected: BroadcastChannel::new(conf.get_or_default::<ConnectionReady>()),
+			peer_disconnected: BroadcastChannel::new(conf.get_or_default::<ConnectionEnded>()),
+			reconnect_requested: BroadcastChannel::new(conf.get_or_default::<ReconnectRequest>()),
+			session_stats: BroadcastChannel::new(conf.get_or_default::<SessionStatsReport>()),
+			kick_peer: MpscChannel::new(conf.get_or_default::<PeerKick>()),
 			key_mismatch_reporter: BroadcastChannel::new(conf.get_or_default::<ProtocolKeyMismatchReporter>()),
 			key_mismatch_approver: BroadcastChannel::new(conf.get_or_default::<ProtocolKeyMismatchApprover>()),
-		}
+		};
+		ChannelRegistry::register::<NetMsgOutbound>(&channels.net_msg_outbound);
+		ChannelRegistry::register::<NetMsgInbound>(&channels.net_msg_inbound);
+		ChannelRegistry::register::<ConnectInternal>(&channels.connect_internal);
+		ChannelRegistry::register::<ConnectionReady>(&channels.peer_connected);
+		ChannelRegistry::register::<ConnectionEnded>(&channels.peer_disconnected);
+		ChannelRegistry::register::<ReconnectRequest>(&channels.reconnect_requested);
+		ChannelRegistry::register::<SessionStatsReport>(&channels.session_stats);
+		ChannelRegistry::register::<PeerKick>(&channels.kick_peer);
+		ChannelRegistry::register::<ProtocolKeyMismatchReporter>(&channels.key_mismatch_reporter);
+		ChannelRegistry::register::<ProtocolKeyMismatchApprover>(&channels.key_mismatch_approver);
+		channels
 	}
 }
 
@@ -312,6 +414,14 @@ pub struct NetSystemChannels {
 	pub connect_internal: MpscReceiver<SuccessfulConnect>,
 	#[channel(ConnectionReady)]
 	pub announce_connection: BroadcastChannel<ConnectAnnounce>,
+	#[channel(ConnectionEnded)]
+	pub announce_disconnection: BroadcastChannel<DisconnectAnnounce>,
+	#[channel(ReconnectRequest)]
+	pub announce_reconnect: BroadcastChannel<ReconnectRequested>,
+	#[channel(SessionStatsReport)]
+	pub session_stats: BroadcastChannel<SessionStats>,
+	#[take_receiver(PeerKick)]
+	pub kick_requests: MpscReceiver<KickPeer>,
 	/// Net-system-internal, used to push OuterEnvelopes from session to socket.
 	#[channel(PacketPush, new_channel)]
 	pub session_to_socket: <PacketPush as StaticChannelAtom>::Channel,
@@ -335,6 +445,11 @@ impl NetSystemChannels {
 	}
 	pub fn drop_peer(&self, session: &FullSessionName, ident: &NodeIdentity) {
 		self.net_msg_outbound.drop_peer(ident);
+		self.drop_session(session);
+	}
+	/// Drops the channels which belong to one particular session, but not the ones belonging to the peer,
+	/// so the engine can keep queueing messages for them while a new session gets set up.
+	pub fn drop_session(&self, session: &FullSessionName) {
 		self.raw_to_session.drop_domain(session);
 		self.system_kill_session.drop_domain(session);
 	}
@@ -349,12 +464,15 @@ pub struct SessionChannels {
 	/// Network-to-game. Inbound i.e. inbound from net.
 	#[channel(NetMsgInbound)]
 	pub to_engine: InboundNetChannel,
-	#[take_receiver(NetMsgOutbound, domain: "peer_identity")]
+	/// Reusable so that, if this session times out, whatever's still queued is left for the next session with this peer.
+	#[take_receiver(NetMsgOutbound, domain: "peer_identity", reusable)]
 	pub from_engine: OutboundNetMsgReceiver,
 	/// ConnectionReady is sent as soon as our session object has decided that it's safe
 	/// to tell the rest of the engine that this connection has occurred.
 	#[sender(ConnectionReady)]
 	pub announce_connection: BroadcastSender<ConnectAnnounce>,
+	#[sender(SessionStatsReport)]
+	pub session_stats: BroadcastSender<SessionStats>,
 	/// Net-system-internal, used by sessions to give ready packets to the packet handler.
 	#[sender(PacketPush)]
 	pub push_sender: MpscSender<OutboundRawPackets>,
@@ -1,6 +1,8 @@
+use std::collections::VecDeque;
 use std::net::SocketAddr;
 
 use log::warn;
+use semver::Version;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
@@ -118,6 +120,17 @@ pub enum PacketGuarantees {
 
 pub type StreamId = u8;
 
+/// Which NetMsgs get handed to Laminar first when a session has a backlog of them to send.
+/// Set with a trailing ident in the netmsg attribute, after the stream - e.g. `5, Common, ReliableOrdered, 1, High`.
+/// Only decides which stream goes first - messages on the same stream always go out in the order they were sent.
+#[derive(Debug, Copy, Clone, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub enum MessagePriority {
+	Low,
+	#[default]
+	Normal,
+	High,
+}
+
 /// Which "stream" is this on?
 /// A stream in this context must be a u8-identified separate channel of packets
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Hash)]
@@ -330,6 +343,21 @@ impl OuterEnvelope {
 	}
 }
 
+/// Most bytes Laminar can put in front of one packet: standard header (5), acks (8), fragment (4) and arranging (3).
+pub const LAMINAR_MAX_HEADER_SIZE: usize = 5 + 8 + 4 + 3;
+/// Length of the authentication tag Noise's AEAD adds to every ciphertext.
+pub const AEAD_TAG_SIZE: usize = 16;
+/// Session ID, message counter, and the longest possible vu64 ciphertext length.
+pub const OUTER_ENVELOPE_MAX_HEADER_SIZE: usize =
+	std::mem::size_of::<SessionId>() + std::mem::size_of::<MessageCounter>() + 9;
+/// Worst case for how much bigger a NetMsg's payload gets on its way to becoming an encoded OuterEnvelope.
+pub const MAX_WIRE_OVERHEAD: usize = LAMINAR_MAX_HEADER_SIZE + AEAD_TAG_SIZE + OUTER_ENVELOPE_MAX_HEADER_SIZE;
+
+/// Largest NetMsg payload which is sure to fit in max_message_size bytes once it's on the wire.
+pub const fn max_payload_size(max_message_size: usize) -> usize {
+	max_message_size.saturating_sub(MAX_WIRE_OVERHEAD)
+}
+
 /// Subset of an OuterEnvelope which cannot be a ProtocolMessage.
 #[derive(Debug, Clone)]
 pub struct CiphertextEnvelope {
@@ -343,7 +371,31 @@ pub type NetMsgId = u32;
 
 impl ChannelDomain for NetMsgId {}
 
+/// Only DisconnectMsg gets this one. gestalt-core's build script won't let a #[netmsg] take it as a literal ID.
 pub const DISCONNECT_RESERVED: NetMsgId = 0;
+/// Never the ID of an actual message type - marks a piece of a message which was too big to send in one go.
+/// See fragment.rs. gestalt-core's build script rejects a #[netmsg] which tries to use it.
+pub const FRAGMENT_RESERVED: NetMsgId = 1;
+
+mod name_hash {
+	include!("../../../gestalt-proc-macros/src/netmsg_name_hash.rs");
+}
+/// The same hash the netmsg macro uses at compile time for name-derived IDs, so e.g. mods and scripts can work out
+/// what ID a named message type has without linking against it.
+pub use name_hash::{netmsg_id_from_name, NAMED_NETMSG_ID_BIT};
+
+/// Reads the varint NetMsgId off the front of a serialized NetMsg.
+/// Returns the ID and how many bytes it took up, or None if there isn't a whole ID there.
+pub fn read_netmsg_id(payload: &[u8]) -> Option<(NetMsgId, usize)> {
+	let first_byte = *payload.first()?;
+	let id_len = vu64::decoded_len(first_byte) as usize;
+	if payload.len() < id_len {
+		return None;
+	}
+	vu64::decode_with_length(id_len as u8, &payload[0..id_len])
+		.ok()
+		.map(|id| (id as NetMsgId, id_len))
+}
 
 /// Information required to interconvert between raw packets and structured Rust types.
 #[derive(Debug, Clone)]
@@ -353,6 +405,37 @@ pub struct NetMsgType {
 	pub sidedness: MessageSidedness,
 	pub guarantees: PacketGuarantees,
 	pub stream: StreamSelector,
+	/// Engine version this message type was introduced in, if it was added after the initial protocol.
+	pub since: Option<Version>,
+}
+
+impl NetMsgType {
+	/// Can a peer running the given engine version make sense of this message type?
+	/// Peers who didn't report an engine version are assumed to predate every gated message.
+	pub fn supported_by(&self, peer_engine_version: Option<&Version>) -> bool {
+		match (&self.since, peer_engine_version) {
+			(None, _) => true,
+			(Some(since), Some(peer_version)) => since <= peer_version,
+			(Some(_), None) => false,
+		}
+	}
+}
+
+/// Human-readable form of a NetMsgId for error messages - e.g. "41 (VoxelChangeAnnounce)",
+/// or "41 (unknown to this build)" if no message type in this build uses that ID. Unknown name-derived IDs
+/// are shown in hex, since they're hashes and the decimal form means nothing to anyone.
+/// Never panics, so it's safe to use on IDs which came in off the wire.
+pub fn describe_netmsg_id(id: NetMsgId) -> String {
+	match try_netmsg_name(id) {
+		Some(name) => format!("{id} ({name})"),
+		None if id & NAMED_NETMSG_ID_BIT != 0 => format!("{id:#010x} (unknown to this build)"),
+		None => format!("{id} (unknown to this build)"),
+	}
+}
+
+/// The name of the message type using this ID, or None if no message type in this build uses it.
+pub fn try_netmsg_name(id: NetMsgId) -> Option<&'static str> {
+	super::generated::get_netmsg_table().get(&id).map(|msg_type| msg_type.name)
 }
 
 /// A NetMsg coming in off the wire
@@ -423,45 +506,103 @@ pub type NetMsgBroadcast = Vec<(InboundNetMsg, NodeIdentity)>;
 pub struct PacketIntermediary {
 	pub guarantees: PacketGuarantees,
 	pub stream: StreamSelector,
+	pub priority: MessagePriority,
 	pub payload: Vec<u8>,
 }
 
 impl PacketIntermediary {
 	pub fn make_full_packet(self, send_to: SocketAddr) -> laminar::Packet {
-		use laminar::Packet;
-		// Branch on our message properties to figure out what kind of packet to construct.
-		match self.guarantees {
-			PacketGuarantees::UnreliableUnordered => {
-				// Unordered packets have no concept of a "stream"
-				Packet::unreliable(send_to, self.payload)
-			}
-			PacketGuarantees::UnreliableSequenced => match self.stream {
-				StreamSelector::Any => Packet::unreliable_sequenced(send_to, self.payload, None),
-				StreamSelector::Specific(id) => {
-					Packet::unreliable_sequenced(send_to, self.payload, Some(id))
-				}
-			},
-			PacketGuarantees::ReliableUnordered => {
-				// Unordered packets have no concept of a "stream"
-				Packet::reliable_unordered(send_to, self.payload)
+		self.guarantees.to_laminar_packet(self.payload, send_to, self.stream)
+	}
+}
+
+/// Everything a session has been asked to send in one go, kept as one FIFO per stream.
+/// Draining repeatedly takes whichever stream's next packet has the highest MessagePriority (the one pushed
+/// first, on a tie), so priority only ever reorders packets between streams, never within one.
+#[derive(Default, Debug)]
+pub struct OutboundQueue {
+	/// Each packet is tagged with the order it was pushed in, to break ties between streams.
+	streams: Vec<(StreamSelector, VecDeque<(u64, PacketIntermediary)>)>,
+	pushed: u64,
+}
+
+impl OutboundQueue {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn push(&mut self, packet: PacketIntermediary) {
+		let order = self.pushed;
+		self.pushed += 1;
+		match self.streams.iter_mut().find(|(stream, _)| *stream == packet.stream) {
+			Some((_, queue)) => queue.push_back((order, packet)),
+			None => self.streams.push((packet.stream, VecDeque::from([(order, packet)]))),
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.streams.iter().map(|(_, queue)| queue.len()).sum()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn drain(&mut self) -> Vec<PacketIntermediary> {
+		let mut out = Vec::with_capacity(self.len());
+		loop {
+			let next = self.streams
+				.iter_mut()
+				.filter_map(|(_, queue)| {
+					let (priority, order) = queue.front().map(|(order, packet)| (packet.priority, *order))?;
+					Some((priority, std::cmp::Reverse(order), queue))
+				})
+				.max_by_key(|(priority, order, _)| (*priority, *order));
+			match next {
+				Some((_, _, queue)) => out.push(queue.pop_front().unwrap().1),
+				None => break,
 			}
-			PacketGuarantees::ReliableOrdered => match self.stream {
-				StreamSelector::Any => Packet::reliable_ordered(send_to, self.payload, None),
-				StreamSelector::Specific(id) => {
-					Packet::reliable_ordered(send_to, self.payload, Some(id))
-				}
-			},
-			PacketGuarantees::ReliableSequenced => match self.stream {
-				StreamSelector::Any => Packet::reliable_sequenced(send_to, self.payload, None),
-				StreamSelector::Specific(id) => {
-					Packet::reliable_sequenced(send_to, self.payload, Some(id))
-				}
-			},
+		}
+		self.streams.clear();
+		out
+	}
+}
+
+impl Extend<PacketIntermediary> for OutboundQueue {
+	fn extend<T: IntoIterator<Item = PacketIntermediary>>(&mut self, iter: T) {
+		for packet in iter {
+			self.push(packet);
+		}
+	}
+}
+
+impl PacketGuarantees {
+	/// The one place our delivery guarantees get turned into Laminar packet kinds.
+	/// Unordered packets have no concept of a "stream", so the stream is ignored for those. For the
+	/// sequenced and ordered kinds, StreamSelector::Any puts the packet on Laminar's default stream.
+	pub fn to_laminar_packet(&self, payload: Vec<u8>, send_to: SocketAddr, stream: StreamSelector) -> laminar::Packet {
+		use laminar::Packet;
+		let stream_id: Option<StreamId> = stream.into();
+		match self {
+			PacketGuarantees::UnreliableUnordered => Packet::unreliable(send_to, payload),
+			PacketGuarantees::UnreliableSequenced => Packet::unreliable_sequenced(send_to, payload, stream_id),
+			PacketGuarantees::ReliableUnordered => Packet::reliable_unordered(send_to, payload),
+			PacketGuarantees::ReliableOrdered => Packet::reliable_ordered(send_to, payload, stream_id),
+			PacketGuarantees::ReliableSequenced => Packet::reliable_sequenced(send_to, payload, stream_id),
 		}
 	}
 }
 
 pub const PACKET_ENCODE_MAX: usize = 1024 * 1024 * 512;
+
+/// Something went wrong turning a NetMsg into a PacketIntermediary.
+#[derive(thiserror::Error, Debug)]
+pub enum PacketConstructError {
+	#[error("Could not serialize a {0} NetMsg: {1}")]
+	Serialize(&'static str, rmp_serde::encode::Error),
+	#[error("A {name} NetMsg is too large to encode - the maximum is {max} bytes")]
+	TooLarge { name: &'static str, max: usize },
+}
 pub const RECEIVED_PACKET_BROADCASTER_MAX: usize = 2048;
 
 /// Any type which can be encoded as a NetMessage to be sent out over the wire.
@@ -472,6 +613,13 @@ pub trait NetMsg: Serialize + DeserializeOwned + Clone {
 	/// Used with the `stringify!()` macro
 	fn net_msg_name() -> &'static str;
 	fn net_msg_sidedness() -> MessageSidedness;
+	/// Engine version this message was introduced in, set with `since = "x.y.z"` in the netmsg attribute.
+	fn net_msg_since() -> Option<Version> {
+		None
+	}
+	fn net_msg_priority() -> MessagePriority {
+		MessagePriority::Normal
+	}
 	fn net_msg_type() -> NetMsgType {
 		NetMsgType {
 			id: Self::net_msg_id(),
@@ -479,20 +627,31 @@ pub trait NetMsg: Serialize + DeserializeOwned + Clone {
 			sidedness: Self::net_msg_sidedness(),
 			guarantees: Self::net_msg_guarantees(),
 			stream: Self::net_msg_stream(),
+			since: Self::net_msg_since(),
 		}
 	}
 
-	fn construct_packet(&self) -> Result<PacketIntermediary, Box<dyn std::error::Error>> {
+	fn construct_packet(&self) -> Result<PacketIntermediary, PacketConstructError> {
 		// Start by writing our tag.
 		let encode_start: Vec<u8> = vu64::encode(Self::net_msg_id() as u64).as_ref().to_vec();
 		// Write our data.
 		let mut buffer = GrowableBuf::new(encode_start, PACKET_ENCODE_MAX);
-		rmp_serde::encode::write(&mut buffer, self)?;
+		if let Err(e) = rmp_serde::encode::write(&mut buffer, self) {
+			return Err(if buffer.overflowed() {
+				PacketConstructError::TooLarge {
+					name: Self::net_msg_name(),
+					max: buffer.get_maximum(),
+				}
+			} else {
+				PacketConstructError::Serialize(Self::net_msg_name(), e)
+			});
+		}
 		let encoded = buffer.into_inner();
 
 		Ok(PacketIntermediary {
 			guarantees: Self::net_msg_guarantees(),
 			stream: Self::net_msg_stream(),
+			priority: Self::net_msg_priority(),
 			payload: encoded,
 		})
 	}
@@ -515,3 +674,37 @@ pub trait NetMsg: Serialize + DeserializeOwned + Clone {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use laminar::{DeliveryGuarantee, OrderingGuarantee};
+
+	use super::*;
+
+	#[test]
+	fn guarantees_map_to_laminar_packet_kinds() {
+		let addr: SocketAddr = "127.0.0.1:3223".parse().unwrap();
+		let cases = [
+			(PacketGuarantees::UnreliableUnordered, StreamSelector::Specific(3), DeliveryGuarantee::Unreliable, OrderingGuarantee::None),
+			(PacketGuarantees::UnreliableSequenced, StreamSelector::Specific(3), DeliveryGuarantee::Unreliable, OrderingGuarantee::Sequenced(Some(3))),
+			(PacketGuarantees::ReliableUnordered, StreamSelector::Specific(3), DeliveryGuarantee::Reliable, OrderingGuarantee::None),
+			(PacketGuarantees::ReliableOrdered, StreamSelector::Specific(3), DeliveryGuarantee::Reliable, OrderingGuarantee::Ordered(Some(3))),
+			(PacketGuarantees::ReliableOrdered, StreamSelector::Any, DeliveryGuarantee::Reliable, OrderingGuarantee::Ordered(None)),
+			(PacketGuarantees::ReliableSequenced, StreamSelector::Specific(7), DeliveryGuarantee::Reliable, OrderingGuarantee::Sequenced(Some(7))),
+			(PacketGuarantees::ReliableSequenced, StreamSelector::Any, DeliveryGuarantee::Reliable, OrderingGuarantee::Sequenced(None)),
+		];
+		for (guarantees, stream, expected_delivery, expected_ordering) in cases {
+			let packet = PacketIntermediary {
+				guarantees,
+				stream,
+				priority: MessagePriority::Normal,
+				payload: vec![1, 2, 3],
+			}
+			.make_full_packet(addr);
+			assert_eq!(packet.delivery_guarantee(), expected_delivery, "{guarantees:?} on {stream:?}");
+			assert_eq!(packet.order_guarantee(), expected_ordering, "{guarantees:?} on {stream:?}");
+			assert_eq!(packet.addr(), addr);
+			assert_eq!(packet.payload(), &[1, 2, 3]);
+		}
+	}
+}
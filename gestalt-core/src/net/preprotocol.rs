@@ -17,7 +17,7 @@
 
 use lazy_static::lazy_static;
 
-use log::{error, info, trace};
+use log::{error, info, trace, warn};
 use parking_lot::Mutex;
 use semver::Version;
 use serde::{Deserialize, Serialize};
@@ -29,7 +29,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use crate::common::identity::{DecodeIdentityError, IdentityKeyPair};
 use crate::common::identity::NodeIdentity;
 use crate::net::handshake::{PROTOCOL_NAME, PROTOCOL_VERSION};
-use crate::{BuildSubset, MessageSender, SubsetBuilder};
+use crate::{BroadcastReceiver, BuildSubset, MessageReceiverAsync, MessageSender, SubsetBuilder};
 
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
@@ -43,7 +43,7 @@ use super::handshake::{
 use super::net_channels::{PreprotocolChannels, PreprotocolSessionChannels};
 use super::{
 	handshake::{HandshakeError, HandshakeInitiator, HandshakeReceiver},
-	SessionId, SuccessfulConnect,
+	ReconnectRequested, SessionId, SuccessfulConnect,
 };
 
 use super::{MessageCounter, NetworkRole, SelfNetworkRole};
@@ -67,6 +67,9 @@ pub struct ProtocolDef {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SupportedProtocols {
 	pub supported_protocols: HashSet<ProtocolDef>,
+	/// Engine version of the replying node. Optional so that older peers' replies still parse.
+	#[serde(default)]
+	pub engine_version: Option<Version>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
@@ -88,6 +91,9 @@ pub struct StartHandshakeMsg {
 	pub handshake: HandshakeStepMessage,
 	pub initiator_role: NetworkRole, //"I am connecting as an initiator_role in relation to you"
 	pub use_protocol: ProtocolDef,
+	/// Engine version of the initiator. Optional so that older peers' messages still parse.
+	#[serde(default)]
+	pub engine_version: Option<Version>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -192,6 +198,7 @@ pub struct PreProtocolReceiver {
 	protocol_dir: PathBuf,
 	our_identity: IdentityKeyPair,
 	peer_role: Option<NetworkRole>,
+	peer_engine_version: Option<Version>,
 	mismatch_reporter: Option<NewProtocolKeyReporter>,
 	mismatch_approver: Option<NewProtocolKeyApprover>,
 	start_time: Instant,
@@ -210,6 +217,7 @@ impl PreProtocolReceiver {
 			protocol_dir,
 			our_identity,
 			peer_role: None,
+			peer_engine_version: None,
 			mismatch_reporter: Some(mismatch_reporter),
 			mismatch_approver: Some(mismatch_approver),
 			start_time: Instant::now(),
@@ -247,6 +255,7 @@ impl PreProtocolReceiver {
 			PreProtocolQuery::SupportedProtocols => {
 				PreProtocolOutput::Reply(PreProtocolReply::SupportedProtocols(SupportedProtocols {
 					supported_protocols: SUPPORTED_PROTOCOL_SET.clone(),
+					engine_version: Some(crate::ENGINE_VERSION),
 				}))
 			}
 			PreProtocolQuery::RequestServerStatus => {
@@ -254,6 +263,7 @@ impl PreProtocolReceiver {
 			}
 			PreProtocolQuery::StartHandshake(start_handshake) => {
 				self.peer_role = Some(start_handshake.initiator_role);
+				self.peer_engine_version = start_handshake.engine_version.clone();
 				if !self.state.is_in_handshake() {
 					// For when noise keys changed.
 					let mismatch_reporter = self
@@ -413,6 +423,7 @@ pub async fn preprotocol_receiver_session(
 														peer_identity,
 														peer_address,
 														peer_role,
+														peer_engine_version: receiver.peer_engine_version.clone(),
 														transport_cryptography: transport,
 														transport_counter: seq as u32,
 													};
@@ -559,8 +570,8 @@ pub async fn preprotocol_connect_inner(
 		.await
 		.map_err(HandshakeError::NetIoError)?;
 	let reply = serde_json::from_str::<PreProtocolReply>(&msg)?;
-	let server_protocols = if let PreProtocolReply::SupportedProtocols(protocols) = reply {
-		protocols.supported_protocols
+	let (server_protocols, server_engine_version) = if let PreProtocolReply::SupportedProtocols(protocols) = reply {
+		(protocols.supported_protocols, protocols.engine_version)
 	} else {
 		return Err(HandshakeError::NoProtocolsInCommon);
 	};
@@ -589,6 +600,7 @@ pub async fn preprotocol_connect_inner(
 		use_protocol: current_protocol,
 		handshake: handshake_first,
 		initiator_role: NetworkRole::Client,
+		engine_version: Some(crate::ENGINE_VERSION),
 	});
 	let json_query = serde_json::to_string(&query)?;
 	write_preprotocol_message(&json_query, stream)
@@ -637,6 +649,7 @@ pub async fn preprotocol_connect_inner(
 		transport_cryptography: transport,
 		transport_counter: counter as u32,
 		peer_role: NetworkRole::Server,
+		peer_engine_version: server_engine_version,
 	})
 }
 
@@ -697,6 +710,62 @@ pub async fn preprotocol_connect_to_server(
 	}
 }
 
+/// Client-side: whenever the network system gives up on a session because it timed out, quietly runs the
+/// pre-protocol and handshake against that server again. Noise keys come out of protocol_dir exactly like
+/// they did for the first connection, so a server we've already met doesn't trigger a key-change prompt.
+/// Gives up on a reconnect after max_attempts failures in a row, waiting retry_delay between attempts.
+pub async fn reconnect_on_timeout(
+	our_identity: IdentityKeyPair,
+	connect_timeout: Duration,
+	retry_delay: Duration,
+	max_attempts: usize,
+	protocol_dir: PathBuf,
+	mut requests: BroadcastReceiver<ReconnectRequested>,
+	channels: PreprotocolChannels,
+) {
+	loop {
+		let request = match requests.recv_wait().await {
+			Ok(request) => request,
+			Err(e) => {
+				info!("Reconnect request channel closed: {e:?}");
+				break;
+			}
+		};
+		for attempt in 1..=max_attempts {
+			info!(
+				"Attempting to reconnect to {} at {} (attempt {attempt} of {max_attempts})",
+				request.peer_identity.to_base64(),
+				request.peer_address
+			);
+			let session_channels = match channels.build_subset(SubsetBuilder::new(())) {
+				Ok(session_channels) => session_channels,
+				Err(e) => {
+					error!("Could not set up channels to reconnect with: {e:?}");
+					break;
+				}
+			};
+			match preprotocol_connect_to_server(
+				our_identity.clone(),
+				request.peer_address,
+				connect_timeout,
+				protocol_dir.clone(),
+				session_channels,
+			)
+			.await
+			{
+				Ok(()) => break,
+				Err(e) if attempt == max_attempts => {
+					error!("Giving up on reconnecting to {}: {e:?}", request.peer_address);
+				}
+				Err(e) => {
+					warn!("Reconnect attempt {attempt} to {} failed: {e:?}", request.peer_address);
+					tokio::time::sleep(retry_delay).await;
+				}
+			}
+		}
+	}
+}
+
 #[cfg(test)]
 pub mod test {
 	use super::*;
@@ -1,8 +1,14 @@
 use std::{collections::VecDeque, net::SocketAddr, time::Instant};
 
-use laminar::{Connection, VirtualConnection};
+use laminar::{Connection, OrderingGuarantee, VirtualConnection};
 use log::trace;
 
+use super::netmsg::StreamId;
+
+/// Laminar puts ordered and sequenced packets which were sent without a stream ID on stream 255,
+/// so by default a NetMsg can pick any stream below that.
+pub const DEFAULT_MAX_STREAMS: u8 = u8::MAX;
+
 /// Thin wrapper used to pretend, from the perspective of Laminar,
 /// that Noise protocol encryption and async UDP are a transparent synchronous UDP socket.
 #[derive(Default)]
@@ -42,12 +48,14 @@ pub type LaminarConfig = laminar::Config;
 /// Partial reimplementation of a Laminar::ConnectionManager with somewhat different logic since we're using async here, and there will be one of these per peer per node.
 pub struct LaminarConnectionManager {
 	peer_address: SocketAddr,
+	/// Ordered / sequenced packets have to be on a stream below this.
+	max_streams: u8,
 	pub(in crate::net) connection_state: VirtualConnection,
 	pub(in crate::net) messenger: TransportWrapper,
 }
 
 impl LaminarConnectionManager {
-	pub fn new(peer_address: SocketAddr, laminar_config: &LaminarConfig, time: Instant) -> Self {
+	pub fn new(peer_address: SocketAddr, laminar_config: &LaminarConfig, max_streams: u8, time: Instant) -> Self {
 		let mut messenger = TransportWrapper {
 			laminar_config: laminar_config.clone(),
 			outbox: VecDeque::default(),
@@ -58,11 +66,29 @@ impl LaminarConnectionManager {
 
 		LaminarConnectionManager {
 			peer_address,
+			max_streams,
 			connection_state,
 			messenger,
 		}
 	}
 
+	pub fn get_max_streams(&self) -> u8 {
+		self.max_streams
+	}
+
+	/// If this packet asks for a stream we don't allow, returns that stream.
+	/// Unordered packets have no stream, so they're always fine.
+	pub fn invalid_stream(&self, packet: &laminar::Packet) -> Option<StreamId> {
+		match packet.order_guarantee() {
+			OrderingGuarantee::Ordered(Some(stream)) | OrderingGuarantee::Sequenced(Some(stream))
+				if stream >= self.max_streams =>
+			{
+				Some(stream)
+			}
+			_ => None,
+		}
+	}
+
 	/// Ingests a batch of packets coming off the wire.
 	pub fn process_inbound<T: IntoIterator<Item: AsRef<[u8]>>>(
 		&mut self,
@@ -143,3 +169,59 @@ impl LaminarConnectionManager {
 		self.messenger.inbox.drain(0..).collect()
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn unreliable_sequenced_drops_stale_packets() {
+		let sender_addr: SocketAddr = "127.0.0.1:3223".parse().unwrap();
+		let receiver_addr: SocketAddr = "127.0.0.1:3224".parse().unwrap();
+		let config = LaminarConfig::default();
+		let now = Instant::now();
+		let mut sender = LaminarConnectionManager::new(receiver_addr, &config, DEFAULT_MAX_STREAMS, now);
+		let mut receiver = LaminarConnectionManager::new(sender_addr, &config, DEFAULT_MAX_STREAMS, now);
+
+		let stream = Some(1);
+		sender
+			.process_outbound(
+				[
+					laminar::Packet::unreliable_sequenced(receiver_addr, vec![1], stream),
+					laminar::Packet::unreliable_sequenced(receiver_addr, vec![2], stream),
+				],
+				now,
+			)
+			.unwrap();
+		let mut on_the_wire: Vec<(SocketAddr, Vec<u8>)> = sender.empty_outbox();
+		assert_eq!(on_the_wire.len(), 2);
+		// Newer one shows up first.
+		on_the_wire.reverse();
+		receiver
+			.process_inbound(on_the_wire.into_iter().map(|(_, bytes)| bytes), now)
+			.unwrap();
+
+		let received: Vec<laminar::SocketEvent> = receiver.empty_inbox();
+		let payloads: Vec<Vec<u8>> = received
+			.into_iter()
+			.filter_map(|event| match event {
+				laminar::SocketEvent::Packet(packet) => Some(packet.payload().to_vec()),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(payloads, vec![vec![2]]);
+	}
+
+	#[test]
+	fn streams_past_max_are_invalid() {
+		let peer_addr: SocketAddr = "127.0.0.1:3225".parse().unwrap();
+		let manager = LaminarConnectionManager::new(peer_addr, &LaminarConfig::default(), 4, Instant::now());
+
+		assert_eq!(manager.invalid_stream(&laminar::Packet::reliable_ordered(peer_addr, vec![1], Some(3))), None);
+		assert_eq!(manager.invalid_stream(&laminar::Packet::reliable_ordered(peer_addr, vec![1], Some(4))), Some(4));
+		assert_eq!(manager.invalid_stream(&laminar::Packet::unreliable_sequenced(peer_addr, vec![1], Some(9))), Some(9));
+		// Default stream, and no stream at all.
+		assert_eq!(manager.invalid_stream(&laminar::Packet::reliable_ordered(peer_addr, vec![1], None)), None);
+		assert_eq!(manager.invalid_stream(&laminar::Packet::reliable_unordered(peer_addr, vec![1])), None);
+	}
+}
@@ -4,10 +4,11 @@ use std::{
 	time::{Duration, Instant},
 };
 
-use crate::{common::message::{MessageReceiverAsync, MessageSender}, MpscReceiver, MpscSender, SendError};
+use crate::{common::message::{MessageReceiver, MessageReceiverAsync, MessageSender}, MpscReceiver, MpscSender, SendError};
 use gestalt_proc_macros::netmsg;
 use laminar::ConnectionMessenger;
-use log::{error, info, trace};
+use log::{error, info, trace, warn};
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use tokio::time::MissedTickBehavior;
 
@@ -16,14 +17,16 @@ use crate::{
 		identity::{IdentityKeyPair, NodeIdentity},
 		new_fast_hash_map, new_fast_hash_set, FastHashMap, FastHashSet,
 	},
-	net::{InboundNetMsg, NetMsgId, DISCONNECT_RESERVED}, BroadcastSender, ChannelDomain,
+	net::{InboundNetMsg, NetMsgId, PacketIntermediary, DISCONNECT_RESERVED}, BroadcastSender, ChannelDomain,
 };
 
 use super::{
-	generated, net_channels::{InboundNetMsgs, SessionChannels}, netmsg::{CiphertextEnvelope, CiphertextMessage, MessageSidedness}, reliable_udp::{LaminarConfig, LaminarConnectionManager, LaminarWrapperError}, MessageCounter, NetMsgDomain, OuterEnvelope, SelfNetworkRole, SuccessfulConnect
+	generated, net_channels::{InboundNetMsgs, SessionChannels}, fragment::{packet_stream, parse_fragment, split_packet, FragmentError, Reassembler}, netmsg::{describe_netmsg_id, max_payload_size, read_netmsg_id, CiphertextEnvelope, CiphertextMessage, MessageSidedness, OutboundQueue, FRAGMENT_RESERVED}, reliable_udp::{LaminarConfig, LaminarConnectionManager, LaminarWrapperError}, MessageCounter, NetMsgDomain, OuterEnvelope, SelfNetworkRole, SessionStats, SuccessfulConnect
 };
 
 pub const SESSION_ID_LEN: usize = 4;
+/// How often each session publishes a SessionStats.
+pub const SESSION_STATS_INTERVAL: Duration = Duration::from_secs(1);
 pub type SessionId = [u8; SESSION_ID_LEN];
 
 /// Runtime information specifying what kind of connection we are looking at.
@@ -105,14 +108,50 @@ pub enum SessionLayerError {
 	ConnectAfterStarted(SocketAddr),
 	#[error("Variable-length integer could not be decoded: {0:?}")]
 	VarIntError(#[from] vu64::Error),
-	#[error("A NetMessage of type {0} has been receved from {1}, but no type has been associated with this ID in the engine. \n It's possible this peer is using a newer version of Gestalt.")]
+	#[error("A NetMessage of type {} has been receved from {}, but no type has been associated with this ID in the engine. \n It's possible this peer is using a newer version of Gestalt.", describe_netmsg_id(*.0), .1)]
 	UnrecognizedMsg(NetMsgId, String),
-	#[error("A NetMessage of type {0} has been receved from {1}, but we are a {2:?} and this message's sidedness is a {3:?}.")]
+	#[error("A NetMessage of type {} has been receved from {}, but we are a {:?} and this message's sidedness is a {:?}.", describe_netmsg_id(*.0), .1, .2, .3)]
 	WrongSidedness(NetMsgId, String, SelfNetworkRole, MessageSidedness),
 	#[error(
 		"Counter for a session with {0:?} is at the maximum value for a 4-byte unsized integer!"
 	)]
 	ExhaustedCounter(SocketAddr),
+	#[error("Tried to send a NetMessage of type {} on stream {}, which is past the highest stream this session allows.", describe_netmsg_id(*.0), .1)]
+	InvalidStream(NetMsgId, u8),
+	#[error("A NetMessage of type {} serialized to {} bytes, which is more than this session will send.", describe_netmsg_id(*.0), .1)]
+	MessageTooLarge(NetMsgId, usize),
+	#[error("Could not fragment or reassemble a NetMessage: {0}")]
+	Fragment(#[from] FragmentError),
+}
+
+/// Reads the NetMsg ID off the front of an outbound packet's payload.
+fn outbound_netmsg_id(payload: &[u8]) -> Option<NetMsgId> {
+	read_netmsg_id(payload).map(|(id, _)| id)
+}
+
+/// Makes sure a serialized NetMsg will still fit in max_message_size once Laminar's header, encryption and the
+/// OuterEnvelope have been added to it.
+pub fn check_message_size(packet: &PacketIntermediary, max_message_size: usize) -> Result<(), SessionLayerError> {
+	if packet.payload.len() > max_payload_size(max_message_size) {
+		return Err(SessionLayerError::MessageTooLarge(
+			outbound_netmsg_id(&packet.payload).unwrap_or_default(),
+			packet.payload.len(),
+		));
+	}
+	Ok(())
+}
+
+impl SessionLayerError {
+	/// Did this session end because we stopped hearing from the peer, as opposed to the peer leaving?
+	/// LaminarConnectionManager reports should_drop() (i.e. the connection went idle) as a Disconnect.
+	pub fn is_timeout(&self) -> bool {
+		match self {
+			SessionLayerError::LaminarTimeout(_) => true,
+			SessionLayerError::LaminarWrapper(LaminarWrapperError::Disconnect(_)) => true,
+			SessionLayerError::ErrorBatch(errors) => errors.iter().any(|e| e.is_timeout()),
+			_ => false,
+		}
+	}
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -127,6 +166,8 @@ pub struct Session {
 	pub local_identity: IdentityKeyPair,
 	pub peer_identity: NodeIdentity,
 	pub peer_address: SocketAddr,
+	/// Engine version the peer reported in the pre-protocol, if any.
+	pub peer_engine_version: Option<Version>,
 
 	pub session_id: SessionId,
 	/// Counter we put on outgoing `OuterEnvelope`s, should increase monotonically.
@@ -138,6 +179,21 @@ pub struct Session {
 
 	pub disconnect_deliberate: bool,
 
+	/// Outbound NetMsgs which won't fit in this many bytes on the wire get fragmented, or dropped before they're
+	/// encrypted if fragment_large_messages is off.
+	pub max_message_size: usize,
+	pub fragment_large_messages: bool,
+	/// Inbound fragments from this peer, waiting on the rest of their message.
+	reassembler: Reassembler,
+	/// Sequence number for the next outbound message we split up.
+	next_fragment_sequence: u32,
+
+	/// Ciphertext bytes received since stats were last published.
+	bytes_in: u64,
+	/// Ciphertext bytes sent since stats were last published.
+	bytes_out: u64,
+	stats_interval_start: Instant,
+
 	/// Valid NetMsg types for our network role.
 	valid_incoming_messages: FastHashSet<NetMsgId>,
 
@@ -158,11 +214,14 @@ impl Session {
 		peer_address: SocketAddr,
 		connection: SuccessfulConnect,
 		laminar_config: LaminarConfig,
+		max_streams: u8,
+		max_message_size: usize,
+		fragment_large_messages: bool,
 		time: Instant,
 		channels: SessionChannels,
 	) -> Self {
 		let mut laminar_layer =
-			LaminarConnectionManager::new(connection.peer_address, &laminar_config, time);
+			LaminarConnectionManager::new(connection.peer_address, &laminar_config, max_streams, time);
 		laminar_layer.connection_state.last_heard = time;
 
 		let mut valid_incoming_messages = new_fast_hash_set();
@@ -184,6 +243,7 @@ impl Session {
 			local_role,
 			peer_identity: connection.peer_identity,
 			peer_address,
+			peer_engine_version: connection.peer_engine_version,
 			session_id: connection.session_id,
 			local_counter: connection.transport_counter,
 			transport_cryptography: connection.transport_cryptography,
@@ -191,8 +251,64 @@ impl Session {
 			inbound_channels: new_fast_hash_map(),
 			valid_incoming_messages,
 			disconnect_deliberate: false,
+			max_message_size,
+			fragment_large_messages,
+			reassembler: Reassembler::default(),
+			next_fragment_sequence: 0,
+			bytes_in: 0,
+			bytes_out: 0,
+			stats_interval_start: time,
 		}
 	}
+	/// Is the peer running a newer engine than we are? If so, it may send us NetMsg types we've never heard of.
+	fn peer_is_newer(&self) -> bool {
+		match &self.peer_engine_version {
+			Some(version) => *version > crate::ENGINE_VERSION,
+			None => false,
+		}
+	}
+
+	/// Should this inbound message type be ignored rather than treated as an error, because one of us is too old for it?
+	fn should_skip_for_version(&self, message_type: NetMsgId) -> bool {
+		match generated::get_netmsg_table().get(&message_type) {
+			Some(info) => !info.supported_by(self.peer_engine_version.as_ref()),
+			None => self.peer_is_newer(),
+		}
+	}
+
+	/// Splits up (or, if fragmentation is off, drops) anything too big to send as-is.
+	/// Dropped messages and messages too big even to fragment get logged, and don't stop the rest from going out.
+	pub fn split_oversized(&mut self, packets: Vec<PacketIntermediary>) -> Vec<PacketIntermediary> {
+		let mut result = Vec::with_capacity(packets.len());
+		for packet in packets {
+			let outcome = if packet.payload.len() <= max_payload_size(self.max_message_size) {
+				Ok(vec![packet])
+			} else if self.fragment_large_messages {
+				let sequence = self.next_fragment_sequence;
+				self.next_fragment_sequence = self.next_fragment_sequence.wrapping_add(1);
+				split_packet(&packet, sequence).map_err(SessionLayerError::from)
+			} else {
+				check_message_size(&packet, self.max_message_size).map(|()| vec![packet])
+			};
+			match outcome {
+				Ok(packets) => result.extend(packets),
+				Err(e) => error!("Dropping a NetMsg to {}: {e}", self.peer_identity.to_base64()),
+			}
+		}
+		result
+	}
+
+	/// Can the peer make sense of this outbound packet, given its engine version?
+	pub fn peer_supports_packet(&self, packet: &PacketIntermediary) -> bool {
+		match outbound_netmsg_id(&packet.payload) {
+			Some(id) => match generated::get_netmsg_table().get(&id) {
+				Some(info) => info.supported_by(self.peer_engine_version.as_ref()),
+				None => true,
+			},
+			None => true,
+		}
+	}
+
 	pub fn get_session_name(&self) -> FullSessionName {
 		FullSessionName {
 			peer_address: self.peer_address.clone(),
@@ -215,6 +331,7 @@ impl Session {
 			&mut buffer,
 		)?;
 		buffer.truncate(len_written);
+		self.bytes_out += len_written as u64;
 		let full_session_name = self.get_session_name();
 		Ok(OuterEnvelope {
 			session: full_session_name,
@@ -237,6 +354,7 @@ impl Session {
 				ciphertext,
 			},
 		} = envelope;
+		self.bytes_in += ciphertext.len() as u64;
 
 		let mut buf = vec![0u8; (ciphertext.len() * 3) / 2];
 		let len_read =
@@ -283,6 +401,30 @@ impl Session {
 						message_type_len,
 						&pkt.payload()[0..message_type_len as usize],
 					) {
+						Ok(message_type_id) if message_type_id as NetMsgId == FRAGMENT_RESERVED => {
+							let reassembled = parse_fragment(&pkt.payload()[message_type_len as usize..])
+								.and_then(|(header, body)| self.reassembler.ingest(packet_stream(&pkt), header, body, time));
+							match reassembled {
+								Ok(Some(whole)) => match read_netmsg_id(&whole) {
+									Some((message_type_id, id_len)) => {
+										trace!(
+											"Reassembled a {}-byte NetMsg from {} with message_type_id {}",
+											whole.len(),
+											self.peer_identity.to_base64(),
+											message_type_id
+										);
+										finished_packets.entry(message_type_id).or_default().push(InboundNetMsg {
+											message_type_id,
+											payload: whole[id_len..].to_vec(),
+											peer_identity: self.peer_identity.clone(),
+										});
+									}
+									None => errors.push(FragmentError::NoMessageId.into()),
+								},
+								Ok(None) => {}
+								Err(e) => errors.push(e.into()),
+							}
+						}
 						Ok(message_type_id) => {
 							let message_type_id = message_type_id as NetMsgId;
 							trace!(
@@ -320,6 +462,17 @@ impl Session {
 		}
 		// Push our messages out to the rest of the application.
 		for (message_type, message_buf) in finished_packets {
+			if self.should_skip_for_version(message_type) {
+				trace!(
+					"Skipping {} NetMsg(s) of type {} from {}, which is not supported between our engine version ({}) and theirs ({:?}).",
+					message_buf.len(),
+					message_type,
+					self.peer_identity.to_base64(),
+					crate::ENGINE_VERSION,
+					self.peer_engine_version
+				);
+				continue;
+			}
 			if self.valid_incoming_messages.contains(&message_type) {
 				match message_type {
 					// Handle network-subsystem builtin messages
@@ -384,6 +537,13 @@ impl Session {
 
 	pub fn process_update(&mut self, time: Instant) -> Result<(), SessionLayerError> {
 		let mut errors: Vec<SessionLayerError> = Vec::default();
+		for message_type in self.reassembler.expire(time) {
+			warn!(
+				"Gave up waiting on the rest of a fragmented NetMsg of type {} from {}",
+				describe_netmsg_id(message_type),
+				self.peer_identity.to_base64()
+			);
+		}
 		match self.laminar.process_update(time) {
 			Ok(()) => {}
 			Err(e) => errors.push(e.into()),
@@ -415,13 +575,25 @@ impl Session {
 	}
 
 	/// Adds Laminar connection logic to messages that we are sending.
+	/// Packets asking for a stream past the session's maximum are not sent, and each one is reported
+	/// as an InvalidStream error - the rest of the batch still goes out.
 	pub fn process_outbound<T: IntoIterator<Item = laminar::Packet>>(
 		&mut self,
 		outbound_messages: T,
 		time: Instant,
 	) -> Result<(), SessionLayerError> {
 		let mut errors: Vec<SessionLayerError> = Vec::default();
-		match self.laminar.process_outbound(outbound_messages, time) {
+		let mut valid_messages: Vec<laminar::Packet> = Vec::default();
+		for packet in outbound_messages {
+			match self.laminar.invalid_stream(&packet) {
+				Some(stream) => errors.push(SessionLayerError::InvalidStream(
+					outbound_netmsg_id(packet.payload()).unwrap_or_default(),
+					stream,
+				)),
+				None => valid_messages.push(packet),
+			}
+		}
+		match self.laminar.process_outbound(valid_messages, time) {
 			Ok(()) => {}
 			Err(e) => errors.push(e.into()),
 		}
@@ -455,6 +627,26 @@ impl Session {
 		}
 	}
 
+	/// If SESSION_STATS_INTERVAL has passed, publishes traffic since the last time and starts counting again.
+	pub fn publish_stats(&mut self, time: Instant) {
+		let interval = time.saturating_duration_since(self.stats_interval_start);
+		if interval < SESSION_STATS_INTERVAL {
+			return;
+		}
+		let stats = SessionStats {
+			peer: self.peer_identity.clone(),
+			bytes_in: self.bytes_in,
+			bytes_out: self.bytes_out,
+			packets_in_flight: self.laminar.connection_state.packets_in_flight(),
+			interval,
+		};
+		// Nobody listening is fine, these are purely informational.
+		let _ = self.channels.session_stats.send(stats);
+		self.bytes_in = 0;
+		self.bytes_out = 0;
+		self.stats_interval_start = time;
+	}
+
 	/// Network connection CPR.
 	pub fn force_heartbeat(&mut self) -> Result<(), laminar::error::ErrorKind> {
 		let packets = self.laminar.connection_state.process_outgoing(
@@ -524,6 +716,25 @@ pub async fn handle_session(
 				match send_packets_maybe {
 					Ok(send_packets) => {
 						session_manager.laminar.connection_state.record_send();
+						// Grab everything else the engine has queued up for this peer, so higher-priority
+						// messages go to Laminar ahead of anything sent earlier in the same tick.
+						let mut queue = OutboundQueue::new();
+						queue.extend(send_packets);
+						while let Ok(Some(more_packets)) = session_manager.channels.from_engine.recv_poll() {
+							queue.extend(more_packets);
+						}
+						// Don't send message types the peer's engine version predates.
+						let send_packets: Vec<PacketIntermediary> = queue.drain().into_iter()
+							.filter(|packet| {
+								let supported = session_manager.peer_supports_packet(packet);
+								if !supported {
+									trace!("Not sending a NetMsg to {} because their engine version ({:?}) predates it.", session_manager.peer_identity.to_base64(), session_manager.peer_engine_version);
+								}
+								supported
+							})
+							.collect();
+						// Oversized messages are a problem with the message, not the connection, so drop just them.
+						let send_packets = session_manager.split_oversized(send_packets);
 						let serialize_results = session_manager.process_outbound(send_packets.into_iter().map(|intermediary| intermediary.make_full_packet(peer_address)), Instant::now());
 						if let Err(e) = serialize_results {
 							error!("Error encountered attempting to send a packet to peer {}: {:?}", session_manager.peer_identity.to_base64(), e);
@@ -543,16 +754,29 @@ pub async fn handle_session(
 				}
 			},
 			_ = (&mut ticker).tick() => {
-				let update_results = session_manager.process_update(Instant::now());
+				let now = Instant::now();
+				let update_results = session_manager.process_update(now);
 				if let Err(e) = update_results {
 					trace!("Connection indicated as should_drop(). packets_in_flight() is {} and last_heard() is {:?}. Established? : {}", session_manager.laminar.connection_state.packets_in_flight(), session_manager.laminar.connection_state.last_heard(Instant::now()), session_manager.laminar.connection_state.is_established());
 					error!("Error encountered while ticking network connection to peer {}: {:?}", session_manager.peer_identity.to_base64(), e);
 					session_manager.channels.kill_session.send((session_manager.get_session_name(), vec![e])).unwrap();
 					break;
 				}
+				session_manager.publish_stats(now);
 			}
 			_ = (&mut kill_recv) => {
 				info!("Shutting down session with user {}", session_manager.peer_identity.to_base64() );
+				// Whatever the engine queued up before asking us to stop (e.g. the DisconnectMsg from a kick) still goes out.
+				let mut remaining: Vec<PacketIntermediary> = Vec::new();
+				while let Ok(Some(more_packets)) = session_manager.channels.from_engine.recv_poll() {
+					remaining.extend(more_packets);
+				}
+				if !remaining.is_empty() {
+					let remaining = session_manager.split_oversized(remaining);
+					if let Err(e) = session_manager.process_outbound(remaining.into_iter().map(|intermediary| intermediary.make_full_packet(peer_address)), Instant::now()) {
+						error!("Could not flush the last packets to peer {}: {:?}", session_manager.peer_identity.to_base64(), e);
+					}
+				}
 				break;
 			}
 		}
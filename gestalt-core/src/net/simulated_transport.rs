@@ -0,0 +1,363 @@
+//! Deliberately bad network conditions, for testing how sessions hold up against packet loss,
+//! duplication, reordering and latency without needing an actually-bad network.
+//! Everything is driven by a seeded RNG, so a failing run can be reproduced exactly.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::net::UdpSocket;
+
+/// How badly a SimulatedLink should treat the packets going through it.
+/// Chances are from 0.0 (never) to 1.0 (always).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SimulatedConditions {
+	/// Chance any given packet never arrives.
+	pub loss: f64,
+	/// Chance a packet which does arrive shows up twice.
+	pub duplication: f64,
+	/// Chance a packet gets held back for an extra reorder_delay, so that packets sent after it overtake it.
+	pub reorder: f64,
+	pub reorder_delay: Duration,
+	/// Every packet is delayed by at least this much.
+	pub latency: Duration,
+	/// Each packet is additionally delayed by a random amount between zero and this.
+	pub jitter: Duration,
+	pub seed: u64,
+}
+
+impl Default for SimulatedConditions {
+	/// A perfect network - useful as a starting point with `..Default::default()`.
+	fn default() -> Self {
+		Self {
+			loss: 0.0,
+			duplication: 0.0,
+			reorder: 0.0,
+			reorder_delay: Duration::from_millis(50),
+			latency: Duration::ZERO,
+			jitter: Duration::ZERO,
+			seed: 0,
+		}
+	}
+}
+
+struct InFlightPacket {
+	deliver_at: Instant,
+	/// Breaks ties between packets due at the same instant, in the order they were sent.
+	send_order: u64,
+	address: SocketAddr,
+	payload: Vec<u8>,
+}
+
+impl PartialEq for InFlightPacket {
+	fn eq(&self, other: &Self) -> bool {
+		(self.deliver_at, self.send_order) == (other.deliver_at, other.send_order)
+	}
+}
+impl Eq for InFlightPacket {}
+impl PartialOrd for InFlightPacket {
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+		Some(self.cmp(other))
+	}
+}
+impl Ord for InFlightPacket {
+	fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+		(self.deliver_at, self.send_order).cmp(&(other.deliver_at, other.send_order))
+	}
+}
+
+/// One direction of an impaired connection. Packets go in with send() and come out of poll()
+/// once they are due - or never, if they got "lost". Doesn't touch a real socket or a real clock,
+/// so tests can step time forward however they like.
+pub struct SimulatedLink {
+	conditions: SimulatedConditions,
+	rng: StdRng,
+	in_flight: BinaryHeap<Reverse<InFlightPacket>>,
+	sent_count: u64,
+}
+
+impl SimulatedLink {
+	pub fn new(conditions: SimulatedConditions) -> Self {
+		Self {
+			rng: StdRng::seed_from_u64(conditions.seed),
+			conditions,
+			in_flight: BinaryHeap::new(),
+			sent_count: 0,
+		}
+	}
+
+	pub fn get_conditions(&self) -> &SimulatedConditions {
+		&self.conditions
+	}
+
+	fn roll(&mut self, chance: f64) -> bool {
+		chance > 0.0 && self.rng.gen_bool(chance.min(1.0))
+	}
+
+	fn delay(&mut self) -> Duration {
+		let mut delay = self.conditions.latency;
+		if !self.conditions.jitter.is_zero() {
+			delay += self.conditions.jitter.mul_f64(self.rng.gen_range(0.0..1.0));
+		}
+		if self.roll(self.conditions.reorder) {
+			delay += self.conditions.reorder_delay;
+		}
+		delay
+	}
+
+	pub fn send(&mut self, now: Instant, address: SocketAddr, payload: &[u8]) {
+		if self.roll(self.conditions.loss) {
+			return;
+		}
+		let copies = if self.roll(self.conditions.duplication) { 2 } else { 1 };
+		for _ in 0..copies {
+			let deliver_at = now + self.delay();
+			self.in_flight.push(Reverse(InFlightPacket {
+				deliver_at,
+				send_order: self.sent_count,
+				address,
+				payload: payload.to_vec(),
+			}));
+			self.sent_count += 1;
+		}
+	}
+
+	/// The next packet which is due to arrive as of now, if there is one.
+	pub fn poll(&mut self, now: Instant) -> Option<(SocketAddr, Vec<u8>)> {
+		match self.in_flight.peek() {
+			Some(Reverse(next)) if next.deliver_at <= now => {
+				let Reverse(packet) = self.in_flight.pop().unwrap();
+				Some((packet.address, packet.payload))
+			}
+			_ => None,
+		}
+	}
+
+	/// When the next packet is due, whether or not that's in the past.
+	pub fn next_delivery(&self) -> Option<Instant> {
+		self.in_flight.peek().map(|Reverse(packet)| packet.deliver_at)
+	}
+
+	pub fn packets_in_flight(&self) -> usize {
+		self.in_flight.len()
+	}
+}
+
+/// A UDP socket which runs everything it receives through a SimulatedLink first.
+/// Outgoing packets are sent untouched - put a SimulatedTransport on both ends to impair both directions.
+pub struct SimulatedTransport {
+	socket: UdpSocket,
+	inbound: SimulatedLink,
+}
+
+impl SimulatedTransport {
+	pub fn new(socket: UdpSocket, conditions: SimulatedConditions) -> Self {
+		Self {
+			socket,
+			inbound: SimulatedLink::new(conditions),
+		}
+	}
+
+	/// Swap in new conditions. Anything still in flight under the old ones is dropped.
+	pub fn with_conditions(self, conditions: SimulatedConditions) -> Self {
+		Self::new(self.socket, conditions)
+	}
+
+	pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+		self.socket.send_to(buf, target).await
+	}
+
+	/// Cancel-safe in the same way UdpSocket::recv_from() is, so it can sit in a tokio::select!
+	pub async fn recv_from(&mut self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+		loop {
+			if let Some((address, payload)) = self.inbound.poll(Instant::now()) {
+				let len = payload.len().min(buf.len());
+				buf[..len].copy_from_slice(&payload[..len]);
+				return Ok((len, address));
+			}
+			let next_delivery = self.inbound.next_delivery();
+			tokio::select! {
+				received = self.socket.recv_from(buf) => {
+					let (len, address) = received?;
+					self.inbound.send(Instant::now(), address, &buf[..len]);
+				}
+				_ = tokio::time::sleep_until(next_delivery.unwrap_or_else(Instant::now).into()), if next_delivery.is_some() => {}
+			}
+		}
+	}
+
+	pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+		self.socket.local_addr()
+	}
+}
+
+/// What the NetworkSystem actually reads from and writes to - either the real socket, or one with
+/// simulated network trouble in front of it. Loopback network systems never touch a socket at all,
+/// so there's nothing to read and writing is an error.
+pub(in crate::net) enum NetSocket {
+	Udp(UdpSocket),
+	Simulated(SimulatedTransport),
+	Loopback,
+}
+
+impl NetSocket {
+	pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+		match self {
+			NetSocket::Udp(socket) => socket.send_to(buf, target).await,
+			NetSocket::Simulated(transport) => transport.send_to(buf, target).await,
+			NetSocket::Loopback => Err(std::io::Error::new(
+				std::io::ErrorKind::Unsupported,
+				format!("Tried to send a datagram to {target} from a loopback network system"),
+			)),
+		}
+	}
+
+	pub async fn recv_from(&mut self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+		match self {
+			NetSocket::Udp(socket) => socket.recv_from(buf).await,
+			NetSocket::Simulated(transport) => transport.recv_from(buf).await,
+			NetSocket::Loopback => std::future::pending().await,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::net::reliable_udp::{LaminarConfig, LaminarConnectionManager, DEFAULT_MAX_STREAMS};
+
+	fn received_payloads(connection: &mut LaminarConnectionManager) -> Vec<Vec<u8>> {
+		connection
+			.empty_inbox::<Vec<laminar::SocketEvent>>()
+			.into_iter()
+			.filter_map(|event| match event {
+				laminar::SocketEvent::Packet(packet) => Some(packet.payload().to_vec()),
+				_ => None,
+			})
+			.collect()
+	}
+
+	#[test]
+	fn same_seed_same_results() {
+		let conditions = SimulatedConditions {
+			loss: 0.3,
+			duplication: 0.2,
+			reorder: 0.2,
+			jitter: Duration::from_millis(20),
+			seed: 1234,
+			..Default::default()
+		};
+		let address: SocketAddr = "127.0.0.1:3225".parse().unwrap();
+		let start = Instant::now();
+		let run = || {
+			let mut link = SimulatedLink::new(conditions);
+			for i in 0..100u8 {
+				link.send(start + Duration::from_millis(i as u64), address, &[i]);
+			}
+			let mut arrived = Vec::new();
+			while let Some((_, payload)) = link.poll(start + Duration::from_secs(1)) {
+				arrived.push(payload[0]);
+			}
+			arrived
+		};
+		let arrived = run();
+		assert_eq!(arrived, run());
+		assert!(arrived.len() < 100);
+		assert!(arrived.windows(2).any(|pair| pair[0] > pair[1]));
+	}
+
+	#[test]
+	fn reliable_ordered_survives_packet_loss() {
+		let sender_addr: SocketAddr = "127.0.0.1:3226".parse().unwrap();
+		let receiver_addr: SocketAddr = "127.0.0.1:3227".parse().unwrap();
+		let mut config = LaminarConfig::default();
+		config.heartbeat_interval = Some(Duration::from_millis(50));
+		let conditions = SimulatedConditions {
+			loss: 0.2,
+			latency: Duration::from_millis(20),
+			jitter: Duration::from_millis(10),
+			..Default::default()
+		};
+		let mut to_receiver = SimulatedLink::new(SimulatedConditions { seed: 1, ..conditions });
+		let mut to_sender = SimulatedLink::new(SimulatedConditions { seed: 2, ..conditions });
+
+		let mut now = Instant::now();
+		let mut sender = LaminarConnectionManager::new(receiver_addr, &config, DEFAULT_MAX_STREAMS, now);
+		let mut receiver = LaminarConnectionManager::new(sender_addr, &config, DEFAULT_MAX_STREAMS, now);
+
+		let stream = Some(1);
+		let mut received = Vec::new();
+		for step in 0..400u32 {
+			now += Duration::from_millis(10);
+			// Only the first 200 steps send anything new, the rest give lost packets a chance to be resent.
+			let outbound = (step < 200)
+				.then(|| laminar::Packet::reliable_ordered(receiver_addr, step.to_le_bytes().to_vec(), stream));
+			sender.process_outbound(outbound, now).unwrap();
+			// Keep acknowledgements flowing back the other way.
+			receiver
+				.process_outbound([laminar::Packet::reliable_unordered(sender_addr, vec![0])], now)
+				.unwrap();
+
+			for (address, payload) in sender.empty_outbox::<Vec<_>>() {
+				to_receiver.send(now, address, &payload);
+			}
+			for (address, payload) in receiver.empty_outbox::<Vec<_>>() {
+				to_sender.send(now, address, &payload);
+			}
+			let arrived: Vec<Vec<u8>> = std::iter::from_fn(|| to_receiver.poll(now)).map(|(_, bytes)| bytes).collect();
+			receiver.process_inbound(arrived, now).unwrap();
+			let arrived: Vec<Vec<u8>> = std::iter::from_fn(|| to_sender.poll(now)).map(|(_, bytes)| bytes).collect();
+			sender.process_inbound(arrived, now).unwrap();
+
+			received.extend(
+				received_payloads(&mut receiver)
+					.into_iter()
+					.map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap())),
+			);
+		}
+		// Nothing out of order, nothing skipped, nothing repeated.
+		let expected: Vec<u32> = (0..received.len() as u32).collect();
+		assert_eq!(received, expected);
+		assert!(received.len() >= 150, "only {} of 200 messages made it through", received.len());
+	}
+
+	#[test]
+	fn sequenced_drops_stale_under_reordering() {
+		let sender_addr: SocketAddr = "127.0.0.1:3228".parse().unwrap();
+		let receiver_addr: SocketAddr = "127.0.0.1:3229".parse().unwrap();
+		let config = LaminarConfig::default();
+		let mut link = SimulatedLink::new(SimulatedConditions {
+			reorder: 0.3,
+			reorder_delay: Duration::from_millis(35),
+			duplication: 0.1,
+			seed: 42,
+			..Default::default()
+		});
+
+		let mut now = Instant::now();
+		let mut sender = LaminarConnectionManager::new(receiver_addr, &config, DEFAULT_MAX_STREAMS, now);
+		let mut receiver = LaminarConnectionManager::new(sender_addr, &config, DEFAULT_MAX_STREAMS, now);
+
+		let stream = Some(1);
+		let mut received = Vec::new();
+		for step in 0..100u8 {
+			now += Duration::from_millis(10);
+			sender
+				.process_outbound([laminar::Packet::unreliable_sequenced(receiver_addr, vec![step], stream)], now)
+				.unwrap();
+			for (address, payload) in sender.empty_outbox::<Vec<_>>() {
+				link.send(now, address, &payload);
+			}
+			let arrived: Vec<Vec<u8>> = std::iter::from_fn(|| link.poll(now)).map(|(_, bytes)| bytes).collect();
+			receiver.process_inbound(arrived, now).unwrap();
+			received.extend(received_payloads(&mut receiver).into_iter().map(|bytes| bytes[0]));
+		}
+		assert!(!received.is_empty());
+		// Anything that shows up after something newer (or shows up twice) gets thrown out.
+		assert!(received.windows(2).all(|pair| pair[0] < pair[1]), "{received:?}");
+		assert!(received.len() < 100);
+	}
+}
@@ -1,10 +1,13 @@
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+
 use futures::Future;
-use image::{ImageError, RgbaImage};
+use image::{ImageError, Rgba, RgbaImage};
 
-use crate::common::identity::NodeIdentity;
+use crate::common::{identity::NodeIdentity, new_fast_hash_map, new_fast_hash_set, FastHashMap, FastHashSet};
 
 use super::{
-	provider::{RawResourceProvider, ResourceProvider},
+	provider::{RawResourceProvider, ResourceLoader, ResourceProvider},
 	ResourceError, Caid, ResourceLocation, ResourcePoll, ResourceRetrievalError,
 };
 
@@ -101,6 +104,7 @@ impl ResourceProvider<InternalImage> for ImageProvider {
 				Err(e) => ResourcePoll::Err(super::ResourceError::Parse(id, e.into())),
 			},
 			ResourcePoll::Err(e) => ResourcePoll::Err(e.into()),
+			ResourcePoll::Pending => ResourcePoll::Pending,
 			ResourcePoll::None => ResourcePoll::None,
 		}
 	}
@@ -112,3 +116,207 @@ impl ResourceProvider<InternalImage> for ImageProvider {
 		self.recv_wait_inner()
 	}
 }
+
+/// Loads images out of files on this machine, for when there's no server to fetch them from (i.e. in development).
+/// Files get registered with add_image_file(), which hands back the Caid the rest of the engine can refer to them by.
+/// Decoding happens on a background thread, so poll() reports Pending until it's done.
+pub struct LocalImageLoader {
+	/// Undecoded file contents, by the Caid of those contents.
+	sources: FastHashMap<Caid, Arc<Vec<u8>>>,
+	decoded: FastHashMap<Caid, InternalImage>,
+	/// Requested, but not decoded yet.
+	in_flight: FastHashSet<Caid>,
+	/// Errors which haven't been handed out by poll() yet.
+	failed: FastHashMap<ResourceLocation, LoadImageError>,
+	decode_sender: mpsc::Sender<(Caid, Result<InternalImage, ImageError>)>,
+	decode_receiver: mpsc::Receiver<(Caid, Result<InternalImage, ImageError>)>,
+}
+
+impl LocalImageLoader {
+	pub fn new() -> Self {
+		let (decode_sender, decode_receiver) = mpsc::channel();
+		Self {
+			sources: new_fast_hash_map(),
+			decoded: new_fast_hash_map(),
+			in_flight: new_fast_hash_set(),
+			failed: new_fast_hash_map(),
+			decode_sender,
+			decode_receiver,
+		}
+	}
+	/// Read a file in so it can be requested later. Doesn't decode it yet.
+	pub fn add_image_file<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<Caid> {
+		let buf = std::fs::read(path)?;
+		let id = Caid::from_buf(&buf);
+		self.sources.insert(id, Arc::new(buf));
+		Ok(id)
+	}
+	/// Pick up anything the background threads have finished.
+	fn collect_decoded(&mut self) {
+		while let Ok((id, result)) = self.decode_receiver.try_recv() {
+			self.in_flight.remove(&id);
+			match result {
+				Ok(image) => {
+					self.decoded.insert(id, image);
+				}
+				Err(e) => {
+					self.failed.insert(ResourceLocation::Caid(id), e.into());
+				}
+			}
+		}
+	}
+}
+
+impl Default for LocalImageLoader {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl ResourceLoader<InternalImage> for LocalImageLoader {
+	type ParseError = LoadImageError;
+
+	fn request(&mut self, resource: &ResourceLocation) {
+		let id = match resource {
+			ResourceLocation::Caid(id) => *id,
+			_ => {
+				self.failed.insert(resource.clone(), LoadImageError::DoesNotExist(format!("{resource:?}")));
+				return;
+			}
+		};
+		if self.decoded.contains_key(&id) || self.in_flight.contains(&id) {
+			return;
+		}
+		let buf = match self.sources.get(&id) {
+			Some(buf) => buf.clone(),
+			None => {
+				self.failed.insert(resource.clone(), LoadImageError::DoesNotExist(id.to_string()));
+				return;
+			}
+		};
+		self.in_flight.insert(id);
+		let sender = self.decode_sender.clone();
+		std::thread::spawn(move || {
+			let result = image::load_from_memory(buf.as_slice()).map(|image| image.into_rgba8());
+			// Only fails if the loader's gone, in which case nobody wants this anymore.
+			let _ = sender.send((id, result));
+		});
+	}
+
+	fn poll(&mut self, resource: &ResourceLocation) -> ResourcePoll<InternalImage, Self::ParseError> {
+		self.collect_decoded();
+		if let Some(e) = self.failed.remove(resource) {
+			return ResourcePoll::Err(ResourceError::Parse(resource.clone(), e));
+		}
+		let id = match resource {
+			ResourceLocation::Caid(id) => id,
+			_ => return ResourcePoll::None,
+		};
+		if let Some(image) = self.decoded.get(id) {
+			ResourcePoll::Ready(resource.clone(), image.clone())
+		} else if self.in_flight.contains(id) {
+			ResourcePoll::Pending
+		} else {
+			ResourcePoll::None
+		}
+	}
+}
+
+/// How many mip levels a full chain for a texture this size has, counting the full-size image as level 0.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+	32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Every mip level below the full-size image, from largest to smallest (1x1). Each level is a 2x2 box filter
+/// of the one before it - an odd-sized edge just reuses its last row / column.
+pub fn generate_mip_chain(image: &InternalImage) -> Vec<RgbaImage> {
+	let (width, height) = image.dimensions();
+	let levels = mip_level_count(width, height);
+	let mut chain: Vec<RgbaImage> = Vec::with_capacity(levels.saturating_sub(1) as usize);
+	for _ in 1..levels {
+		let previous = chain.last().unwrap_or(image);
+		let (prev_width, prev_height) = previous.dimensions();
+		let (mip_width, mip_height) = ((prev_width / 2).max(1), (prev_height / 2).max(1));
+		let mip = RgbaImage::from_fn(mip_width, mip_height, |x, y| {
+			let x0 = (x * 2).min(prev_width - 1);
+			let x1 = (x * 2 + 1).min(prev_width - 1);
+			let y0 = (y * 2).min(prev_height - 1);
+			let y1 = (y * 2 + 1).min(prev_height - 1);
+			let samples = [previous.get_pixel(x0, y0), previous.get_pixel(x1, y0), previous.get_pixel(x0, y1), previous.get_pixel(x1, y1)];
+			let mut out = [0u8; 4];
+			for (channel, value) in out.iter_mut().enumerate() {
+				let sum: u32 = samples.iter().map(|pixel| pixel.0[channel] as u32).sum();
+				*value = ((sum + 2) / 4) as u8;
+			}
+			Rgba(out)
+		});
+		chain.push(mip);
+	}
+	chain
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn mip_chain_for_64x64() {
+		let image = RgbaImage::from_fn(64, 64, |x, y| if (x + y) % 2 == 0 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) });
+		assert_eq!(mip_level_count(64, 64), 7);
+		let chain = generate_mip_chain(&image);
+		assert_eq!(chain.len(), 6);
+		assert_eq!(chain.first().unwrap().dimensions(), (32, 32));
+		assert_eq!(chain.last().unwrap().dimensions(), (1, 1));
+		// A one-pixel checkerboard averages out to flat grey on the very first level down.
+		assert!(chain[0].pixels().all(|pixel| *pixel == Rgba([128, 128, 128, 255])));
+
+		// Non-square, odd sizes still bottom out at 1x1.
+		assert_eq!(mip_level_count(5, 3), 3);
+		let odd = generate_mip_chain(&RgbaImage::new(5, 3));
+		assert_eq!(odd.iter().map(|level| level.dimensions()).collect::<Vec<_>>(), vec![(2, 1), (1, 1)]);
+		assert!(generate_mip_chain(&RgbaImage::new(1, 1)).is_empty());
+	}
+
+	#[test]
+	fn local_images_load_in_the_background() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("grass.png");
+		let image = RgbaImage::from_pixel(4, 2, Rgba([20, 200, 40, 255]));
+		image.save(&path).unwrap();
+		let garbage_path = dir.path().join("garbage.png");
+		std::fs::write(&garbage_path, b"not a png").unwrap();
+
+		let mut loader = LocalImageLoader::new();
+		let id = loader.add_image_file(&path).unwrap();
+		assert_eq!(id, Caid::from_buf(&std::fs::read(&path).unwrap()));
+		let location = ResourceLocation::Caid(id);
+		assert!(loader.poll(&location).is_none());
+
+		let loaded = loop {
+			if let Some(result) = loader.poll_or_request(&location) {
+				// ResourceError<LoadImageError> isn't Debug, so no unwrap().
+				break result.ok().expect("a valid png failed to decode");
+			}
+			std::thread::yield_now();
+		};
+		assert_eq!(loaded, image);
+		// Sticks around once it's decoded.
+		assert!(matches!(loader.poll(&location), ResourcePoll::Ready(_, _)));
+
+		// Files which were never added don't exist as far as the loader is concerned.
+		let unknown = ResourceLocation::Caid(Caid::from_buf(b"nope"));
+		assert!(matches!(
+			loader.poll_or_request(&unknown),
+			Some(Err(ResourceError::Parse(_, LoadImageError::DoesNotExist(_))))
+		));
+
+		let garbage = ResourceLocation::Caid(loader.add_image_file(&garbage_path).unwrap());
+		let result = loop {
+			if let Some(result) = loader.poll_or_request(&garbage) {
+				break result;
+			}
+			std::thread::yield_now();
+		};
+		assert!(matches!(result, Err(ResourceError::Parse(_, LoadImageError::EncodeDecodeError(_)))));
+	}
+}
@@ -1,4 +1,4 @@
-use crate::common::identity::{NodeIdentity, PublicKey};
+use crate::common::identity::{NodeIdentity, PublicKey, SignatureError};
 use crate::common::{new_fast_hash_map, FastHashMap};
 use crate::message::RecvError;
 
@@ -280,6 +280,19 @@ impl ResourceLocation {
 			ResourceLocation::Link(_) => todo!(),
 		}
 	}
+
+	/// Checks bytes which were just loaded for this resource against its content hash, so that a corrupt cache
+	/// file or a misbehaving peer can't hand us something else under this ID. Only CAIDs can be checked -
+	/// local resources have no hash to check against, so they always pass.
+	pub fn verify_loaded(&self, buf: &[u8]) -> Result<(), ResourceRetrievalError> {
+		match self {
+			ResourceLocation::Caid(expected) => expected.verify(buf).map_err(|_| ResourceRetrievalError::IntegrityMismatch {
+				expected: *expected,
+				actual: Caid::from_buf(buf),
+			}),
+			ResourceLocation::Local(_) | ResourceLocation::Link(_) => Ok(()),
+		}
+	}
 }
 
 pub(crate) enum ResourceFilelike {
@@ -308,6 +321,14 @@ pub struct ResourceInfo {
 	pub signature: Signature,
 }
 
+impl ResourceInfo {
+	/// Checks that creator really did sign these bytes (the resource's contents), so that nobody can claim
+	/// someone else made a resource, or pass their own off as someone else's.
+	pub fn verify_signature(&self, bytes: &[u8]) -> Result<(), SignatureError> {
+		self.creator.verify_signature(bytes, &self.signature.to_bytes())
+	}
+}
+
 impl Hash for ResourceInfo {
 	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
 		self.id.hash(state);
@@ -338,6 +359,8 @@ pub enum ResourceRetrievalError {
 	Timeout(Caid),
 	#[error("Failed to verify resource {0:?} due to error {1:?}.")]
 	Verification(Caid, VerifyResourceError),
+	#[error("Loaded bytes for resource {expected:?} do not match its ID - they hash to {actual:?}. The cached copy may be corrupt.")]
+	IntegrityMismatch { expected: Caid, actual: Caid },
 	#[error("Message-passing error while trying to load resource {0:?}: {1}.")]
 	ChannelError(ResourceLocation, String),
 }
@@ -511,6 +534,8 @@ where
 {
 	Ready(ResourceLocation, T),
 	Err(ResourceError<E>),
+	/// Requested, but it hasn't arrived yet. Poll again later.
+	Pending,
 	/// End of stream, the channel is empty. If you are polling in a loop you can stop polling.
 	None,
 }
@@ -523,6 +548,7 @@ where
 		match self {
 			ResourcePoll::Ready(_, _) => false,
 			ResourcePoll::Err(_) => false,
+			ResourcePoll::Pending => false,
 			ResourcePoll::None => true,
 		}
 	}
@@ -534,6 +560,14 @@ pub fn update_global_resource_metadata(id: &Caid, info: ResourceInfo) {
 	RESOURCE_METADATA.update_blocking(id, info);
 }
 
+/// update_global_resource_metadata() for metadata we didn't make ourselves, e.g. metadata which came from a peer.
+/// Anything whose signature doesn't check out against its creator's key and the resource's bytes is turned away.
+pub fn update_global_resource_metadata_verified(id: &Caid, info: ResourceInfo, bytes: &[u8]) -> Result<(), SignatureError> {
+	info.verify_signature(bytes)?;
+	update_global_resource_metadata(id, info);
+	Ok(())
+}
+
 pub fn get_resource_metadata(id: &Caid) -> Option<ResourceInfo> {
 	RESOURCE_METADATA.get_blocking(id)
 }
@@ -635,3 +669,60 @@ fn resource_id_to_string() {
 	assert_eq!(after_split.len(), 3);
 	assert_eq!(after_split.get(1).unwrap().parse::<u64>().unwrap(), BUF_SIZE as u64);
 }
+
+#[test]
+fn corrupted_resource_fails_verification() {
+	let mut buf: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+	let caid = Caid::from_buf(&buf);
+	let location = ResourceLocation::Caid(caid);
+	assert!(location.verify_loaded(&buf).is_ok());
+
+	// One flipped bit is enough.
+	buf[1234] ^= 0x01;
+	assert!(caid.verify(&buf).is_err());
+	match location.verify_loaded(&buf) {
+		Err(ResourceRetrievalError::IntegrityMismatch { expected, actual }) => {
+			assert_eq!(expected, caid);
+			assert_eq!(actual, Caid::from_buf(&buf));
+			assert_ne!(actual, expected);
+		}
+		other => panic!("Expected an integrity mismatch, got {other:?}"),
+	}
+
+	// Local files have no hash to check.
+	let local = ResourceLocation::Local(LocalResource::User(PathBuf::from("test.png")));
+	assert!(local.verify_loaded(&buf).is_ok());
+}
+
+#[test]
+fn resource_signature_must_match_creator() {
+	use crate::common::identity::IdentityKeyPair;
+
+	let real_author = IdentityKeyPair::generate_for_tests_seeded(77);
+	let impostor = IdentityKeyPair::generate_for_tests_seeded(78);
+	let bytes = b"A very important texture".to_vec();
+	let id = Caid::from_buf(&bytes);
+	let info = ResourceInfo {
+		id,
+		filename: String::from("important.png"),
+		creator: real_author.public,
+		resource_type: String::from("image/png"),
+		authors: String::from("Someone"),
+		description: None,
+		signature: real_author.sign(&bytes).unwrap(),
+	};
+	assert!(info.verify_signature(&bytes).is_ok());
+	assert!(info.verify_signature(b"Some other texture").is_err());
+
+	// Signed by one key, but claiming to be from another.
+	let forged = ResourceInfo {
+		creator: impostor.public,
+		..info.clone()
+	};
+	assert!(forged.verify_signature(&bytes).is_err());
+	assert!(update_global_resource_metadata_verified(&id, forged, &bytes).is_err());
+	assert!(get_resource_metadata(&id).is_none());
+
+	update_global_resource_metadata_verified(&id, info, &bytes).unwrap();
+	assert_eq!(get_resource_metadata(&id).unwrap().creator, real_author.public);
+}
@@ -65,6 +65,8 @@ pub trait ResourceProvider<T> {
 						buf.push(ResourcePoll::Err(e));
 					}
 				}
+				// Nothing more is ready yet.
+				ResourcePoll::Pending => return buf,
 				ResourcePoll::None => {
 					unreachable!("Unreachable due to \"while next != ResourcePoll::None\" above.")
 				}
@@ -75,6 +77,34 @@ pub trait ResourceProvider<T> {
 	}
 }
 
+/// Non-blocking loader for resources which may take a while to show up, e.g. ones being fetched from a server.
+/// request() starts fetching in the background and returns right away, then poll() reports Pending until
+/// the resource is Ready (or failed). Once a resource has come back Ready or Err, it's up to the
+/// implementation whether it keeps it around for later polls.
+pub trait ResourceLoader<T> {
+	type ParseError: Debug;
+
+	/// Start fetching this resource, if it isn't already on its way.
+	fn request(&mut self, resource: &ResourceLocation);
+	/// Pending while the resource is on its way, None if it was never requested.
+	fn poll(&mut self, resource: &ResourceLocation) -> ResourcePoll<T, Self::ParseError>;
+
+	/// poll(), but request() the resource first if that hasn't happened yet.
+	/// Returns None while it's still pending.
+	fn poll_or_request(&mut self, resource: &ResourceLocation) -> Option<Result<T, ResourceError<Self::ParseError>>> {
+		let mut poll = self.poll(resource);
+		if poll.is_none() {
+			self.request(resource);
+			poll = self.poll(resource);
+		}
+		match poll {
+			ResourcePoll::Ready(_, value) => Some(Ok(value)),
+			ResourcePoll::Err(e) => Some(Err(e)),
+			ResourcePoll::Pending | ResourcePoll::None => None,
+		}
+	}
+}
+
 pub struct RawResourceProvider {
 	fetch_sender: MpscSender<ResourceFetch>,
 	return_receiver: MpscReceiver<ResourceFetchResponse>,
@@ -165,3 +195,57 @@ impl ResourceProvider<Arc<Vec<u8>>> for RawResourceProvider {
 		self.recv_wait_inner()
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use std::collections::HashMap;
+
+	use super::*;
+	use crate::resource::{Caid, LocalResource};
+
+	/// Hands back the resource's location as a string after a set number of polls.
+	struct SlowLoader {
+		polls_left: HashMap<ResourceLocation, u32>,
+		requests: u32,
+		delay: u32,
+	}
+
+	impl ResourceLoader<String> for SlowLoader {
+		type ParseError = ResourceRetrievalError;
+
+		fn request(&mut self, resource: &ResourceLocation) {
+			self.requests += 1;
+			self.polls_left.entry(resource.clone()).or_insert(self.delay);
+		}
+		fn poll(&mut self, resource: &ResourceLocation) -> ResourcePoll<String, Self::ParseError> {
+			match self.polls_left.get_mut(resource) {
+				None => ResourcePoll::None,
+				Some(0) => ResourcePoll::Ready(resource.clone(), format!("{resource:?}")),
+				Some(left) => {
+					*left -= 1;
+					ResourcePoll::Pending
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn pending_until_ready() {
+		let mut loader = SlowLoader {
+			polls_left: HashMap::new(),
+			requests: 0,
+			delay: 3,
+		};
+		let resource = ResourceLocation::Caid(Caid::from_buf(b"grass"));
+		let other = ResourceLocation::Local(LocalResource::Internal(String::from("stone")));
+		assert!(loader.poll(&other).is_none());
+
+		for _ in 0..3 {
+			assert!(loader.poll_or_request(&resource).is_none());
+		}
+		assert_eq!(loader.poll_or_request(&resource).unwrap().unwrap(), format!("{resource:?}"));
+		// Only the first call needed to kick off the fetch.
+		assert_eq!(loader.requests, 1);
+		assert!(loader.poll(&other).is_none());
+	}
+}
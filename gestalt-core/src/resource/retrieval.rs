@@ -127,9 +127,17 @@ async fn load_from_file(
 									)),
 								}).map_err(|e| FileLoadError::NoSendChannel(resource.clone()))?;
 							} else {
+								// Files on disk can bit-rot, so check them against their ID before anything uses them.
+								let data = match resource.verify_loaded(&buffer) {
+									Ok(()) => Ok(Arc::new(buffer)),
+									Err(e) => {
+										error!("Could not use file {0:?} for resource {1:?}: {2}", path, resource, e);
+										Err(e)
+									}
+								};
 								chan.send(ResourceFetchResponse {
 									id: resource.clone(),
-									data: Result::Ok(Arc::new(buffer)),
+									data,
 								}).map_err(|_e| FileLoadError::NoSendChannel(resource.clone()))?;
 							}
 						} else {
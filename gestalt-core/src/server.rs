@@ -1,23 +1,73 @@
 use std::io::{BufReader, Read};
+use std::path::PathBuf;
+use std::time::Duration;
 
-use log::warn;
+use log::{error, info, trace, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::{
-	common::identity::IdentityKeyPair,
-	world::{World, WorldId},
+	admin_console::{AdminCommand, AdminRequest},
+	common::identity::{IdentityKeyPair, NodeIdentity},
+	message::{
+		MessageReceiverAsync, MessageSender, MpscReceiver, MpscSender, QuitReceiver, ReceiverSubscribe, SenderSubscribe,
+	},
+	message_types::{
+		voxel::{ChunkData, ChunkDataRequest, ChunkDelta, ChunkUpdate, VoxelChangeAnnounce, VoxelChangeRequest},
+		JoinAnnounce, JoinDefaultEntry, PlayerPositionReport,
+	},
+	net::{net_channels::EngineNetChannels, ConnectedPeers, KickPeer, NetMsg, PacketIntermediary},
+	world::{
+		fsworldstorage::{self, StoredWorldRole},
+		tickscheduler::{TickNumber, TickScheduler},
+		tilespace::{chunk_to_world_pos, world_to_chunk_pos, TileSpace},
+		voxelstorage::{VoxelSpace, VoxelStorage},
+		worldgen::{ChunkGenerator, GeneratedChunk, WorldGenQueue},
+		ChunkPos, TickLength, TileId, TilePos, World, WorldId,
+	},
+	DomainMessageSender, DomainSenderSubscribe,
 };
 
+fn default_world_dir() -> PathBuf {
+	PathBuf::from("./")
+}
+fn default_autosave_interval_secs() -> u64 {
+	300
+}
+fn default_view_distance() -> u32 {
+	8
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
 	/// What is the IP address of this server?
 	pub server_ip: String,
+	/// Worlds get saved to the "worlds/" directory inside this one.
+	#[serde(default = "default_world_dir")]
+	pub world_dir: PathBuf,
+	/// How often changed chunks get saved, in seconds. 0 turns autosave off.
+	/// Either way, everything gets saved when the server shuts down.
+	#[serde(default = "default_autosave_interval_secs")]
+	pub autosave_interval_secs: u64,
+	/// How far out from each client, in chunks, the server keeps track of things for them.
+	#[serde(default = "default_view_distance")]
+	pub view_distance: u32,
 }
 impl Default for ServerConfig {
 	fn default() -> Self {
 		Self {
 			server_ip: String::from("127.0.0.1"),
+			world_dir: default_world_dir(),
+			autosave_interval_secs: default_autosave_interval_secs(),
+			view_distance: default_view_distance(),
+		}
+	}
+}
+impl ServerConfig {
+	pub fn get_autosave_interval(&self) -> Option<Duration> {
+		match self.autosave_interval_secs {
+			0 => None,
+			secs => Some(Duration::from_secs(secs)),
 		}
 	}
 }
@@ -34,12 +84,13 @@ pub enum StartServerError {
 
 pub const SERVER_CONFIG_FILENAME: &str = "server_config.ron";
 
-pub fn load_server_config() -> Result<ServerConfig, StartServerError> {
+/// Reads server_config.ron, without falling back to defaults if that doesn't work.
+pub fn load_server_config_file() -> Result<ServerConfig, StartServerError> {
 	// Open config
 	let mut open_options = std::fs::OpenOptions::new();
 	open_options.read(true).append(true).create(true);
 
-	let config_maybe: Result<ServerConfig, StartServerError> = open_options
+	open_options
 		.open(SERVER_CONFIG_FILENAME)
 		.map_err(StartServerError::from)
 		.and_then(|file| {
@@ -50,7 +101,11 @@ pub fn load_server_config() -> Result<ServerConfig, StartServerError> {
 				.map_err(StartServerError::from)?;
 			Ok(contents)
 		})
-		.and_then(|file| ron::from_str(file.as_str()).map_err(StartServerError::from));
+		.and_then(|file| ron::from_str(file.as_str()).map_err(StartServerError::from))
+}
+
+pub fn load_server_config() -> Result<ServerConfig, StartServerError> {
+	let config_maybe = load_server_config_file();
 	//If that didn't load, just use built-in defaults.
 	Ok(match config_maybe {
 		Ok(c) => c,
@@ -74,3 +129,884 @@ impl ServerNode {
 		}
 	}
 }
+
+/// How many chunks an autosave writes per tick, so that saving a busy world doesn't stall the server.
+pub const AUTOSAVE_CHUNKS_PER_TICK: usize = 16;
+
+/// A world's terrain, as hosted by this server, along with where it gets saved to.
+pub struct HostedWorld {
+	pub world_id: WorldId,
+	pub space: TileSpace,
+	pub base_dir: PathBuf,
+}
+
+impl HostedWorld {
+	pub fn new(world_id: WorldId, base_dir: PathBuf) -> Self {
+		Self {
+			world_id,
+			space: TileSpace::new(),
+			base_dir,
+		}
+	}
+
+	/// Loads a chunk from disk if it isn't loaded already. Returns whether the chunk is loaded now.
+	/// Chunks which have never been saved have to be generated instead, see ingest_generated().
+	pub fn ensure_chunk_loaded(&mut self, pos: &ChunkPos) -> bool {
+		if self.space.borrow_chunk(pos).is_ok() {
+			return true;
+		}
+		let path = fsworldstorage::path_for_chunk(&self.base_dir, &self.world_id, StoredWorldRole::Local, pos);
+		if !path.exists() {
+			return false;
+		}
+		match fsworldstorage::load_chunk(&self.base_dir, &self.world_id, StoredWorldRole::Local, pos) {
+			Ok(chunk) => self.space.ingest_loaded_chunk(*pos, chunk).is_ok(),
+			Err(e) => {
+				error!("Could not load chunk {pos} from disk: {e}");
+				false
+			}
+		}
+	}
+
+	/// Moves a freshly-generated chunk into this world. Returns false if it was generated for some other world,
+	/// or if the chunk got loaded some other way while it was generating.
+	pub fn ingest_generated(&mut self, generated: GeneratedChunk) -> bool {
+		if generated.world != self.world_id {
+			warn!("Got a generated chunk for {:?}, which this server doesn't host.", generated.world);
+			return false;
+		}
+		match self.space.ingest_loaded_chunk(generated.pos, generated.chunk) {
+			Ok(()) => true,
+			Err(e) => {
+				trace!("Discarding generated chunk {}: {e:?}", generated.pos);
+				false
+			}
+		}
+	}
+
+	/// Applies an edit if its chunk is hosted here (loading it first if need be).
+	/// Returns the chunk's revision before and after, or None if the edit couldn't be applied.
+	pub fn apply_edit(&mut self, pos: TilePos, tile: TileId) -> Option<(u64, u64)> {
+		let chunk_pos = world_to_chunk_pos(&pos);
+		if !self.ensure_chunk_loaded(&chunk_pos) {
+			trace!("Voxel change at {pos} is in a chunk this server doesn't have.");
+			return None;
+		}
+		let revision_before = self.space.borrow_chunk(&chunk_pos).unwrap().revision;
+		if let Err(e) = self.space.set(pos, tile) {
+			warn!("Could not apply voxel change at {pos}: {e}");
+			return None;
+		}
+		Some((revision_before, self.space.borrow_chunk(&chunk_pos).unwrap().revision))
+	}
+
+	/// Saves up to max_chunks chunks which have changed since they were last saved.
+	/// Returns how many were saved. Chunks which failed to save stay marked as changed.
+	pub fn save_dirty(&mut self, max_chunks: usize) -> usize {
+		let taken = self.space.take_dirty_chunks(max_chunks);
+		self.save_taken(taken)
+	}
+
+	/// Like save_dirty(), but only for the given chunks. Ones which were saved since (or never changed) are skipped.
+	pub fn save_chunks(&mut self, positions: &[ChunkPos]) -> usize {
+		let taken: Vec<ChunkPos> = positions
+			.iter()
+			.filter(|pos| self.space.take_dirty_chunk(pos))
+			.copied()
+			.collect();
+		self.save_taken(taken)
+	}
+
+	/// Saves chunks which have already been taken off of the dirty list, putting back the ones which fail.
+	fn save_taken(&mut self, taken: Vec<ChunkPos>) -> usize {
+		let mut saved = 0;
+		for pos in taken {
+			// Only loaded chunks are ever on the dirty list.
+			let chunk = self.space.borrow_chunk(&pos).unwrap();
+			match fsworldstorage::save_chunk(&self.base_dir, &self.world_id, StoredWorldRole::Local, &pos, chunk) {
+				Ok(()) => saved += 1,
+				Err(e) => {
+					error!("Could not save chunk {pos}: {e}");
+					self.space.mark_chunk_dirty(pos);
+				}
+			}
+		}
+		saved
+	}
+}
+
+/// Collects the edits made to hosted chunks over one batch, to go out as one ChunkDelta per chunk.
+#[derive(Default)]
+pub struct ChunkDeltaBatch {
+	deltas: HashMap<ChunkPos, ChunkDelta>,
+}
+
+impl ChunkDeltaBatch {
+	pub fn record(&mut self, pos: TilePos, tile: TileId, revision_before: u64, revision_after: u64) {
+		let (chunk, local) = pos.to_chunk_and_local();
+		let delta = self.deltas.entry(chunk).or_insert_with(|| ChunkDelta {
+			chunk,
+			edits: Vec::new(),
+			base_revision: revision_before,
+			revision: revision_after,
+		});
+		delta.edits.push((local, tile));
+		delta.revision = revision_after;
+	}
+
+	pub fn into_deltas(self) -> impl Iterator<Item = ChunkDelta> {
+		self.deltas.into_values()
+	}
+}
+
+/// Where a peer is and how far they can see, which decides which voxel changes they need to hear about.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PeerInterest {
+	/// Chunk the peer is in.
+	pub center: ChunkPos,
+	/// In chunks, out from center along each axis.
+	pub view_distance: u32,
+}
+
+impl PeerInterest {
+	pub fn contains(&self, pos: &TilePos) -> bool {
+		let chunk = world_to_chunk_pos(pos);
+		let view_distance = self.view_distance as i64;
+		(chunk.x as i64 - self.center.x as i64).abs() <= view_distance
+			&& (chunk.y as i64 - self.center.y as i64).abs() <= view_distance
+			&& (chunk.z as i64 - self.center.z as i64).abs() <= view_distance
+	}
+}
+
+/// Keeps track of each peer's PeerInterest, so changes only get sent to the peers close enough to care.
+#[derive(Default)]
+pub struct InterestMap {
+	peers: HashMap<NodeIdentity, PeerInterest>,
+}
+
+impl InterestMap {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn update(&mut self, peer: NodeIdentity, interest: PeerInterest) {
+		self.peers.insert(peer, interest);
+	}
+
+	pub fn remove(&mut self, peer: &NodeIdentity) {
+		self.peers.remove(peer);
+	}
+
+	/// Forgets every peer for whom `keep` returns false.
+	pub fn retain(&mut self, mut keep: impl FnMut(&NodeIdentity) -> bool) {
+		self.peers.retain(|peer, _| keep(peer));
+	}
+
+	pub fn get(&self, peer: &NodeIdentity) -> Option<&PeerInterest> {
+		self.peers.get(peer)
+	}
+
+	/// Peers who have never said where they are hear about everything, since
+	/// we have no way of knowing what they can see.
+	pub fn is_interested(&self, peer: &NodeIdentity, pos: &TilePos) -> bool {
+		match self.peers.get(peer) {
+			Some(interest) => interest.contains(pos),
+			None => true,
+		}
+	}
+}
+
+/// Sends a loaded chunk to one peer, in full.
+fn send_chunk_data(
+	world: &HostedWorld,
+	pos: ChunkPos,
+	peer: &NodeIdentity,
+	sender: &impl DomainMessageSender<Vec<PacketIntermediary>, NodeIdentity>,
+) {
+	let chunk = match world.space.borrow_chunk(&pos) {
+		Ok(chunk) => chunk,
+		Err(e) => {
+			error!("Could not send chunk {pos} to {}: {e:?}", peer.to_base64());
+			return;
+		}
+	};
+	match ChunkData::new(pos, chunk) {
+		Ok(data) => {
+			if let Err(e) = sender.send_to(vec![data.construct_packet().unwrap()], peer) {
+				warn!("Could not send chunk {pos} to {}: {e}", peer.to_base64());
+			}
+		}
+		Err(e) => error!("Could not encode chunk {pos}: {e}"),
+	}
+}
+
+/// Things the server main loop schedules for itself on its TickScheduler.
+enum ServerTickEvent {
+	/// Start an autosave cycle, covering whichever chunks have changed as of now.
+	Autosave,
+	/// Save the next few chunks of an autosave cycle, since a cycle is spread over however many ticks it takes
+	/// to get through all of them. Chunks changed after the cycle started (or which failed to save during it)
+	/// wait for the next cycle, so that a chunk which can't be saved doesn't keep a cycle going forever.
+	AutosaveContinue { saved_so_far: usize, remaining: Vec<ChunkPos> },
+}
+
+fn autosave_ticks(config: &ServerConfig, tick_length: TickLength) -> Option<TickNumber> {
+	config
+		.get_autosave_interval()
+		.map(|interval| ((interval.as_secs_f32() / tick_length.get()).ceil() as TickNumber).max(1))
+}
+
+/// Asks the network system to drop (and possibly ban) a peer, and forgets they joined.
+fn remove_peer(
+	ident: NodeIdentity,
+	ban: bool,
+	joined_users: &mut HashMap<NodeIdentity, String>,
+	kick_sender: &MpscSender<KickPeer>,
+) -> String {
+	let name = match joined_users.remove(&ident) {
+		Some(display_name) => format!("{display_name} ({})", ident.to_base64()),
+		None => ident.to_base64(),
+	};
+	match kick_sender.send(KickPeer { peer_identity: ident, ban }) {
+		Ok(()) if ban => format!("Banned {name}."),
+		Ok(()) => format!("Kicked {name}."),
+		Err(e) => format!("Could not remove {name}: {e}"),
+	}
+}
+
+/// Carries out an admin console command, returning the text to send back to the console.
+fn handle_admin_command(
+	command: AdminCommand,
+	world: &mut HostedWorld,
+	config: &mut ServerConfig,
+	connected_peers: &ConnectedPeers,
+	joined_users: &mut HashMap<NodeIdentity, String>,
+	kick_sender: &MpscSender<KickPeer>,
+) -> String {
+	match command {
+		AdminCommand::Save => {
+			let saved = world.save_dirty(usize::MAX);
+			let failed = world.space.dirty_chunk_count();
+			if failed == 0 {
+				format!("Saved {saved} chunks.")
+			} else {
+				format!("Saved {saved} chunks, {failed} could not be saved (see log).")
+			}
+		}
+		// run_server_mainloop() stops once this reply is sent, and leaves quitting the rest of the engine to its caller.
+		AdminCommand::Stop => String::from("Stopping the server."),
+		AdminCommand::Kick(ident) => remove_peer(ident, false, joined_users, kick_sender),
+		AdminCommand::Ban(ident) => remove_peer(ident, true, joined_users, kick_sender),
+		AdminCommand::List => {
+			let connected = connected_peers.snapshot();
+			if connected.is_empty() {
+				String::from("No users are connected.")
+			} else {
+				// Peers who haven't sent a JoinDefaultEntry yet don't have a display name.
+				let mut lines: Vec<String> = connected
+					.iter()
+					.map(|ident| match joined_users.get(ident) {
+						Some(name) => format!("{name} ({})", ident.to_base64()),
+						None => format!("(not joined yet) ({})", ident.to_base64()),
+					})
+					.collect();
+				lines.sort();
+				format!("{} users are connected:\n{}", lines.len(), lines.join("\n"))
+			}
+		}
+		AdminCommand::SetViewDistance(distance) => {
+			config.view_distance = distance;
+			format!("View distance set to {distance} chunks.")
+		}
+		AdminCommand::ReloadConfig => match load_server_config_file() {
+			Ok(new_config) => {
+				let mut response = String::from("Reloaded server config.");
+				if new_config.world_dir != config.world_dir {
+					response.push_str(" The new world_dir will take effect after a restart.");
+				}
+				config.autosave_interval_secs = new_config.autosave_interval_secs;
+				config.view_distance = new_config.view_distance;
+				config.server_ip = new_config.server_ip;
+				response
+			}
+			Err(e) => format!("Could not reload server config, keeping the old one: {e}"),
+		},
+	}
+}
+
+/// The server's gameplay message loop: applies voxel changes to the hosted world and relays them between clients,
+/// catches newly-joined clients up on every change made so far, hands out chunks (generating the ones which were
+/// never saved with `worldgen`), autosaves on the interval in config (if any), and carries out commands from the
+/// admin console. Runs until the engine quits or the admin console says to stop, saving the world on the way out.
+/// Only the former shuts down the rest of the engine.
+pub async fn run_server_mainloop<G: ChunkGenerator>(
+	net_channels: EngineNetChannels,
+	connected_peers: ConnectedPeers,
+	mut world: HostedWorld,
+	mut worldgen: WorldGenQueue<G>,
+	mut config: ServerConfig,
+	mut admin_commands: MpscReceiver<AdminRequest>,
+) {
+	let tick_length = TickLength::default();
+	let mut ticker = tokio::time::interval(tick_length.get_duration());
+	ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+	let mut current_tick: TickNumber = 0;
+	let mut scheduler: TickScheduler<ServerTickEvent> = TickScheduler::new();
+	// Whether there's an Autosave on the scheduler, so a config reload that turns autosave on doesn't double up.
+	let mut autosave_pending = false;
+	if let Some(ticks) = autosave_ticks(&config, tick_length) {
+		scheduler.schedule(ticks, ServerTickEvent::Autosave);
+		autosave_pending = true;
+	}
+	// Goes false once every sender (i.e. the admin console) is gone.
+	let mut admin_console_open = true;
+	let mut joined_users: HashMap<NodeIdentity, String> = HashMap::new();
+	let mut interest = InterestMap::new();
+	// Who asked for each chunk that's still being generated, so they can be sent it once it's done.
+	let mut awaiting_generation: HashMap<ChunkPos, Vec<NodeIdentity>> = HashMap::new();
+
+	let mut total_changes: Vec<VoxelChangeAnnounce> = Vec::new();
+	let mut quit_receiver = QuitReceiver::new();
+	let mut voxel_from_client =
+		net_channels.net_msg_inbound.receiver_typed::<VoxelChangeRequest>().unwrap();
+	let mut joins_to_server =
+		net_channels.net_msg_inbound.receiver_typed::<JoinDefaultEntry>().unwrap();
+	let mut positions_from_client =
+		net_channels.net_msg_inbound.receiver_typed::<PlayerPositionReport>().unwrap();
+	let mut chunk_requests =
+		net_channels.net_msg_inbound.receiver_typed::<ChunkDataRequest>().unwrap();
+	let mut disconnects = net_channels.peer_disconnected.receiver_subscribe();
+	let net_msg_broadcast = net_channels.net_msg_outbound.sender_subscribe_all();
+	let kick_sender = net_channels.kick_peer.sender_subscribe();
+	loop {
+		tokio::select! {
+			// Batch up voxel edits so a flurry of them doesn't wake this loop once per packet.
+			voxel_events_maybe = voxel_from_client.recv_wait_batched(64, Duration::from_millis(5)) => {
+				if let Ok(voxel_events) = voxel_events_maybe {
+					let mut batch = ChunkDeltaBatch::default();
+					for (ident, event) in voxel_events {
+						info!("Received {:?} from {}", &event, ident.to_base64());
+						match world.apply_edit(event.pos, event.new_tile) {
+							Some((revision_before, revision_after)) => {
+								batch.record(event.pos, event.new_tile, revision_before, revision_after);
+							}
+							None => {
+								// No hosted chunk means no revision to sync against, so just relay it as-is.
+								let announce: VoxelChangeAnnounce = event.clone().into();
+								// Still kept below even if nobody is close enough to need it right now.
+								net_msg_broadcast.send_to_matching(
+									vec![announce.construct_packet().unwrap()],
+									|peer| (*peer != ident) && interest.is_interested(peer, &announce.pos),
+								).unwrap();
+							}
+						}
+						total_changes.push(event.into());
+					}
+					for delta in batch.into_deltas() {
+						let origin = chunk_to_world_pos(&delta.chunk);
+						let chunk = world.space.borrow_chunk(&delta.chunk).unwrap();
+						let packet = match ChunkUpdate::choose(delta, chunk) {
+							Ok(ChunkUpdate::Delta(delta)) => delta.construct_packet().unwrap(),
+							Ok(ChunkUpdate::Full(full)) => full.construct_packet().unwrap(),
+							Err(e) => {
+								error!("Could not encode chunk at {origin} for a full resend: {e}");
+								continue;
+							}
+						};
+						// Unlike plain announces, this goes back to whoever made the edit too - deltas are
+						// authoritative, and carry the revision everyone needs to be in sync with.
+						net_msg_broadcast.send_to_matching(vec![packet], |peer| interest.is_interested(peer, &origin)).unwrap();
+					}
+				}
+			}
+			join_event_maybe = joins_to_server.recv_wait() => {
+				if let Ok(events) = join_event_maybe {
+					for (ident, event) in events {
+						info!("User {} has joined with display name {}", ident.to_base64(), &event.display_name);
+						joined_users.insert(ident, event.display_name.clone());
+						let announce = JoinAnnounce {
+							display_name: event.display_name,
+							identity: ident,
+						};
+						net_msg_broadcast.send_to_all_except(vec![announce.clone().construct_packet().unwrap()], &ident).unwrap();
+						info!("Sending all previous changes to the newly-joined user.");
+
+						let sender_to_new_join = net_channels.net_msg_outbound.sender_subscribe_domain(&ident).unwrap();
+						sender_to_new_join.send(
+							total_changes
+								.iter()
+								.map(|ev| ev.construct_packet().unwrap())
+								.collect::<Vec<PacketIntermediary>>()
+						).unwrap();
+					}
+				}
+			}
+			position_events_maybe = positions_from_client.recv_wait() => {
+				if let Ok(events) = position_events_maybe {
+					for (ident, report) in events {
+						let tile = vpos!(
+							report.position.x.floor() as i32,
+							report.position.y.floor() as i32,
+							report.position.z.floor() as i32
+						);
+						interest.update(ident, PeerInterest {
+							center: world_to_chunk_pos(&tile),
+							view_distance: report.view_distance.min(config.view_distance),
+						});
+					}
+				}
+			}
+			chunk_requests_maybe = chunk_requests.recv_wait() => {
+				if let Ok(events) = chunk_requests_maybe {
+					for (ident, request) in events {
+						if world.ensure_chunk_loaded(&request.chunk) {
+							send_chunk_data(&world, request.chunk, &ident, &net_msg_broadcast);
+							continue;
+						}
+						trace!("{} asked for chunk {}, which has to be generated first.", ident.to_base64(), request.chunk);
+						// Does nothing if someone else already asked for it.
+						worldgen.request(world.world_id.clone(), request.chunk);
+						let waiting = awaiting_generation.entry(request.chunk).or_default();
+						if !waiting.contains(&ident) {
+							waiting.push(ident);
+						}
+					}
+				}
+			}
+			// recv_completed() returns right away when nothing is in flight, so only wait on it when something is.
+			generated_maybe = worldgen.recv_completed(), if worldgen.queue_depth() > 0 => {
+				if let Some(generated) = generated_maybe {
+					let pos = generated.pos;
+					world.ingest_generated(generated);
+					for ident in awaiting_generation.remove(&pos).unwrap_or_default() {
+						send_chunk_data(&world, pos, &ident, &net_msg_broadcast);
+					}
+				}
+			}
+			disconnect_maybe = disconnects.recv_wait() => {
+				match disconnect_maybe {
+					Ok(disconnect) => {
+						let ident = disconnect.peer_identity;
+						if let Some(display_name) = joined_users.remove(&ident) {
+							info!("User {display_name} ({}) has left.", ident.to_base64());
+						}
+						interest.remove(&ident);
+						for waiting in awaiting_generation.values_mut() {
+							waiting.retain(|peer| peer != &ident);
+						}
+					}
+					Err(e) => {
+						// Can't tell who left, so fall back on checking everyone against who's still connected.
+						warn!("Missed some disconnections: {e:?}");
+						joined_users.retain(|ident, _| connected_peers.contains(ident));
+						interest.retain(|ident| connected_peers.contains(ident));
+					}
+				}
+			}
+			_ = ticker.tick() => {
+				current_tick += 1;
+				for event in scheduler.drain_due(current_tick) {
+					let (saved_so_far, mut remaining) = match event {
+						ServerTickEvent::Autosave => (0, world.space.dirty_chunks()),
+						ServerTickEvent::AutosaveContinue { saved_so_far, remaining } => (saved_so_far, remaining),
+					};
+					let this_tick: Vec<ChunkPos> = remaining.drain(..remaining.len().min(AUTOSAVE_CHUNKS_PER_TICK)).collect();
+					let saved_so_far = saved_so_far + world.save_chunks(&this_tick);
+					if !remaining.is_empty() {
+						// Pick this back up next tick.
+						scheduler.schedule(0, ServerTickEvent::AutosaveContinue { saved_so_far, remaining });
+					} else {
+						match world.space.dirty_chunk_count() {
+							0 => info!("Autosave complete, saved {saved_so_far} chunks."),
+							left => info!("Autosave complete, saved {saved_so_far} chunks. {left} chunks are left for the next autosave."),
+						}
+						// Autosave may have been turned off by a config reload since this was scheduled.
+						match autosave_ticks(&config, tick_length) {
+							Some(ticks) => scheduler.schedule(ticks, ServerTickEvent::Autosave),
+							None => autosave_pending = false,
+						}
+					}
+				}
+			}
+			request_maybe = admin_commands.recv_wait(), if admin_console_open => {
+				match request_maybe {
+					Ok(AdminRequest { command, reply }) => {
+						info!("Admin command: {command:?}");
+						let stopping = command == AdminCommand::Stop;
+						let response = handle_admin_command(command, &mut world, &mut config, &connected_peers, &mut joined_users, &kick_sender);
+						if !autosave_pending {
+							if let Some(ticks) = autosave_ticks(&config, tick_length) {
+								scheduler.schedule(ticks, ServerTickEvent::Autosave);
+								autosave_pending = true;
+							}
+						}
+						// The console may have given up waiting, which is fine.
+						let _ = reply.send(response);
+						if stopping {
+							let saved = world.save_dirty(usize::MAX);
+							info!("Saved {saved} chunks before stopping.");
+							break;
+						}
+					}
+					Err(_) => admin_console_open = false,
+				}
+			}
+			quit_ready_indicator = quit_receiver.wait_for_quit() => {
+				let saved = world.save_dirty(usize::MAX);
+				info!("Saved {saved} chunks before shutting down.");
+				quit_ready_indicator.notify_ready();
+				break;
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+	use std::path::PathBuf;
+
+	use tempfile::TempDir;
+	use tokio::task::JoinHandle;
+
+	use super::*;
+	use crate::admin_console::AdminConsole;
+	use crate::world::tilespace::chunk_to_world_pos;
+	use crate::world::worldgen::NoiseWorldGen;
+	use crate::world::TilePos;
+	use crate::message::{MessageReceiverAsync, ReceiverSubscribe, SenderSubscribe};
+	use crate::net::generated::get_netmsg_table;
+	use crate::net::handshake::approver_no_mismatch;
+	use crate::net::preprotocol::{launch_preprotocol_listener, preprotocol_connect_to_server};
+	use crate::net::reliable_udp::LaminarConfig;
+	use crate::net::test::{find_available_udp_port, NET_TEST_MUTEX};
+	use crate::net::{NetworkSystem, SelfNetworkRole, DEFAULT_MAX_MESSAGE_SIZE};
+	use crate::{BuildSubset, ChannelCapacityConf, MpscChannel, SubsetBuilder};
+
+	/// Channel set for one node, with every NetMsg domain initialized the way main() does it.
+	fn new_test_channels() -> EngineNetChannels {
+		let channels = EngineNetChannels::new(&ChannelCapacityConf::new());
+		for net_msg_id in get_netmsg_table().keys() {
+			let _ = channels.net_msg_inbound.init_domain(*net_msg_id);
+		}
+		tokio::spawn(approver_no_mismatch(
+			channels.key_mismatch_reporter.receiver_subscribe(),
+			channels.key_mismatch_approver.sender_subscribe(),
+		));
+		channels
+	}
+
+	/// Starts a client network system, connects it to the server, and waits until its session is ready.
+	async fn connect_test_client(
+		server_keys: &IdentityKeyPair,
+		server_addr: SocketAddr,
+	) -> (IdentityKeyPair, EngineNetChannels, JoinHandle<()>, TempDir) {
+		let keys = IdentityKeyPair::generate_for_tests();
+		let channels = new_test_channels();
+		let protocol_dir = tempfile::tempdir().unwrap();
+
+		let netsys_channels = channels.build_subset(SubsetBuilder::new(())).unwrap();
+		let keys_for_net = keys.clone();
+		let join_handle = tokio::spawn(async move {
+			let mut sys = NetworkSystem::new(
+				SelfNetworkRole::Client,
+				server_addr,
+				keys_for_net,
+				LaminarConfig::default(),
+				DEFAULT_MAX_MESSAGE_SIZE,
+				Duration::from_millis(50),
+				netsys_channels,
+			)
+			.await
+			.unwrap();
+			sys.run().await
+		});
+
+		let mut connected = channels.peer_connected.receiver_subscribe();
+		preprotocol_connect_to_server(
+			keys.clone(),
+			server_addr,
+			Duration::new(5, 0),
+			PathBuf::from(protocol_dir.path()),
+			channels.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		let connected_peer = tokio::time::timeout(Duration::from_secs(5), connected.recv_wait())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(connected_peer.peer_identity, server_keys.public);
+
+		(keys, channels, join_handle, protocol_dir)
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn voxel_change_relayed_between_clients() {
+		let mutex_guard = NET_TEST_MUTEX.lock().await;
+
+		let server_keys = IdentityKeyPair::generate_for_tests();
+		let server_channels = new_test_channels();
+		let server_protocol_dir = tempfile::tempdir().unwrap();
+		let port = find_available_udp_port(54534..54934).await.unwrap_or(8081);
+		let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+
+		let mut server_sys = NetworkSystem::new(
+			SelfNetworkRole::Server,
+			server_addr,
+			server_keys,
+			LaminarConfig::default(),
+			DEFAULT_MAX_MESSAGE_SIZE,
+			Duration::from_millis(50),
+			server_channels.build_subset(SubsetBuilder::new(())).unwrap(),
+		)
+		.await
+		.unwrap();
+		let server_peers = server_sys.connected_peers_handle();
+		let server_net = tokio::spawn(async move { server_sys.run().await });
+		let server_listener = tokio::spawn(launch_preprotocol_listener(
+			server_keys.clone(),
+			Some(server_addr),
+			port,
+			PathBuf::from(server_protocol_dir.path()),
+			server_channels.build_subset(SubsetBuilder::new(())).unwrap(),
+		));
+		let world_dir = tempfile::tempdir().unwrap();
+		let world = HostedWorld::new(
+			WorldId {
+				uuid: uuid::Uuid::new_v4(),
+				host: server_keys.public,
+			},
+			PathBuf::from(world_dir.path()),
+		);
+		let worldgen = NoiseWorldGen::for_world(&world.world_id);
+		let config = ServerConfig {
+			autosave_interval_secs: 0,
+			..Default::default()
+		};
+		let admin_commands: MpscChannel<AdminRequest> = MpscChannel::new(4);
+		let server_mainloop = tokio::spawn(run_server_mainloop(
+			server_channels.clone(),
+			server_peers,
+			world,
+			WorldGenQueue::new(worldgen.clone(), tokio::runtime::Handle::current()),
+			config,
+			admin_commands.take_receiver().unwrap(),
+		));
+		let mut server_connections = server_channels.peer_connected.receiver_subscribe();
+
+		let (editor_keys, editor_channels, editor_net, _editor_dir) =
+			connect_test_client(&server_keys, server_addr).await;
+		let (observer_keys, observer_channels, observer_net, _observer_dir) =
+			connect_test_client(&server_keys, server_addr).await;
+
+		// The server only relays to peers it has a session with, so wait until both are set up on its end.
+		for _ in 0..2 {
+			tokio::time::timeout(Duration::from_secs(5), server_connections.recv_wait())
+				.await
+				.unwrap()
+				.unwrap();
+		}
+
+		let mut observer_joins =
+			observer_channels.net_msg_inbound.receiver_typed::<JoinAnnounce>().unwrap();
+		let mut observer_voxels =
+			observer_channels.net_msg_inbound.receiver_typed::<VoxelChangeAnnounce>().unwrap();
+		let editor_to_server = editor_channels
+			.net_msg_outbound
+			.sender_subscribe_domain(&server_keys.public)
+			.unwrap();
+
+		editor_to_server
+			.send(
+				JoinDefaultEntry {
+					display_name: "editor".to_string(),
+				}
+				.construct_packet()
+				.unwrap(),
+			)
+			.unwrap();
+		{
+			let joins = tokio::time::timeout(Duration::from_secs(5), observer_joins.recv_wait())
+				.await
+				.unwrap()
+				.unwrap();
+			let (from, join) = joins.first().unwrap();
+			assert_eq!(from, &server_keys.public);
+			assert_eq!(join.identity, editor_keys.public);
+			assert_eq!(join.display_name, "editor");
+		}
+		// Lists everyone with a session, whether or not they've joined.
+		let console = AdminConsole::new(admin_commands.sender_subscribe());
+		let listed = console.submit("list").await;
+		assert!(listed.starts_with("2 users are connected:"), "{listed}");
+		assert!(listed.contains(&format!("editor ({})", editor_keys.public.to_base64())), "{listed}");
+		assert!(listed.contains(&format!("(not joined yet) ({})", observer_keys.public.to_base64())), "{listed}");
+
+		let change = VoxelChangeRequest {
+			pos: vpos!(1, -2, 3),
+			new_tile: 7,
+		};
+		editor_to_server.send(change.construct_packet().unwrap()).unwrap();
+		{
+			let announces =
+				tokio::time::timeout(Duration::from_secs(5), observer_voxels.recv_wait())
+					.await
+					.unwrap()
+					.unwrap();
+			let (from, announce) = announces.first().unwrap();
+			assert_eq!(from, &server_keys.public);
+			assert_eq!(announce.pos, change.pos);
+			assert_eq!(announce.new_tile, change.new_tile);
+		}
+
+		// Nothing has been saved, so this chunk has to be generated before the server can answer.
+		let mut editor_chunks = editor_channels.net_msg_inbound.receiver_typed::<ChunkData>().unwrap();
+		let requested: ChunkPos = vpos!(0, 0, 0);
+		editor_to_server
+			.send(ChunkDataRequest { chunk: requested }.construct_packet().unwrap())
+			.unwrap();
+		{
+			let chunks = tokio::time::timeout(Duration::from_secs(5), editor_chunks.recv_wait())
+				.await
+				.unwrap()
+				.unwrap();
+			let (from, data) = chunks.first().unwrap();
+			assert_eq!(from, &server_keys.public);
+			assert_eq!(data.chunk, requested);
+			assert_eq!(data.decode().unwrap().to_rle().unwrap(), worldgen.generate_at(requested).to_rle().unwrap());
+		}
+
+		// Stopping through the admin console only ends this server's main loop, rather than every engine in the process.
+		assert_eq!(console.submit("stop").await, "Stopping the server.");
+		tokio::time::timeout(Duration::from_secs(5), server_mainloop).await.unwrap().unwrap();
+
+		for join_handle in [server_net, editor_net, observer_net] {
+			join_handle.abort();
+			let _ = join_handle.await;
+		}
+		server_listener.abort();
+		let _ = server_listener.await;
+
+		drop(mutex_guard);
+	}
+
+	#[test]
+	fn interest_map_filters_by_view_distance() {
+		let near = IdentityKeyPair::generate_for_tests().public;
+		let far = IdentityKeyPair::generate_for_tests().public;
+		let unreported = IdentityKeyPair::generate_for_tests().public;
+
+		let mut interest = InterestMap::new();
+		interest.update(near, PeerInterest { center: vpos!(0, 0, 0), view_distance: 2 });
+		interest.update(far, PeerInterest { center: vpos!(10, 0, 0), view_distance: 2 });
+
+		let origin_change: TilePos = vpos!(1, 1, 1);
+		// Two chunks out along x from the origin chunk, the edge of near's view.
+		let edge_change = TilePos::from_chunk_and_local(vpos!(2, 0, -2), vpos!(0, 0, 0));
+		let past_edge_change = TilePos::from_chunk_and_local(vpos!(3, 0, 0), vpos!(0, 0, 0));
+
+		assert!(interest.is_interested(&near, &origin_change));
+		assert!(interest.is_interested(&near, &edge_change));
+		assert!(!interest.is_interested(&near, &past_edge_change));
+		assert!(!interest.is_interested(&far, &origin_change));
+		assert!(interest.is_interested(&unreported, &past_edge_change));
+
+		interest.remove(&near);
+		assert!(interest.is_interested(&near, &past_edge_change));
+	}
+
+	#[test]
+	fn edits_coalesce_into_one_delta_per_chunk() {
+		use crate::world::chunk::Chunk;
+
+		let world_dir = tempfile::tempdir().unwrap();
+		let world_id = WorldId {
+			uuid: uuid::Uuid::new_v4(),
+			host: IdentityKeyPair::generate_for_tests().public,
+		};
+		let mut world = HostedWorld::new(world_id, PathBuf::from(world_dir.path()));
+		world.space.ingest_loaded_chunk(vpos!(0, 0, 0), Chunk::new(0)).unwrap();
+
+		let mut batch = ChunkDeltaBatch::default();
+		for (pos, tile) in [(vpos!(1, 1, 1), 4), (vpos!(2, 2, 2), 5), (vpos!(1, 1, 1), 6)] {
+			let (before, after) = world.apply_edit(pos, tile).unwrap();
+			batch.record(pos, tile, before, after);
+		}
+		// Not hosted, so it can't be part of a delta.
+		assert_eq!(world.apply_edit(vpos!(-100, 0, 0), 4), None);
+
+		let deltas: Vec<ChunkDelta> = batch.into_deltas().collect();
+		assert_eq!(deltas.len(), 1);
+		let delta = &deltas[0];
+		assert_eq!(delta.edits.len(), 3);
+		assert_eq!(delta.base_revision, 0);
+		assert_eq!(delta.revision, world.space.borrow_chunk(&vpos!(0, 0, 0)).unwrap().revision);
+
+		let mut client_chunk: Chunk<TileId> = Chunk::new(0);
+		delta.apply_to(&mut client_chunk).unwrap();
+		assert_eq!(*client_chunk.get(vpos!(1, 1, 1)).unwrap(), 6);
+		assert_eq!(*client_chunk.get(vpos!(2, 2, 2)).unwrap(), 5);
+	}
+
+	#[test]
+	fn hosted_world_saves_and_reloads_dirty_chunks() {
+		use crate::world::chunk::Chunk;
+
+		let world_dir = tempfile::tempdir().unwrap();
+		let world_id = WorldId {
+			uuid: uuid::Uuid::new_v4(),
+			host: IdentityKeyPair::generate_for_tests().public,
+		};
+		let chunk_positions: [ChunkPos; 3] = [vpos!(0, 0, 0), vpos!(1, 0, 0), vpos!(0, -1, 2)];
+
+		let mut world = HostedWorld::new(world_id.clone(), PathBuf::from(world_dir.path()));
+		for chunk_pos in chunk_positions.iter() {
+			world.space.ingest_loaded_chunk(*chunk_pos, Chunk::new(0)).unwrap();
+			let tile_pos = TilePos::from_chunk_and_local(*chunk_pos, vpos!(3, 4, 5));
+			world.space.set(tile_pos, 12).unwrap();
+		}
+		assert_eq!(world.space.dirty_chunk_count(), 3);
+		// Partial saves pick up where the last one left off.
+		assert_eq!(world.save_dirty(2), 2);
+		assert_eq!(world.save_dirty(AUTOSAVE_CHUNKS_PER_TICK), 1);
+		assert_eq!(world.save_dirty(AUTOSAVE_CHUNKS_PER_TICK), 0);
+
+		let mut reloaded = HostedWorld::new(world_id, PathBuf::from(world_dir.path()));
+		for chunk_pos in chunk_positions.iter() {
+			assert!(reloaded.ensure_chunk_loaded(chunk_pos));
+			let tile_pos = TilePos::from_chunk_and_local(*chunk_pos, vpos!(3, 4, 5));
+			assert_eq!(*reloaded.space.get(tile_pos).unwrap(), 12);
+			assert_eq!(*reloaded.space.get(chunk_to_world_pos(chunk_pos)).unwrap(), 0);
+		}
+		// Never-saved chunks stay unloaded rather than getting made up.
+		assert!(!reloaded.ensure_chunk_loaded(&vpos!(5, 5, 5)));
+		assert_eq!(reloaded.space.dirty_chunk_count(), 0);
+	}
+
+	#[test]
+	fn save_chunks_skips_clean_chunks_and_keeps_failures_dirty() {
+		use crate::world::chunk::Chunk;
+
+		let world_dir = tempfile::tempdir().unwrap();
+		let world_id = WorldId {
+			uuid: uuid::Uuid::new_v4(),
+			host: IdentityKeyPair::generate_for_tests().public,
+		};
+		let mut world = HostedWorld::new(world_id, PathBuf::from(world_dir.path()));
+		for chunk_pos in [vpos!(0, 0, 0), vpos!(1, 0, 0), vpos!(2, 0, 0)] {
+			world.space.ingest_loaded_chunk(chunk_pos, Chunk::new(0)).unwrap();
+			world.space.set(chunk_to_world_pos(&chunk_pos), 3).unwrap();
+		}
+		// Saved since, so it should be skipped.
+		assert_eq!(world.save_dirty(1), 1);
+		assert_eq!(world.save_chunks(&[vpos!(0, 0, 0), vpos!(1, 0, 0)]), 1);
+		assert_eq!(world.space.dirty_chunks(), vec![vpos!(2, 0, 0)]);
+
+		// A directory where the chunk's in-progress file should go makes its save fail.
+		let chunk_path = fsworldstorage::path_for_chunk(&world.base_dir, &world.world_id, StoredWorldRole::Local, &vpos!(2, 0, 0));
+		std::fs::create_dir_all(chunk_path.with_extension("chunk.lock")).unwrap();
+		assert_eq!(world.save_chunks(&[vpos!(2, 0, 0)]), 0);
+		assert_eq!(world.space.dirty_chunks(), vec![vpos!(2, 0, 0)]);
+	}
+}
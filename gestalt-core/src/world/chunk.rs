@@ -1,3 +1,4 @@
+use std::io::{Read, Write};
 use std::ops::Range;
 
 use semver::Version;
@@ -6,12 +7,12 @@ use serde::{Deserialize, Serialize};
 use crate::common::{new_fast_hash_map, voxelmath::*, FastHashMap};
 
 use super::{
-	voxelarray::{VoxelArrayError, VoxelArrayStatic},
+	voxelarray::{chunk_i_to_xyz, chunk_xyz_to_i, VoxelArrayError, VoxelArrayStatic},
 	voxelstorage::Voxel,
 	TileId, VoxelStorage, VoxelStorageBounded,
 };
 
-pub const NEWEST_CHUNK_FILE_VERSION: Version = Version::new(0, 0, 1);
+pub const NEWEST_CHUNK_FILE_VERSION: Version = Version::new(0, 0, 2);
 
 pub const CHUNK_EXP: usize = 5;
 pub const CHUNK_SIZE: usize = 2_usize.pow(CHUNK_EXP as u32);
@@ -83,6 +84,8 @@ pub enum ChunkValidationError {
 pub const SMALL_PALETTE_ENTRY_LEN: usize = 5;
 // Mapping a u16 to a u32 so it'll be 6 bytes.
 pub const LARGE_PALETTE_ENTRY_LEN: usize = 6;
+// A TileId and a u16 run length, so also 6 bytes. CHUNK_SIZE_CUBED has to fit in a u16 for this to work.
+pub const RLE_RUN_LEN: usize = 6;
 
 // Now we start getting into things that are not just for validating and parsing things,
 // but which will be more generally useful later.
@@ -94,11 +97,14 @@ pub const LARGE_PALETTE_ENTRY_LEN: usize = 6;
 /// * Large chunks have more than 256 unique tile IDs in them, and so it has to be
 /// CHUNK_SIZE_CUBED u16s. To be more specific, a sort of unsigned 16-bit integer
 /// that is always little-endian on disk and in memory.
+/// * Rle chunks only exist on disk - they're (TileId, run length) pairs over chunk index order,
+/// which is much smaller than Small for chunks that are nearly all one tile.
 #[derive(Debug, Copy, Clone)]
 pub enum ChunkTilesVariant {
 	Uniform,
 	Small,
 	Large,
+	Rle,
 }
 impl ChunkTilesVariant {
 	pub fn as_upper_metadata_byte(&self) -> u8 {
@@ -106,6 +112,7 @@ impl ChunkTilesVariant {
 			Self::Uniform => 0,
 			Self::Small => 1,
 			Self::Large => 2,
+			Self::Rle => 3,
 		}
 	}
 }
@@ -117,6 +124,7 @@ pub fn chunk_variant_from_metadata(
 		0 => Ok(ChunkTilesVariant::Uniform),
 		1 => Ok(ChunkTilesVariant::Small),
 		2 => Ok(ChunkTilesVariant::Large),
+		3 => Ok(ChunkTilesVariant::Rle),
 		_ => Err(ChunkValidationError::InvalidTilesVariant(upper_byte)),
 	}
 }
@@ -128,6 +136,9 @@ pub fn voxel_data_expected_size(
 		ChunkTilesVariant::Uniform => ExpectedSublayerLength::Exact(0),
 		ChunkTilesVariant::Small => ExpectedSublayerLength::Exact(CHUNK_SIZE_CUBED),
 		ChunkTilesVariant::Large => ExpectedSublayerLength::Exact(CHUNK_SIZE_CUBED * 2),
+		ChunkTilesVariant::Rle => ExpectedSublayerLength::Range(
+			(RLE_RUN_LEN)..(CHUNK_SIZE_CUBED * RLE_RUN_LEN + 1),
+		),
 	})
 }
 
@@ -136,7 +147,8 @@ pub fn voxel_palette_expected_size(
 ) -> Result<ExpectedSublayerLength, ChunkValidationError> {
 	Ok(match chunk_variant_from_metadata(metadata[0])? {
 		// Not here in a Uniform chunk - the one individual TileID lives in metadata instead.
-		ChunkTilesVariant::Uniform => ExpectedSublayerLength::Exact(0),
+		// Runs carry their own TileIds, so there's no palette for those either.
+		ChunkTilesVariant::Uniform | ChunkTilesVariant::Rle => ExpectedSublayerLength::Exact(0),
 		ChunkTilesVariant::Small => ExpectedSublayerLength::Range(
 			(SMALL_PALETTE_ENTRY_LEN)..(256 * SMALL_PALETTE_ENTRY_LEN),
 		),
@@ -265,6 +277,9 @@ pub struct ChunkTilesSmall<T: Voxel> {
 	pub highest_idx: u8,
 	// Used by the serializer to tell if the palette has changed.
 	pub palette_dirty: bool,
+	/// How many tiles in this chunk currently use each palette index. Kept up to date by set_raw(),
+	/// so that we can tell when a chunk has gone back to being all one tile.
+	pub counts: [u16; 256],
 }
 
 impl<T: Voxel> ChunkTilesSmall<T> {
@@ -286,8 +301,28 @@ impl<T: Voxel> ChunkTilesSmall<T> {
 	}
 	#[inline(always)]
 	pub fn set_raw(&mut self, coord: VoxelPos<u8>, value: u8) {
+		let previous = *self.inner.get_raw(coord);
+		if previous != value {
+			self.counts[previous as usize] -= 1;
+			self.counts[value as usize] += 1;
+		}
 		self.inner.set_raw(coord, value);
 	}
+	/// Sets len tiles starting at chunk index start to value, keeping counts up to date.
+	/// Returns how many of them were something else before.
+	#[inline]
+	pub fn fill_span_raw(&mut self, start: usize, len: usize, value: u8) -> usize {
+		let mut changed = 0;
+		for raw in self.inner.data[start..start + len].iter_mut() {
+			if *raw != value {
+				self.counts[*raw as usize] -= 1;
+				*raw = value;
+				changed += 1;
+			}
+		}
+		self.counts[value as usize] += changed as u16;
+		changed
+	}
 	#[inline(always)]
 	pub fn index_from_palette(&self, tile: T) -> Option<u8> {
 		self.reverse_palette.get(&tile).copied()
@@ -325,6 +360,7 @@ impl<T: Voxel> ChunkTilesSmall<T> {
 			palette: new_palette,
 			reverse_palette: new_reverse_palette,
 			palette_dirty: true,
+			counts: self.counts.to_vec(),
 		}
 	}
 	/// Adds a Tile ID to its palette. If we succeeded in adding it, return the associated index.
@@ -361,6 +397,8 @@ pub struct ChunkTilesLarge<T: Voxel> {
 	pub palette: Vec<T>,
 	pub reverse_palette: FastHashMap<T, AlwaysLeU16>,
 	pub palette_dirty: bool,
+	/// How many tiles in this chunk currently use each palette index, see ChunkTilesSmall::counts
+	pub counts: Vec<u16>,
 }
 
 impl<T: Voxel> ChunkTilesLarge<T> {
@@ -381,8 +419,27 @@ impl<T: Voxel> ChunkTilesLarge<T> {
 	}
 	#[inline(always)]
 	pub fn set_raw(&mut self, coord: VoxelPos<u8>, value: AlwaysLeU16) {
+		let previous = *self.inner.get_raw(coord);
+		if previous != value {
+			self.counts[previous.as_usize()] -= 1;
+			self.counts[value.as_usize()] += 1;
+		}
 		self.inner.set_raw(coord, value);
 	}
+	/// See ChunkTilesSmall::fill_span_raw()
+	#[inline]
+	pub fn fill_span_raw(&mut self, start: usize, len: usize, value: AlwaysLeU16) -> usize {
+		let mut changed = 0;
+		for raw in self.inner.data[start..start + len].iter_mut() {
+			if *raw != value {
+				self.counts[raw.as_usize()] -= 1;
+				*raw = value;
+				changed += 1;
+			}
+		}
+		self.counts[value.as_usize()] += changed as u16;
+		changed
+	}
 	#[inline(always)]
 	pub fn index_from_palette(&self, tile: T) -> Option<u16> {
 		self.reverse_palette.get(&tile).map(|v| v.get())
@@ -404,6 +461,7 @@ impl<T: Voxel> ChunkTilesLarge<T> {
 				self.palette_dirty = true;
 				let next_index = AlwaysLeU16::new(self.palette.len() as u16);
 				self.palette.push(tile.clone());
+				self.counts.push(0);
 				self.reverse_palette.insert(tile, next_index);
 
 				next_index
@@ -412,6 +470,9 @@ impl<T: Voxel> ChunkTilesLarge<T> {
 	}
 }
 
+/// Chunks move between these automatically as tiles get set: a Uniform chunk becomes Small once a second tile
+/// is set in it, a Small chunk becomes Large once its palette runs out of room, and either one goes back to
+/// Uniform as soon as every tile in it is the same again.
 pub enum ChunkInner<T: Voxel> {
 	///Chunk that is all one value (usually this is for chunks that are 100% air). Note that, after being converted, idx 0 maps to
 	Uniform(T),
@@ -509,6 +570,65 @@ impl<T: Voxel> Chunk<T> {
 			ChunkInner::Large(ref mut inner) => inner.palette_dirty = set_to,
 		}
 	}
+	/// How many tiles in this chunk are the tile at palette index idx.
+	#[inline(always)]
+	pub fn tile_count(&self, idx: u16) -> usize {
+		match &self.tiles {
+			ChunkInner::Uniform(_) => {
+				if idx == 0 {
+					CHUNK_SIZE_CUBED
+				} else {
+					0
+				}
+			}
+			ChunkInner::Small(inner) => inner.counts.get(idx as usize).copied().unwrap_or(0) as usize,
+			ChunkInner::Large(inner) => inner.counts.get(idx as usize).copied().unwrap_or(0) as usize,
+		}
+	}
+	/// Sets every tile in range to tile. Much cheaper than calling set() on each of them, since
+	/// it writes straight into the backing array a column at a time - and if range is the whole
+	/// chunk, the chunk just becomes Uniform. Revision goes up by one per tile that changed, same as set().
+	pub fn set_region(&mut self, range: VoxelRange<u8>, tile: T) -> Result<(), VoxelArrayError<u8>> {
+		let range = range.get_validated();
+		let size = CHUNK_SIZE as u8;
+		if range.upper.x > size || range.upper.y > size || range.upper.z > size {
+			return Err(VoxelArrayError::OutOfBounds(range.upper));
+		}
+		let span = (range.upper.y - range.lower.y) as usize;
+		if span == 0 || range.lower.x == range.upper.x || range.lower.z == range.upper.z {
+			return Ok(());
+		}
+
+		if range == self.get_bounds() {
+			let already = match self.index_from_palette(tile.clone()) {
+				Some(idx) => self.tile_count(idx),
+				None => 0,
+			};
+			self.revision += (CHUNK_SIZE_CUBED - already) as u64;
+			self.tiles = ChunkInner::Uniform(tile);
+			return Ok(());
+		}
+
+		let idx = self.add_to_palette(tile.clone());
+		let mut changed = 0;
+		// Y is the contiguous axis in chunk index order, so each (x, z) is one run of indices.
+		for z in range.lower.z..range.upper.z {
+			for x in range.lower.x..range.upper.x {
+				let start = chunk_xyz_to_i(x as usize, range.lower.y as usize, z as usize, CHUNK_SIZE);
+				changed += match &mut self.tiles {
+					// A Uniform chunk is only still Uniform here if tile is what it's already made of.
+					ChunkInner::Uniform(_) => 0,
+					ChunkInner::Small(inner) => inner.fill_span_raw(start, span, idx.get() as u8),
+					ChunkInner::Large(inner) => inner.fill_span_raw(start, span, idx),
+				};
+			}
+		}
+		self.revision += changed as u64;
+		if !matches!(self.tiles, ChunkInner::Uniform(_)) && self.tile_count(idx.get()) == CHUNK_SIZE_CUBED {
+			self.tiles = ChunkInner::Uniform(tile);
+		}
+		Ok(())
+	}
 	#[inline]
 	pub fn add_to_palette(&mut self, tile: T) -> AlwaysLeU16 {
 		match &mut self.tiles {
@@ -530,12 +650,15 @@ impl<T: Voxel> Chunk<T> {
 					let mut reverse_palette: FastHashMap<T, u8> = new_fast_hash_map();
 					reverse_palette.insert(val.clone(), 0);
 					reverse_palette.insert(tile, 1);
+					let mut counts = [0u16; 256];
+					counts[0] = CHUNK_SIZE_CUBED as u16;
 					self.tiles = ChunkInner::Small(Box::new(ChunkTilesSmall {
 						inner: structure,
 						palette,
 						reverse_palette,
 						highest_idx: 1,
 						palette_dirty: false,
+						counts,
 					}));
 					AlwaysLeU16::new(1)
 				}
@@ -557,6 +680,231 @@ impl<T: Voxel> Chunk<T> {
 	}
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum ChunkIoError {
+	#[error("I/O error while reading or writing a chunk: {0:?}")]
+	Io(#[from] std::io::Error),
+	#[error("Chunk file is version {0}, which is newer than the newest version we can read ({1}).")]
+	UnsupportedVersion(Version, Version),
+	#[error("Invalid chunk file: {0}")]
+	Validation(#[from] ChunkValidationError),
+	#[error("Chunk file refers to palette index {0}, but its palette only has {1} entries.")]
+	PaletteIndexOutOfRange(usize, usize),
+	#[error("Chunk data error: {0:?}")]
+	ChunkData(#[from] VoxelArrayError<u8>),
+}
+
+fn read_u64_le<R: Read>(reader: &mut R) -> Result<u64, std::io::Error> {
+	let mut buf = [0u8; 8];
+	reader.read_exact(&mut buf)?;
+	Ok(u64::from_le_bytes(buf))
+}
+fn read_u32_le<R: Read>(reader: &mut R) -> Result<u32, std::io::Error> {
+	let mut buf = [0u8; 4];
+	reader.read_exact(&mut buf)?;
+	Ok(u32::from_le_bytes(buf))
+}
+
+impl Chunk<TileId> {
+	/// Writes this chunk out in the chunk file format:
+	/// * File version - major, minor, patch, each as a little-endian u64
+	/// * Revision - little-endian u64
+	/// * Tiles variant - one byte, see ChunkTilesVariant::as_upper_metadata_byte()
+	/// * Palette length - little-endian u32, then that many little-endian TileIds
+	/// * Voxel data - one palette index per tile in chunk index order, as a u8 for Small chunks
+	/// or a little-endian u16 for Large chunks. Uniform chunks have no voxel data, and exactly one palette entry.
+	///
+	/// If it's smaller, the palette and voxel data are replaced with the Rle variant: a little-endian u32
+	/// run count, then that many runs of (little-endian TileId, little-endian u16 run length). See to_rle().
+	pub fn write_chunk<W: Write>(&self, writer: &mut W) -> Result<(), ChunkIoError> {
+		writer.write_all(&NEWEST_CHUNK_FILE_VERSION.major.to_le_bytes())?;
+		writer.write_all(&NEWEST_CHUNK_FILE_VERSION.minor.to_le_bytes())?;
+		writer.write_all(&NEWEST_CHUNK_FILE_VERSION.patch.to_le_bytes())?;
+		writer.write_all(&self.revision.to_le_bytes())?;
+
+		if let ChunkInner::Uniform(tile) = &self.tiles {
+			writer.write_all(&[ChunkTilesVariant::Uniform.as_upper_metadata_byte()])?;
+			writer.write_all(&1u32.to_le_bytes())?;
+			writer.write_all(&tile.to_le_bytes())?;
+			return Ok(());
+		}
+
+		// Build a compact palette of only the tiles which are actually still present,
+		// so stale entries from tiles that got overwritten don't end up on disk.
+		let mut palette: Vec<TileId> = Vec::new();
+		let mut reverse_palette: FastHashMap<TileId, u16> = new_fast_hash_map();
+		let mut indices: Vec<u16> = Vec::with_capacity(CHUNK_SIZE_CUBED);
+		for i in 0..CHUNK_SIZE_CUBED {
+			let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+			let tile = *self.get(vpos!(x as u8, y as u8, z as u8))?;
+			let idx = *reverse_palette.entry(tile).or_insert_with(|| {
+				palette.push(tile);
+				(palette.len() - 1) as u16
+			});
+			indices.push(idx);
+		}
+
+		let variant = if palette.len() <= 256 {
+			ChunkTilesVariant::Small
+		} else {
+			ChunkTilesVariant::Large
+		};
+
+		let runs = self.to_rle()?;
+		let index_width = match variant {
+			ChunkTilesVariant::Small => 1,
+			_ => 2,
+		};
+		let raw_len = palette.len() * std::mem::size_of::<TileId>() + CHUNK_SIZE_CUBED * index_width;
+		if runs.len() * RLE_RUN_LEN < raw_len {
+			writer.write_all(&[ChunkTilesVariant::Rle.as_upper_metadata_byte()])?;
+			writer.write_all(&(runs.len() as u32).to_le_bytes())?;
+			for (tile, run_length) in runs.iter() {
+				writer.write_all(&tile.to_le_bytes())?;
+				writer.write_all(&run_length.to_le_bytes())?;
+			}
+			return Ok(());
+		}
+
+		writer.write_all(&[variant.as_upper_metadata_byte()])?;
+		writer.write_all(&(palette.len() as u32).to_le_bytes())?;
+		for tile in palette.iter() {
+			writer.write_all(&tile.to_le_bytes())?;
+		}
+		match variant {
+			ChunkTilesVariant::Small => {
+				let bytes: Vec<u8> = indices.iter().map(|idx| *idx as u8).collect();
+				writer.write_all(&bytes)?;
+			}
+			_ => {
+				let bytes: Vec<u8> = indices.iter().flat_map(|idx| idx.to_le_bytes()).collect();
+				writer.write_all(&bytes)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Reads a chunk written by write_chunk().
+	pub fn read_chunk<R: Read>(reader: &mut R) -> Result<Self, ChunkIoError> {
+		let version = Version::new(read_u64_le(reader)?, read_u64_le(reader)?, read_u64_le(reader)?);
+		if version > NEWEST_CHUNK_FILE_VERSION {
+			return Err(ChunkIoError::UnsupportedVersion(version, NEWEST_CHUNK_FILE_VERSION));
+		}
+		let revision = read_u64_le(reader)?;
+		let mut variant_byte = [0u8; 1];
+		reader.read_exact(&mut variant_byte)?;
+		let variant = chunk_variant_from_metadata(variant_byte[0])?;
+
+		if let ChunkTilesVariant::Rle = variant {
+			let run_count = read_u32_le(reader)? as usize;
+			if run_count == 0 || run_count > CHUNK_SIZE_CUBED {
+				return Err(ChunkValidationError::InvalidSizeSublayer(
+					String::from("voxel_runs"),
+					run_count,
+					ExpectedSublayerLength::Range(1..(CHUNK_SIZE_CUBED + 1)),
+				)
+				.into());
+			}
+			let mut runs: Vec<(TileId, u16)> = Vec::with_capacity(run_count);
+			for _ in 0..run_count {
+				let tile = read_u32_le(reader)?;
+				let mut run_length = [0u8; 2];
+				reader.read_exact(&mut run_length)?;
+				runs.push((tile, u16::from_le_bytes(run_length)));
+			}
+			let mut chunk = Self::from_rle(&runs)?;
+			chunk.revision = revision;
+			return Ok(chunk);
+		}
+
+		let palette_len = read_u32_le(reader)? as usize;
+		let palette_max = match variant {
+			ChunkTilesVariant::Uniform => 1,
+			ChunkTilesVariant::Small => 256,
+			ChunkTilesVariant::Large | ChunkTilesVariant::Rle => CHUNK_SIZE_CUBED,
+		};
+		if palette_len == 0 || palette_len > palette_max {
+			return Err(ChunkValidationError::InvalidSizeSublayer(
+				String::from("voxel_palette"),
+				palette_len,
+				ExpectedSublayerLength::Range(1..(palette_max + 1)),
+			)
+			.into());
+		}
+		let mut palette: Vec<TileId> = Vec::with_capacity(palette_len);
+		for _ in 0..palette_len {
+			palette.push(read_u32_le(reader)?);
+		}
+
+		let mut chunk = Chunk::new(palette[0]);
+		let index_width = match variant {
+			ChunkTilesVariant::Uniform | ChunkTilesVariant::Rle => 0,
+			ChunkTilesVariant::Small => 1,
+			ChunkTilesVariant::Large => 2,
+		};
+		if index_width > 0 {
+			let mut data = vec![0u8; CHUNK_SIZE_CUBED * index_width];
+			reader.read_exact(&mut data)?;
+			for i in 0..CHUNK_SIZE_CUBED {
+				let idx = match index_width {
+					1 => data[i] as usize,
+					_ => u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]) as usize,
+				};
+				let tile = *palette
+					.get(idx)
+					.ok_or(ChunkIoError::PaletteIndexOutOfRange(idx, palette_len))?;
+				let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+				chunk.set(vpos!(x as u8, y as u8, z as u8), tile)?;
+			}
+		}
+		// Setting every tile above bumped this, put back the real one.
+		chunk.revision = revision;
+		Ok(chunk)
+	}
+
+	/// Run-length encodes this chunk's tiles as (tile, run length) pairs, in chunk index order.
+	/// Adjacent runs never have the same tile, and the run lengths always add up to CHUNK_SIZE_CUBED.
+	pub fn to_rle(&self) -> Result<Vec<(TileId, u16)>, ChunkIoError> {
+		if let ChunkInner::Uniform(tile) = &self.tiles {
+			return Ok(vec![(*tile, CHUNK_SIZE_CUBED as u16)]);
+		}
+		let mut runs: Vec<(TileId, u16)> = Vec::new();
+		for i in 0..CHUNK_SIZE_CUBED {
+			let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+			let tile = *self.get(vpos!(x as u8, y as u8, z as u8))?;
+			match runs.last_mut() {
+				Some((last_tile, run_length)) if *last_tile == tile => *run_length += 1,
+				_ => runs.push((tile, 1)),
+			}
+		}
+		Ok(runs)
+	}
+
+	/// Builds a chunk back up from the output of to_rle(). Revision starts at 0.
+	pub fn from_rle(runs: &[(TileId, u16)]) -> Result<Self, ChunkIoError> {
+		let total: usize = runs.iter().map(|(_, run_length)| *run_length as usize).sum();
+		if total != CHUNK_SIZE_CUBED || runs.iter().any(|(_, run_length)| *run_length == 0) {
+			return Err(ChunkValidationError::InvalidSizeSublayer(
+				String::from("voxel_runs"),
+				total,
+				ExpectedSublayerLength::Exact(CHUNK_SIZE_CUBED),
+			)
+			.into());
+		}
+		let mut chunk = Chunk::new(runs[0].0);
+		let mut i = 0;
+		for (tile, run_length) in runs.iter() {
+			for _ in 0..*run_length {
+				let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+				chunk.set(vpos!(x as u8, y as u8, z as u8), *tile)?;
+				i += 1;
+			}
+		}
+		chunk.revision = 0;
+		Ok(chunk)
+	}
+}
+
 impl<T: Voxel> VoxelStorage<T, u8> for Chunk<T> {
 	type Error = VoxelArrayError<u8>;
 	#[inline(always)]
@@ -576,6 +924,10 @@ impl<T: Voxel> VoxelStorage<T, u8> for Chunk<T> {
 			self.revision += 1;
 		}
 		self.set_raw(pos, idx);
+		// If that was the last tile which differed from the rest, go back to being a Uniform chunk.
+		if !matches!(self.tiles, ChunkInner::Uniform(_)) && self.tile_count(idx.get()) == CHUNK_SIZE_CUBED {
+			self.tiles = ChunkInner::Uniform(tile);
+		}
 
 		Ok(())
 	}
@@ -825,3 +1177,196 @@ fn always_le_u16_expectation() {
 		std::mem::size_of::<[AlwaysLeU16; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE]>(),
 	)
 }
+
+#[test]
+fn chunk_file_round_trip() {
+	use rand::Rng;
+
+	fn round_trip(chunk: &Chunk<TileId>) -> (Chunk<TileId>, usize) {
+		let mut buf: Vec<u8> = Vec::new();
+		chunk.write_chunk(&mut buf).unwrap();
+		let len = buf.len();
+		(Chunk::read_chunk(&mut buf.as_slice()).unwrap(), len)
+	}
+	fn assert_same(a: &Chunk<TileId>, b: &Chunk<TileId>) {
+		assert_eq!(a.revision, b.revision);
+		for pos in CHUNK_RANGE_USIZE {
+			let pos = vpos!(pos.x as u8, pos.y as u8, pos.z as u8);
+			assert_eq!(a.get(pos).unwrap(), b.get(pos).unwrap());
+		}
+	}
+
+	let uniform: Chunk<TileId> = Chunk::new(7);
+	let (loaded, uniform_len) = round_trip(&uniform);
+	assert!(matches!(loaded.tiles, ChunkInner::Uniform(7)));
+	assert_same(&uniform, &loaded);
+
+	let mut rng = rand::thread_rng();
+	let mut small: Chunk<TileId> = Chunk::new(0);
+	for _ in 0..2048 {
+		let pos = vpos!(
+			rng.gen_range(0..CHUNK_SIZE as u8),
+			rng.gen_range(0..CHUNK_SIZE as u8),
+			rng.gen_range(0..CHUNK_SIZE as u8)
+		);
+		small.set(pos, rng.gen_range(1..16)).unwrap();
+	}
+	let (loaded, small_len) = round_trip(&small);
+	assert_same(&small, &loaded);
+
+	// Every tile differs from its neighbors, so this can't be run-length encoded and has to be written out raw.
+	let mut large: Chunk<TileId> = Chunk::new(0);
+	for i in 0..CHUNK_SIZE_CUBED {
+		let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+		large.set(vpos!(x as u8, y as u8, z as u8), (i % 1024) as TileId).unwrap();
+	}
+	let (loaded, large_len) = round_trip(&large);
+	assert_same(&large, &loaded);
+
+	assert!(uniform_len < small_len);
+	assert!(small_len < large_len);
+
+	// Truncated files are an error rather than a panic.
+	let mut buf: Vec<u8> = Vec::new();
+	small.write_chunk(&mut buf).unwrap();
+	buf.truncate(buf.len() / 2);
+	assert!(Chunk::read_chunk(&mut buf.as_slice()).is_err());
+}
+
+#[test]
+fn chunk_rle_round_trip() {
+	use rand::Rng;
+
+	fn written_len(chunk: &Chunk<TileId>) -> usize {
+		let mut buf: Vec<u8> = Vec::new();
+		chunk.write_chunk(&mut buf).unwrap();
+		let loaded = Chunk::read_chunk(&mut buf.as_slice()).unwrap();
+		for i in 0..CHUNK_SIZE_CUBED {
+			let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+			let pos = vpos!(x as u8, y as u8, z as u8);
+			assert_eq!(chunk.get(pos).unwrap(), loaded.get(pos).unwrap());
+		}
+		buf.len()
+	}
+
+	let mut rng = rand::thread_rng();
+
+	// Nearly all stone, with a handful of ore.
+	let mut mostly_uniform: Chunk<TileId> = Chunk::new(1);
+	for _ in 0..16 {
+		let pos = vpos!(
+			rng.gen_range(0..CHUNK_SIZE as u8),
+			rng.gen_range(0..CHUNK_SIZE as u8),
+			rng.gen_range(0..CHUNK_SIZE as u8)
+		);
+		mostly_uniform.set(pos, rng.gen_range(2..8)).unwrap();
+	}
+	let runs = mostly_uniform.to_rle().unwrap();
+	assert!(runs.len() <= 16 * 2 + 1);
+	assert_eq!(runs.iter().map(|(_, len)| *len as usize).sum::<usize>(), CHUNK_SIZE_CUBED);
+	let decoded = Chunk::from_rle(&runs).unwrap();
+	assert_eq!(decoded.to_rle().unwrap(), runs);
+
+	let mut random: Chunk<TileId> = Chunk::new(0);
+	for i in 0..CHUNK_SIZE_CUBED {
+		let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+		random.set(vpos!(x as u8, y as u8, z as u8), rng.gen_range(0..200)).unwrap();
+	}
+	let decoded = Chunk::from_rle(&random.to_rle().unwrap()).unwrap();
+	assert_eq!(decoded.to_rle().unwrap(), random.to_rle().unwrap());
+
+	let mostly_uniform_len = written_len(&mostly_uniform);
+	let random_len = written_len(&random);
+	// Runs are a tiny fraction of one byte per tile...
+	assert!(mostly_uniform_len < CHUNK_SIZE_CUBED / 64);
+	// ...and noise falls back to the raw one-byte-per-tile Small layout rather than ballooning to 6 bytes a tile.
+	assert!(random_len >= CHUNK_SIZE_CUBED);
+	assert!(random_len < CHUNK_SIZE_CUBED * 2);
+
+	// Runs which don't cover the chunk exactly are rejected.
+	assert!(Chunk::from_rle(&[(1, 16)]).is_err());
+	assert!(Chunk::from_rle(&[]).is_err());
+	assert!(Chunk::from_rle(&[(1, CHUNK_SIZE_CUBED as u16), (2, 0)]).is_err());
+}
+
+#[test]
+fn chunk_variant_transitions() {
+	fn check_everywhere(chunk: &Chunk<TileId>, expected: impl Fn(usize) -> TileId) {
+		assert_eq!(chunk.get_bounds(), VoxelRange::new(vpos!(0, 0, 0), vpos!(CHUNK_SIZE as u8, CHUNK_SIZE as u8, CHUNK_SIZE as u8)));
+		for i in 0..CHUNK_SIZE_CUBED {
+			let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+			assert_eq!(*chunk.get(vpos!(x as u8, y as u8, z as u8)).unwrap(), expected(i));
+		}
+	}
+	let pos_for = |i: usize| {
+		let (x, y, z) = chunk_i_to_xyz(i, CHUNK_SIZE);
+		vpos!(x as u8, y as u8, z as u8)
+	};
+
+	// Uniform -> Small
+	let mut chunk: Chunk<TileId> = Chunk::new(0);
+	chunk.set(pos_for(5), 1).unwrap();
+	assert!(matches!(chunk.tiles, ChunkInner::Small(_)));
+	check_everywhere(&chunk, |i| if i == 5 { 1 } else { 0 });
+
+	// Small -> Uniform, by setting the odd one out back to the rest.
+	chunk.set(pos_for(5), 0).unwrap();
+	assert!(matches!(chunk.tiles, ChunkInner::Uniform(0)));
+	check_everywhere(&chunk, |_| 0);
+
+	// Small -> Uniform, by overwriting the rest with the odd one out.
+	chunk.set(pos_for(5), 1).unwrap();
+	for i in 0..CHUNK_SIZE_CUBED {
+		if i != CHUNK_SIZE_CUBED - 1 {
+			chunk.set(pos_for(i), 1).unwrap();
+			assert!(matches!(chunk.tiles, ChunkInner::Small(_)));
+		}
+	}
+	chunk.set(pos_for(CHUNK_SIZE_CUBED - 1), 1).unwrap();
+	assert!(matches!(chunk.tiles, ChunkInner::Uniform(1)));
+	check_everywhere(&chunk, |_| 1);
+
+	// Small -> Large, once there are more distinct tiles than a u8 can index.
+	for i in 0..300 {
+		chunk.set(pos_for(i), 100 + i as TileId).unwrap();
+	}
+	assert!(matches!(chunk.tiles, ChunkInner::Large(_)));
+	check_everywhere(&chunk, |i| if i < 300 { 100 + i as TileId } else { 1 });
+
+	// Large -> Uniform
+	for i in 0..300 {
+		chunk.set(pos_for(i), 1).unwrap();
+	}
+	assert!(matches!(chunk.tiles, ChunkInner::Uniform(1)));
+	check_everywhere(&chunk, |_| 1);
+}
+
+#[test]
+fn chunk_set_region() {
+	let size = CHUNK_SIZE as u8;
+	let mut chunk: Chunk<TileId> = Chunk::new(0);
+	chunk.set(vpos!(1, 2, 3), 5).unwrap();
+
+	// Covering the whole chunk makes it Uniform, and counts every tile that changed.
+	chunk.set_region(VoxelRange::new(vpos!(0, 0, 0), vpos!(size, size, size)), 4).unwrap();
+	assert!(matches!(chunk.tiles, ChunkInner::Uniform(4)));
+	assert_eq!(chunk.revision, 1 + CHUNK_SIZE_CUBED as u64);
+
+	let region = VoxelRange::new(vpos!(2, 0, 30), vpos!(5, 32, 32));
+	chunk.set_region(region, 9).unwrap();
+	for pos in chunk.get_bounds() {
+		let expected = if region.contains(pos) { 9 } else { 4 };
+		assert_eq!(*chunk.get(pos).unwrap(), expected);
+	}
+	assert_eq!(chunk.tile_count(chunk.index_from_palette(9).unwrap()), 3 * 32 * 2);
+
+	// Filling the rest with the same tile goes back to Uniform.
+	chunk.set_region(VoxelRange::new(vpos!(0, 0, 0), vpos!(size, size, 30)), 9).unwrap();
+	assert!(matches!(chunk.tiles, ChunkInner::Small(_)));
+	for x in (0..2).chain(5..size) {
+		chunk.set_region(VoxelRange::new(vpos!(x, 0, 30), vpos!(x + 1, size, size)), 9).unwrap();
+	}
+	assert!(matches!(chunk.tiles, ChunkInner::Uniform(9)));
+
+	assert!(chunk.set_region(VoxelRange::new(vpos!(0, 0, 0), vpos!(size + 1, 1, 1)), 1).is_err());
+}
@@ -0,0 +1,207 @@
+//! Undo / redo for voxel edits. Edits get recorded as they are made (see TileSpace::set_tracked()),
+//! grouped into transactions so that one click or one stamp undoes as a unit.
+
+use std::collections::VecDeque;
+
+use super::tilespace::{TileSpace, TileSpaceError};
+use super::voxelstorage::VoxelSpace;
+use super::{TileId, TilePos, VoxelStorage};
+
+/// How many transactions an EditHistory keeps by default before it starts forgetting the oldest ones.
+pub const DEFAULT_EDIT_HISTORY_DEPTH: usize = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VoxelEdit {
+	pub pos: TilePos,
+	pub old_tile: TileId,
+	pub new_tile: TileId,
+}
+
+impl VoxelEdit {
+	/// The edit which puts things back the way they were.
+	pub fn inverse(&self) -> Self {
+		Self {
+			pos: self.pos,
+			old_tile: self.new_tile,
+			new_tile: self.old_tile,
+		}
+	}
+}
+
+pub struct EditHistory {
+	/// Oldest at the front.
+	undo_stack: VecDeque<Vec<VoxelEdit>>,
+	redo_stack: Vec<Vec<VoxelEdit>>,
+	/// The transaction currently being recorded into, if begin_transaction() has been called.
+	open_transaction: Option<Vec<VoxelEdit>>,
+	max_depth: usize,
+}
+
+impl EditHistory {
+	pub fn new(max_depth: usize) -> Self {
+		Self {
+			undo_stack: VecDeque::new(),
+			redo_stack: Vec::new(),
+			open_transaction: None,
+			max_depth,
+		}
+	}
+
+	pub fn get_max_depth(&self) -> usize {
+		self.max_depth
+	}
+	/// Shrinking this forgets the oldest transactions right away.
+	pub fn set_max_depth(&mut self, max_depth: usize) {
+		self.max_depth = max_depth;
+		self.enforce_depth();
+	}
+	fn enforce_depth(&mut self) {
+		while self.undo_stack.len() > self.max_depth {
+			self.undo_stack.pop_front();
+		}
+	}
+
+	/// Everything recorded until end_transaction() gets undone and redone together.
+	/// Calling this with a transaction already open just keeps adding to that one.
+	pub fn begin_transaction(&mut self) {
+		if self.open_transaction.is_none() {
+			self.open_transaction = Some(Vec::new());
+		}
+	}
+	pub fn end_transaction(&mut self) {
+		if let Some(transaction) = self.open_transaction.take() {
+			self.push_transaction(transaction);
+		}
+	}
+	fn push_transaction(&mut self, transaction: Vec<VoxelEdit>) {
+		if transaction.is_empty() {
+			return;
+		}
+		// A new edit branches off from history, so whatever was undone can't be redone anymore.
+		self.redo_stack.clear();
+		self.undo_stack.push_back(transaction);
+		self.enforce_depth();
+	}
+
+	/// Outside of a transaction, each edit is its own transaction.
+	pub fn record(&mut self, edit: VoxelEdit) {
+		if edit.old_tile == edit.new_tile {
+			return;
+		}
+		match self.open_transaction.as_mut() {
+			Some(transaction) => transaction.push(edit),
+			None => self.push_transaction(vec![edit]),
+		}
+	}
+
+	pub fn can_undo(&self) -> bool {
+		!self.undo_stack.is_empty()
+	}
+	pub fn can_redo(&self) -> bool {
+		!self.redo_stack.is_empty()
+	}
+
+	/// Checks every edit can be made before making any of them, then makes them in order.
+	fn apply(space: &mut TileSpace, edits: &[VoxelEdit]) -> Result<(), TileSpaceError> {
+		if let Some(edit) = edits.iter().find(|edit| !space.is_loaded(edit.pos)) {
+			return Err(TileSpaceError::NotYetLoaded(edit.pos));
+		}
+		for edit in edits {
+			space.set(edit.pos, edit.new_tile)?;
+		}
+		Ok(())
+	}
+
+	/// Reverts the most recent transaction. Returns the edits that were made to do so (empty if there was
+	/// nothing to undo), so the caller can announce them and remesh. If part of the transaction is in a
+	/// chunk which isn't loaded anymore, nothing changes and the transaction stays where it is.
+	pub fn undo(&mut self, space: &mut TileSpace) -> Result<Vec<VoxelEdit>, TileSpaceError> {
+		// Undoing mid-transaction would leave it in a strange state, so close it off first.
+		self.end_transaction();
+		let transaction = match self.undo_stack.pop_back() {
+			Some(transaction) => transaction,
+			None => return Ok(Vec::new()),
+		};
+		let inverse: Vec<VoxelEdit> = transaction.iter().rev().map(VoxelEdit::inverse).collect();
+		if let Err(e) = Self::apply(space, &inverse) {
+			self.undo_stack.push_back(transaction);
+			return Err(e);
+		}
+		self.redo_stack.push(transaction);
+		Ok(inverse)
+	}
+
+	/// Re-applies the most recently undone transaction. Same rules as undo().
+	pub fn redo(&mut self, space: &mut TileSpace) -> Result<Vec<VoxelEdit>, TileSpaceError> {
+		self.end_transaction();
+		let transaction = match self.redo_stack.pop() {
+			Some(transaction) => transaction,
+			None => return Ok(Vec::new()),
+		};
+		if let Err(e) = Self::apply(space, &transaction) {
+			self.redo_stack.push(transaction);
+			return Err(e);
+		}
+		let applied = transaction.clone();
+		self.undo_stack.push_back(transaction);
+		self.enforce_depth();
+		Ok(applied)
+	}
+}
+
+impl Default for EditHistory {
+	fn default() -> Self {
+		Self::new(DEFAULT_EDIT_HISTORY_DEPTH)
+	}
+}
+
+#[test]
+fn undo_redo_transactions() {
+	use crate::world::chunk::Chunk;
+
+	let mut space = TileSpace::new();
+	space.ingest_loaded_chunk(vpos!(0, 0, 0), Chunk::new(0)).unwrap();
+	let mut history = EditHistory::new(8);
+
+	space.set_tracked(vpos!(1, 1, 1), 5, &mut history).unwrap();
+	history.begin_transaction();
+	space.set_tracked(vpos!(2, 2, 2), 6, &mut history).unwrap();
+	space.set_tracked(vpos!(1, 1, 1), 7, &mut history).unwrap();
+	history.end_transaction();
+
+	let undone = history.undo(&mut space).unwrap();
+	assert_eq!(undone.len(), 2);
+	assert_eq!(*space.get(vpos!(1, 1, 1)).unwrap(), 5);
+	assert_eq!(*space.get(vpos!(2, 2, 2)).unwrap(), 0);
+
+	history.undo(&mut space).unwrap();
+	assert_eq!(*space.get(vpos!(1, 1, 1)).unwrap(), 0);
+	assert!(!history.can_undo());
+	assert!(history.undo(&mut space).unwrap().is_empty());
+
+	history.redo(&mut space).unwrap();
+	history.redo(&mut space).unwrap();
+	assert_eq!(*space.get(vpos!(1, 1, 1)).unwrap(), 7);
+	assert_eq!(*space.get(vpos!(2, 2, 2)).unwrap(), 6);
+
+	// A fresh edit after undoing throws away the redo stack.
+	history.undo(&mut space).unwrap();
+	space.set_tracked(vpos!(3, 3, 3), 1, &mut history).unwrap();
+	assert!(!history.can_redo());
+}
+
+#[test]
+fn edit_history_depth_cap() {
+	use crate::world::chunk::Chunk;
+
+	let mut space = TileSpace::new();
+	space.ingest_loaded_chunk(vpos!(0, 0, 0), Chunk::new(0)).unwrap();
+	let mut history = EditHistory::new(2);
+	for tile in 1..=4 {
+		space.set_tracked(vpos!(0, 0, 0), tile, &mut history).unwrap();
+	}
+	history.undo(&mut space).unwrap();
+	history.undo(&mut space).unwrap();
+	assert!(!history.can_undo());
+	assert_eq!(*space.get(vpos!(0, 0, 0)).unwrap(), 2);
+}
@@ -8,6 +8,8 @@ use log::trace;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::chunk::{Chunk, ChunkIoError};
+use super::tilespace::{TileSpace, TileSpaceError};
 use super::{ChunkCoord, ChunkPos, TileId, WorldId};
 
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
@@ -86,9 +88,8 @@ pub fn path_for_chunk(
 	path_for_terrain(base_dir, world_id, role).join(filename_for_chunk(pos))
 }
 
-/*
-pub fn load_chunk(world_id: &WorldId, role: StoredWorldRole, pos: &ChunkPos) -> std::result::Result<Chunk<TileId>, ChunkIoError> {
-	let path = path_for_chunk(world_id, role, pos);
+pub fn load_chunk(base_dir: &PathBuf, world_id: &WorldId, role: StoredWorldRole, pos: &ChunkPos) -> std::result::Result<Chunk<TileId>, ChunkIoError> {
+	let path = path_for_chunk(base_dir, world_id, role, pos);
 	let file = OpenOptions::new()
 		.read(true)
 		.write(false)
@@ -96,11 +97,11 @@ pub fn load_chunk(world_id: &WorldId, role: StoredWorldRole, pos: &ChunkPos) ->
 		.open(path)?;
 
 	let mut reader = BufReader::new(file);
-	deserialize_chunk(&mut reader)
+	Chunk::read_chunk(&mut reader)
 }
 
-pub fn save_chunk(world_id: &WorldId, role: StoredWorldRole, pos: &ChunkPos, chunk: &Chunk<TileId>) -> std::result::Result<(), ChunkIoError> {
-	let target_path = path_for_chunk(world_id, role, pos);
+pub fn save_chunk(base_dir: &PathBuf, world_id: &WorldId, role: StoredWorldRole, pos: &ChunkPos, chunk: &Chunk<TileId>) -> std::result::Result<(), ChunkIoError> {
+	let target_path = path_for_chunk(base_dir, world_id, role, pos);
 	// Write the file to a temporary path so that, if it crashes in the process of serializing,
 	// it does not corrupt previously-existing world state.
 	let in_progress_path = target_path.with_extension("chunk.lock");
@@ -115,10 +116,68 @@ pub fn save_chunk(world_id: &WorldId, role: StoredWorldRole, pos: &ChunkPos, chu
 
 	let mut writer = BufWriter::new(file);
 	chunk.write_chunk(&mut writer)?;
+	// Make sure everything is actually on disk before we swap it in.
+	writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
 	//This should be an atomic operation, so world state won't get corrupted here.
 	std::fs::rename(&in_progress_path, target_path)?;
 	Ok(())
-}*/
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WorldIoError {
+	#[error("Could not save or load chunk {0}: {1}")]
+	Chunk(ChunkPos, ChunkIoError),
+	#[error("I/O error while reading or writing the terrain manifest: {0:?}")]
+	Io(#[from] std::io::Error),
+	#[error("Could not parse the terrain manifest: {0}")]
+	ManifestParse(#[from] ron::error::SpannedError),
+	#[error("Could not write the terrain manifest: {0}")]
+	ManifestWrite(#[from] ron::Error),
+	#[error("Terrain manifest is invalid: {0}")]
+	TileSpace(#[from] TileSpaceError),
+}
+
+/// Every chunk a saved world has, so load_world() knows what to read without having to list the directory.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TerrainManifest {
+	pub chunks: Vec<ChunkPos>,
+}
+
+pub fn path_for_terrain_manifest(base_dir: &PathBuf, world_id: &WorldId, role: StoredWorldRole) -> PathBuf {
+	path_for_terrain(base_dir, world_id, role).join("manifest.ron")
+}
+
+/// Save every chunk loaded in tiles, plus a manifest listing them. The manifest gets written last, so
+/// an interrupted save leaves the previous manifest (and so the previous set of chunks) in place.
+pub fn save_world(base_dir: &PathBuf, world_id: &WorldId, role: StoredWorldRole, tiles: &TileSpace) -> std::result::Result<(), WorldIoError> {
+	let mut manifest = TerrainManifest::default();
+	for (pos, chunk) in tiles.iter_loaded_sorted() {
+		save_chunk(base_dir, world_id, role, pos, chunk).map_err(|e| WorldIoError::Chunk(*pos, e))?;
+		manifest.chunks.push(*pos);
+	}
+	let target_path = path_for_terrain_manifest(base_dir, world_id, role);
+	let in_progress_path = target_path.with_extension("ron.lock");
+	let manifest_string = ron::ser::to_string_pretty(&manifest, ron::ser::PrettyConfig::default())?;
+	std::fs::write(&in_progress_path, manifest_string)?;
+	std::fs::rename(&in_progress_path, target_path)?;
+	Ok(())
+}
+
+/// Load every chunk listed in a world's terrain manifest. A world which has never been saved loads as empty.
+pub fn load_world(base_dir: &PathBuf, world_id: &WorldId, role: StoredWorldRole) -> std::result::Result<TileSpace, WorldIoError> {
+	let manifest_path = path_for_terrain_manifest(base_dir, world_id, role);
+	let manifest: TerrainManifest = match std::fs::read_to_string(&manifest_path) {
+		Ok(contents) => ron::from_str(&contents)?,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => TerrainManifest::default(),
+		Err(e) => return Err(e.into()),
+	};
+	let mut tiles = TileSpace::new();
+	for pos in manifest.chunks {
+		let chunk = load_chunk(base_dir, world_id, role, &pos).map_err(|e| WorldIoError::Chunk(pos, e))?;
+		tiles.ingest_loaded_chunk(pos, chunk)?;
+	}
+	Ok(tiles)
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WorldDefaults {
@@ -126,3 +185,60 @@ pub struct WorldDefaults {
 	/// None on first launch. Should auto-fill at first launch
 	pub lobby_world_id: Option<Uuid>,
 }
+
+/// Reads the lobby world's UUID out of world_defaults.ron, picking a new one (and writing it back) if there isn't one yet.
+pub fn load_or_create_lobby_world_uuid(base_dir: &PathBuf) -> std::result::Result<Uuid, std::io::Error> {
+	let world_defaults_path = path_worlds(base_dir).join("world_defaults.ron");
+	let mut world_defaults: WorldDefaults = match std::fs::read_to_string(&world_defaults_path) {
+		Ok(contents) => ron::from_str(&contents)
+			.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => WorldDefaults { lobby_world_id: None },
+		Err(e) => return Err(e),
+	};
+	match world_defaults.lobby_world_id {
+		Some(uuid) => Ok(uuid),
+		None => {
+			let uuid = Uuid::new_v4();
+			world_defaults.lobby_world_id = Some(uuid);
+			let defaults_string = ron::ser::to_string_pretty(&world_defaults, ron::ser::PrettyConfig::default())
+				.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+			std::fs::write(&world_defaults_path, defaults_string)?;
+			Ok(uuid)
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::common::identity::IdentityKeyPair;
+	use crate::world::VoxelStorage;
+
+	#[test]
+	fn save_and_load_whole_world() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let base_dir = temp_dir.path().to_path_buf();
+		let world_id = WorldId::from_seed("save_and_load_whole_world", IdentityKeyPair::generate_for_tests().public);
+
+		let mut tiles = TileSpace::new();
+		for pos in [vpos!(0, 0, 0), vpos!(-1, 0, 0), vpos!(0, 2, -3)] {
+			tiles.ingest_loaded_chunk(pos, Chunk::new(0)).unwrap();
+		}
+		tiles.set(vpos!(3, 4, 5), 7).unwrap();
+		tiles.set(vpos!(-2, 0, 0), 9).unwrap();
+		tiles.set(vpos!(1, 65, -90), 11).unwrap();
+
+		save_world(&base_dir, &world_id, StoredWorldRole::Local, &tiles).unwrap();
+		let loaded = load_world(&base_dir, &world_id, StoredWorldRole::Local).unwrap();
+
+		let original: Vec<_> = tiles.iter_loaded_sorted().map(|(pos, chunk)| (*pos, chunk.to_rle().unwrap())).collect();
+		let reloaded: Vec<_> = loaded.iter_loaded_sorted().map(|(pos, chunk)| (*pos, chunk.to_rle().unwrap())).collect();
+		assert_eq!(original.len(), 3);
+		assert_eq!(original, reloaded);
+		assert_eq!(*loaded.get(vpos!(1, 65, -90)).unwrap(), 11);
+
+		// Nothing saved under this one.
+		let other_world = WorldId::from_seed("never saved", IdentityKeyPair::generate_for_tests().public);
+		assert_eq!(load_world(&base_dir, &other_world, StoredWorldRole::Local).unwrap().iter_loaded_sorted().count(), 0);
+	}
+}
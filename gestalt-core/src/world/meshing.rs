@@ -0,0 +1,230 @@
+//! The parts of turning a chunk into quads which don't care how those quads get drawn.
+//! The voxel mesher in the client's renderer builds on these.
+
+use super::chunk::CHUNK_SIZE;
+
+/// Ambient occlusion value for a vertex with nothing solid around it.
+pub const MAX_AO: u8 = 3;
+/// Ambient occlusion for each corner of a face, indexed by u + 2 * v of that corner.
+pub type FaceAo = [u8; 4];
+pub const NO_OCCLUSION: FaceAo = [MAX_AO; 4];
+
+/// Which voxels in and right around a chunk are solid, for ambient occlusion.
+/// Covers one voxel past each side of the chunk so that faces on the chunk's edge can see into its neighbors.
+pub struct OcclusionGrid {
+	solid: Vec<bool>,
+}
+
+impl OcclusionGrid {
+	const PADDED_SIZE: usize = CHUNK_SIZE + 2;
+
+	pub fn new() -> Self {
+		Self {
+			solid: vec![false; Self::PADDED_SIZE * Self::PADDED_SIZE * Self::PADDED_SIZE],
+		}
+	}
+	#[inline(always)]
+	fn index(x: i32, y: i32, z: i32) -> Option<usize> {
+		let range = -1..=(CHUNK_SIZE as i32);
+		if !(range.contains(&x) && range.contains(&y) && range.contains(&z)) {
+			return None;
+		}
+		let size = Self::PADDED_SIZE;
+		Some((x + 1) as usize + (y + 1) as usize * size + (z + 1) as usize * size * size)
+	}
+	/// Takes chunk-local coordinates, from -1 to CHUNK_SIZE inclusive. Anything further out isn't solid.
+	#[inline(always)]
+	pub fn is_solid(&self, x: i32, y: i32, z: i32) -> bool {
+		Self::index(x, y, z).map(|i| self.solid[i]).unwrap_or(false)
+	}
+	pub fn set_solid(&mut self, x: i32, y: i32, z: i32, value: bool) {
+		if let Some(i) = Self::index(x, y, z) {
+			self.solid[i] = value;
+		}
+	}
+	/// Standard voxel AO for one corner of a face: look at the two voxels beside the corner and the one diagonal
+	/// from it, in the layer the face is looking out into. Two sides are enough to fully occlude it, whether or not
+	/// the diagonal is there too. outside is the voxel the face looks out into, and toward is which way the corner
+	/// is from the middle of the face - 1 or -1 along each of the face's two axes, 0 along its normal.
+	pub fn corner_ao(&self, outside: [i32; 3], toward: [i32; 3]) -> u8 {
+		let solid_at = |offset: [i32; 3]| self.is_solid(outside[0] + offset[0], outside[1] + offset[1], outside[2] + offset[2]);
+		let mut sides_solid = (0..3).filter(|axis| toward[*axis] != 0).map(|axis| {
+			let mut beside = [0; 3];
+			beside[axis] = toward[axis];
+			solid_at(beside)
+		});
+		let (first, second) = (sides_solid.next().unwrap_or(false), sides_solid.next().unwrap_or(false));
+		if first && second {
+			0
+		} else {
+			MAX_AO - (first as u8 + second as u8 + solid_at(toward) as u8)
+		}
+	}
+}
+
+impl Default for OcclusionGrid {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// What the mesher needs to know about how a tile gets drawn.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct CubeArtNotes {
+	/// Should *this voxel mesher code* draw this tile?
+	pub visible_this_pass: bool,
+	/// Do we cull the same material? i.e do other tiles with the same ID get culled by this one?
+	pub cull_self: bool,
+	/// Do we cull other materials? i.e do other tiles with different IDs get culled by this one?
+	pub cull_others: bool,
+	/// Do this tile's faces get darkened by ambient occlusion?
+	pub ao_enabled: bool,
+	/// Is this drawn as two crossed quads (VoxelArt::Cross) rather than a cube? Crosses don't cull, and don't get culled.
+	pub is_cross: bool,
+}
+
+impl CubeArtNotes {
+	/// Can the face of this tile which touches the given neighbor be skipped? tile and neighbor_tile only get
+	/// compared with each other, to tell whether the two are the same material.
+	pub fn is_face_hidden_by<T: PartialEq>(&self, tile: T, neighbor: &CubeArtNotes, neighbor_tile: T) -> bool {
+		!self.is_cross
+			&& neighbor.visible_this_pass
+			&& !neighbor.is_cross
+			&& ((self.cull_self && (tile == neighbor_tile)) || (self.cull_others && (tile != neighbor_tile)))
+	}
+}
+
+/// Greedy meshing for one size x size layer of faces: starting from the first face which hasn't been merged yet,
+/// grow a rectangle as far as it can go along a, then a row at a time along b, for as long as every face it covers
+/// is the same. face_at(a, b) gives the face at that spot, or None if nothing's visible there. Faces for which
+/// can_merge() is false always come out on their own.
+/// Calls emit(a, b, width, height, face) once for each rectangle, where (a, b) is its lowest corner.
+pub fn greedy_rects<K: Copy + PartialEq>(
+	size: usize,
+	face_at: impl Fn(usize, usize) -> Option<K>,
+	can_merge: impl Fn(&K) -> bool,
+	mut emit: impl FnMut(usize, usize, usize, usize, K),
+) {
+	let mut merged = vec![false; size * size];
+	for b in 0..size {
+		let mut a = 0;
+		while a < size {
+			let key = match face_at(a, b) {
+				Some(key) if !merged[b * size + a] => key,
+				_ => {
+					a += 1;
+					continue;
+				}
+			};
+			let mut width = 1;
+			let mut height = 1;
+			if can_merge(&key) {
+				while a + width < size && !merged[b * size + a + width] && face_at(a + width, b) == Some(key) {
+					width += 1;
+				}
+				'grow: while b + height < size {
+					for offset in 0..width {
+						if merged[(b + height) * size + a + offset] || face_at(a + offset, b + height) != Some(key) {
+							break 'grow;
+						}
+					}
+					height += 1;
+				}
+			}
+			for row in b..(b + height) {
+				merged[(row * size + a)..(row * size + a + width)].fill(true);
+			}
+			emit(a, b, width, height, key);
+			a += width;
+		}
+	}
+}
+
+#[cfg(test)]
+fn collect_rects<K: Copy + PartialEq>(
+	size: usize,
+	face_at: impl Fn(usize, usize) -> Option<K>,
+	can_merge: impl Fn(&K) -> bool,
+) -> Vec<(usize, usize, usize, usize, K)> {
+	let mut rects = Vec::new();
+	greedy_rects(size, face_at, can_merge, |a, b, width, height, key| rects.push((a, b, width, height, key)));
+	rects
+}
+
+#[test]
+fn greedy_meshing_merges_flat_slab() {
+	// A 16x16 patch of the same face in the corner of a 32x32 layer becomes one quad.
+	let slab = |a: usize, b: usize| (a < 16 && b < 16).then_some(1u16);
+	assert_eq!(collect_rects(32, slab, |_| true), vec![(0, 0, 16, 16, 1)]);
+
+	// Without merging, that's one per face, and they still cover exactly the same area.
+	let unmerged = collect_rects(32, slab, |_| false);
+	assert_eq!(unmerged.len(), 16 * 16);
+	assert!(unmerged.iter().all(|(a, b, width, height, _)| *a < 16 && *b < 16 && *width == 1 && *height == 1));
+
+	// Two different textures side by side stay apart.
+	let halves = |a: usize, b: usize| (b < 4).then_some(if a < 2 { 7u16 } else { 9 });
+	assert_eq!(collect_rects(4, halves, |_| true), vec![(0, 0, 2, 4, 7), (2, 0, 2, 4, 9)]);
+
+	// An L shape can't be one rectangle - the first row grows as wide as it can, then the rest gets its own.
+	let l_shape = |a: usize, b: usize| (b == 0 || a == 0).then_some(1u16);
+	let rects = collect_rects(3, l_shape, |_| true);
+	assert_eq!(rects, vec![(0, 0, 3, 1, 1), (0, 1, 1, 2, 1)]);
+	assert_eq!(rects.iter().map(|(_, _, width, height, _)| width * height).sum::<usize>(), 5);
+}
+
+#[test]
+fn inside_corner_gets_more_occlusion() {
+	// A floor with two walls meeting in the corner at x = 0, z = 0, and the top faces of the floor looking up into y = 1.
+	let mut grid = OcclusionGrid::new();
+	for x in 0..8 {
+		for z in 0..8 {
+			grid.set_solid(x, 0, z, true);
+			if x == 0 || z == 0 {
+				grid.set_solid(x, 1, z, true);
+			}
+		}
+	}
+	let top_face_ao = |x: i32, z: i32| -> Vec<u8> {
+		let outside = [x, 1, z];
+		[(-1, -1), (1, -1), (-1, 1), (1, 1)].iter().map(|(dx, dz)| grid.corner_ao(outside, [*dx, 0, *dz])).collect()
+	};
+
+	let exposed = top_face_ao(5, 5);
+	assert!(exposed.iter().all(|ao| *ao == MAX_AO));
+	// Right in the corner, both walls touch the corner nearest them.
+	let corner = top_face_ao(1, 1);
+	assert_eq!(corner, vec![0, MAX_AO - 2, MAX_AO - 2, MAX_AO]);
+	assert!(corner.iter().map(|ao| *ao as u32).sum::<u32>() < exposed.iter().map(|ao| *ao as u32).sum::<u32>());
+	// Along one wall, only the side by the wall darkens.
+	assert_eq!(top_face_ao(5, 1), vec![MAX_AO - 2, MAX_AO - 2, MAX_AO, MAX_AO]);
+
+	// One voxel past the chunk's edge is still tracked, but anything further out is never solid.
+	grid.set_solid(-1, 0, 0, true);
+	grid.set_solid(-2, 0, 0, true);
+	assert!(grid.is_solid(-1, 0, 0));
+	assert!(!grid.is_solid(-2, 0, 0));
+}
+
+#[test]
+fn cross_tiles_neither_cull_nor_get_culled() {
+	const STONE: u16 = 1;
+	const GLASS: u16 = 2;
+	const FERN: u16 = 3;
+	let cube = CubeArtNotes { visible_this_pass: true, cull_self: true, cull_others: true, ao_enabled: true, is_cross: false };
+	let glass = CubeArtNotes { cull_others: false, ..cube };
+	let cross = CubeArtNotes { visible_this_pass: true, is_cross: true, ..Default::default() };
+	let air = CubeArtNotes::default();
+
+	// Solid cubes hide each other's faces, and nothing hides a face looking out into air.
+	assert!(cube.is_face_hidden_by(STONE, &cube, STONE));
+	assert!(!cube.is_face_hidden_by(STONE, &air, 0));
+	// Glass only merges with more glass.
+	assert!(glass.is_face_hidden_by(GLASS, &glass, GLASS));
+	assert!(!glass.is_face_hidden_by(GLASS, &cube, STONE));
+
+	// Stone right next to a fern keeps its face, and the fern keeps all of its quads no matter what's around it.
+	assert!(!cube.is_face_hidden_by(STONE, &cross, FERN));
+	assert!(!cross.is_face_hidden_by(FERN, &cube, STONE));
+	assert!(!cross.is_face_hidden_by(FERN, &cross, FERN));
+}
@@ -1,22 +1,32 @@
 pub mod chunk;
+pub mod edithistory;
 pub mod fsworldstorage;
+pub mod meshing;
+pub mod tickscheduler;
 pub mod tilespace;
 pub mod voxelarray;
+pub mod voxelmodel;
 pub mod voxelstorage;
+pub mod worldgen;
 
+use std::fmt::Display;
 use std::ops::Add;
 use std::ops::Div;
 use std::ops::Mul;
 use std::ops::Sub;
 use std::time::Duration;
 
+use base64::engine::general_purpose::URL_SAFE as BASE_64;
+use base64::Engine;
+use sha2::Digest;
+use log::warn;
 use uuid::Uuid;
 
 //pub use space::Space;
 pub use voxelstorage::VoxelStorage;
 pub use voxelstorage::VoxelStorageBounded;
 
-use crate::common::identity::NodeIdentity;
+use crate::common::identity::{DecodeIdentityError, NodeIdentity};
 use crate::common::voxelmath::VoxelPos;
 
 /// Tiles as they are interacted with in the world (not as stored in a chunk, necessarily) - as in, what a Space will return when you call world_voxel_space.get(x, y, z)
@@ -31,12 +41,75 @@ pub type TilePos = VoxelPos<TileCoord>;
 pub type ChunkCoord = i32;
 pub type ChunkPos = VoxelPos<ChunkCoord>;
 
+/// Position of a tile within its chunk, with each coordinate in 0..CHUNK_SIZE.
+pub type LocalTilePos = VoxelPos<u8>;
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct WorldId {
 	pub uuid: Uuid,
 	/// Either us or the server we're mirroring this from.
 	pub host: NodeIdentity,
 }
+
+/// Mixed into the seed before hashing, so world UUIDs never collide with some other use of the same hash.
+const WORLD_SEED_DOMAIN: &[u8] = b"gestalt-world-seed:";
+/// Bytes in a decoded share string - the UUID followed by the host's public key.
+const WORLD_SHARE_BYTES: usize = 16 + 32;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ParseWorldIdError {
+	#[error("error decoding a world ID from a Base-64 string: {0:?}")]
+	Base64Error(#[from] base64::DecodeError),
+	#[error("world ID length was incorrect. Expected {WORLD_SHARE_BYTES} bytes, got {0}")]
+	WrongLength(usize),
+	#[error("world ID had an invalid host identity: {0}")]
+	BadHost(#[from] DecodeIdentityError),
+}
+
+impl WorldId {
+	/// The same seed always produces the same UUID, so a world can be regenerated by anyone
+	/// who has been given its seed. Seeds are hashed as-is - "Foo" and "foo" are different worlds.
+	pub fn from_seed(seed: &str, host: NodeIdentity) -> Self {
+		let mut hasher = sha2::Sha512_256::new();
+		hasher.update(WORLD_SEED_DOMAIN);
+		hasher.update(seed.as_bytes());
+		let hash = hasher.finalize();
+		let mut bytes = [0u8; 16];
+		bytes.copy_from_slice(&hash[0..16]);
+		Self {
+			// Marked as a custom (version 8) UUID, since it isn't random and isn't one of the standard name-based kinds.
+			uuid: uuid::Builder::from_custom_bytes(bytes).into_uuid(),
+			host,
+		}
+	}
+
+	/// Encodes the UUID and host together as one short, URL-safe string, e.g. for pasting into chat.
+	pub fn to_share_string(&self) -> String {
+		let mut bytes = Vec::with_capacity(WORLD_SHARE_BYTES);
+		bytes.extend_from_slice(self.uuid.as_bytes());
+		bytes.extend_from_slice(&self.host.0);
+		BASE_64.encode(bytes)
+	}
+
+	/// Inverse of to_share_string(). Surrounding whitespace is ignored.
+	pub fn from_share_string(share: &str) -> Result<Self, ParseWorldIdError> {
+		let bytes = BASE_64.decode(share.trim())?;
+		if bytes.len() != WORLD_SHARE_BYTES {
+			return Err(ParseWorldIdError::WrongLength(bytes.len()));
+		}
+		let (uuid_bytes, host_bytes) = bytes.split_at(16);
+		// Length was checked above.
+		let uuid = Uuid::from_slice(uuid_bytes).unwrap();
+		let host = NodeIdentity::from_bytes(host_bytes)?;
+		Ok(Self { uuid, host })
+	}
+}
+
+impl Display for WorldId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.to_share_string())
+	}
+}
 #[derive(Default, Debug, Clone)]
 pub struct WorldInfo {
 	pub name: String,
@@ -81,6 +154,52 @@ impl TickLength {
 }
 
 pub const DEFAULT_TPS: f32 = 30.0;
+/// See TickAccumulator.
+pub const DEFAULT_MAX_TICKS_PER_FRAME: u32 = 5;
+
+/// Turns real elapsed time into a number of fixed-length ticks to run. If a frame stalls badly, catching up
+/// on every missed tick would make the next frame slower still (the "spiral of death"), so no more than
+/// max_ticks_per_frame get run at once and whatever time is left over gets dropped.
+#[derive(Copy, Clone, Debug)]
+pub struct TickAccumulator {
+	accumulated: f32,
+	tick_length: TickLength,
+	max_ticks_per_frame: u32,
+}
+impl TickAccumulator {
+	pub fn new(tick_length: TickLength, max_ticks_per_frame: u32) -> Self {
+		Self {
+			accumulated: 0.0,
+			tick_length,
+			max_ticks_per_frame: max_ticks_per_frame.max(1),
+		}
+	}
+	/// Add a frame's worth of elapsed time, and get back how many ticks to run for it.
+	pub fn advance(&mut self, elapsed_secs: f32) -> u32 {
+		self.accumulated += elapsed_secs;
+		let due = (self.accumulated / self.tick_length.get()).floor();
+		if due > self.max_ticks_per_frame as f32 {
+			let dropped = self.accumulated - (self.max_ticks_per_frame as f32 * self.tick_length.get());
+			warn!("Fell {due} ticks behind in one frame, only running {} and skipping {dropped} seconds of game time.", self.max_ticks_per_frame);
+			self.accumulated = 0.0;
+			self.max_ticks_per_frame
+		} else {
+			let due = due as u32;
+			self.accumulated -= due as f32 * self.tick_length.get();
+			due
+		}
+	}
+	/// Time carried over toward the next tick, for interpolating between ticks.
+	pub fn get_accumulated(&self) -> f32 {
+		self.accumulated
+	}
+	pub fn get_tick_length(&self) -> TickLength {
+		self.tick_length
+	}
+	pub fn set_max_ticks_per_frame(&mut self, max_ticks_per_frame: u32) {
+		self.max_ticks_per_frame = max_ticks_per_frame.max(1);
+	}
+}
 
 impl Default for TickLength {
 	fn default() -> Self {
@@ -153,3 +272,48 @@ impl Div<TickLength> for f32 {
 fn zero_tps_does_panic() {
 	let _value = TickLength::from_tps(0.0);
 }
+
+#[test]
+fn tick_accumulator_clamps_stalled_frames() {
+	let mut accumulator = TickAccumulator::new(TickLength::from_tps(10.0), 5);
+	assert_eq!(accumulator.advance(0.25), 2);
+	assert!((accumulator.get_accumulated() - 0.05).abs() < 0.0001);
+	// A ten second hitch would be 100 ticks.
+	assert_eq!(accumulator.advance(10.0), 5);
+	assert_eq!(accumulator.get_accumulated(), 0.0);
+	assert_eq!(accumulator.advance(0.1), 1);
+}
+
+#[test]
+fn world_id_from_seed_is_deterministic() {
+	use crate::common::identity::IdentityKeyPair;
+	let host = IdentityKeyPair::generate_for_tests().public;
+	let other_host = IdentityKeyPair::generate_for_tests().public;
+
+	let first = WorldId::from_seed("big mountains", host);
+	assert_eq!(first, WorldId::from_seed("big mountains", host));
+	// The seed alone decides the UUID.
+	assert_eq!(first.uuid, WorldId::from_seed("big mountains", other_host).uuid);
+	assert_ne!(first.uuid, WorldId::from_seed("Big mountains", host).uuid);
+	assert_eq!(first.uuid.get_version_num(), 8);
+}
+
+#[test]
+fn world_id_share_string_round_trip() {
+	use crate::common::identity::IdentityKeyPair;
+	let world_id = WorldId {
+		uuid: Uuid::new_v4(),
+		host: IdentityKeyPair::generate_for_tests().public,
+	};
+	let share = world_id.to_share_string();
+	assert_eq!(share, world_id.to_string());
+	assert_eq!(share.len(), 64);
+	assert_eq!(WorldId::from_share_string(&share).unwrap(), world_id);
+	assert_eq!(WorldId::from_share_string(&format!("  {share}\n")).unwrap(), world_id);
+
+	assert!(matches!(
+		WorldId::from_share_string(&share[0..32]),
+		Err(ParseWorldIdError::WrongLength(24))
+	));
+	assert!(matches!(WorldId::from_share_string("not*base64"), Err(ParseWorldIdError::Base64Error(_))));
+}
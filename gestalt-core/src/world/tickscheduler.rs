@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+
+/// Index of a fixed-length game tick (see TickLength), counting up from when the world started running.
+pub type TickNumber = u64;
+
+/// Holds onto events which should happen some number of ticks from now
+/// (block updates, timers, scheduled saves...) and hands them back once they're due.
+///
+/// This is deterministic: events which come due on the same tick are always
+/// returned in the order they were scheduled in.
+pub struct TickScheduler<E> {
+	/// Keyed on (target tick, order scheduled in), so iteration order is exactly the order to run them in.
+	pending: BTreeMap<(TickNumber, u64), E>,
+	next_sequence: u64,
+	/// The tick most recently passed to drain_due().
+	current_tick: TickNumber,
+}
+
+impl<E> TickScheduler<E> {
+	pub fn new() -> Self {
+		Self::starting_at(0)
+	}
+	/// Make a scheduler for a world whose tick counter has already advanced to current_tick, e.g. one loaded from disk.
+	pub fn starting_at(current_tick: TickNumber) -> Self {
+		Self {
+			pending: BTreeMap::new(),
+			next_sequence: 0,
+			current_tick,
+		}
+	}
+
+	/// Run the event delay_ticks ticks after the current one. Since the current tick has
+	/// (presumably) already been drained, a delay of 0 means it comes due on the next tick.
+	pub fn schedule(&mut self, delay_ticks: TickNumber, event: E) {
+		self.schedule_at(self.current_tick.saturating_add(delay_ticks), event);
+	}
+
+	/// Run the event on a specific tick. Ticks in the past come due on the next call to drain_due().
+	pub fn schedule_at(&mut self, target_tick: TickNumber, event: E) {
+		let sequence = self.next_sequence;
+		self.next_sequence += 1;
+		self.pending.insert((target_tick, sequence), event);
+	}
+
+	/// Takes every event due on or before current_tick, in the order they should run.
+	/// Call this once per tick.
+	pub fn drain_due(&mut self, current_tick: TickNumber) -> Vec<E> {
+		self.current_tick = current_tick;
+		let not_yet_due = match current_tick.checked_add(1) {
+			Some(next_tick) => self.pending.split_off(&(next_tick, 0)),
+			None => BTreeMap::new(),
+		};
+		let due = std::mem::replace(&mut self.pending, not_yet_due);
+		due.into_values().collect()
+	}
+
+	/// The soonest tick anything is scheduled for, if anything is scheduled at all.
+	pub fn next_due_tick(&self) -> Option<TickNumber> {
+		self.pending.keys().next().map(|(tick, _)| *tick)
+	}
+	pub fn current_tick(&self) -> TickNumber {
+		self.current_tick
+	}
+	pub fn len(&self) -> usize {
+		self.pending.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.pending.is_empty()
+	}
+}
+
+impl<E> Default for TickScheduler<E> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[test]
+fn tick_scheduler_runs_events_when_due() {
+	let mut scheduler: TickScheduler<&'static str> = TickScheduler::new();
+	scheduler.schedule(3, "three");
+	scheduler.schedule(1, "one");
+	scheduler.schedule(2, "two");
+	assert_eq!(scheduler.next_due_tick(), Some(1));
+
+	assert!(scheduler.drain_due(0).is_empty());
+	assert_eq!(scheduler.drain_due(1), vec!["one"]);
+	// Relative to the tick we last drained.
+	scheduler.schedule(1, "also two");
+	assert_eq!(scheduler.drain_due(2), vec!["two", "also two"]);
+	assert_eq!(scheduler.len(), 1);
+	// Skipping ahead catches up on everything overdue.
+	assert_eq!(scheduler.drain_due(10), vec!["three"]);
+	assert!(scheduler.is_empty());
+}
+
+#[test]
+fn tick_scheduler_same_tick_is_stable() {
+	let mut scheduler = TickScheduler::starting_at(100);
+	for i in 0..64 {
+		scheduler.schedule(5, i);
+	}
+	scheduler.schedule_at(50, -1);
+	assert_eq!(scheduler.drain_due(104), vec![-1]);
+	assert_eq!(scheduler.drain_due(105), (0..64).collect::<Vec<i32>>());
+}
+
+#[test]
+fn tick_scheduler_max_tick() {
+	let mut scheduler = TickScheduler::starting_at(TickNumber::MAX - 1);
+	scheduler.schedule(5, "saturated");
+	assert!(scheduler.drain_due(TickNumber::MAX - 1).is_empty());
+	assert_eq!(scheduler.drain_due(TickNumber::MAX), vec!["saturated"]);
+}
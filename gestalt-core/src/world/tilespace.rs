@@ -1,14 +1,16 @@
 //! A space made up of multiple chunks - the voxel-only parts of a "world". A "Dimension". Can be multiple per server.
 use crate::common::voxelmath::*;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use std::result::Result;
 
 use crate::world::voxelstorage::*;
-use crate::world::{ChunkCoord, ChunkPos, TileCoord, TilePos};
+use crate::world::{ChunkCoord, ChunkPos, LocalTilePos, TileCoord, TilePos};
 
 use super::chunk::{CHUNK_EXP, CHUNK_SIZE};
+use super::edithistory::{EditHistory, VoxelEdit};
+use super::voxelmodel::{StampMode, VoxelModel};
 use super::{chunk, TileId};
 
 #[derive(thiserror::Error, Debug, Clone)]
@@ -46,14 +48,123 @@ impl VoxelError for TileSpaceError {
 	}
 }
 
+/// Why a flood fill gave up before it ran out of matching voxels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FloodFillStop {
+	/// Found max_voxels matching voxels and there were still more to go.
+	HitLimit,
+	/// The region carries on into a chunk which isn't loaded - this is the first unloaded position it ran into.
+	HitUnloaded(TilePos),
+}
+
+/// Result of TileSpace::flood_fill().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FloodFill {
+	/// Every voxel found in the region, in the order they were reached (nearest first).
+	pub voxels: Vec<TilePos>,
+	/// None if the whole connected region was found, otherwise why it wasn't.
+	pub stopped: Option<FloodFillStop>,
+}
+
+impl FloodFill {
+	pub fn is_complete(&self) -> bool {
+		self.stopped.is_none()
+	}
+}
+
+/// A loaded chunk along with whichever of its six face neighbors are loaded, borrowed all at once so that
+/// something walking over the chunk (e.g. the mesher) can look across its edges without going back
+/// through the TileSpace's chunk map for every voxel.
+pub struct ChunkNeighborhood<'a> {
+	pub center_pos: ChunkPos,
+	pub center: &'a chunk::Chunk<TileId>,
+	/// Indexed by VoxelSide::to_id(). None if that neighbor isn't loaded.
+	pub neighbors: [Option<&'a chunk::Chunk<TileId>>; 6],
+}
+
+impl<'a> ChunkNeighborhood<'a> {
+	pub fn neighbor(&self, side: VoxelSide) -> Option<&'a chunk::Chunk<TileId>> {
+		self.neighbors[side.to_id() as usize]
+	}
+	/// Looks up a tile by its position relative to the center chunk's (0, 0, 0) corner, so anything from
+	/// -CHUNK_SIZE to 2 * CHUNK_SIZE - 1 along one axis can be reached. Returns None for positions which are
+	/// outside the center chunk and its face neighbors (i.e. diagonal from it), or in a neighbor that isn't loaded.
+	#[inline]
+	pub fn get_local(&self, pos: VoxelPos<TileCoord>) -> Option<&'a TileId> {
+		let size = CHUNK_SIZE as TileCoord;
+		let local: LocalTilePos = vpos!(pos.x.rem_euclid(size) as u8, pos.y.rem_euclid(size) as u8, pos.z.rem_euclid(size) as u8);
+		let chunk = match (pos.x.div_euclid(size), pos.y.div_euclid(size), pos.z.div_euclid(size)) {
+			(0, 0, 0) => Some(self.center),
+			(1, 0, 0) => self.neighbor(VoxelSide::PosiX),
+			(-1, 0, 0) => self.neighbor(VoxelSide::NegaX),
+			(0, 1, 0) => self.neighbor(VoxelSide::PosiY),
+			(0, -1, 0) => self.neighbor(VoxelSide::NegaY),
+			(0, 0, 1) => self.neighbor(VoxelSide::PosiZ),
+			(0, 0, -1) => self.neighbor(VoxelSide::NegaZ),
+			_ => None,
+		}?;
+		chunk.get(local).ok()
+	}
+}
+
 pub struct TileSpace {
 	pub(crate) chunks: HashMap<ChunkPos, chunk::Chunk<TileId>>,
+	/// Chunks which have changed since they were last saved.
+	dirty: HashSet<ChunkPos>,
 }
 impl TileSpace {
 	pub fn new() -> Self {
 		Self {
 			chunks: HashMap::new(),
+			dirty: HashSet::new(),
+		}
+	}
+	/// Flag a chunk as needing to be saved. Chunks get flagged automatically by set(),
+	/// this is for changes made some other way (e.g. through borrow_chunk_mut()).
+	pub fn mark_chunk_dirty(&mut self, pos: ChunkPos) {
+		if self.chunks.contains_key(&pos) {
+			self.dirty.insert(pos);
+		}
+	}
+	pub fn is_chunk_dirty(&self, pos: &ChunkPos) -> bool {
+		self.dirty.contains(pos)
+	}
+	pub fn dirty_chunk_count(&self) -> usize {
+		self.dirty.len()
+	}
+	/// Every chunk on the dirty list, in the same order as iter_loaded_sorted(). Leaves them flagged.
+	pub fn dirty_chunks(&self) -> Vec<ChunkPos> {
+		let mut dirty: Vec<ChunkPos> = self.dirty.iter().copied().collect();
+		dirty.sort_unstable_by_key(|pos| (pos.z, pos.y, pos.x));
+		dirty
+	}
+	/// Takes one chunk off of the dirty list. Returns false if it wasn't on there.
+	pub fn take_dirty_chunk(&mut self, pos: &ChunkPos) -> bool {
+		self.dirty.remove(pos)
+	}
+	/// Takes up to max_chunks chunks off of the dirty list, in the same order as iter_loaded_sorted(),
+	/// so that a save can be spread out over several calls. The caller is expected to save them.
+	pub fn take_dirty_chunks(&mut self, max_chunks: usize) -> Vec<ChunkPos> {
+		let mut dirty = self.dirty_chunks();
+		dirty.truncate(max_chunks);
+		for pos in dirty.iter() {
+			self.dirty.remove(pos);
+		}
+		dirty
+	}
+	/// Borrow the chunk at center and its face neighbors together, see ChunkNeighborhood.
+	/// Only the center chunk has to be loaded.
+	pub fn borrow_chunk_neighborhood(&self, center: ChunkPos) -> Result<ChunkNeighborhood<'_>, TileSpaceError> {
+		let center_chunk = self.chunks.get(&center).ok_or(TileSpaceError::NotYetLoaded(center))?;
+		let mut neighbors = [None; 6];
+		for side in VoxelSide::iter_all() {
+			neighbors[side.to_id() as usize] = self.chunks.get(&center.get_neighbor(side));
 		}
+		Ok(ChunkNeighborhood {
+			center_pos: center,
+			center: center_chunk,
+			neighbors,
+		})
 	}
 	/// Pull in a chunk that has been successfully loaded elsewhere in the engine.
 	pub fn ingest_loaded_chunk(
@@ -68,6 +179,189 @@ impl TileSpace {
 			Ok(())
 		}
 	}
+
+	/// Iterate over every loaded chunk in a deterministic order, unlike iterating the underlying hashmap.
+	/// Chunks are ordered by z, then y, then x, each ascending - so identical spaces always
+	/// produce identical save sets and hashes regardless of the order chunks were loaded in.
+	pub fn iter_loaded_sorted(&self) -> impl Iterator<Item = (&ChunkPos, &chunk::Chunk<TileId>)> {
+		let mut loaded: Vec<(&ChunkPos, &chunk::Chunk<TileId>)> = self.chunks.iter().collect();
+		loaded.sort_unstable_by_key(|(pos, _)| (pos.z, pos.y, pos.x));
+		loaded.into_iter()
+	}
+
+	/// Finds the region of face-connected voxels around start for which matches() returns true.
+	/// This is a breadth-first search, so it never visits a voxel twice, and it stops early
+	/// (saying so in FloodFill::stopped) either once it has max_voxels voxels or as soon as it
+	/// would have to look into a chunk which is not loaded. If start itself does not match,
+	/// the result is empty.
+	pub fn flood_fill<F: Fn(TileId) -> bool>(&self, start: TilePos, matches: F, max_voxels: usize) -> FloodFill {
+		let mut voxels = Vec::new();
+		let start_tile = match self.get(start) {
+			Ok(tile) => *tile,
+			Err(_) => {
+				return FloodFill {
+					voxels,
+					stopped: Some(FloodFillStop::HitUnloaded(start)),
+				};
+			}
+		};
+		if !matches(start_tile) {
+			return FloodFill { voxels, stopped: None };
+		}
+
+		// Everything that has ever been queued, so nothing gets queued twice.
+		let mut seen: HashSet<TilePos> = HashSet::new();
+		let mut queue: VecDeque<TilePos> = VecDeque::new();
+		seen.insert(start);
+		queue.push_back(start);
+
+		while let Some(pos) = queue.pop_front() {
+			if voxels.len() >= max_voxels {
+				return FloodFill {
+					voxels,
+					stopped: Some(FloodFillStop::HitLimit),
+				};
+			}
+			voxels.push(pos);
+			for side in VoxelSide::iter_all() {
+				let neighbor = pos.get_neighbor(side);
+				if seen.contains(&neighbor) {
+					continue;
+				}
+				match self.get(neighbor) {
+					Ok(tile) => {
+						if matches(*tile) {
+							seen.insert(neighbor);
+							queue.push_back(neighbor);
+						}
+					}
+					Err(_) => {
+						return FloodFill {
+							voxels,
+							stopped: Some(FloodFillStop::HitUnloaded(neighbor)),
+						};
+					}
+				}
+			}
+		}
+		FloodFill { voxels, stopped: None }
+	}
+
+	/// Writes model into this space with the model's (0, 0, 0) corner at origin. Every chunk the model
+	/// overlaps has to be loaded - if any of them aren't, nothing is written at all.
+	/// Returns the chunks whose contents actually changed, which are the ones that need remeshing.
+	pub fn stamp(&mut self, model: &VoxelModel, origin: TilePos, mode: StampMode) -> Result<HashSet<ChunkPos>, TileSpaceError> {
+		self.stamp_inner(model, origin, mode, |_| {})
+	}
+
+	/// stamp(), recorded into history as a single transaction.
+	pub fn stamp_tracked(&mut self,
+			model: &VoxelModel,
+			origin: TilePos,
+			mode: StampMode,
+			history: &mut EditHistory) -> Result<HashSet<ChunkPos>, TileSpaceError> {
+		history.begin_transaction();
+		let result = self.stamp_inner(model, origin, mode, |edit| history.record(edit));
+		history.end_transaction();
+		result
+	}
+
+	fn stamp_inner<F: FnMut(VoxelEdit)>(&mut self,
+			model: &VoxelModel,
+			origin: TilePos,
+			mode: StampMode,
+			mut on_change: F) -> Result<HashSet<ChunkPos>, TileSpaceError> {
+		let (size_x, size_y, size_z) = model.get_size();
+		if model.volume() == 0 {
+			return Ok(HashSet::new());
+		}
+		let upper = origin + vpos!(size_x as TileCoord - 1, size_y as TileCoord - 1, size_z as TileCoord - 1);
+		let lower_chunk = world_to_chunk_pos(&origin);
+		let upper_chunk = world_to_chunk_pos(&upper);
+		for chunk_z in lower_chunk.z..=upper_chunk.z {
+			for chunk_y in lower_chunk.y..=upper_chunk.y {
+				for chunk_x in lower_chunk.x..=upper_chunk.x {
+					let chunk_pos = vpos!(chunk_x, chunk_y, chunk_z);
+					if !self.chunks.contains_key(&chunk_pos) {
+						// Report a voxel which is actually part of the model.
+						let corner = chunk_to_world_pos(&chunk_pos);
+						return Err(TileSpaceError::NotYetLoaded(vpos!(
+							corner.x.max(origin.x),
+							corner.y.max(origin.y),
+							corner.z.max(origin.z)
+						)));
+					}
+				}
+			}
+		}
+
+		let mut touched = HashSet::new();
+		for ((x, y, z), tile) in model.iter() {
+			if mode == StampMode::SkipTile(tile) {
+				continue;
+			}
+			let pos = origin + vpos!(x as TileCoord, y as TileCoord, z as TileCoord);
+			let (chunk_pos, local) = pos.to_chunk_and_local();
+			// Checked above.
+			let chunk = self.chunks.get_mut(&chunk_pos).unwrap();
+			let revision_before = chunk.revision;
+			let old_tile = *chunk.get(local)?;
+			chunk.set(local, tile)?;
+			if chunk.revision != revision_before {
+				touched.insert(chunk_pos);
+				on_change(VoxelEdit {
+					pos,
+					old_tile,
+					new_tile: tile,
+				});
+			}
+		}
+		self.dirty.extend(touched.iter().copied());
+		Ok(touched)
+	}
+
+	/// Sets every tile in range to tile, a chunk at a time through Chunk::set_region(). Like stamp(), every chunk
+	/// the range overlaps has to be loaded or nothing is written at all, and the chunks whose contents actually
+	/// changed get flagged dirty and returned so they can be remeshed.
+	pub fn set_region(&mut self, range: VoxelRange<TileCoord>, tile: TileId) -> Result<HashSet<ChunkPos>, TileSpaceError> {
+		let pieces: Vec<VoxelRange<TileCoord>> = range.chunked(CHUNK_SIZE as u32).collect();
+		for piece in pieces.iter() {
+			if !self.chunks.contains_key(&world_to_chunk_pos(&piece.lower)) {
+				return Err(TileSpaceError::NotYetLoaded(piece.lower));
+			}
+		}
+
+		let mut touched = HashSet::new();
+		for piece in pieces {
+			let (chunk_pos, local_lower) = piece.lower.to_chunk_and_local();
+			let size = piece.get_size();
+			let local_range = VoxelRange {
+				lower: local_lower,
+				upper: local_lower + vpos!(size.x as u8, size.y as u8, size.z as u8),
+			};
+			// Checked above.
+			let chunk = self.chunks.get_mut(&chunk_pos).unwrap();
+			let revision_before = chunk.revision;
+			chunk.set_region(local_range, tile)?;
+			if chunk.revision != revision_before {
+				touched.insert(chunk_pos);
+			}
+		}
+		self.dirty.extend(touched.iter().copied());
+		Ok(touched)
+	}
+
+	/// set(), but the change gets recorded in history so it can be undone.
+	pub fn set_tracked(&mut self, pos: TilePos, value: TileId, history: &mut EditHistory) -> Result<(), TileSpaceError> {
+		let old_tile = *self.get(pos)?;
+		self.set(pos, value)?;
+		history.record(VoxelEdit {
+			pos,
+			old_tile,
+			new_tile: value,
+		});
+		Ok(())
+	}
 }
 
 impl Default for TileSpace {
@@ -111,24 +405,46 @@ pub fn chunk_to_world_pos(v: &ChunkPos) -> TilePos {
 	)
 }
 
+impl TilePos {
+	/// Separate into the chunk cell this tile is in and its offset within that chunk.
+	/// Rounds towards negative infinity, so (-1, -1, -1) is the last tile of chunk (-1, -1, -1) rather than part of chunk (0, 0, 0).
+	#[inline(always)]
+	pub fn to_chunk_and_local(&self) -> (ChunkPos, LocalTilePos) {
+		let (x, chx) = world_to_chunk_local_coord(self.x);
+		let (y, chy) = world_to_chunk_local_coord(self.y);
+		let (z, chz) = world_to_chunk_local_coord(self.z);
+		(vpos!(chx, chy, chz), vpos!(x as u8, y as u8, z as u8))
+	}
+
+	/// Inverse of to_chunk_and_local()
+	#[inline(always)]
+	pub fn from_chunk_and_local(chunk: ChunkPos, local: LocalTilePos) -> TilePos {
+		chunk_to_world_pos(&chunk)
+			+ vpos!(local.x as TileCoord, local.y as TileCoord, local.z as TileCoord)
+	}
+}
+
 impl VoxelStorage<TileId, TileCoord> for TileSpace {
 	type Error = TileSpaceError;
 
 	fn get(&self, pos: TilePos) -> Result<&TileId, TileSpaceError> {
-		let (x, chx) = world_to_chunk_local_coord(pos.x);
-		let (y, chy) = world_to_chunk_local_coord(pos.y);
-		let (z, chz) = world_to_chunk_local_coord(pos.z);
-		match self.chunks.get(&vpos!(chx, chy, chz)) {
-			Some(chunk) => Ok(chunk.get(vpos!(x as u8, y as u8, z as u8))?),
+		let (chunk_pos, local) = pos.to_chunk_and_local();
+		match self.chunks.get(&chunk_pos) {
+			Some(chunk) => Ok(chunk.get(local)?),
 			None => Err(TileSpaceError::NotYetLoaded(pos)),
 		}
 	}
 	fn set(&mut self, pos: TilePos, value: TileId) -> Result<(), TileSpaceError> {
-		let (x, chx) = world_to_chunk_local_coord(pos.x);
-		let (y, chy) = world_to_chunk_local_coord(pos.y);
-		let (z, chz) = world_to_chunk_local_coord(pos.z);
-		match self.chunks.get_mut(&vpos!(chx, chy, chz)) {
-			Some(chunk) => Ok((*chunk).set(vpos!(x as u8, y as u8, z as u8), value)?),
+		let (chunk_pos, local) = pos.to_chunk_and_local();
+		match self.chunks.get_mut(&chunk_pos) {
+			Some(chunk) => {
+				let revision_before = chunk.revision;
+				(*chunk).set(local, value)?;
+				if chunk.revision != revision_before {
+					self.dirty.insert(chunk_pos);
+				}
+				Ok(())
+			}
 			None => Err(TileSpaceError::NotYetLoaded(pos)),
 		}
 	}
@@ -140,10 +456,7 @@ impl VoxelSpace<TileId> for TileSpace {
 	type Chunk = chunk::Chunk<TileId>;
 
 	fn is_loaded(&self, voxel: TilePos) -> bool {
-		let (_, chx) = world_to_chunk_local_coord(voxel.x);
-		let (_, chy) = world_to_chunk_local_coord(voxel.y);
-		let (_, chz) = world_to_chunk_local_coord(voxel.z);
-		self.chunks.contains_key(&vpos!(chx, chy, chz))
+		self.chunks.contains_key(&world_to_chunk_pos(&voxel))
 	}
 
 	/// Try to borrow a chunk immutably. If it isn't loaded yet, returns error.
@@ -170,3 +483,268 @@ impl VoxelSpace<TileId> for TileSpace {
 		self.chunks.keys().collect()
 	}
 }
+
+#[test]
+fn iter_loaded_sorted_is_z_y_x_order() {
+	let mut space = TileSpace::new();
+	let positions: [ChunkPos; 6] = [
+		vpos!(1, 0, 0),
+		vpos!(0, 0, 1),
+		vpos!(-1, 2, 0),
+		vpos!(0, 1, 0),
+		vpos!(0, 0, -1),
+		vpos!(0, 0, 0),
+	];
+	for pos in positions {
+		space.ingest_loaded_chunk(pos, chunk::Chunk::new(0)).unwrap();
+	}
+	let order: Vec<ChunkPos> = space.iter_loaded_sorted().map(|(pos, _)| *pos).collect();
+	assert_eq!(order, vec![
+		vpos!(0, 0, -1),
+		vpos!(0, 0, 0),
+		vpos!(1, 0, 0),
+		vpos!(0, 1, 0),
+		vpos!(-1, 2, 0),
+		vpos!(0, 0, 1),
+	]);
+}
+
+#[test]
+fn set_marks_chunks_dirty() {
+	let mut space = TileSpace::new();
+	for pos in [vpos!(0, 0, 0), vpos!(1, 0, 0), vpos!(0, 0, 1)] {
+		space.ingest_loaded_chunk(pos, chunk::Chunk::new(0)).unwrap();
+	}
+	assert_eq!(space.dirty_chunk_count(), 0);
+
+	// Setting a tile to what it already was isn't a change.
+	space.set(vpos!(1, 1, 1), 0).unwrap();
+	assert_eq!(space.dirty_chunk_count(), 0);
+
+	let size = CHUNK_SIZE as TileCoord;
+	space.set(vpos!(1, 1, 1), 1).unwrap();
+	space.set(vpos!(2, 2, 2), 1).unwrap();
+	space.set(vpos!(size, 0, 0), 1).unwrap();
+	space.set(vpos!(0, 0, size), 1).unwrap();
+	assert_eq!(space.dirty_chunk_count(), 3);
+	assert!(space.is_chunk_dirty(&vpos!(1, 0, 0)));
+
+	assert_eq!(space.take_dirty_chunks(2), vec![vpos!(0, 0, 0), vpos!(1, 0, 0)]);
+	assert_eq!(space.take_dirty_chunks(2), vec![vpos!(0, 0, 1)]);
+	assert!(space.take_dirty_chunks(2).is_empty());
+}
+
+#[test]
+fn flood_fill_finds_connected_region() {
+	let mut space = TileSpace::new();
+	space.ingest_loaded_chunk(vpos!(0, 0, 0), chunk::Chunk::new(0)).unwrap();
+	// A 3x3x3 cube of 1s...
+	let cube: VoxelRange<TileCoord> = VoxelRange {
+		lower: vpos!(2, 2, 2),
+		upper: vpos!(5, 5, 5),
+	};
+	for pos in cube {
+		space.set(pos, 1).unwrap();
+	}
+	// ...plus one voxel touching it only at a corner, and one touching a face.
+	space.set(vpos!(5, 5, 5), 1).unwrap();
+	space.set(vpos!(3, 3, 5), 1).unwrap();
+	// Different tile, same region - only counts when matches() accepts it.
+	space.set(vpos!(3, 3, 1), 2).unwrap();
+
+	let fill = space.flood_fill(vpos!(3, 3, 3), |tile| tile == 1, 1000);
+	assert!(fill.is_complete());
+	assert_eq!(fill.voxels.len(), 28);
+	assert_eq!(fill.voxels[0], vpos!(3, 3, 3));
+	let found: HashSet<TilePos> = fill.voxels.iter().copied().collect();
+	assert_eq!(found.len(), fill.voxels.len());
+	assert!(cube.into_iter().all(|pos| found.contains(&pos)));
+	assert!(found.contains(&vpos!(3, 3, 5)));
+	assert!(!found.contains(&vpos!(5, 5, 5)));
+
+	let fill = space.flood_fill(vpos!(3, 3, 3), |tile| tile != 0, 1000);
+	assert_eq!(fill.voxels.len(), 29);
+
+	let fill = space.flood_fill(vpos!(3, 3, 3), |tile| tile == 1, 10);
+	assert_eq!(fill.voxels.len(), 10);
+	assert_eq!(fill.stopped, Some(FloodFillStop::HitLimit));
+
+	// Exactly enough room is not a truncation.
+	let fill = space.flood_fill(vpos!(3, 3, 3), |tile| tile == 1, 28);
+	assert!(fill.is_complete());
+
+	let fill = space.flood_fill(vpos!(0, 0, 0), |tile| tile == 1, 1000);
+	assert!(fill.is_complete());
+	assert!(fill.voxels.is_empty());
+}
+
+#[test]
+fn flood_fill_stops_at_unloaded_chunks() {
+	let size = CHUNK_SIZE as TileCoord;
+	let mut space = TileSpace::new();
+	space.ingest_loaded_chunk(vpos!(0, 0, 0), chunk::Chunk::new(0)).unwrap();
+	// A line running up against the +X edge of the only loaded chunk.
+	for x in (size - 3)..size {
+		space.set(vpos!(x, 1, 1), 1).unwrap();
+	}
+	let fill = space.flood_fill(vpos!(size - 3, 1, 1), |tile| tile == 1, 1000);
+	assert_eq!(fill.voxels.len(), 3);
+	assert_eq!(fill.stopped, Some(FloodFillStop::HitUnloaded(vpos!(size, 1, 1))));
+
+	let fill = space.flood_fill(vpos!(-1, 0, 0), |tile| tile == 1, 1000);
+	assert!(fill.voxels.is_empty());
+	assert_eq!(fill.stopped, Some(FloodFillStop::HitUnloaded(vpos!(-1, 0, 0))));
+}
+
+#[test]
+fn tile_pos_chunk_split_negative() {
+	let size = CHUNK_SIZE as TileCoord;
+	let last = (CHUNK_SIZE - 1) as u8;
+
+	assert_eq!(vpos!(0, 0, 0).to_chunk_and_local(), (vpos!(0, 0, 0), vpos!(0, 0, 0)));
+	// Naive `/` and `%` would put these in chunk 0 with a negative offset.
+	assert_eq!(vpos!(-1, -1, -1).to_chunk_and_local(), (vpos!(-1, -1, -1), vpos!(last, last, last)));
+	assert_eq!(vpos!(-size, -size - 1, size - 1).to_chunk_and_local(), (vpos!(-1, -2, 0), vpos!(0, last, last)));
+	assert_eq!(vpos!(size, -size + 1, -2 * size).to_chunk_and_local(), (vpos!(1, -1, -2), vpos!(0, 1, 0)));
+}
+
+#[test]
+fn tile_pos_chunk_split_round_trip() {
+	let range: VoxelRange<TileCoord> = VoxelRange {
+		lower: vpos!(-70, -70, -3),
+		upper: vpos!(70, 3, 70),
+	};
+	for pos in range {
+		let (chunk, local) = pos.to_chunk_and_local();
+		assert!((local.x as usize) < CHUNK_SIZE);
+		assert!((local.y as usize) < CHUNK_SIZE);
+		assert!((local.z as usize) < CHUNK_SIZE);
+		assert_eq!(chunk, world_to_chunk_pos(&pos));
+		assert_eq!(TilePos::from_chunk_and_local(chunk, local), pos);
+	}
+}
+
+#[test]
+fn stamp_spans_chunks() {
+	let mut space = TileSpace::new();
+	for pos in [vpos!(0, 0, 0), vpos!(1, 0, 0)] {
+		space.ingest_loaded_chunk(pos, chunk::Chunk::new(0)).unwrap();
+	}
+	let size = CHUNK_SIZE as TileCoord;
+	let mut model = VoxelModel::new(4, 1, 1, 3).unwrap();
+	// Air in the middle of the model.
+	model.set(1, 0, 0, 0);
+	space.set(vpos!(size - 1, 0, 0), 9).unwrap();
+	space.take_dirty_chunks(usize::MAX);
+
+	let touched = space.stamp(&model, vpos!(size - 2, 0, 0), StampMode::SkipTile(0)).unwrap();
+	assert_eq!(touched, HashSet::from([vpos!(0, 0, 0), vpos!(1, 0, 0)]));
+	assert_eq!(*space.get(vpos!(size - 2, 0, 0)).unwrap(), 3);
+	// Skipped, so the existing tile survives.
+	assert_eq!(*space.get(vpos!(size - 1, 0, 0)).unwrap(), 9);
+	assert_eq!(*space.get(vpos!(size + 1, 0, 0)).unwrap(), 3);
+	assert_eq!(space.dirty_chunk_count(), 2);
+
+	space.stamp(&model, vpos!(size - 2, 0, 0), StampMode::Overwrite).unwrap();
+	assert_eq!(*space.get(vpos!(size - 1, 0, 0)).unwrap(), 0);
+
+	// Hanging off into an unloaded chunk writes nothing.
+	assert!(matches!(
+		space.stamp(&model, vpos!(2 * size - 2, 5, 0), StampMode::Overwrite),
+		Err(TileSpaceError::NotYetLoaded(_))
+	));
+	assert_eq!(*space.get(vpos!(2 * size - 2, 5, 0)).unwrap(), 0);
+}
+
+#[test]
+fn set_region_matches_per_voxel_set() {
+	let size = CHUNK_SIZE as TileCoord;
+	let chunks: VoxelRange<ChunkCoord> = VoxelRange::new(vpos!(-1, -1, -1), vpos!(1, 1, 1));
+	let mut bulk = TileSpace::new();
+	let mut naive = TileSpace::new();
+	for chunk_pos in chunks {
+		bulk.ingest_loaded_chunk(chunk_pos, chunk::Chunk::new(0)).unwrap();
+		naive.ingest_loaded_chunk(chunk_pos, chunk::Chunk::new(0)).unwrap();
+	}
+	let regions: [(VoxelRange<TileCoord>, TileId); 4] = [
+		// Covers chunk (-1, -1, -1) entirely and spills into its neighbors.
+		(VoxelRange::new(vpos!(-size, -size, -size), vpos!(3, 2, 1)), 1),
+		// Entirely inside one chunk.
+		(VoxelRange::new(vpos!(4, 5, 6), vpos!(9, 20, 7)), 2),
+		// Straddles the origin, partly overwriting the first region.
+		(VoxelRange::new(vpos!(-5, -7, -3), vpos!(6, 4, 11)), 3),
+		// Back to what it already was, for part of it.
+		(VoxelRange::new(vpos!(-5, -7, -3), vpos!(0, 0, 0)), 0),
+	];
+	for (range, tile) in regions {
+		let touched = bulk.set_region(range, tile).unwrap();
+		let mut expected_touched = HashSet::new();
+		for pos in range {
+			let (chunk_pos, _) = pos.to_chunk_and_local();
+			if *naive.get(pos).unwrap() != tile {
+				expected_touched.insert(chunk_pos);
+			}
+			naive.set(pos, tile).unwrap();
+		}
+		assert_eq!(touched, expected_touched);
+	}
+
+	for chunk_pos in chunks {
+		let bulk_chunk = bulk.borrow_chunk(&chunk_pos).unwrap();
+		let naive_chunk = naive.borrow_chunk(&chunk_pos).unwrap();
+		assert_eq!(bulk_chunk.revision, naive_chunk.revision);
+		assert_eq!(bulk.is_chunk_dirty(&chunk_pos), naive.is_chunk_dirty(&chunk_pos));
+		for local in bulk_chunk.get_bounds() {
+			assert_eq!(bulk_chunk.get(local).unwrap(), naive_chunk.get(local).unwrap());
+		}
+	}
+	// Running off into unloaded chunks writes nothing.
+	assert!(matches!(
+		bulk.set_region(VoxelRange::new(vpos!(size - 1, 0, 0), vpos!(size + 1, 1, 1)), 7),
+		Err(TileSpaceError::NotYetLoaded(_))
+	));
+	assert_eq!(*bulk.get(vpos!(size - 1, 0, 0)).unwrap(), 0);
+}
+
+#[test]
+fn neighborhood_matches_space_get() {
+	let size = CHUNK_SIZE as TileCoord;
+	let mut space = TileSpace::new();
+	// Center chunk at (1, 0, 0), with every neighbor loaded except -Y.
+	let center: ChunkPos = vpos!(1, 0, 0);
+	space.ingest_loaded_chunk(center, chunk::Chunk::new(0)).unwrap();
+	for side in VoxelSide::iter_all() {
+		if side != VoxelSide::NegaY {
+			space.ingest_loaded_chunk(center.get_neighbor(side), chunk::Chunk::new(0)).unwrap();
+		}
+	}
+	// A diagonal neighbor, which the neighborhood doesn't include.
+	space.ingest_loaded_chunk(vpos!(2, 1, 0), chunk::Chunk::new(0)).unwrap();
+	let origin = chunk_to_world_pos(&center);
+	let mut tile = 1;
+	for pos in VoxelRange::new(origin - vpos!(2, 2, 2), origin + vpos!(size + 2, size + 2, size + 2)) {
+		if space.is_loaded(pos) {
+			space.set(pos, tile).unwrap();
+			tile += 1;
+		}
+	}
+
+	let neighborhood = space.borrow_chunk_neighborhood(center).unwrap();
+	assert!(neighborhood.neighbor(VoxelSide::PosiX).is_some());
+	assert!(neighborhood.neighbor(VoxelSide::NegaY).is_none());
+	for pos in VoxelRange::new(origin - vpos!(2, 2, 2), origin + vpos!(size + 2, size + 2, size + 2)) {
+		let offset = pos - origin;
+		let outside_axes = [offset.x, offset.y, offset.z].iter().filter(|v| **v < 0 || **v >= size).count();
+		let from_neighborhood = neighborhood.get_local(offset);
+		if outside_axes > 1 || (offset.y < 0 && outside_axes == 1) {
+			assert_eq!(from_neighborhood, None);
+		} else {
+			assert_eq!(from_neighborhood, Some(space.get(pos).unwrap()));
+		}
+	}
+
+	assert!(matches!(
+		space.borrow_chunk_neighborhood(vpos!(5, 5, 5)),
+		Err(TileSpaceError::NotYetLoaded(_))
+	));
+}
@@ -0,0 +1,168 @@
+//! Small, self-contained blocks of voxels (structures, prefabs) which can be saved, shared
+//! as resources, and stamped into a TileSpace in one go.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::TileId;
+
+/// What `resource_type` a ResourceInfo describing a voxel model should have.
+pub const VOXEL_MODEL_RESOURCE_TYPE: &str = "@VoxelModel";
+
+/// Biggest model, along any one axis, that we'll agree to load. Keeps a malformed file from allocating the world.
+pub const VOXEL_MODEL_MAX_SIZE: u16 = 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum VoxelModelError {
+	#[error("Could not parse voxel model: {0}")]
+	Parse(#[from] ron::error::SpannedError),
+	#[error("Could not serialize voxel model: {0}")]
+	Serialize(#[from] ron::Error),
+	#[error("Voxel model claims to be {0}x{1}x{2}, but holds {3} tiles.")]
+	WrongTileCount(u16, u16, u16, usize),
+	#[error("Voxel model would be {0}x{1}x{2}, which is bigger than the limit of {VOXEL_MODEL_MAX_SIZE} along each axis.")]
+	TooLarge(u16, u16, u16),
+}
+
+/// Controls what happens when a model gets stamped down on top of voxels which are already there.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StampMode {
+	/// Every voxel in the model's bounds gets replaced, air included.
+	Overwrite,
+	/// Voxels in the model which are this tile (i.e. air) leave whatever was in the world alone.
+	SkipTile(TileId),
+}
+
+/// A bounded 3D array of tiles, stored x-fastest, then y, then z.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoxelModel {
+	size: [u16; 3],
+	tiles: Vec<TileId>,
+	/// Anything the author wants to attach - a name, a description, credits.
+	#[serde(default)]
+	pub metadata: BTreeMap<String, String>,
+}
+
+impl VoxelModel {
+	/// A model of the given size, entirely filled with one tile.
+	pub fn new(size_x: u16, size_y: u16, size_z: u16, fill: TileId) -> Result<Self, VoxelModelError> {
+		Self::check_size(size_x, size_y, size_z)?;
+		Ok(Self {
+			size: [size_x, size_y, size_z],
+			tiles: vec![fill; size_x as usize * size_y as usize * size_z as usize],
+			metadata: BTreeMap::new(),
+		})
+	}
+
+	fn check_size(size_x: u16, size_y: u16, size_z: u16) -> Result<(), VoxelModelError> {
+		if size_x > VOXEL_MODEL_MAX_SIZE || size_y > VOXEL_MODEL_MAX_SIZE || size_z > VOXEL_MODEL_MAX_SIZE {
+			return Err(VoxelModelError::TooLarge(size_x, size_y, size_z));
+		}
+		Ok(())
+	}
+
+	/// Load a model which was shared as a resource (or just saved to disk).
+	pub fn from_ron_bytes(bytes: &[u8]) -> Result<Self, VoxelModelError> {
+		let model: Self = ron::de::from_bytes(bytes)?;
+		let [size_x, size_y, size_z] = model.size;
+		Self::check_size(size_x, size_y, size_z)?;
+		if model.tiles.len() != model.volume() {
+			return Err(VoxelModelError::WrongTileCount(size_x, size_y, size_z, model.tiles.len()));
+		}
+		Ok(model)
+	}
+
+	pub fn to_ron_string(&self) -> Result<String, VoxelModelError> {
+		Ok(ron::ser::to_string(self)?)
+	}
+
+	/// (x, y, z) extents.
+	pub fn get_size(&self) -> (u16, u16, u16) {
+		(self.size[0], self.size[1], self.size[2])
+	}
+
+	pub fn volume(&self) -> usize {
+		self.size[0] as usize * self.size[1] as usize * self.size[2] as usize
+	}
+
+	fn index(&self, x: u16, y: u16, z: u16) -> Option<usize> {
+		let [size_x, size_y, size_z] = self.size;
+		if x >= size_x || y >= size_y || z >= size_z {
+			return None;
+		}
+		Some(x as usize + (y as usize * size_x as usize) + (z as usize * size_x as usize * size_y as usize))
+	}
+
+	/// None if out of bounds.
+	pub fn get(&self, x: u16, y: u16, z: u16) -> Option<&TileId> {
+		self.index(x, y, z).map(|i| &self.tiles[i])
+	}
+
+	/// Returns false (and does nothing) if out of bounds.
+	pub fn set(&mut self, x: u16, y: u16, z: u16, tile: TileId) -> bool {
+		match self.index(x, y, z) {
+			Some(i) => {
+				self.tiles[i] = tile;
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Every voxel in the model, as ((x, y, z), tile).
+	pub fn iter(&self) -> impl Iterator<Item = ((u16, u16, u16), TileId)> + '_ {
+		let [size_x, size_y, _] = self.size;
+		self.tiles.iter().enumerate().map(move |(i, tile)| {
+			let x = i % size_x as usize;
+			let y = (i / size_x as usize) % size_y as usize;
+			let z = i / (size_x as usize * size_y as usize);
+			((x as u16, y as u16, z as u16), *tile)
+		})
+	}
+
+	/// This model turned around the Y axis, the same way as VoxelSide::rotated_y() - each quarter turn
+	/// takes +X to +Z. The result still starts at (0, 0, 0), so its X and Z sizes swap on odd turns.
+	#[must_use]
+	pub fn rotated_y(&self, quarter_turns: u8) -> Self {
+		let mut result = self.clone();
+		for _ in 0..(quarter_turns % 4) {
+			let [size_x, size_y, size_z] = result.size;
+			let mut turned = Self {
+				size: [size_z, size_y, size_x],
+				tiles: result.tiles.clone(),
+				metadata: BTreeMap::new(),
+			};
+			for ((x, y, z), tile) in result.iter() {
+				turned.set(size_z - 1 - z, y, x, tile);
+			}
+			turned.metadata = result.metadata;
+			result = turned;
+		}
+		result
+	}
+}
+
+#[test]
+fn voxel_model_rotation() {
+	let mut model = VoxelModel::new(3, 1, 2, 0).unwrap();
+	model.set(2, 0, 0, 7);
+	let turned = model.rotated_y(1);
+	assert_eq!(turned.get_size(), (2, 1, 3));
+	// Moving +X in the original is moving +Z in the turned model.
+	assert_eq!(turned.get(1, 0, 2), Some(&7));
+	assert_eq!(model.rotated_y(4), model);
+	assert_eq!(turned.rotated_y(3), model);
+}
+
+#[test]
+fn voxel_model_round_trip() {
+	let mut model = VoxelModel::new(2, 2, 2, 1).unwrap();
+	model.set(1, 1, 1, 5);
+	model.metadata.insert(String::from("name"), String::from("Tiny cube"));
+	let text = model.to_ron_string().unwrap();
+	assert_eq!(VoxelModel::from_ron_bytes(text.as_bytes()).unwrap(), model);
+
+	let truncated = text.replacen("1,5", "5", 1);
+	assert!(VoxelModel::from_ron_bytes(truncated.as_bytes()).is_err());
+}
@@ -0,0 +1,394 @@
+//! Generating terrain for chunks nobody has built yet, off of the main loop's thread.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use log::{trace, warn};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use super::chunk::{Chunk, CHUNK_SIZE};
+use super::tilespace::{TileSpace, TileSpaceError};
+use super::voxelstorage::VoxelStorage;
+use super::{ChunkPos, TileId, WorldId};
+
+/// Something which can produce the terrain for a chunk of a world from scratch.
+/// This will be called from worker threads, possibly several chunks at once.
+pub trait ChunkGenerator: Send + Sync + 'static {
+	fn generate_chunk(&self, world: &WorldId, pos: ChunkPos) -> Chunk<TileId>;
+}
+
+/// Mixes bits up thoroughly, for turning (seed, position) into something random-looking.
+/// SplitMix64's finalizer - plain integer math, so it comes out the same on every platform.
+fn mix_bits(mut value: u64) -> u64 {
+	value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+	value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+	value ^ (value >> 31)
+}
+
+/// Random value from -1 to 1 at one point of the noise lattice.
+fn lattice_value(seed: u64, octave: u32, x: i32, z: i32) -> f32 {
+	let hash = mix_bits(seed ^ mix_bits(((octave as u64) << 48) ^ ((x as u32 as u64) << 24) ^ (z as u32 as u64)));
+	// Top 24 bits, which fit exactly in an f32.
+	((hash >> 40) as f32 / (1u64 << 23) as f32) - 1.0
+}
+
+/// Smoothly interpolated value noise, with a lattice point at every integer coordinate. Ranges from -1 to 1.
+fn value_noise(seed: u64, octave: u32, x: f32, z: f32) -> f32 {
+	let (x0, z0) = (x.floor(), z.floor());
+	let (ix, iz) = (x0 as i32, z0 as i32);
+	let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+	let (tx, tz) = (smooth(x - x0), smooth(z - z0));
+	let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+	let top = lerp(lattice_value(seed, octave, ix, iz), lattice_value(seed, octave, ix + 1, iz), tx);
+	let bottom = lerp(lattice_value(seed, octave, ix, iz + 1), lattice_value(seed, octave, ix + 1, iz + 1), tx);
+	lerp(top, bottom, tz)
+}
+
+/// Rolling heightmap terrain: stone, a few layers of dirt, and then grass on top. Uses the same tiles as the
+/// client's old flat test chunks. The terrain depends only on the seed and the chunk position.
+/// The same seed always produces the same chunks, whichever node generates them and in whatever order.
+#[derive(Clone, Debug)]
+pub struct NoiseWorldGen {
+	pub seed: u64,
+	/// Furthest the surface goes above or below base_height, in voxels.
+	pub amplitude: f32,
+	/// Surface height where the noise comes out to zero.
+	pub base_height: i32,
+	/// Width of the biggest hills, in voxels.
+	pub scale: f32,
+	/// Each octave adds detail half the size (and half the height) of the one before.
+	pub octaves: u32,
+}
+
+impl NoiseWorldGen {
+	pub const AIR_ID: TileId = 0;
+	pub const STONE_ID: TileId = 1;
+	pub const DIRT_ID: TileId = 2;
+	pub const GRASS_ID: TileId = 3;
+	/// Dirt layers under the grass.
+	const DIRT_DEPTH: i32 = 3;
+
+	pub fn new(seed: u64, amplitude: f32) -> Self {
+		Self {
+			seed,
+			amplitude,
+			base_height: -1,
+			scale: 64.0,
+			octaves: 4,
+		}
+	}
+
+	/// Seeded from the world's UUID, so whoever hosts a world gets the same terrain for it.
+	pub fn for_world(world: &WorldId) -> Self {
+		Self::new(world.uuid.as_u128() as u64, 24.0)
+	}
+
+	/// World-space y of the topmost solid voxel (the grass) in this column.
+	pub fn surface_height(&self, x: i32, z: i32) -> i32 {
+		let mut total = 0.0;
+		let mut total_weight = 0.0;
+		let mut weight = 1.0;
+		let mut frequency = 1.0 / self.scale.max(1.0);
+		for octave in 0..self.octaves.max(1) {
+			total += value_noise(self.seed, octave, x as f32 * frequency, z as f32 * frequency) * weight;
+			total_weight += weight;
+			weight *= 0.5;
+			frequency *= 2.0;
+		}
+		self.base_height + ((total / total_weight) * self.amplitude).round() as i32
+	}
+
+	pub fn generate_at(&self, pos: ChunkPos) -> Chunk<TileId> {
+		let size = CHUNK_SIZE as i32;
+		let (base_x, base_y, base_z) = (pos.x * size, pos.y * size, pos.z * size);
+		let mut heights = vec![0i32; CHUNK_SIZE * CHUNK_SIZE];
+		for z in 0..size {
+			for x in 0..size {
+				heights[(z * size + x) as usize] = self.surface_height(base_x + x, base_z + z);
+			}
+		}
+		let highest = heights.iter().copied().max().unwrap_or(self.base_height);
+		let lowest = heights.iter().copied().min().unwrap_or(self.base_height);
+
+		if base_y > highest {
+			return Chunk::new(Self::AIR_ID);
+		}
+		if base_y + size - 1 <= lowest - Self::DIRT_DEPTH - 1 {
+			return Chunk::new(Self::STONE_ID);
+		}
+		let mut chunk = Chunk::new(Self::AIR_ID);
+		for z in 0..size {
+			for x in 0..size {
+				let height = heights[(z * size + x) as usize];
+				for y in 0..size {
+					let world_y = base_y + y;
+					let tile = if world_y > height {
+						continue;
+					} else if world_y == height {
+						Self::GRASS_ID
+					} else if world_y > height - Self::DIRT_DEPTH - 1 {
+						Self::DIRT_ID
+					} else {
+						Self::STONE_ID
+					};
+					// Always in bounds - x, y and z all stay under CHUNK_SIZE.
+					chunk.set(vpos!(x as u8, y as u8, z as u8), tile).unwrap();
+				}
+			}
+		}
+		chunk
+	}
+}
+
+impl ChunkGenerator for NoiseWorldGen {
+	fn generate_chunk(&self, _world: &WorldId, pos: ChunkPos) -> Chunk<TileId> {
+		self.generate_at(pos)
+	}
+}
+
+/// A chunk that finished generating.
+pub struct GeneratedChunk {
+	pub world: WorldId,
+	pub pos: ChunkPos,
+	pub chunk: Chunk<TileId>,
+}
+
+type WorldGenKey = (WorldId, ChunkPos);
+
+struct WorldGenJobResult {
+	key: WorldGenKey,
+	/// Identifies which request this result answers, in case a chunk was cancelled and then re-requested.
+	cancelled: Arc<AtomicBool>,
+	chunk: Chunk<TileId>,
+}
+
+/// Runs chunk generation on the async runtime's blocking thread pool, so that generating
+/// a lot of terrain doesn't stall the server's main loop.
+/// Requests are deduplicated: asking for a chunk that is already being generated does nothing.
+pub struct WorldGenQueue<G: ChunkGenerator> {
+	generator: Arc<G>,
+	runtime: tokio::runtime::Handle,
+	/// Every chunk that has been requested but not yet received, along with its cancellation flag.
+	in_flight: HashMap<WorldGenKey, Arc<AtomicBool>>,
+	result_sender: UnboundedSender<WorldGenJobResult>,
+	result_receiver: UnboundedReceiver<WorldGenJobResult>,
+}
+
+impl<G: ChunkGenerator> WorldGenQueue<G> {
+	pub fn new(generator: G, runtime: tokio::runtime::Handle) -> Self {
+		let (result_sender, result_receiver) = mpsc::unbounded_channel();
+		Self {
+			generator: Arc::new(generator),
+			runtime,
+			in_flight: HashMap::new(),
+			result_sender,
+			result_receiver,
+		}
+	}
+
+	/// Start generating a chunk. Returns false if this chunk was already requested and hasn't been received yet.
+	pub fn request(&mut self, world: WorldId, pos: ChunkPos) -> bool {
+		let key = (world, pos);
+		if self.in_flight.contains_key(&key) {
+			return false;
+		}
+		let cancelled = Arc::new(AtomicBool::new(false));
+		self.in_flight.insert(key.clone(), cancelled.clone());
+
+		let generator = self.generator.clone();
+		let sender = self.result_sender.clone();
+		self.runtime.spawn_blocking(move || {
+			// Don't bother if nobody wants this chunk anymore.
+			if cancelled.load(Ordering::Acquire) {
+				return;
+			}
+			let chunk = generator.generate_chunk(&key.0, key.1);
+			// The receiving end only goes away if the queue was dropped, in which case nobody cares.
+			let _ = sender.send(WorldGenJobResult {
+				key,
+				cancelled,
+				chunk,
+			});
+		});
+		true
+	}
+
+	/// Stop waiting on a chunk which is no longer needed. If generation hasn't started yet,
+	/// it won't happen at all. Returns false if the chunk wasn't requested in the first place.
+	pub fn cancel(&mut self, world: &WorldId, pos: &ChunkPos) -> bool {
+		match self.in_flight.remove(&(world.clone(), *pos)) {
+			Some(cancelled) => {
+				cancelled.store(true, Ordering::Release);
+				true
+			}
+			None => false,
+		}
+	}
+
+	pub fn is_pending(&self, world: &WorldId, pos: &ChunkPos) -> bool {
+		self.in_flight.contains_key(&(world.clone(), *pos))
+	}
+
+	/// How many chunks have been requested but not yet received, for metrics.
+	pub fn queue_depth(&self) -> usize {
+		self.in_flight.len()
+	}
+
+	/// Returns the result if it answers a live request, or None if that request was cancelled.
+	fn accept_result(&mut self, result: WorldGenJobResult) -> Option<GeneratedChunk> {
+		if result.cancelled.load(Ordering::Acquire) {
+			return None;
+		}
+		match self.in_flight.get(&result.key) {
+			Some(current) if Arc::ptr_eq(current, &result.cancelled) => {
+				self.in_flight.remove(&result.key);
+				let (world, pos) = result.key;
+				Some(GeneratedChunk {
+					world,
+					pos,
+					chunk: result.chunk,
+				})
+			}
+			_ => None,
+		}
+	}
+
+	/// Takes every chunk which has finished generating so far, without waiting.
+	pub fn poll_completed(&mut self) -> Vec<GeneratedChunk> {
+		let mut completed = Vec::new();
+		while let Ok(result) = self.result_receiver.try_recv() {
+			if let Some(generated) = self.accept_result(result) {
+				completed.push(generated);
+			}
+		}
+		completed
+	}
+
+	/// Waits for the next chunk to finish generating. Returns None if nothing is in flight.
+	/// Cancel-safe, so this can be used in `tokio::select!`.
+	pub async fn recv_completed(&mut self) -> Option<GeneratedChunk> {
+		while !self.in_flight.is_empty() {
+			// We hold a sender ourselves, so this can't return None.
+			let result = self.result_receiver.recv().await?;
+			if let Some(generated) = self.accept_result(result) {
+				return Some(generated);
+			}
+		}
+		None
+	}
+
+	/// Moves every finished chunk for the given world into its TileSpace. Returns how many chunks were ingested.
+	/// Chunks finished for other worlds are handed back so that they can be ingested into their own spaces.
+	pub fn ingest_completed(&mut self, world: &WorldId, space: &mut TileSpace) -> (usize, Vec<GeneratedChunk>) {
+		let mut ingested = 0;
+		let mut others = Vec::new();
+		for generated in self.poll_completed() {
+			if &generated.world != world {
+				others.push(generated);
+				continue;
+			}
+			match space.ingest_loaded_chunk(generated.pos, generated.chunk) {
+				Ok(()) => ingested += 1,
+				Err(TileSpaceError::LoadExistingChunk(pos)) => {
+					trace!("Generated chunk {pos} was already loaded by the time it finished generating, discarding it.")
+				}
+				Err(e) => warn!("Could not ingest generated chunk {}: {e:?}", generated.pos),
+			}
+		}
+		(ingested, others)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::atomic::AtomicUsize;
+	use std::time::Duration;
+
+	use uuid::Uuid;
+
+	use super::*;
+	use crate::common::identity::IdentityKeyPair;
+	use crate::world::voxelstorage::VoxelStorage;
+
+	struct CountingGenerator {
+		calls: Arc<AtomicUsize>,
+	}
+	impl ChunkGenerator for CountingGenerator {
+		fn generate_chunk(&self, _world: &WorldId, pos: ChunkPos) -> Chunk<TileId> {
+			self.calls.fetch_add(1, Ordering::SeqCst);
+			Chunk::new(pos.y as TileId)
+		}
+	}
+
+	#[test]
+	fn noise_worldgen_is_deterministic() {
+		let positions = [vpos!(0, -1, 0), vpos!(0, 0, 0), vpos!(-3, -1, 7), vpos!(0, -4, 0), vpos!(0, 4, 0)];
+		let first = NoiseWorldGen::new(1234, 24.0);
+		let second = NoiseWorldGen::new(1234, 24.0);
+		for pos in positions {
+			assert_eq!(first.generate_at(pos).to_rle().unwrap(), second.generate_at(pos).to_rle().unwrap());
+		}
+		// Way up in the sky is all air, way down is all stone.
+		assert_eq!(first.generate_at(vpos!(0, 4, 0)).to_rle().unwrap(), vec![(NoiseWorldGen::AIR_ID, 32768)]);
+		assert_eq!(first.generate_at(vpos!(0, -4, 0)).to_rle().unwrap(), vec![(NoiseWorldGen::STONE_ID, 32768)]);
+
+		let other_seed = NoiseWorldGen::new(4321, 24.0);
+		let differs = [vpos!(0, -1, 0), vpos!(0, 0, 0), vpos!(-3, -1, 7)].iter().any(|pos| {
+			first.generate_at(*pos).to_rle().unwrap() != other_seed.generate_at(*pos).to_rle().unwrap()
+		});
+		assert!(differs);
+	}
+
+	fn test_world() -> WorldId {
+		WorldId {
+			uuid: Uuid::new_v4(),
+			host: IdentityKeyPair::generate_for_tests().public,
+		}
+	}
+
+	#[tokio::test]
+	async fn worldgen_queue_dedup_and_ingest() {
+		let calls = Arc::new(AtomicUsize::new(0));
+		let mut queue = WorldGenQueue::new(CountingGenerator { calls: calls.clone() }, tokio::runtime::Handle::current());
+		let world = test_world();
+
+		assert!(queue.request(world.clone(), vpos!(0, 1, 0)));
+		assert!(!queue.request(world.clone(), vpos!(0, 1, 0)));
+		assert!(queue.request(world.clone(), vpos!(0, 2, 0)));
+		assert_eq!(queue.queue_depth(), 2);
+
+		let mut received = Vec::new();
+		while let Some(generated) = tokio::time::timeout(Duration::from_secs(5), queue.recv_completed()).await.unwrap() {
+			received.push(generated);
+		}
+		assert_eq!(received.len(), 2);
+		assert_eq!(queue.queue_depth(), 0);
+		assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+		let mut space = TileSpace::new();
+		for generated in received {
+			space.ingest_loaded_chunk(generated.pos, generated.chunk).unwrap();
+		}
+		assert_eq!(space.get(vpos!(0, 64, 0)).unwrap(), &2);
+	}
+
+	#[tokio::test]
+	async fn worldgen_queue_cancel() {
+		let calls = Arc::new(AtomicUsize::new(0));
+		let mut queue = WorldGenQueue::new(CountingGenerator { calls }, tokio::runtime::Handle::current());
+		let world = test_world();
+
+		queue.request(world.clone(), vpos!(5, 5, 5));
+		assert!(queue.cancel(&world, &vpos!(5, 5, 5)));
+		assert!(!queue.cancel(&world, &vpos!(5, 5, 5)));
+		assert!(!queue.is_pending(&world, &vpos!(5, 5, 5)));
+		// Nothing in flight, so this returns right away rather than handing back the cancelled chunk.
+		assert!(queue.recv_completed().await.is_none());
+
+		// Re-requesting after a cancel gets a fresh result.
+		queue.request(world.clone(), vpos!(5, 5, 5));
+		let generated = tokio::time::timeout(Duration::from_secs(5), queue.recv_completed()).await.unwrap().unwrap();
+		assert_eq!(generated.pos, vpos!(5, 5, 5));
+		assert!(queue.poll_completed().is_empty());
+	}
+}
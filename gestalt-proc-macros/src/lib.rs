@@ -6,44 +6,122 @@ use proc_macro::TokenStream;
 use proc_macro2::TokenTree;
 use quote::{quote, ToTokens, format_ident};
 use syn::parse::{Parse, ParseStream};
-use syn::{parse_macro_input, DeriveInput, Ident, LitInt, MetaList, Token, Type};
+use syn::{parse_macro_input, DeriveInput, Ident, LitInt, LitStr, MetaList, Token, Type};
 extern crate proc_macro2;
 
+mod netmsg_name_hash;
+use netmsg_name_hash::{netmsg_id_from_name, NAMED_NETMSG_ID_BIT};
+
 struct NetMsgAttr {
 	id_lit: Option<LitInt>,
 	id_ident: Option<Ident>,
+	/// `name = "..."` in place of an ID - the ID gets derived from a hash of this.
+	id_name: Option<LitStr>,
 	sidedness: Ident,
 	guarantee: Ident,
-	stream_select: Option<Ident>,
+	/// Either an integer literal or an identifier (e.g. a const).
+	stream_select: Option<proc_macro2::TokenStream>,
+	/// Variant of MessagePriority, only allowed after a stream. Normal if not given.
+	priority: Option<Ident>,
+	/// Engine version this message was introduced in, as (major, minor, patch).
+	since: Option<(u64, u64, u64)>,
+}
+
+const SINCE_STR: &'static str = "since";
+const NAME_STR: &'static str = "name";
+
+fn parse_since_version(lit: &LitStr) -> syn::Result<(u64, u64, u64)> {
+	let value = lit.value();
+	let parts = value
+		.split('.')
+		.map(|part| part.trim().parse::<u64>())
+		.collect::<Result<Vec<u64>, _>>();
+	match parts.as_deref() {
+		Ok([major, minor, patch]) => Ok((*major, *minor, *patch)),
+		_ => Err(syn::Error::new(
+			lit.span(),
+			format!("Expected a version of the form \"major.minor.patch\", got \"{value}\""),
+		)),
+	}
 }
 
 impl Parse for NetMsgAttr {
 	fn parse(input: ParseStream) -> syn::Result<Self> {
 		let mut id_lit = None;
 		let mut id_ident = None;
+		let mut id_name = None;
 		if input.peek(LitInt) {
-			id_lit = input.parse()?;
+			let lit: LitInt = input.parse()?;
+			let value: u32 = lit.base10_parse()?;
+			if value & NAMED_NETMSG_ID_BIT != 0 {
+				return Err(syn::Error::new(
+					lit.span(),
+					format!("Literal NetMsg IDs must be below {NAMED_NETMSG_ID_BIT:#x}, IDs above that are for `{NAME_STR} = \"...\"`"),
+				));
+			}
+			id_lit = Some(lit);
+		} else if input.peek(Ident) && input.peek2(Token![=]) {
+			let key: Ident = input.parse()?;
+			if key != NAME_STR {
+				return Err(syn::Error::new(
+					key.span(),
+					format!("Expected a NetMsg ID, or `{NAME_STR} = \"...\"`, found \"{key}\""),
+				));
+			}
+			input.parse::<Token![=]>()?;
+			id_name = Some(input.parse()?);
 		} else if input.peek(Ident) {
 			id_ident = input.parse()?;
 		} else {
-			return Err(input.error("Expected literal integer or identifier"));
+			return Err(input.error("Expected literal integer, identifier, or `name = \"...\"`"));
 		}
 		input.parse::<Token![,]>()?;
 		let sidedness = input.parse()?;
 		input.parse::<Token![,]>()?;
 		let guarantee = input.parse()?;
-		let stream_select = if !input.is_empty() {
+		let mut stream_select = None;
+		let mut priority = None;
+		let mut since = None;
+		while !input.is_empty() {
 			input.parse::<Token![,]>()?;
-			Some(input.parse()?)
-		} else {
-			None
-		};
+			if input.is_empty() {
+				// Trailing comma
+				break;
+			}
+			if input.peek(Ident) && input.peek2(Token![=]) {
+				let key: Ident = input.parse()?;
+				input.parse::<Token![=]>()?;
+				if key != SINCE_STR {
+					return Err(syn::Error::new(
+						key.span(),
+						format!("Unrecognized netmsg option \"{key}\", expected \"{SINCE_STR}\""),
+					));
+				}
+				if since.is_some() {
+					return Err(syn::Error::new(key.span(), "\"since\" specified more than once"));
+				}
+				since = Some(parse_since_version(&input.parse::<LitStr>()?)?);
+			} else if stream_select.is_none() && since.is_none() {
+				stream_select = Some(if input.peek(LitInt) {
+					input.parse::<LitInt>()?.to_token_stream()
+				} else {
+					input.parse::<Ident>()?.to_token_stream()
+				});
+			} else if priority.is_none() && since.is_none() {
+				priority = Some(input.parse()?);
+			} else {
+				return Err(input.error("Expected `since = \"x.y.z\"`"));
+			}
+		}
 		Ok(NetMsgAttr {
 			id_lit,
 			id_ident,
+			id_name,
 			sidedness,
 			guarantee,
 			stream_select,
+			priority,
+			since,
 		})
 	}
 }
@@ -53,27 +131,39 @@ pub fn netmsg(attr: TokenStream, item: TokenStream) -> TokenStream {
 	let NetMsgAttr {
 		id_lit,
 		id_ident,
+		id_name,
 		sidedness,
 		guarantee,
 		stream_select,
+		priority,
+		since,
 	} = parse_macro_input!(attr as NetMsgAttr);
+	let priority = priority.unwrap_or_else(|| format_ident!("Normal"));
 	let stream_select = match stream_select {
 		Some(s) => quote! { crate::net::netmsg::StreamSelector::Specific(#s) },
 		None => quote! { crate::net::netmsg::StreamSelector::Any },
 	};
+	let since = match since {
+		Some((major, minor, patch)) => quote! { Some(semver::Version::new(#major, #minor, #patch)) },
+		None => quote! { None },
+	};
 
+	let tokens = item.clone();
+	let msg_struct = parse_macro_input!(tokens as syn::ItemStruct);
+	let message = msg_struct.ident;
+
+	// Two messages claiming the same ID get caught by gestalt-core's build script, which sees every NetMsg at once.
 	let id = if let Some(i) = id_lit {
 		quote!(#i)
 	} else if let Some(i) = id_ident {
 		quote!(#i)
+	} else if let Some(name) = id_name {
+		let hashed = netmsg_id_from_name(&name.value());
+		quote!(#hashed)
 	} else {
 		unreachable!()
 	};
 
-	let tokens = item.clone();
-	let msg_struct = parse_macro_input!(tokens as syn::ItemStruct);
-	let message = msg_struct.ident;
-
 	let item: syn::Item = syn::parse(item).expect("failed to parse item");
 
 	(quote! {
@@ -92,11 +182,15 @@ pub fn netmsg(attr: TokenStream, item: TokenStream) -> TokenStream {
 		fn net_msg_sidedness() -> crate::net::netmsg::MessageSidedness {
 			crate::net::netmsg::MessageSidedness::#sidedness
 		}
+		#[inline(always)]
+		fn net_msg_since() -> Option<semver::Version> { #since }
+		#[inline(always)]
+		fn net_msg_priority() -> crate::net::netmsg::MessagePriority { crate::net::netmsg::MessagePriority::#priority }
 	}
 
 	impl TryInto<crate::net::netmsg::PacketIntermediary> for &#message {
-		type Error = Box<dyn std::error::Error>;
-		fn try_into(self) -> Result<crate::net::netmsg::PacketIntermediary, Box<dyn std::error::Error>> {
+		type Error = crate::net::netmsg::PacketConstructError;
+		fn try_into(self) -> Result<crate::net::netmsg::PacketIntermediary, crate::net::netmsg::PacketConstructError> {
 			use crate::net::netmsg::NetMsg;
 			self.construct_packet()
 		}
@@ -113,6 +207,7 @@ const TAKE_RECEIVER_STR: &'static str = "take_receiver";
 
 const NEW_CHANNEL_STR: &'static str = "new_channel";
 const MANUAL_INIT_STR: &'static str = "manual_init";
+const REUSABLE_STR: &'static str = "reusable";
 
 const DOMAIN_STR: &'static str = "domain";
 const DOMAIN_SUFFIX: &'static str = "_domain";
@@ -181,33 +276,78 @@ struct ChannelHeader {
 	/// Holds the suffixed domain field rather than domain_ty.
 	pub domain: Option<Ident>,
 	pub init_kind: Option<ChannelInitKind>,
+	/// Only valid on take_receiver fields - uses reset_and_take() so the receiver
+	/// can be taken again after it's dropped.
+	pub reusable: bool,
 }
 impl ChannelHeader {
-	pub fn from_attr(meta: &MetaList) -> Option<Self> {
+	/// Returns Ok(None) if this isn't one of our attributes, and an error pointing
+	/// at the offending tokens if it is one of ours but it's malformed.
+	pub fn from_attr(meta: &MetaList) -> syn::Result<Option<Self>> {
 		let attribute_parsed = meta.path.segments.last().unwrap().ident.to_string();
-		let subset_kind = SubsetKind::from_attr(&attribute_parsed)?;
+		let subset_kind = match SubsetKind::from_attr(&attribute_parsed) {
+			Some(kind) => kind,
+			None => return Ok(None),
+		};
 
 		let mut iter = meta.tokens.clone().into_iter();
-		let first_token = iter.next()?; // There should *at least* be one.
+		// There should *at least* be one.
+		let first_token = iter.next().ok_or_else(|| {
+			syn::Error::new_spanned(
+				meta,
+				format!("#[{attribute_parsed}(...)] requires a static channel identifier as its first argument."),
+			)
+		})?;
 		if let TokenTree::Ident(channel_ident) = &first_token {
 			let mut prev_token = first_token.clone();
 			let mut domain: Option<Ident> = None;
 			let mut init_kind: Option<ChannelInitKind> = None;
+			let mut reusable = false;
 			// Equality assignment begun, next token is the value
 			let mut assigning_domain = false;
+			let unrecognized = |token: &TokenTree| {
+				syn::Error::new_spanned(
+					token,
+					format!("Unrecognized argument `{token}` to #[{attribute_parsed}({channel_ident}, ...)]. Expected new_channel, manual_init, reusable or domain = \"...\"."),
+				)
+			};
 			while let Some(token) = iter.next() {
 				let prev_token_string = prev_token.to_string();
 				let token_string = token.to_string();
+				// Set once this token has been handled as a flag, so it doesn't get rejected below.
+				let mut recognized = false;
 				if domain.is_some() && token_string.ends_with(DOMAIN_STR) { 
-					panic!("Can only define one domain field per channel!");
+					return Err(syn::Error::new_spanned(
+						&token,
+						"Can only define one domain field per channel!",
+					));
 				}
 				if let Some(init) = ChannelInitKind::from_attr(&token_string.to_lowercase()) { 
 					if init_kind.is_some() { 
-						panic!("Cannot declare a channel new_channel and manual_init at the same time!");
+						return Err(syn::Error::new_spanned(
+							&token,
+							"Cannot declare a channel new_channel and manual_init at the same time!",
+						));
 					}
-					else { 
-						init_kind = Some(init);
+					if (init == ChannelInitKind::NewChannel) && (subset_kind != SubsetKind::Channel) { 
+						//Make sure we're not attempting to do something extremely nonsensical.
+						return Err(syn::Error::new_spanned(
+							&token,
+							format!("Cannot impl for {channel_ident}: new_channel may only be used on a field that holds a channel, not a receiver or a sender."),
+						));
 					}
+					init_kind = Some(init);
+					recognized = true;
+				}
+				if token_string.to_lowercase() == REUSABLE_STR { 
+					if subset_kind != SubsetKind::TakeReceiver { 
+						return Err(syn::Error::new_spanned(
+							&token,
+							format!("Cannot impl for {channel_ident}: reusable may only be used on a take_receiver field."),
+						));
+					}
+					reusable = true;
+					recognized = true;
 				}
 				match &token {
 					TokenTree::Punct(punct) => match punct.as_char() {
@@ -215,8 +355,12 @@ impl ChannelHeader {
 							if prev_token_string.to_lowercase().ends_with(DOMAIN_STR) { 
 								assigning_domain = true
 							}
+							else {
+								return Err(unrecognized(&token));
+							}
 						}
-						_ => {}, // Skip, separator-ness is already implicit in being tokenized.
+						',' => {}, // Skip, separator-ness is already implicit in being tokenized.
+						_ => return Err(unrecognized(&token)),
 					},
 					TokenTree::Literal(literal) => {
 						prev_token = token.clone();
@@ -224,10 +368,17 @@ impl ChannelHeader {
 							let mut domain_ident = literal.to_string();
 							domain_ident.remove_matches("\"");
 							domain_ident.remove_matches("\'");
-							let domain_suffixed = format_ident!("{domain_ident}{DOMAIN_SUFFIX}");
+							let domain_suffixed = syn::parse_str::<Ident>(&format!("{domain_ident}{DOMAIN_SUFFIX}"))
+								.map_err(|_| syn::Error::new_spanned(
+									literal,
+									format!("{literal} is not a valid name for a domain field."),
+								))?;
 							domain = Some(domain_suffixed);
 							assigning_domain = false;
 						}
+						else {
+							return Err(unrecognized(&token));
+						}
 					},
 					_ => {
 						prev_token = token.clone();
@@ -235,26 +386,39 @@ impl ChannelHeader {
 							let mut domain_ident = token_string.clone();
 							domain_ident.remove_matches("\"");
 							domain_ident.remove_matches("\'");
-							let domain_suffixed = format_ident!("{domain_ident}{DOMAIN_SUFFIX}");
+							let domain_suffixed = syn::parse_str::<Ident>(&format!("{domain_ident}{DOMAIN_SUFFIX}"))
+								.map_err(|_| syn::Error::new_spanned(
+									&token,
+									format!("{token_string} is not a valid name for a domain field."),
+								))?;
 							domain = Some(domain_suffixed);
 							assigning_domain = false;
 						}
+						else if !recognized && token_string.to_lowercase() != DOMAIN_STR {
+							return Err(unrecognized(&token));
+						}
 					}
 				}
 			}
-			//Make sure we're not attempting to do something extremely nonsensical.
-			if (init_kind == Some(ChannelInitKind::NewChannel)) && (subset_kind != SubsetKind::Channel) { 
-				panic!("Cannot impl for {channel_ident:#?}: new_channel may only be used on a field that holds a channel, not a receiver or a sender.")
+			if assigning_domain { 
+				return Err(syn::Error::new_spanned(
+					meta,
+					"Expected a domain field name after `domain =`.",
+				));
 			}
-			Some(Self{
+			Ok(Some(Self{
 				static_channel: channel_ident.clone(),
 				subset_kind,
 				domain,
 				init_kind,
-			})
+				reusable,
+			}))
 		}
 		else { 
-			panic!("Non-ident for channel field!");
+			Err(syn::Error::new_spanned(
+				&first_token,
+				format!("Expected a static channel identifier as the first argument to #[{attribute_parsed}(...)], found `{first_token}`."),
+			))
 		}
 	}
 }
@@ -325,6 +489,12 @@ impl IdentifiedChannel {
 			(SubsetKind::Receiver, false) => {
 				quote!{T: crate::common::message::StaticReceiverSubscribe<#static_channel>,}
 			},
+			(SubsetKind::TakeReceiver, true) if self.header.reusable => { 
+				quote!{T: crate::common::message::StaticDomainResetTakeReceiver<#static_channel>,}
+			},
+			(SubsetKind::TakeReceiver, false) if self.header.reusable => { 
+				quote!{T: crate::common::message::StaticResetTakeReceiver<#static_channel>,}
+			},
 			(SubsetKind::TakeReceiver, true) => { 
 				quote!{T: crate::common::message::StaticDomainTakeReceiver<#static_channel>,}
 			},
@@ -364,7 +534,11 @@ impl IdentifiedChannel {
 			let field_name = &self.field_name;
 			let static_channel = &self.header.static_channel;
 			Some(
-				quote!{#field_name: <<#static_channel as crate::common::message::StaticChannelAtom>::Channel as ChannelInit>::new(builder.capacity_conf.get_or_default::<#static_channel>()),}
+				quote!{#field_name: {
+					let channel = <<#static_channel as crate::common::message::StaticChannelAtom>::Channel as ChannelInit>::new(builder.capacity_conf.get_or_default::<#static_channel>());
+					crate::common::message::ChannelRegistry::register::<#static_channel>(&channel);
+					channel
+				},}
 			)
 		} else {
 			None
@@ -400,6 +574,15 @@ impl IdentifiedChannel {
 					.map_err(|e| e.to_string_form())?
 					.into(),
 			},
+			(SubsetKind::TakeReceiver, None) if self.header.reusable => quote!{
+				#field_name: <T as crate::common::message::StaticResetTakeReceiver<#static_channel>>::reset_and_take(parent)?
+					.into(),
+			},
+			(SubsetKind::TakeReceiver, Some(domain)) if self.header.reusable => quote!{
+				#field_name: <T as crate::common::message::StaticDomainResetTakeReceiver<#static_channel>>::reset_and_take(parent, &builder.static_fields.#domain)
+					.map_err(|e| e.to_string_form())?
+					.into(),
+			},
 			(SubsetKind::TakeReceiver, None) => quote!{
 				#field_name: <T as crate::common::message::StaticTakeReceiver<#static_channel>>::take_receiver(parent)?
 					.into(),
@@ -441,11 +624,20 @@ pub fn impl_channel_set(channel_set: TokenStream) -> TokenStream {
 		let mut domain_already_impl: HashSet<Ident> = HashSet::new();
 		// Loop through, appending each HasChannel impl to our implementations.
 		// Find fields with #[channel(T)] attributes
+		// Every problem we find gets reported, rather than just the first one.
+		let mut errors: Option<syn::Error> = None;
 		for field in struct_data.fields.iter() {
 			let mut non_channel = true;
 
 			let field_ty = &field.ty;
-			let field_ident = field.ident.as_ref().unwrap();
+			let field_ident = match field.ident.as_ref() {
+				Some(ident) => ident,
+				None => {
+					return syn::Error::new_spanned(field, "#[derive(ChannelSet)] requires named fields.")
+						.to_compile_error()
+						.into();
+				}
+			};
 
 			for attr in field.attrs.iter() {
 				if attr.meta.path().segments.len() == 0 {
@@ -456,7 +648,19 @@ pub fn impl_channel_set(channel_set: TokenStream) -> TokenStream {
 					Err(_) => { continue; }
 				};
 				// Check to see if this is *our* attribute and not something else.
-				if let Some(header) = ChannelHeader::from_attr(meta) {
+				let header = match ChannelHeader::from_attr(meta) {
+					Ok(header) => header,
+					Err(e) => {
+						match errors.as_mut() {
+							Some(existing) => existing.combine(e),
+							None => errors = Some(e),
+						}
+						// Don't also treat it as a plain builder field.
+						non_channel = false;
+						continue;
+					}
+				};
+				if let Some(header) = header {
 					non_channel = false;
 					let identified_channel = IdentifiedChannel {
 						field_name: field_ident.clone(),
@@ -499,6 +703,9 @@ pub fn impl_channel_set(channel_set: TokenStream) -> TokenStream {
 				}
 			}
 		}
+		if let Some(errors) = errors { 
+			return errors.to_compile_error().into();
+		}
 		let no_builder_fields = static_builder_fields.is_empty();
 		let static_builder_fields = proc_macro2::TokenStream::from_iter(static_builder_fields.into_iter());
 		let builder_ident =  if no_builder_fields {
@@ -544,6 +751,8 @@ pub fn impl_channel_set(channel_set: TokenStream) -> TokenStream {
 		impls.into()
 	}
 	else { 
-		panic!("Cannot use #[derive(ChannelSet)] on non-structs!")
+		syn::Error::new_spanned(&struct_ident, "Cannot use #[derive(ChannelSet)] on non-structs!")
+			.to_compile_error()
+			.into()
 	}
 }
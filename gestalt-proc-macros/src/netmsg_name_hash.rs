@@ -0,0 +1,22 @@
+// Shared between the netmsg macro, gestalt-core's build script, and gestalt-core's net::netmsg (the latter two
+// include!() this file), so that all of them agree on what ID a name maps to. Do not change this without bumping the protocol -
+// it decides what goes on the wire.
+
+/// Top bit is set on every name-derived NetMsgId, and never on a literal one,
+/// so hand-picked IDs and name-derived IDs cannot collide with each other.
+pub const NAMED_NETMSG_ID_BIT: u32 = 0x8000_0000;
+
+/// FNV-1a, 32-bit, with NAMED_NETMSG_ID_BIT forced on.
+pub const fn netmsg_id_from_name(name: &str) -> u32 {
+	const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+	const FNV_PRIME: u32 = 0x0100_0193;
+	let bytes = name.as_bytes();
+	let mut hash = FNV_OFFSET_BASIS;
+	let mut i = 0;
+	while i < bytes.len() {
+		hash ^= bytes[i] as u32;
+		hash = hash.wrapping_mul(FNV_PRIME);
+		i += 1;
+	}
+	hash | NAMED_NETMSG_ID_BIT
+}
@@ -0,0 +1,9 @@
+use gestalt_proc_macros::ChannelSet;
+
+#[derive(ChannelSet)]
+struct Channels {
+	#[channel(SomeChannel, domain = "first", domain = "second")]
+	some: (),
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use gestalt_proc_macros::ChannelSet;
+
+#[derive(ChannelSet)]
+struct Channels {
+	#[channel()]
+	empty: (),
+	#[receiver("SomeChannel")]
+	not_an_ident: (),
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use gestalt_proc_macros::ChannelSet;
+
+#[derive(ChannelSet)]
+struct Channels {
+	#[channel(SomeChannel, new_channel, manual_init)]
+	some: (),
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use gestalt_proc_macros::ChannelSet;
+
+// Only a field holding the channel itself can create it.
+#[derive(ChannelSet)]
+struct Channels {
+	#[sender(SomeChannel, new_channel)]
+	sender: (),
+	#[receiver(OtherChannel, new_channel)]
+	receiver: (),
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use gestalt_proc_macros::ChannelSet;
+
+// Typos in ChannelSet attributes used to be silently ignored, leaving the field set up differently than intended.
+#[derive(ChannelSet)]
+struct Channels {
+	#[channel(SomeChannel, new_chanel)]
+	some: (),
+	#[take_receiver(OtherChannel, reuseable)]
+	other: (),
+}
+
+fn main() {}
@@ -0,0 +1,52 @@
+// Fullscreen pass which takes the (possibly HDR) scene and squashes it down to something the screen can show.
+struct ToneMapUniform {
+    exposure: f32,
+    // Exponent applied after tonemapping - already accounts for whether the surface does its own sRGB encoding.
+    gamma_power: f32,
+    // 0 = Reinhard, 1 = ACES
+    operator: u32,
+    _padding: u32,
+};
+@group(0) @binding(0)
+var scene: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemap: ToneMapUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// One triangle which covers the whole screen, no vertex buffer needed.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (color + vec3<f32>(1.0));
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(scene, vec2<i32>(in.clip_position.xy), 0).rgb * tonemap.exposure;
+    var mapped: vec3<f32>;
+    if (tonemap.operator == 1u) {
+        mapped = aces(hdr);
+    } else {
+        mapped = reinhard(hdr);
+    }
+    return vec4<f32>(pow(mapped, vec3<f32>(tonemap.gamma_power)), 1.0);
+}
@@ -13,12 +13,14 @@ var<push_constant> model_matrix: ModelPush;
 
 struct VertexInput {
     @location(0) @interpolate(flat) vertex_data: u32,
+    @location(1) @interpolate(flat) uv_data: u32,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) tex_idx: i32,
+    @location(2) ao: f32,
 }
 
 @vertex
@@ -43,11 +45,15 @@ fn vs_main(
 	var t_id = i32((vertex.vertex_data >> u32(18)) & bitmask_12);
     out.tex_idx = t_id;
 	
-	var bitmask_1 = u32(1); //Lowest one bit time.
-	var u = f32((vertex.vertex_data >> u32(30)) & bitmask_1);
-	var v = f32((vertex.vertex_data >> u32(31)) & bitmask_1);
+	//Texture coordinates count voxels, so textures repeat across merged quads.
+	var u = f32(vertex.uv_data & bitmask_6);
+	var v = f32((vertex.uv_data >> u32(6)) & bitmask_6);
     out.tex_coords = vec2<f32>(u, v);
 
+	//Extract ambient occlusion, 0 (darkest) to 3 (unoccluded)
+	var ao = f32((vertex.uv_data >> u32(12)) & u32(3));
+	out.ao = 0.4 + 0.2 * ao;
+
     return out;
 }
 
@@ -64,5 +70,6 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     // it throws a shader compilation error, so we convert it to an
     // i32 instead.
     var tex_idx: i32 = i32(in.tex_idx);
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords, tex_idx);
+    var color = textureSample(t_diffuse, s_diffuse, in.tex_coords, tex_idx);
+    return vec4<f32>(color.rgb * in.ao, color.a);
 }